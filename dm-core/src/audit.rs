@@ -0,0 +1,119 @@
+//! A security sweep of a tree: what anyone may write to, what runs with
+//! its owner's rights, and what belongs to accounts that no longer exist.
+
+use std::{
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use walkdir::WalkDir;
+
+use crate::{
+    owners::Names,
+    scan::{allocated_size, build_excludes, ScanOptions},
+    tree::DirStats,
+};
+
+/// Something wrong with an entry's permissions or owner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Problem {
+    WorldWritable,
+    Setuid,
+    Setgid,
+    NoOwner, // owned by a user id without an account
+}
+
+impl Problem {
+    pub fn label(self) -> &'static str {
+        match self {
+            Problem::WorldWritable => "world-writable",
+            Problem::Setuid => "setuid",
+            Problem::Setgid => "setgid",
+            Problem::NoOwner => "deleted owner",
+        }
+    }
+}
+
+/// An entry the sweep flagged, with its permission bits and owner.
+#[derive(Debug, Clone)]
+pub struct Flagged {
+    pub entry: DirStats,
+    pub mode: u32,
+    pub uid: u32,
+    pub problems: Vec<Problem>,
+}
+
+/// Searches `root` for entries anyone may write to, setuid and setgid
+/// files, and entries of users without an account, sorted by path.
+/// World-writable directories with the sticky bit, like `/tmp`, are left
+/// out, as only owners may remove what's in them, and so are setgid
+/// directories, which just pass their group on. Windows has none of this,
+/// so nothing is found there.
+#[cfg(unix)]
+pub fn find_flagged(root: &Path, opts: &ScanOptions, cancel: &AtomicBool) -> Vec<Flagged> {
+    use std::os::unix::fs::MetadataExt;
+
+    let (excludes, _) = build_excludes(root, opts);
+    let names = Names::load();
+    let mut found = Vec::new();
+    for entry in WalkDir::new(root)
+        .follow_links(false)
+        .same_file_system(opts.one_file_system)
+        .into_iter()
+        .filter_entry(|e| {
+            e.depth() == 0
+                || !excludes
+                    .matched(e.path(), e.file_type().is_dir())
+                    .is_ignore()
+        })
+        .flatten()
+    {
+        if cancel.load(Ordering::Relaxed) {
+            return Vec::new();
+        }
+        let Ok(md) = entry.metadata() else {
+            continue;
+        };
+        let (mode, uid) = (md.mode(), md.uid());
+        let is_dir = md.is_dir();
+        let mut problems = Vec::new();
+        // A link's own bits are always rwxrwxrwx and mean nothing.
+        if mode & 0o002 != 0 && !md.is_symlink() && !(is_dir && mode & 0o1000 != 0) {
+            problems.push(Problem::WorldWritable);
+        }
+        if md.is_file() && mode & 0o4000 != 0 {
+            problems.push(Problem::Setuid);
+        }
+        if md.is_file() && mode & 0o2000 != 0 {
+            problems.push(Problem::Setgid);
+        }
+        if !names.has_user(uid) {
+            problems.push(Problem::NoOwner);
+        }
+        if problems.is_empty() {
+            continue;
+        }
+        let mut stats = DirStats::new(entry.path().to_path_buf());
+        stats.dir_count = is_dir.into();
+        stats.file_count = (!is_dir).into();
+        if !is_dir {
+            stats.total_bytes = md.len().into();
+            stats.disk_bytes = allocated_size(&md, entry.path()).into();
+        }
+        stats.mtime = md.modified().ok();
+        stats.complete = true;
+        found.push(Flagged {
+            entry: stats,
+            mode: mode & 0o7777,
+            uid,
+            problems,
+        });
+    }
+    found.sort_by(|a, b| a.entry.path.cmp(&b.entry.path));
+    found
+}
+
+#[cfg(not(unix))]
+pub fn find_flagged(_root: &Path, _opts: &ScanOptions, _cancel: &AtomicBool) -> Vec<Flagged> {
+    Vec::new()
+}
@@ -0,0 +1,65 @@
+//! Symbolic links whose targets are gone, and on Windows junctions and
+//! other link reparse points pointing nowhere.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use walkdir::WalkDir;
+
+use crate::{
+    scan::{build_excludes, ScanOptions},
+    tree::DirStats,
+};
+
+/// A link and where it was meant to lead.
+#[derive(Debug, Clone)]
+pub struct BrokenLink {
+    pub link: DirStats,
+    pub target: PathBuf, // as written in the link, so possibly relative to it
+}
+
+/// Searches `root` for links whose targets don't exist, sorted by path.
+/// Links that can't be followed for other reasons, like permissions, are
+/// left out.
+pub fn find_broken(root: &Path, opts: &ScanOptions, cancel: &AtomicBool) -> Vec<BrokenLink> {
+    let (excludes, _) = build_excludes(root, opts);
+    let mut found = Vec::new();
+    for entry in WalkDir::new(root)
+        .follow_links(false)
+        .same_file_system(opts.one_file_system)
+        .into_iter()
+        .filter_entry(|e| {
+            e.depth() == 0
+                || !excludes
+                    .matched(e.path(), e.file_type().is_dir())
+                    .is_ignore()
+        })
+        .flatten()
+    {
+        if cancel.load(Ordering::Relaxed) {
+            return Vec::new();
+        }
+        // Junctions count as symlinks here too.
+        if !entry.path_is_symlink() {
+            continue;
+        }
+        match fs::metadata(entry.path()) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            _ => continue,
+        }
+        let mut link = DirStats::new(entry.path().to_path_buf());
+        link.dir_count = 0;
+        link.file_count = 1;
+        link.mtime = entry.metadata().ok().and_then(|md| md.modified().ok());
+        link.complete = true;
+        found.push(BrokenLink {
+            link,
+            target: fs::read_link(entry.path()).unwrap_or_default(),
+        });
+    }
+    found.sort_by(|a, b| a.link.path.cmp(&b.link.path));
+    found
+}
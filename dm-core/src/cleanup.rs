@@ -0,0 +1,150 @@
+//! Build artifacts and package-manager caches: directories that tools
+//! recreate on demand, so deleting them only costs a rebuild, reinstall or
+//! download.
+
+use std::{ffi::OsStr, path::Path, time::SystemTime};
+
+use crate::tree::{DirTree, NodeId};
+
+/// Names of directories recognized as build artifacts.
+pub const NAMES: &[&str] = &[
+    "node_modules",
+    "target",
+    "__pycache__",
+    ".venv",
+    "build",
+    "DerivedData",
+];
+
+pub fn is_artifact(name: &OsStr) -> bool {
+    NAMES.iter().any(|n| name == *n)
+}
+
+/// A package manager's cache, with the command that clears it without
+/// leaving the manager confused.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Cache {
+    /// Whose cache it is, for the list.
+    pub label: &'static str,
+    /// How its path ends, on each platform it's kept differently on.
+    suffixes: &'static [&'static str],
+    /// Run from the directory holding the cache; `{path}` is the cache.
+    pub command: &'static str,
+}
+
+/// The caches recognized by where they are.
+pub const CACHES: &[Cache] = &[
+    Cache {
+        label: "cargo registry",
+        suffixes: &[".cargo/registry"],
+        // The index stays; crates are downloaded and unpacked again.
+        command: "rm -rf {path}/cache {path}/src",
+    },
+    Cache {
+        label: "npm cache",
+        suffixes: &[".npm", "AppData/Local/npm-cache"],
+        command: "npm cache clean --force",
+    },
+    Cache {
+        label: "yarn cache",
+        suffixes: &[
+            ".cache/yarn",
+            "Library/Caches/Yarn",
+            "AppData/Local/Yarn/Cache",
+        ],
+        command: "yarn cache clean",
+    },
+    Cache {
+        label: "pnpm store",
+        suffixes: &[
+            ".local/share/pnpm/store",
+            "Library/pnpm/store",
+            "AppData/Local/pnpm/store",
+            ".pnpm-store",
+        ],
+        command: "pnpm store prune",
+    },
+    Cache {
+        label: "pip cache",
+        suffixes: &[
+            ".cache/pip",
+            "Library/Caches/pip",
+            "AppData/Local/pip/Cache",
+        ],
+        command: "pip cache purge",
+    },
+    Cache {
+        label: "Homebrew cache",
+        suffixes: &["Library/Caches/Homebrew", ".cache/Homebrew"],
+        command: "brew cleanup --prune=all",
+    },
+    Cache {
+        label: "apt archives",
+        suffixes: &["var/cache/apt/archives"],
+        command: "sudo apt-get clean",
+    },
+    Cache {
+        label: "Gradle cache",
+        suffixes: &[".gradle/caches"],
+        // The daemons hold it open; they start again when needed.
+        command: "gradle --stop; rm -rf {path}",
+    },
+    Cache {
+        label: "Maven repository",
+        suffixes: &[".m2/repository"],
+        command: "rm -rf {path}",
+    },
+];
+
+/// A Cargo project's `target`, told apart from other `target` directories
+/// by the `Cargo.toml` next to it.
+const CARGO_TARGET: Cache = Cache {
+    label: "cargo target",
+    suffixes: &["target"],
+    command: "cargo clean",
+};
+
+/// The cache at `path`, if it's one of the known ones. Only a `target`
+/// needs looking at the disk for.
+pub fn cache(path: &Path) -> Option<&'static Cache> {
+    if path.file_name()? == "target" {
+        return path
+            .with_file_name("Cargo.toml")
+            .is_file()
+            .then_some(&CARGO_TARGET);
+    }
+    CACHES
+        .iter()
+        .find(|c| c.suffixes.iter().any(|s| path.ends_with(s)))
+}
+
+/// The artifact and cache directories in the tree below `top`. Ones inside
+/// others go with them and are not listed on their own.
+pub fn find(tree: &DirTree, top: NodeId) -> Vec<NodeId> {
+    let mut found = Vec::new();
+    let mut stack: Vec<_> = tree
+        .children(top)
+        .iter()
+        .map(|&c| (c, tree.path(c)))
+        .collect();
+    while let Some((id, path)) = stack.pop() {
+        if path.file_name().is_some_and(is_artifact) || cache(&path).is_some() {
+            found.push(id);
+        } else {
+            stack.extend(
+                tree.children(id)
+                    .iter()
+                    .map(|&c| (c, path.join(tree.name(c)))),
+            );
+        }
+    }
+    found
+}
+
+/// When the project holding artifact `id` was last used: the latest change
+/// anywhere in its parent directory. Builds count, as they change the
+/// artifacts themselves.
+pub fn project_used(tree: &DirTree, id: NodeId) -> Option<SystemTime> {
+    let parent = tree.parent(id)?;
+    tree.stats(parent).newest
+}
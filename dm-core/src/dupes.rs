@@ -0,0 +1,439 @@
+//! Duplicate files: candidates are grouped by size, then narrowed down by a
+//! BLAKE3 hash of their first bytes and finally of their whole contents.
+//!
+//! Duplicate directories, like a backup copied twice, are told apart by
+//! the names and sizes of everything in them, and optionally the contents.
+
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use rayon::prelude::*;
+use walkdir::WalkDir;
+
+use crate::{
+    scan::{allocated_size, build_excludes, device_of, hardlink_key, ScanOptions},
+    tree::DirStats,
+};
+
+/// Bytes hashed in the first pass, which weeds out most same-size files
+/// without reading them in full.
+const PREFIX: u64 = 16 * 1024;
+
+/// Files with identical contents, sorted by path.
+#[derive(Debug, Clone)]
+pub struct DupGroup {
+    pub files: Vec<DirStats>,
+}
+
+impl DupGroup {
+    pub fn size(&self) -> u128 {
+        self.files[0].total_bytes
+    }
+
+    /// What keeping only one of the copies would free.
+    pub fn reclaimable(&self) -> u128 {
+        self.size() * (self.files.len() as u128 - 1)
+    }
+
+    /// What replacing the other copies with hard links to copy `keep`
+    /// would do. Copies on another filesystem than `keep` can't be linked
+    /// to it; they come back separately.
+    pub fn link_plan(&self, keep: usize) -> (Vec<Link>, Vec<PathBuf>) {
+        let original = &self.files[keep];
+        let device = device_of(&original.path);
+        let (mut links, mut skipped) = (Vec::new(), Vec::new());
+        for (i, copy) in self.files.iter().enumerate() {
+            if i == keep {
+                continue;
+            }
+            let other = device_of(&copy.path);
+            if device.is_some() && other.is_some() && device != other {
+                skipped.push(copy.path.clone());
+            } else {
+                links.push(Link {
+                    keep: original.path.clone(),
+                    copy: copy.path.clone(),
+                    bytes: copy.disk_bytes,
+                });
+            }
+        }
+        (links, skipped)
+    }
+}
+
+/// A copy to be replaced with a hard link to the one kept; a directory's
+/// files are replaced one by one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    pub keep: PathBuf,
+    pub copy: PathBuf,
+    /// What the copy takes on disk, and linking it should free.
+    pub bytes: u128,
+}
+
+/// Searches `root` for duplicate files, most reclaimable bytes first. Empty
+/// files are left out, and so are extra hard links to a file, which share
+/// its data already. Unreadable files are skipped.
+pub fn find_duplicates(root: &Path, opts: &ScanOptions, cancel: &AtomicBool) -> Vec<DupGroup> {
+    let (excludes, _) = build_excludes(root, opts);
+    let mut by_size: HashMap<u64, Vec<DirStats>> = HashMap::new();
+    let mut inodes = HashSet::new();
+    for entry in WalkDir::new(root)
+        .follow_links(false)
+        .same_file_system(opts.one_file_system)
+        .into_iter()
+        .filter_entry(|e| {
+            e.depth() == 0
+                || !excludes
+                    .matched(e.path(), e.file_type().is_dir())
+                    .is_ignore()
+        })
+        .flatten()
+    {
+        if cancel.load(Ordering::Relaxed) {
+            return Vec::new();
+        }
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let Ok(md) = entry.metadata() else {
+            continue;
+        };
+        if md.len() == 0 || hardlink_key(&md).is_some_and(|key| !inodes.insert(key)) {
+            continue;
+        }
+        let mut stats = DirStats::new(entry.path().to_path_buf());
+        stats.dir_count = 0;
+        stats.file_count = 1;
+        stats.total_bytes = md.len() as u128;
+        stats.disk_bytes = allocated_size(&md, entry.path()) as u128;
+        stats.mtime = md.modified().ok();
+        stats.complete = true;
+        by_size.entry(md.len()).or_default().push(stats);
+    }
+
+    let candidates = by_size.into_values().filter(|g| g.len() > 1).collect();
+    let candidates = split_by_hash(candidates, Some(PREFIX), cancel);
+    let mut groups: Vec<DupGroup> = split_by_hash(candidates, None, cancel)
+        .into_iter()
+        .map(|mut files| {
+            files.sort_by(|a, b| a.path.cmp(&b.path));
+            DupGroup { files }
+        })
+        .collect();
+    groups.sort_by_key(|g| Reverse(g.reclaimable()));
+    groups
+}
+
+/// A directory being read for [`find_duplicate_dirs`], until everything in
+/// it has been.
+struct Partial {
+    stats: DirStats,
+    /// Names, kinds and sizes of what's in it, subdirectories by theirs.
+    layout: blake3::Hasher,
+    /// Which files they are, for telling copies apart from hard links.
+    files: blake3::Hasher,
+}
+
+impl Partial {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            stats: DirStats::new(path),
+            layout: blake3::Hasher::new(),
+            files: blake3::Hasher::new(),
+        }
+    }
+
+    /// Adds an entry called `name` of `kind` with `data` to the layout.
+    fn add(&mut self, kind: u8, name: &std::ffi::OsStr, data: &[u8]) {
+        let name = name.as_encoded_bytes();
+        self.layout.update(&[kind]);
+        self.layout.update(&(name.len() as u64).to_le_bytes());
+        self.layout.update(name);
+        self.layout.update(&(data.len() as u64).to_le_bytes());
+        self.layout.update(data);
+    }
+}
+
+/// A directory read in full, which may turn out to have copies.
+struct Candidate {
+    stats: DirStats,
+    layout: blake3::Hash,
+    files: blake3::Hash,
+}
+
+/// Searches `root` for directories holding the same files and
+/// subdirectories by name and size, most reclaimable bytes first. With
+/// `contents` set, the files have to have the same contents too. Only the
+/// topmost of nested copies are listed, and directories whose files are
+/// all hard links to each other's already are left out, as are empty ones.
+pub fn find_duplicate_dirs(
+    root: &Path,
+    opts: &ScanOptions,
+    contents: bool,
+    cancel: &AtomicBool,
+) -> Vec<DupGroup> {
+    let (excludes, _) = build_excludes(root, opts);
+    let mut open: HashMap<PathBuf, Partial> = HashMap::new();
+    let mut read = Vec::new();
+    for entry in WalkDir::new(root)
+        .follow_links(false)
+        .same_file_system(opts.one_file_system)
+        .sort_by_file_name()
+        .contents_first(true)
+        .into_iter()
+        .filter_entry(|e| {
+            e.depth() == 0
+                || !excludes
+                    .matched(e.path(), e.file_type().is_dir())
+                    .is_ignore()
+        })
+        .flatten()
+    {
+        if cancel.load(Ordering::Relaxed) {
+            return Vec::new();
+        }
+        if entry.depth() == 0 {
+            continue;
+        }
+        let Some(parent) = entry.path().parent() else {
+            continue;
+        };
+        let name = entry.file_name();
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            // Everything in it came first, so it's complete now.
+            let mut dir = open
+                .remove(entry.path())
+                .unwrap_or_else(|| Partial::new(entry.path().to_path_buf()));
+            dir.stats.mtime = entry.metadata().ok().and_then(|md| md.modified().ok());
+            dir.stats.complete = true;
+            let done = Candidate {
+                stats: dir.stats,
+                layout: dir.layout.finalize(),
+                files: dir.files.finalize(),
+            };
+            let up = open
+                .entry(parent.to_path_buf())
+                .or_insert_with(|| Partial::new(parent.to_path_buf()));
+            up.add(b'd', name, done.layout.as_bytes());
+            up.files.update(done.files.as_bytes());
+            up.stats.add(&done.stats);
+            if done.stats.file_count > 0 {
+                read.push(done);
+            }
+        } else if file_type.is_symlink() {
+            let target = fs::read_link(entry.path()).unwrap_or_default();
+            let up = open
+                .entry(parent.to_path_buf())
+                .or_insert_with(|| Partial::new(parent.to_path_buf()));
+            up.add(b'l', name, target.as_os_str().as_encoded_bytes());
+        } else if file_type.is_file() {
+            let Ok(md) = entry.metadata() else {
+                continue;
+            };
+            let mut stats = DirStats::new(entry.path().to_path_buf());
+            stats.dir_count = 0;
+            stats.file_count = 1;
+            stats.total_bytes = md.len() as u128;
+            stats.disk_bytes = allocated_size(&md, entry.path()) as u128;
+            let up = open
+                .entry(parent.to_path_buf())
+                .or_insert_with(|| Partial::new(parent.to_path_buf()));
+            up.add(b'f', name, &md.len().to_le_bytes());
+            match hardlink_key(&md) {
+                Some((dev, ino)) => {
+                    up.files.update(&dev.to_le_bytes());
+                    up.files.update(&ino.to_le_bytes());
+                }
+                None => {
+                    up.files.update(entry.path().as_os_str().as_encoded_bytes());
+                }
+            }
+            up.stats.add(&stats);
+        }
+    }
+
+    let mut by_layout: HashMap<blake3::Hash, Vec<Candidate>> = HashMap::new();
+    for dir in read {
+        by_layout.entry(dir.layout).or_default().push(dir);
+    }
+    let mut candidates: Vec<Vec<Candidate>> =
+        by_layout.into_values().filter(|g| g.len() > 1).collect();
+    if contents {
+        candidates = split_by_contents(candidates, cancel);
+    }
+    // Copies that are links to the same files already free nothing.
+    candidates.retain(|g| g.iter().any(|d| d.files != g[0].files));
+    if cancel.load(Ordering::Relaxed) {
+        return Vec::new();
+    }
+
+    // Copies inside copies go with them, unless they're copied elsewhere too.
+    let copied: HashSet<PathBuf> = candidates
+        .iter()
+        .flatten()
+        .map(|d| d.stats.path.clone())
+        .collect();
+    let mut groups: Vec<DupGroup> = candidates
+        .into_iter()
+        .filter_map(|group| {
+            let (mut files, inside): (Vec<DirStats>, Vec<DirStats>) = group
+                .into_iter()
+                .map(|d| d.stats)
+                .partition(|s| s.path.parent().is_none_or(|p| !copied.contains(p)));
+            if files.is_empty() {
+                return None;
+            }
+            if files.len() == 1 {
+                files.extend(inside.into_iter().min_by(|a, b| a.path.cmp(&b.path)));
+            }
+            (files.len() > 1).then(|| {
+                files.sort_by(|a, b| a.path.cmp(&b.path));
+                DupGroup { files }
+            })
+        })
+        .collect();
+    groups.sort_by_key(|g| Reverse(g.reclaimable()));
+    groups
+}
+
+/// Splits each group of directories further by the contents of the files
+/// in them, hashing in parallel. Directories left without a twin are
+/// dropped.
+fn split_by_contents(groups: Vec<Vec<Candidate>>, cancel: &AtomicBool) -> Vec<Vec<Candidate>> {
+    let dirs: Vec<(usize, Candidate)> = groups
+        .into_iter()
+        .enumerate()
+        .flat_map(|(i, group)| group.into_iter().map(move |d| (i, d)))
+        .collect();
+    let hashed: Vec<_> = dirs
+        .into_par_iter()
+        .filter_map(|(i, d)| {
+            if cancel.load(Ordering::Relaxed) {
+                return None;
+            }
+            let hash = hash_dir(&d.stats.path).ok()?;
+            Some(((i, hash), d))
+        })
+        .collect();
+
+    let mut by_key: HashMap<_, Vec<Candidate>> = HashMap::new();
+    for (key, d) in hashed {
+        by_key.entry(key).or_default().push(d);
+    }
+    by_key.into_values().filter(|g| g.len() > 1).collect()
+}
+
+/// A hash of the contents of every file below `dir`, with where they are.
+fn hash_dir(dir: &Path) -> io::Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    for entry in WalkDir::new(dir).follow_links(false).sort_by_file_name() {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let rel = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+        let rel = rel.as_os_str().as_encoded_bytes();
+        hasher.update(&(rel.len() as u64).to_le_bytes());
+        hasher.update(rel);
+        hasher.update(hash_file(entry.path(), None)?.as_bytes());
+    }
+    Ok(hasher.finalize())
+}
+
+/// Splits each group further by the hash of the first `limit` bytes of its
+/// files (all of them if `None`), hashing in parallel. Files left without a
+/// twin are dropped.
+fn split_by_hash(
+    groups: Vec<Vec<DirStats>>,
+    limit: Option<u64>,
+    cancel: &AtomicBool,
+) -> Vec<Vec<DirStats>> {
+    let files: Vec<(usize, DirStats)> = groups
+        .into_iter()
+        .enumerate()
+        .flat_map(|(i, group)| group.into_iter().map(move |f| (i, f)))
+        .collect();
+    let hashed: Vec<_> = files
+        .into_par_iter()
+        .filter_map(|(i, f)| {
+            if cancel.load(Ordering::Relaxed) {
+                return None;
+            }
+            let hash = hash_file(&f.path, limit).ok()?;
+            Some(((i, hash), f))
+        })
+        .collect();
+
+    let mut by_key: HashMap<_, Vec<DirStats>> = HashMap::new();
+    for (key, f) in hashed {
+        by_key.entry(key).or_default().push(f);
+    }
+    by_key.into_values().filter(|g| g.len() > 1).collect()
+}
+
+fn hash_file(path: &Path, limit: Option<u64>) -> io::Result<blake3::Hash> {
+    let file = File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    match limit {
+        Some(n) => hasher.update_reader(file.take(n))?,
+        None => hasher.update_reader(file)?,
+    };
+    Ok(hasher.finalize())
+}
+
+/// Links `copy` to `keep` after checking that they still match. The link is
+/// made under a temporary name and renamed over `copy`, so `copy` is never
+/// missing if something fails.
+pub fn link_copy(keep: &Path, copy: &Path) -> io::Result<()> {
+    if hash_file(keep, None)? != hash_file(copy, None)? {
+        return Err(io::Error::other("contents changed since the search"));
+    }
+    let name = copy.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = copy.with_file_name(format!(".{name}.dm-link"));
+    fs::hard_link(keep, &tmp)?;
+    fs::rename(&tmp, copy).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
+/// Links each file below directory `copy` to the one at the same place
+/// below `keep`, like [`link_copy`] does for a single file. Returns how many
+/// were linked; files already linked to theirs are left as they are.
+pub fn link_dir(keep: &Path, copy: &Path) -> io::Result<u64> {
+    let mut linked = 0;
+    for entry in WalkDir::new(copy).follow_links(false) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let rel = entry.path().strip_prefix(copy).unwrap_or(entry.path());
+        let original = keep.join(rel);
+        if same_file(&original, entry.path())? {
+            continue;
+        }
+        link_copy(&original, entry.path())
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", rel.display())))?;
+        linked += 1;
+    }
+    Ok(linked)
+}
+
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let (a, b) = (fs::metadata(a)?, fs::metadata(b)?);
+    Ok((a.dev(), a.ino()) == (b.dev(), b.ino()))
+}
+
+#[cfg(not(unix))]
+fn same_file(_a: &Path, _b: &Path) -> io::Result<bool> {
+    Ok(false)
+}
@@ -0,0 +1,135 @@
+//! Empty directories and zero-byte files, the skeletons big cleanups tend
+//! to leave behind.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use walkdir::WalkDir;
+
+use crate::{
+    scan::{build_excludes, device_of, ScanOptions},
+    tree::DirStats,
+};
+
+/// What is known about a directory while its contents are walked.
+#[derive(Default)]
+struct Seen {
+    occupied: bool, // holds something other than empty directories
+    dirs: u64,      // empty directories below it, counting nested ones
+}
+
+/// Searches `root` for directories that hold nothing but other empty
+/// directories, and for files of zero bytes. Only the outermost of nested
+/// empty directories is listed, with the others counted in its `dir_count`;
+/// directories come first, then files, each sorted by path. Excluded and
+/// unreadable entries count as contents, so their directories are never
+/// taken for empty, and so do mount points left unwalked.
+pub fn find_empty(root: &Path, opts: &ScanOptions, cancel: &AtomicBool) -> Vec<DirStats> {
+    let (excludes, _) = build_excludes(root, opts);
+    let root_dev = device_of(root);
+    let skipped = RefCell::new(HashSet::new()); // directories with excluded entries
+    let mut seen: HashMap<PathBuf, Seen> = HashMap::new();
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    let walk = WalkDir::new(root)
+        .follow_links(false)
+        .same_file_system(opts.one_file_system)
+        .contents_first(true)
+        .into_iter()
+        .filter_entry(|e| {
+            let keep = e.depth() == 0
+                || !excludes
+                    .matched(e.path(), e.file_type().is_dir())
+                    .is_ignore();
+            if !keep {
+                if let Some(parent) = e.path().parent() {
+                    skipped.borrow_mut().insert(parent.to_path_buf());
+                }
+            }
+            keep
+        });
+    for entry in walk {
+        if cancel.load(Ordering::Relaxed) {
+            return Vec::new();
+        }
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                if let Some(path) = e.path() {
+                    seen.entry(path.to_path_buf()).or_default().occupied = true;
+                    seen.entry(parent_of(path)).or_default().occupied = true;
+                }
+                continue;
+            }
+        };
+        let path = entry.path();
+        if !entry.file_type().is_dir() {
+            seen.entry(parent_of(path)).or_default().occupied = true;
+            let Ok(md) = entry.metadata() else {
+                continue;
+            };
+            if md.is_file() && md.len() == 0 {
+                let mut stats = DirStats::new(path.to_path_buf());
+                stats.dir_count = 0;
+                stats.file_count = 1;
+                stats.mtime = md.modified().ok();
+                stats.complete = true;
+                files.push(stats);
+            }
+            continue;
+        }
+
+        let own = seen.remove(path).unwrap_or_default();
+        let other_fs = opts.one_file_system && device_of(path) != root_dev;
+        if own.occupied || other_fs || skipped.borrow().contains(path) {
+            seen.entry(parent_of(path)).or_default().occupied = true;
+            continue;
+        }
+        if entry.depth() == 0 {
+            continue;
+        }
+        seen.entry(parent_of(path)).or_default().dirs += own.dirs + 1;
+        let mut stats = DirStats::new(path.to_path_buf());
+        stats.dir_count = own.dirs + 1;
+        stats.mtime = entry.metadata().ok().and_then(|md| md.modified().ok());
+        stats.complete = true;
+        dirs.push(stats);
+    }
+
+    // Directories come after their contents, so the outermost empty ones
+    // are those whose parent isn't empty too.
+    let empty: HashSet<PathBuf> = dirs.iter().map(|d| d.path.clone()).collect();
+    dirs.retain(|d| !d.path.parent().is_some_and(|p| empty.contains(p)));
+    dirs.sort_by(|a, b| a.path.cmp(&b.path));
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    dirs.extend(files);
+    dirs
+}
+
+fn parent_of(path: &Path) -> PathBuf {
+    path.parent().unwrap_or(path).to_path_buf()
+}
+
+/// Removes `dir` and the directories below it, deepest first. Only empty
+/// directories can be removed this way, so anything that appeared in them
+/// since the search stops it instead of being deleted.
+pub fn prune(dir: &Path) -> io::Result<u64> {
+    let mut removed = 0;
+    for entry in WalkDir::new(dir).follow_links(false).contents_first(true) {
+        let entry = entry.map_err(io::Error::from)?;
+        if !entry.file_type().is_dir() {
+            return Err(io::Error::other(format!(
+                "{} appeared since the search",
+                entry.path().display()
+            )));
+        }
+        fs::remove_dir(entry.path())?;
+        removed += 1;
+    }
+    Ok(removed)
+}
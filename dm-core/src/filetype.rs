@@ -0,0 +1,97 @@
+//! Coarse file categories, for the breakdown of what a directory is made of,
+//! and extensions across a whole tree.
+
+use std::cmp::Reverse;
+
+use crate::tree::{DirTree, NodeId, SizeMode, TypeMap, TypeTotals};
+
+/// How the types pane groups files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grouping {
+    Category,
+    Extension,
+}
+
+/// The category a lowercase extension belongs to.
+pub fn category(ext: &str) -> &'static str {
+    match ext {
+        "mp4" | "mkv" | "avi" | "mov" | "wmv" | "flv" | "webm" | "m4v" | "mpg" | "mpeg"
+        | "m2ts" | "vob" | "3gp" => "video",
+        "jpg" | "jpeg" | "png" | "gif" | "bmp" | "tif" | "tiff" | "webp" | "heic" | "heif"
+        | "svg" | "ico" | "psd" | "raw" | "cr2" | "nef" | "arw" | "dng" => "images",
+        "mp3" | "flac" | "wav" | "aac" | "ogg" | "oga" | "opus" | "m4a" | "wma" | "aiff" => "audio",
+        "zip" | "tar" | "gz" | "tgz" | "bz2" | "xz" | "zst" | "lz4" | "7z" | "rar" | "iso"
+        | "dmg" | "img" | "deb" | "rpm" | "apk" | "jar" | "whl" | "crate" => "archives",
+        "pdf" | "doc" | "docx" | "odt" | "rtf" | "xls" | "xlsx" | "ods" | "csv" | "ppt"
+        | "pptx" | "odp" | "txt" | "md" | "epub" => "documents",
+        "rs" | "c" | "h" | "cc" | "cpp" | "hpp" | "py" | "js" | "mjs" | "ts" | "tsx" | "jsx"
+        | "go" | "java" | "kt" | "rb" | "php" | "sh" | "swift" | "cs" | "html" | "css" | "json"
+        | "toml" | "yaml" | "yml" | "xml" | "sql" | "lock" => "code",
+        "o" | "a" | "so" | "dll" | "dylib" | "exe" | "rlib" | "rmeta" | "pyc" | "class"
+        | "wasm" => "binaries",
+        "log" | "journal" | "trace" => "logs",
+        "db" | "sqlite" | "sqlite3" | "mdb" | "ldb" | "parquet" => "databases",
+        _ => "other",
+    }
+}
+
+/// Rows for the types pane, biggest first by `mode`.
+pub fn breakdown(types: TypeMap, by: Grouping, mode: SizeMode) -> Vec<(String, TypeTotals)> {
+    let mut rows: Vec<(String, TypeTotals)> = match by {
+        Grouping::Extension => types
+            .into_iter()
+            .map(|(ext, totals)| (label(&ext), totals))
+            .collect(),
+        Grouping::Category => {
+            let mut by_category: Vec<(String, TypeTotals)> = Vec::new();
+            for (ext, totals) in types {
+                let name = category(&ext);
+                match by_category.iter_mut().find(|(c, _)| c == name) {
+                    Some((_, sum)) => sum.add(&totals),
+                    None => by_category.push((name.to_string(), totals)),
+                }
+            }
+            by_category
+        }
+    };
+    rows.sort_by_key(|(label, t)| (Reverse(t.bytes(mode)), label.clone()));
+    rows
+}
+
+/// How extension `ext` is shown, like `.log`.
+pub fn label(ext: &str) -> String {
+    if ext.is_empty() {
+        "(none)".to_string()
+    } else {
+        format!(".{ext}")
+    }
+}
+
+/// The extensions in `types`, biggest first by `mode`.
+pub fn by_extension(types: TypeMap, mode: SizeMode) -> Vec<(Box<str>, TypeTotals)> {
+    let mut rows: Vec<_> = types.into_iter().collect();
+    rows.sort_by_key(|(ext, t)| (Reverse(t.bytes(mode)), ext.clone()));
+    rows
+}
+
+/// The `max` directories below `top` (itself included) whose own files of
+/// extension `ext` add up to the most, biggest first by `mode`.
+pub fn top_dirs(
+    tree: &DirTree,
+    top: NodeId,
+    ext: &str,
+    mode: SizeMode,
+    max: usize,
+) -> Vec<(NodeId, TypeTotals)> {
+    let mut found = Vec::new();
+    let mut stack = vec![top];
+    while let Some(id) = stack.pop() {
+        if let Some(totals) = tree.own_types(id).get(ext) {
+            found.push((id, *totals));
+        }
+        stack.extend_from_slice(tree.children(id));
+    }
+    found.sort_by_key(|(_, t)| Reverse(t.bytes(mode)));
+    found.truncate(max);
+    found
+}
@@ -0,0 +1,54 @@
+//! The engine behind `dm`: walks directories into an in-memory
+//! [`DirTree`] and answers questions about it, with no terminal attached.
+//!
+//! A [`Scanner`] reads one directory hierarchy, reading directories on a
+//! thread pool and reporting [`ScanEvent`]s to a callback while it runs:
+//! totals of the scanned directory's children as they firm up, entries it
+//! couldn't read, and problems with its setup. The finished tree is
+//! navigated by [`NodeId`], and every node carries the totals of its whole
+//! subtree.
+//!
+//! ```no_run
+//! use dm_core::{ScanEvent, ScanOptions, Scanner, SizeMode};
+//!
+//! let scanner = Scanner::new("/var", ScanOptions::default());
+//! let tree = scanner
+//!     .run(|event| match event {
+//!         ScanEvent::Progress(stats) => eprintln!("{}: {}", stats.path.display(), stats.total_bytes),
+//!         ScanEvent::Unreadable(e) | ScanEvent::Warning(e) => eprintln!("{e}"),
+//!         ScanEvent::Checkpoint(_) => {}
+//!     })
+//!     .expect("nothing cancelled it");
+//! for &child in tree.children(tree.root()) {
+//!     let stats = tree.stats(child);
+//!     println!("{:>14} {}", stats.bytes(SizeMode::Disk), tree.path(child).display());
+//! }
+//! ```
+//!
+//! The analyses take a scanned root or tree and work on their own:
+//! [`dupes`] finds identical files, [`empty`] empty directories and files,
+//! [`broken`] links leading nowhere, [`cleanup`] build artifacts and caches,
+//! [`old`] big files left unused, [`audit`] loose permissions and owners
+//! gone, [`filetype`] and [`owners`] break totals down by type and owner.
+
+pub mod audit;
+pub mod broken;
+pub mod cleanup;
+pub mod dupes;
+pub mod empty;
+pub mod filetype;
+pub mod meta;
+#[cfg(windows)]
+mod mft;
+pub mod mounts;
+pub mod old;
+pub mod owners;
+pub mod power;
+pub mod s3;
+pub mod scan;
+pub mod sftp;
+pub mod tree;
+pub mod vfs;
+
+pub use scan::{Profile, ScanEvent, ScanOptions, Scanner};
+pub use tree::{DirStats, DirTree, NodeId, SizeMode};
@@ -0,0 +1,527 @@
+//! Scans of NTFS volumes from the Master File Table, the way WizTree and
+//! Everything do it: a few large sequential reads of the table instead of a
+//! listing per directory and a lookup per file. Opening the raw volume
+//! needs an elevated process; whatever stops this, the caller walks instead.
+
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    fs, io,
+    os::windows::{ffi::OsStringExt, fs::FileExt},
+    path::{Component, Path, PathBuf, Prefix},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use ignore::gitignore::Gitignore;
+
+use crate::{
+    scan::ScanOptions,
+    tree::{DirStats, DirTree},
+};
+
+/// Record number of a volume's root directory.
+const ROOT_RECORD: usize = 5;
+
+/// Records below this are the filesystem's own files ($MFT, $LogFile,
+/// $Extend and so on), which directory listings don't show.
+const FIRST_USER_RECORD: usize = 16;
+
+/// Bytes of the table read at a time.
+const CHUNK: usize = 4 << 20;
+
+// Attribute types.
+const STANDARD_INFORMATION: u32 = 0x10;
+const ATTRIBUTE_LIST: u32 = 0x20;
+const FILE_NAME: u32 = 0x30;
+const DATA: u32 = 0x80;
+const REPARSE_POINT: u32 = 0xC0;
+const END: u32 = 0xFFFF_FFFF;
+
+const IO_REPARSE_TAG_MOUNT_POINT: u32 = 0xA000_0003;
+const IO_REPARSE_TAG_SYMLINK: u32 = 0xA000_000C;
+/// Cloud Files placeholders, as OneDrive makes; bits 12-15 vary by flavour.
+const IO_REPARSE_TAG_CLOUD: u32 = 0x9000_001A;
+const IO_REPARSE_TAG_CLOUD_MASK: u32 = 0xFFFF_0FFF;
+
+/// Low 48 bits of a file reference; the rest is a sequence number.
+const RECORD_MASK: u64 = 0xFFFF_FFFF_FFFF;
+
+/// What the table says about one file or directory.
+#[derive(Default)]
+struct Record {
+    in_use: bool,
+    dir: bool,
+    link: bool,                    // a symlink or junction, including volume mount points
+    placeholder: bool,             // a cloud file kept online, taking only `allocated` here
+    names: Vec<(usize, OsString)>, // (parent record, name), one per hard link
+    len: u64,
+    allocated: u64,
+    mtime: Option<SystemTime>,
+}
+
+/// Where the table is and how it's laid out, from the boot sector.
+struct Geometry {
+    cluster: u64,
+    record: usize,
+    mft_offset: u64,
+}
+
+/// Scans `root`, which must be on a local NTFS volume, into a finished
+/// tree. Counts the way a walk with `opts` would, except that links are
+/// never followed; the caller walks when asked to follow them.
+pub fn scan(
+    root: &Path,
+    opts: &ScanOptions,
+    excludes: &Gitignore,
+    skip: Option<&Path>,
+    cancel: &AtomicBool,
+) -> io::Result<DirTree> {
+    let (letter, below) = locate(root)?;
+    let volume = fs::File::open(format!(r"\\.\{}:", letter as char)).map_err(|e| {
+        if e.kind() == io::ErrorKind::PermissionDenied {
+            io::Error::new(e.kind(), "reading the MFT needs an elevated prompt")
+        } else {
+            e
+        }
+    })?;
+    // A whole sector on any disk, 512 or 4096 bytes.
+    let mut boot = vec![0; 4096];
+    read_at(&volume, &mut boot, 0)?;
+    let geometry = geometry(&boot)?;
+    let records = read_table(&volume, &geometry, cancel)?;
+
+    let mut children: Vec<Vec<(usize, usize)>> = vec![Vec::new(); records.len()];
+    for (number, record) in records.iter().enumerate().skip(FIRST_USER_RECORD) {
+        if !record.in_use {
+            continue;
+        }
+        for (i, &(parent, _)) in record.names.iter().enumerate() {
+            if let Some(siblings) = children.get_mut(parent) {
+                siblings.push((number, i));
+            }
+        }
+    }
+
+    // Names are case-insensitive on NTFS.
+    let mut start = ROOT_RECORD;
+    for name in below {
+        let name = name.to_string_lossy().to_lowercase();
+        let found = children[start].iter().find(|&&(number, i)| {
+            records[number].dir
+                && records[number].names[i].1.to_string_lossy().to_lowercase() == name
+        });
+        match found {
+            Some(&(number, _)) if records[number].link => {
+                return Err(unsupported("the directory is behind a link"));
+            }
+            Some(&(number, _)) => start = number,
+            None => {
+                let e = io::Error::new(io::ErrorKind::NotFound, "not found in the MFT");
+                return Err(e);
+            }
+        }
+    }
+
+    let mut tree = DirTree::new(root.to_path_buf());
+    let top = tree.root();
+    tree.stats_mut(top).mtime = records[start].mtime;
+    tree.stats_mut(top).newest = records[start].mtime;
+    let mut seen = HashSet::new();
+    let mut stack = vec![(top, start)];
+    while let Some((id, number)) = stack.pop() {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(io::Error::from(io::ErrorKind::Interrupted));
+        }
+        let dir = tree.path(id);
+        let mut own = DirStats::new(PathBuf::new());
+        own.dir_count = 0;
+        for &(child, name) in &children[number] {
+            let record = &records[child];
+            let path = dir.join(&record.names[name].1);
+            if skip == Some(path.as_path()) || excludes.matched(&path, record.dir).is_ignore() {
+                continue;
+            }
+            if record.dir {
+                let mut stats = DirStats::new(path);
+                if record.link {
+                    stats.dir_count = 0;
+                    stats.complete = true;
+                    stats.link = true;
+                    stats.target = fs::read_link(&stats.path).ok();
+                    tree.push(id, stats);
+                } else {
+                    stats.mtime = record.mtime;
+                    stats.newest = record.mtime;
+                    stack.push((tree.push(id, stats), child));
+                }
+                continue;
+            }
+            // Links to files aren't counted.
+            if record.link {
+                continue;
+            }
+            let stats = file_stats(record, child, opts, &mut seen);
+            own.add(&stats);
+            tree.count_type(id, &path, &stats);
+            if tree.wants_file(stats.total_bytes) {
+                let mut file = stats;
+                file.path = path;
+                file.mtime = record.mtime;
+                file.complete = true;
+                tree.note_file(id, file);
+            }
+        }
+        tree.stats_mut(id).add(&own);
+    }
+    tree.finish();
+    Ok(tree)
+}
+
+/// What file record `number` contributes to its directory's totals.
+fn file_stats(
+    record: &Record,
+    number: usize,
+    opts: &ScanOptions,
+    seen: &mut HashSet<usize>,
+) -> DirStats {
+    let mut stats = DirStats::new(PathBuf::new());
+    stats.dir_count = 0;
+    stats.file_count = 1;
+    stats.newest = record.mtime;
+    stats.placeholders = record.placeholder.into();
+    if record.names.len() > 1 {
+        stats.shared_bytes = record.len as u128;
+        // Only the first link we come across carries the size.
+        if opts.dedup_hardlinks && !seen.insert(number) {
+            return stats;
+        }
+    }
+    stats.total_bytes = record.len as u128;
+    stats.disk_bytes = record.allocated as u128;
+    stats
+}
+
+/// The drive letter `root` is on and the names leading to it from the
+/// drive's root.
+fn locate(root: &Path) -> io::Result<(u8, Vec<OsString>)> {
+    let canonical = fs::canonicalize(root)?;
+    let mut components = canonical.components();
+    let letter = match components.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(letter) | Prefix::VerbatimDisk(letter) => letter,
+            _ => return Err(unsupported("not on a drive letter")),
+        },
+        _ => return Err(unsupported("not on a drive letter")),
+    };
+    let below = components
+        .filter_map(|c| match c {
+            Component::Normal(name) => Some(name.to_os_string()),
+            _ => None,
+        })
+        .collect();
+    Ok((letter, below))
+}
+
+fn geometry(boot: &[u8]) -> io::Result<Geometry> {
+    if boot.get(3..11) != Some(b"NTFS    ".as_slice()) {
+        return Err(unsupported("not an NTFS volume"));
+    }
+    let bad = || io::Error::new(io::ErrorKind::InvalidData, "unreadable NTFS boot sector");
+    let sector = u64::from(u16_at(boot, 0x0B).ok_or_else(bad)?);
+    // Above 0x80 the count is a negative power of two, for clusters
+    // too large for a byte.
+    let cluster = match boot[0x0D] {
+        n @ 0x81.. => sector.checked_shl(256 - u32::from(n)).unwrap_or(0),
+        n => sector * u64::from(n),
+    };
+    // Likewise, negative sizes are powers of two in bytes rather than
+    // counts of clusters.
+    let record = match boot[0x40] as i8 {
+        n @ ..=-1 => 1usize << n.unsigned_abs().min(31),
+        n => n as usize * cluster as usize,
+    };
+    let mft_offset = u64_at(boot, 0x30)
+        .and_then(|lcn| lcn.checked_mul(cluster))
+        .ok_or_else(bad)?;
+    if cluster == 0 || !record.is_power_of_two() || !(512..=65536).contains(&record) {
+        return Err(bad());
+    }
+    Ok(Geometry {
+        cluster,
+        record,
+        mft_offset,
+    })
+}
+
+/// Reads every record of the table, following where the $MFT file's own
+/// record says the table lies on the volume.
+fn read_table(
+    volume: &fs::File,
+    geometry: &Geometry,
+    cancel: &AtomicBool,
+) -> io::Result<Vec<Record>> {
+    let bad = || io::Error::new(io::ErrorKind::InvalidData, "unreadable MFT");
+    let mut first = vec![0; geometry.record];
+    read_at(volume, &mut first, geometry.mft_offset)?;
+    if !fixup(&mut first) {
+        return Err(bad());
+    }
+    let mut table = None;
+    for (kind, attr) in attributes(&first) {
+        if kind == ATTRIBUTE_LIST {
+            // Then the table's runs continue in other records.
+            return Err(unsupported("the MFT is too fragmented"));
+        }
+        if kind == DATA && !is_named(attr) && attr[8] != 0 {
+            table = runs(attr, geometry.cluster).zip(u64_at(attr, 0x30));
+        }
+    }
+    let (runs, size) = table.ok_or_else(bad)?;
+
+    let count = (size / geometry.record as u64) as usize;
+    let mut records = Vec::new();
+    records.resize_with(count, Record::default);
+    let mut buf = vec![0; CHUNK];
+    let mut number = 0;
+    for (offset, len) in runs {
+        if !len.is_multiple_of(geometry.record as u64) {
+            return Err(unsupported("MFT records split across fragments"));
+        }
+        let mut done = 0;
+        while done < len && number < count {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(io::Error::from(io::ErrorKind::Interrupted));
+            }
+            let chunk = &mut buf[..(len - done).min(CHUNK as u64) as usize];
+            read_at(volume, chunk, offset + done)?;
+            for rec in chunk.chunks_exact_mut(geometry.record) {
+                if number == count {
+                    break;
+                }
+                let in_use = u16_at(rec, 0x16).is_some_and(|flags| flags & 1 != 0);
+                if in_use && fixup(rec) {
+                    // Records that carry on a file's attributes name the
+                    // file's own record.
+                    let base = (u64_at(rec, 0x20).unwrap_or(0) & RECORD_MASK) as usize;
+                    if base == 0 {
+                        parse(rec, &mut records[number], true);
+                    } else if let Some(record) = records.get_mut(base) {
+                        parse(rec, record, false);
+                    }
+                }
+                number += 1;
+            }
+            done += chunk.len() as u64;
+        }
+    }
+    Ok(records)
+}
+
+/// Adds what `rec` says to `record`. Only a file's base record says
+/// whether it's in use and a directory.
+fn parse(rec: &[u8], record: &mut Record, base: bool) {
+    if base {
+        let flags = u16_at(rec, 0x16).unwrap_or(0);
+        record.in_use = flags & 1 != 0;
+        record.dir = flags & 2 != 0;
+    }
+    for (kind, attr) in attributes(rec) {
+        match kind {
+            STANDARD_INFORMATION => {
+                if let Some(ticks) = resident(attr).and_then(|body| u64_at(body, 0x08)) {
+                    record.mtime = filetime(ticks);
+                }
+            }
+            FILE_NAME => {
+                let Some(body) = resident(attr) else {
+                    continue;
+                };
+                let (Some(parent), Some(&len), Some(&namespace)) =
+                    (u64_at(body, 0), body.get(0x40), body.get(0x41))
+                else {
+                    continue;
+                };
+                // DOS 8.3 aliases of a long name aren't links of their own.
+                if namespace == 2 {
+                    continue;
+                }
+                let Some(name) = body.get(0x42..0x42 + 2 * len as usize) else {
+                    continue;
+                };
+                let wide: Vec<u16> = name
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                    .collect();
+                let parent = (parent & RECORD_MASK) as usize;
+                record.names.push((parent, OsString::from_wide(&wide)));
+            }
+            // Only the unnamed stream is the file's content; the others
+            // are alternate data streams.
+            DATA if !is_named(attr) => {
+                if attr[8] == 0 {
+                    // Small enough to live in the record itself, so it takes
+                    // no clusters of its own.
+                    record.len = resident(attr).map_or(0, |body| body.len() as u64);
+                    record.allocated = 0;
+                } else if u64_at(attr, 0x10) == Some(0) {
+                    // Sizes are only in the fragment that starts the stream.
+                    const COMPRESSED_OR_SPARSE: u16 = 0x0001 | 0x8000;
+                    let flags = u16_at(attr, 0x0C).unwrap_or(0);
+                    let allocated_at = if flags & COMPRESSED_OR_SPARSE != 0 {
+                        0x40
+                    } else {
+                        0x28
+                    };
+                    record.len = u64_at(attr, 0x30).unwrap_or(0);
+                    record.allocated = u64_at(attr, allocated_at).unwrap_or(0);
+                }
+            }
+            REPARSE_POINT => {
+                let tag = resident(attr).and_then(|body| u32_at(body, 0));
+                record.link = matches!(
+                    tag,
+                    Some(IO_REPARSE_TAG_MOUNT_POINT | IO_REPARSE_TAG_SYMLINK)
+                );
+                record.placeholder =
+                    tag.is_some_and(|t| t & IO_REPARSE_TAG_CLOUD_MASK == IO_REPARSE_TAG_CLOUD);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Undoes the update sequence NTFS writes over the last two bytes of each
+/// sector of a record, checking them on the way. False for a record that
+/// isn't one or was torn mid-write.
+fn fixup(rec: &mut [u8]) -> bool {
+    if !rec.starts_with(b"FILE") {
+        return false;
+    }
+    let (Some(at), Some(count)) = (u16_at(rec, 4), u16_at(rec, 6)) else {
+        return false;
+    };
+    let (at, count) = (usize::from(at), usize::from(count));
+    if count < 2 || !rec.len().is_multiple_of(count - 1) {
+        return false;
+    }
+    let stride = rec.len() / (count - 1);
+    let Some(usn) = u16_at(rec, at) else {
+        return false;
+    };
+    for i in 1..count {
+        let end = i * stride;
+        let Some(original) = u16_at(rec, at + 2 * i) else {
+            return false;
+        };
+        if end < 2 || u16_at(rec, end - 2) != Some(usn) {
+            return false;
+        }
+        rec[end - 2..end].copy_from_slice(&original.to_le_bytes());
+    }
+    true
+}
+
+/// The attributes of a record as (type, attribute with its header).
+fn attributes(rec: &[u8]) -> impl Iterator<Item = (u32, &[u8])> {
+    let mut at = u16_at(rec, 0x14).map_or(rec.len(), usize::from);
+    std::iter::from_fn(move || {
+        let kind = u32_at(rec, at)?;
+        let len = u32_at(rec, at + 4)? as usize;
+        if kind == END || len < 0x18 {
+            return None;
+        }
+        let attr = rec.get(at..at + len)?;
+        at += len;
+        Some((kind, attr))
+    })
+}
+
+/// The content of an attribute stored in the record itself.
+fn resident(attr: &[u8]) -> Option<&[u8]> {
+    if attr[8] != 0 {
+        return None;
+    }
+    let len = u32_at(attr, 0x10)? as usize;
+    let at = usize::from(u16_at(attr, 0x14)?);
+    attr.get(at..at + len)
+}
+
+fn is_named(attr: &[u8]) -> bool {
+    attr[9] != 0
+}
+
+/// Where on the volume a stream stored elsewhere lies, as (offset, length)
+/// in bytes. `None` for holes, which the table never has.
+fn runs(attr: &[u8], cluster: u64) -> Option<Vec<(u64, u64)>> {
+    let mut at = usize::from(u16_at(attr, 0x20)?);
+    let mut lcn: i64 = 0;
+    let mut runs = Vec::new();
+    loop {
+        let header = *attr.get(at)?;
+        if header == 0 {
+            return Some(runs);
+        }
+        let (len_size, offset_size) = (usize::from(header & 0xF), usize::from(header >> 4));
+        if len_size == 0 || len_size > 8 || offset_size == 0 || offset_size > 8 {
+            return None;
+        }
+        let len = le_uint(attr.get(at + 1..at + 1 + len_size)?);
+        let offset = le_int(attr.get(at + 1 + len_size..at + 1 + len_size + offset_size)?);
+        at += 1 + len_size + offset_size;
+        // Each run starts relative to the one before.
+        lcn = lcn.checked_add(offset)?;
+        runs.push((
+            u64::try_from(lcn).ok()?.checked_mul(cluster)?,
+            len.checked_mul(cluster)?,
+        ));
+    }
+}
+
+fn le_uint(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |n, &b| n << 8 | u64::from(b))
+}
+
+fn le_int(bytes: &[u8]) -> i64 {
+    let shift = 64 - 8 * bytes.len() as u32;
+    ((le_uint(bytes) << shift) as i64) >> shift
+}
+
+/// A FILETIME, in 100 ns ticks since 1601.
+fn filetime(ticks: u64) -> Option<SystemTime> {
+    const TICKS_TO_UNIX_EPOCH: u64 = 116_444_736_000_000_000;
+    let ticks = ticks.checked_sub(TICKS_TO_UNIX_EPOCH)?;
+    let since = Duration::new(ticks / 10_000_000, (ticks % 10_000_000) as u32 * 100);
+    UNIX_EPOCH.checked_add(since)
+}
+
+/// Fills `buf` from `offset` on the volume. Both have to be multiples of
+/// the sector size.
+fn read_at(volume: &fs::File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    let mut done = 0;
+    while done < buf.len() {
+        match volume.seek_read(&mut buf[done..], offset + done as u64) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            Ok(n) => done += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn unsupported(why: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, why)
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(bytes.get(at..at + 8)?.try_into().ok()?))
+}
@@ -0,0 +1,470 @@
+//! Mounted filesystems and how full they are, for picking where to scan.
+
+use std::path::{Path, PathBuf};
+
+use sysinfo::Disks;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mount {
+    pub path: PathBuf,
+    pub device: String,
+    pub fs_type: String,
+    pub total: u64,
+    pub free: u64, // available to unprivileged users
+    /// Only looked up for the mount `containing` returns, and None where
+    /// the filesystem hands out inodes as it goes, like btrfs and ZFS.
+    pub inodes: Option<Inodes>,
+    /// The quota of whoever runs this on it, or else of their group, if
+    /// there is one; also only looked up by `containing`.
+    pub quota: Option<(Owner, Quota)>,
+}
+
+impl Mount {
+    pub fn used(&self) -> u64 {
+        self.total.saturating_sub(self.free)
+    }
+
+    /// Whether it's a network filesystem or a FUSE one, which usually ends
+    /// up on the network too, where every directory read is a round trip
+    /// and a full-speed scan slows the server down for everyone. On
+    /// Windows, a mapped drive or share, which has a UNC path as device.
+    pub fn is_network(&self) -> bool {
+        let fs_type = self.fs_type.to_ascii_lowercase();
+        NETWORK_TYPES.contains(&fs_type.as_str())
+            // fuseblk is a local disk, like NTFS through ntfs-3g.
+            || fs_type.starts_with("fuse") && fs_type != "fuseblk"
+            || fs_type.ends_with("fuse")
+            || self.device.starts_with(r"\\")
+    }
+}
+
+/// Filesystem types that live on another machine.
+const NETWORK_TYPES: &[&str] = &[
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb3",
+    "smbfs",
+    "afpfs",
+    "webdav",
+    "davfs",
+    "ceph",
+    "glusterfs",
+    "lustre",
+];
+
+/// A filesystem's inodes. When they run out no file can be made, however
+/// many bytes are free, which is what millions of tiny files do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Inodes {
+    pub total: u64,
+    pub free: u64, // available to unprivileged users
+}
+
+impl Inodes {
+    pub fn used(&self) -> u64 {
+        self.total.saturating_sub(self.free)
+    }
+
+    /// Share of them in use, in percent.
+    pub fn percent(&self) -> f64 {
+        self.used() as f64 * 100.0 / self.total.max(1) as f64
+    }
+}
+
+/// Whose quota it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Owner {
+    User(u32),
+    Group(u32),
+}
+
+/// A disk quota, in bytes. A limit of 0 means there's none of that kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub used: u64,
+    pub soft: u64,
+    pub hard: u64,
+}
+
+impl Quota {
+    /// The limit reached first.
+    pub fn limit(&self) -> u64 {
+        match (self.soft, self.hard) {
+            (0, hard) => hard,
+            (soft, 0) => soft,
+            (soft, hard) => soft.min(hard),
+        }
+    }
+
+    /// Share of the limit used, in percent.
+    pub fn percent(&self) -> f64 {
+        self.used as f64 * 100.0 / self.limit().max(1) as f64
+    }
+
+    /// Whether it's used up to within a tenth of the limit.
+    pub fn nearly_full(&self) -> bool {
+        self.percent() >= 90.0
+    }
+}
+
+/// The mounted filesystems, by mount point, or the drives on Windows,
+/// mapped network drives included. Pseudo filesystems without a size are
+/// left out, and so are repeated mounts of the same point.
+pub fn list() -> Vec<Mount> {
+    let disks = Disks::new_with_refreshed_list();
+    let mut mounts: Vec<Mount> = disks
+        .iter()
+        .filter(|d| d.total_space() > 0)
+        .map(|d| Mount {
+            path: d.mount_point().to_path_buf(),
+            device: d.name().to_string_lossy().into_owned(),
+            fs_type: d.file_system().to_string_lossy().into_owned(),
+            total: d.total_space(),
+            free: d.available_space(),
+            inodes: None,
+            quota: None,
+        })
+        .collect();
+    mounts.extend(network_drives());
+    mounts.sort_by(|a, b| a.path.cmp(&b.path));
+    mounts.dedup_by(|a, b| a.path == b.path);
+    mounts
+}
+
+/// Whether a network or FUSE filesystem is mounted somewhere below `path`,
+/// which a scan of it goes into unless it stays on one filesystem.
+pub fn network_below(path: &Path) -> bool {
+    let path = plain(path.to_path_buf());
+    list()
+        .iter()
+        .any(|m| m.is_network() && m.path != path && m.path.starts_with(&path))
+}
+
+/// The filesystem `path` is on: the mount with the longest mount point
+/// that contains it, or the share a UNC path is on.
+pub fn containing(path: &Path) -> Option<Mount> {
+    let path = plain(path.to_path_buf());
+    let mut mount = list()
+        .into_iter()
+        .filter(|m| path.starts_with(&m.path))
+        .max_by_key(|m| m.path.components().count())
+        .or_else(|| volume(&share_root(&path)?))?;
+    mount.inodes = inodes(&mount.path);
+    mount.quota = own_quota(&mount);
+    Some(mount)
+}
+
+/// `path` without the `\\?\` prefix Windows puts on canonical paths,
+/// which no mount point or UNC path typed in has, unless it's too long to
+/// do without. Other systems have no such thing.
+#[cfg(windows)]
+pub fn plain(path: PathBuf) -> PathBuf {
+    use std::path::{Component, Prefix};
+    let Some(Component::Prefix(prefix)) = path.components().next() else {
+        return path;
+    };
+    let head = match prefix.kind() {
+        Prefix::VerbatimDisk(letter) => format!("{}:", letter as char),
+        Prefix::VerbatimUNC(server, share) => {
+            format!(
+                r"\\{}\{}",
+                server.to_string_lossy(),
+                share.to_string_lossy()
+            )
+        }
+        _ => return path,
+    };
+    let mut plain = PathBuf::from(head);
+    plain.extend(path.components().skip(1));
+    // Longer ones only work verbatim.
+    if plain.as_os_str().len() < 260 {
+        plain
+    } else {
+        path
+    }
+}
+
+#[cfg(not(windows))]
+pub fn plain(path: PathBuf) -> PathBuf {
+    path
+}
+
+/// The share a UNC path like `\\server\share\dir` is on, as
+/// `\\server\share\`. It has no parent, the way a drive's root has none.
+#[cfg(windows)]
+pub fn share_root(path: &Path) -> Option<PathBuf> {
+    use std::path::{Component, Prefix};
+    let Some(Component::Prefix(prefix)) = path.components().next() else {
+        return None;
+    };
+    match prefix.kind() {
+        Prefix::UNC(server, share) | Prefix::VerbatimUNC(server, share) => {
+            Some(PathBuf::from(format!(
+                r"\\{}\{}\",
+                server.to_string_lossy(),
+                share.to_string_lossy()
+            )))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(not(windows))]
+pub fn share_root(_path: &Path) -> Option<PathBuf> {
+    None
+}
+
+/// Drive letters mapped to network shares, which sysinfo leaves out, with
+/// the share each one is mapped to as its device.
+#[cfg(windows)]
+fn network_drives() -> Vec<Mount> {
+    use windows_sys::Win32::{
+        NetworkManagement::WNet::WNetGetConnectionW,
+        Storage::FileSystem::{GetDriveTypeW, GetLogicalDrives},
+    };
+    const DRIVE_REMOTE: u32 = 4;
+    // Never fails; it only reads which letters are in use.
+    let letters = unsafe { GetLogicalDrives() };
+    (0..26u8)
+        .filter(|i| letters & (1 << i) != 0)
+        .filter_map(|i| {
+            let root = format!("{}:\\", (b'A' + i) as char);
+            // Takes a NUL-terminated path.
+            if unsafe { GetDriveTypeW(wide(Path::new(&root)).as_ptr()) } != DRIVE_REMOTE {
+                return None;
+            }
+            // Disconnected ones fail here and are left out.
+            let mut mount = volume(Path::new(&root))?;
+            let mut target = [0u16; 512];
+            let mut len = target.len() as u32;
+            // Writes at most `len` characters into `target`.
+            let found = unsafe {
+                WNetGetConnectionW(
+                    wide(Path::new(&root[..2])).as_ptr(),
+                    target.as_mut_ptr(),
+                    &mut len,
+                )
+            };
+            if found == 0 {
+                mount.device = from_wide(&target);
+            }
+            Some(mount)
+        })
+        .collect()
+}
+
+#[cfg(not(windows))]
+fn network_drives() -> Vec<Mount> {
+    Vec::new()
+}
+
+/// The size, free space and filesystem of the volume or share at `root`,
+/// itself as the device.
+#[cfg(windows)]
+fn volume(root: &Path) -> Option<Mount> {
+    use std::ptr::null_mut;
+    use windows_sys::Win32::Storage::FileSystem::{GetDiskFreeSpaceExW, GetVolumeInformationW};
+    let path = wide(root);
+    let (mut free, mut total) = (0u64, 0u64);
+    let mut fs_type = [0u16; 32];
+    // Both write only into what they're given, within the sizes given.
+    unsafe {
+        if GetDiskFreeSpaceExW(path.as_ptr(), &mut free, &mut total, null_mut()) == 0 {
+            return None;
+        }
+        GetVolumeInformationW(
+            path.as_ptr(),
+            null_mut(),
+            0,
+            null_mut(),
+            null_mut(),
+            null_mut(),
+            fs_type.as_mut_ptr(),
+            fs_type.len() as u32,
+        );
+    }
+    Some(Mount {
+        path: root.to_path_buf(),
+        device: root.display().to_string(),
+        fs_type: from_wide(&fs_type),
+        total,
+        free,
+        inodes: None,
+        quota: None,
+    })
+}
+
+#[cfg(not(windows))]
+fn volume(_root: &Path) -> Option<Mount> {
+    None
+}
+
+#[cfg(windows)]
+fn wide(path: &Path) -> Vec<u16> {
+    use std::os::windows::ffi::OsStrExt;
+    path.as_os_str().encode_wide().chain(Some(0)).collect()
+}
+
+/// The string in a NUL-terminated buffer.
+#[cfg(windows)]
+fn from_wide(buf: &[u16]) -> String {
+    let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    String::from_utf16_lossy(&buf[..len])
+}
+
+#[cfg(unix)]
+fn own_quota(mount: &Mount) -> Option<(Owner, Quota)> {
+    // Never fail; they only read the process's own ids.
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+    [Owner::User(uid), Owner::Group(gid)]
+        .into_iter()
+        .find_map(|owner| Some((owner, quota(mount, owner)?)))
+}
+
+#[cfg(not(unix))]
+fn own_quota(_mount: &Mount) -> Option<(Owner, Quota)> {
+    None
+}
+
+/// The quota of `owner` on `mount`, None if quotas are off or it has no
+/// limits. Others' quotas take root to read.
+#[cfg(target_os = "linux")]
+pub fn quota(mount: &Mount, owner: Owner) -> Option<Quota> {
+    use std::ffi::CString;
+    const USRQUOTA: libc::c_int = 0;
+    const GRPQUOTA: libc::c_int = 1;
+    let (kind, id) = match owner {
+        Owner::User(uid) => (USRQUOTA, uid),
+        Owner::Group(gid) => (GRPQUOTA, gid),
+    };
+    let device = CString::new(mount.device.as_str()).ok()?;
+    let mut dq = std::mem::MaybeUninit::<libc::dqblk>::zeroed();
+    let cmd = libc::QCMD(libc::Q_GETQUOTA, kind);
+    // quotactl fills in `dq` when it returns 0.
+    let dq = unsafe {
+        if libc::quotactl(
+            cmd,
+            device.as_ptr(),
+            id as libc::c_int,
+            dq.as_mut_ptr().cast(),
+        ) != 0
+        {
+            return None;
+        }
+        dq.assume_init()
+    };
+    // Limits come in 1 KiB blocks.
+    let quota = Quota {
+        used: dq.dqb_curspace,
+        soft: dq.dqb_bsoftlimit.saturating_mul(1024),
+        hard: dq.dqb_bhardlimit.saturating_mul(1024),
+    };
+    (quota.limit() > 0).then_some(quota)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn quota(_mount: &Mount, _owner: Owner) -> Option<Quota> {
+    None
+}
+
+/// The inodes of the filesystem mounted at `path`.
+#[cfg(unix)]
+fn inodes(path: &Path) -> Option<Inodes> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut vfs = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // statvfs fills in `vfs` when it returns 0.
+    let vfs = unsafe {
+        if libc::statvfs(path.as_ptr(), vfs.as_mut_ptr()) != 0 {
+            return None;
+        }
+        vfs.assume_init()
+    };
+    #[allow(clippy::unnecessary_cast)] // the field types differ by platform
+    let (total, free) = (vfs.f_files as u64, vfs.f_favail as u64);
+    (total > 0).then_some(Inodes { total, free })
+}
+
+#[cfg(not(unix))]
+fn inodes(_path: &Path) -> Option<Inodes> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+/// Filesystems whose files are made up by the kernel rather than stored
+/// anywhere, so their sizes mean nothing and some never finish reading.
+const PSEUDO_TYPES: &[&str] = &[
+    "proc",
+    "sysfs",
+    "devtmpfs",
+    "devpts",
+    "cgroup",
+    "cgroup2",
+    "securityfs",
+    "debugfs",
+    "tracefs",
+    "pstore",
+    "bpf",
+    "configfs",
+    "fusectl",
+    "mqueue",
+    "hugetlbfs",
+    "binfmt_misc",
+    "efivarfs",
+    "selinuxfs",
+    "rpc_pipefs",
+    "nsfs",
+    "autofs",
+];
+
+/// Mount points of pseudo filesystems, read from `/proc/mounts`. Memory
+/// filesystems count too when they hold runtime state, under `/run`, `/dev`
+/// or `/sys`, but not elsewhere, as `/tmp` on tmpfs holds real files.
+#[cfg(target_os = "linux")]
+pub fn pseudo() -> Vec<PathBuf> {
+    let Ok(mounts) = std::fs::read_to_string("/proc/mounts") else {
+        return Vec::new();
+    };
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().skip(1);
+            let path = PathBuf::from(unescape(fields.next()?));
+            let fs_type = fields.next()?;
+            let runtime = ["/run", "/dev", "/sys"].iter().any(|p| path.starts_with(p));
+            let pseudo = PSEUDO_TYPES.contains(&fs_type) || fs_type == "tmpfs" && runtime;
+            pseudo.then_some(path)
+        })
+        .collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pseudo() -> Vec<PathBuf> {
+    Vec::new()
+}
+
+/// Undoes the octal escapes `/proc/mounts` uses for spaces and the like.
+#[cfg(target_os = "linux")]
+fn unescape(field: &str) -> String {
+    let mut out = Vec::with_capacity(field.len());
+    let bytes = field.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let code = field
+            .get(i + 1..i + 4)
+            .filter(|_| bytes[i] == b'\\')
+            .and_then(|oct| u8::from_str_radix(oct, 8).ok());
+        match code {
+            Some(b) => {
+                out.push(b);
+                i += 4;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
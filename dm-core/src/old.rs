@@ -0,0 +1,76 @@
+//! Big files nobody has touched in a long while: the ones that could move
+//! off a fast disk to somewhere cheaper.
+
+use std::{
+    cmp::Reverse,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use walkdir::WalkDir;
+
+use crate::{
+    scan::{allocated_size, build_excludes, ScanOptions},
+    tree::DirStats,
+};
+
+/// Searches `root` for files of at least `min_bytes` that nothing has read
+/// or changed for `unused_for`, biggest first. When a file was last used
+/// goes in `newest`: the later of its access and modification times.
+/// Filesystems mounted `noatime` only tell the latter.
+pub fn find_old_files(
+    root: &Path,
+    opts: &ScanOptions,
+    min_bytes: u64,
+    unused_for: Duration,
+    cancel: &AtomicBool,
+) -> Vec<DirStats> {
+    let (excludes, _) = build_excludes(root, opts);
+    let now = SystemTime::now();
+    let mut found = Vec::new();
+    for entry in WalkDir::new(root)
+        .follow_links(false)
+        .same_file_system(opts.one_file_system)
+        .into_iter()
+        .filter_entry(|e| {
+            e.depth() == 0
+                || !excludes
+                    .matched(e.path(), e.file_type().is_dir())
+                    .is_ignore()
+        })
+        .flatten()
+    {
+        if cancel.load(Ordering::Relaxed) {
+            return Vec::new();
+        }
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let Ok(md) = entry.metadata() else {
+            continue;
+        };
+        if md.len() < min_bytes {
+            continue;
+        }
+        let modified = md.modified().ok();
+        let used = modified.max(md.accessed().ok());
+        let unused = used
+            .and_then(|t| now.duration_since(t).ok())
+            .unwrap_or_default();
+        if unused < unused_for {
+            continue;
+        }
+        let mut stats = DirStats::new(entry.path().to_path_buf());
+        stats.dir_count = 0;
+        stats.file_count = 1;
+        stats.total_bytes = md.len() as u128;
+        stats.disk_bytes = allocated_size(&md, entry.path()) as u128;
+        stats.mtime = modified;
+        stats.newest = used;
+        stats.complete = true;
+        found.push(stats);
+    }
+    found.sort_by_key(|f| Reverse(f.total_bytes));
+    found
+}
@@ -0,0 +1,93 @@
+//! Who the bytes belong to: file totals by user or group, with the ids
+//! resolved to names.
+
+use std::{cmp::Reverse, collections::HashMap};
+
+use crate::tree::{OwnerMap, SizeMode, TypeTotals};
+
+/// Whether owners are told apart by user or by group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OwnerKey {
+    User,
+    Group,
+}
+
+/// User and group names by id, as of when they were read.
+#[derive(Debug, Default)]
+pub struct Names {
+    users: HashMap<u32, String>,
+    groups: HashMap<u32, String>,
+}
+
+impl Names {
+    /// Reads the local user and group databases. Ids without a name, like
+    /// those of deleted accounts, show as numbers.
+    #[cfg(unix)]
+    pub fn load() -> Self {
+        use sysinfo::{Groups, Users};
+        Self {
+            users: Users::new_with_refreshed_list()
+                .iter()
+                .map(|u| (**u.id(), u.name().to_string()))
+                .collect(),
+            groups: Groups::new_with_refreshed_list()
+                .iter()
+                .map(|g| (**g.id(), g.name().to_string()))
+                .collect(),
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn load() -> Self {
+        Self::default()
+    }
+
+    pub fn user(&self, uid: u32) -> String {
+        self.users
+            .get(&uid)
+            .cloned()
+            .unwrap_or_else(|| uid.to_string())
+    }
+
+    /// Whether `uid` has an account. Without a user database, as on
+    /// Windows, every id counts as having one.
+    pub fn has_user(&self, uid: u32) -> bool {
+        self.users.is_empty() || self.users.contains_key(&uid)
+    }
+
+    pub fn group(&self, gid: u32) -> String {
+        self.groups
+            .get(&gid)
+            .cloned()
+            .unwrap_or_else(|| gid.to_string())
+    }
+}
+
+/// Rows for the owners pane and list, biggest first by `mode`.
+pub fn breakdown(
+    owners: OwnerMap,
+    by: OwnerKey,
+    names: &Names,
+    mode: SizeMode,
+) -> Vec<(String, TypeTotals)> {
+    let mut by_id: HashMap<u32, TypeTotals> = HashMap::new();
+    for ((uid, gid), totals) in owners {
+        let id = match by {
+            OwnerKey::User => uid,
+            OwnerKey::Group => gid,
+        };
+        by_id.entry(id).or_default().add(&totals);
+    }
+    let mut rows: Vec<(String, TypeTotals)> = by_id
+        .into_iter()
+        .map(|(id, totals)| {
+            let name = match by {
+                OwnerKey::User => names.user(id),
+                OwnerKey::Group => names.group(id),
+            };
+            (name, totals)
+        })
+        .collect();
+    rows.sort_by_key(|(name, t)| (Reverse(t.bytes(mode)), name.clone()));
+    rows
+}
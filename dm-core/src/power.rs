@@ -0,0 +1,46 @@
+//! Whether the machine runs on battery, when a full scan every so often
+//! costs more than it's worth.
+
+/// Whether the machine is running on battery right now. False wherever
+/// that can't be told, as on desktops and in containers.
+pub fn on_battery() -> bool {
+    platform_on_battery()
+}
+
+/// A laptop battery being drained. Mice and other peripherals report
+/// their batteries too, with a scope of "Device".
+#[cfg(target_os = "linux")]
+fn platform_on_battery() -> bool {
+    use std::fs;
+    let Ok(supplies) = fs::read_dir("/sys/class/power_supply") else {
+        return false;
+    };
+    supplies.flatten().any(|supply| {
+        let read = |name: &str| fs::read_to_string(supply.path().join(name)).unwrap_or_default();
+        read("type").trim() == "Battery"
+            && read("scope").trim() != "Device"
+            && read("status").trim() == "Discharging"
+    })
+}
+
+#[cfg(target_os = "macos")]
+fn platform_on_battery() -> bool {
+    std::process::Command::new("pmset")
+        .args(["-g", "batt"])
+        .output()
+        .is_ok_and(|out| String::from_utf8_lossy(&out.stdout).contains("'Battery Power'"))
+}
+
+#[cfg(windows)]
+fn platform_on_battery() -> bool {
+    use windows_sys::Win32::System::Power::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+    let mut status: SYSTEM_POWER_STATUS = unsafe { std::mem::zeroed() };
+    let known = unsafe { GetSystemPowerStatus(&mut status) } != 0;
+    // 0 is offline; 255, unknown, goes with machines without a battery.
+    known && status.ACLineStatus == 0
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn platform_on_battery() -> bool {
+    false
+}
@@ -0,0 +1,1509 @@
+//! Filesystem walking: turns a directory on disk into a [`DirTree`].
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc, Arc, Condvar, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use rayon::ThreadPoolBuilder;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    meta::{Entry, FileMeta, Kind},
+    mounts,
+    tree::{DirStats, DirTree, HardLink, NodeId, OwnerMap, SizeMode, TypeMap},
+    vfs::{self, Stat, Vfs},
+};
+
+/// Number of walked entries between partial updates for one of the scanned
+/// directory's children.
+const PROGRESS_BATCH: u64 = 5_000;
+
+/// Scan errors reported individually; the rest only show up in the counts.
+const MAX_REPORTED_ERRORS: usize = 20;
+
+/// Per-directory ignore file, gitignore syntax.
+pub const IGNORE_FILE: &str = ".dmignore";
+
+/// Levels below the scanned directory a quick scan reads.
+const QUICK_DEPTH: usize = 4;
+
+/// Files a quick scan looks up per directory; the others are assumed to
+/// be like them.
+const QUICK_SAMPLE: usize = 256;
+
+/// How often a scan with a memory budget looks at what the process takes.
+const MEMORY_CHECK: Duration = Duration::from_millis(500);
+
+/// Directory reads on one device left stuck past `dir_timeout` before the
+/// scan stops trying that device, giving up on the rest of it at once.
+const MAX_STUCK: usize = 4;
+
+/// Knobs that change what a scan walks and how it counts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanOptions {
+    pub dedup_hardlinks: bool,
+    pub one_file_system: bool,
+    pub follow_links: bool,
+    pub include_pseudo: bool, // walk /proc, /sys and the like instead of skipping them
+    pub threads: usize,       // for reading directories, 0 for one per CPU
+    pub low_priority: bool,   // yield CPU and disk to everything else
+    pub mft: bool,            // read NTFS volumes' file table instead of walking
+    pub max_depth: Option<usize>, // levels below the scanned directory to read
+    pub sample: Option<usize>, // files looked up per directory at most
+    pub reflinks: bool,       // look up the extents files share with reflinked copies
+    /// Seconds to wait for a directory to list before giving up on it and
+    /// what's below, for hung network mounts. None waits forever.
+    pub dir_timeout: Option<u64>,
+    /// Levels below the scanned directory kept as directories of their own.
+    /// Deeper ones are still walked, but only counted into the one they're
+    /// in at the last kept level, so huge trees take a fraction of the
+    /// memory. At least 1; None keeps every level.
+    pub keep_depth: Option<usize>,
+    /// Bytes the process may take before the scan stops keeping
+    /// directories below the level it has got to, as with `keep_depth`.
+    pub memory_budget: Option<u64>,
+    /// Filesystem operations per second at most, a directory listing or a
+    /// file looked up counting as one each, so a scan doesn't hold up
+    /// everything else on the disk. None goes as fast as it can.
+    pub io_limit: Option<u64>,
+    pub excludes: Vec<String>,
+}
+
+/// Everything walked, hard links counted once, on one thread per CPU.
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            dedup_hardlinks: true,
+            one_file_system: false,
+            follow_links: false,
+            include_pseudo: false,
+            threads: 0,
+            low_priority: false,
+            mft: false,
+            max_depth: None,
+            sample: None,
+            reflinks: false,
+            dir_timeout: None,
+            keep_depth: None,
+            memory_budget: None,
+            io_limit: None,
+            excludes: Vec::new(),
+        }
+    }
+}
+
+/// Presets trading accuracy for speed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+    /// A rough picture in moments: a few levels deep, big directories
+    /// sampled, apparent sizes, hard links counted every time.
+    Quick,
+    /// Everything walked and looked up, disk usage, hard links counted once.
+    Thorough,
+}
+
+impl Profile {
+    pub fn toggled(self) -> Self {
+        match self {
+            Profile::Quick => Profile::Thorough,
+            Profile::Thorough => Profile::Quick,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Profile::Quick => "quick",
+            Profile::Thorough => "thorough",
+        }
+    }
+
+    /// Sets what the profile decides in `opts`, leaving the rest alone.
+    pub fn apply(self, opts: &mut ScanOptions) {
+        match self {
+            Profile::Quick => {
+                opts.max_depth = Some(QUICK_DEPTH);
+                opts.sample = Some(QUICK_SAMPLE);
+                opts.dedup_hardlinks = false;
+            }
+            Profile::Thorough => {
+                opts.max_depth = None;
+                opts.sample = None;
+                opts.dedup_hardlinks = true;
+            }
+        }
+    }
+
+    /// The sizes that go with the profile.
+    pub fn size_mode(self) -> SizeMode {
+        match self {
+            Profile::Quick => SizeMode::Apparent,
+            Profile::Thorough => SizeMode::Disk,
+        }
+    }
+}
+
+/// What a scan reports while it runs, from whichever of its threads
+/// comes across it.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ScanEvent {
+    /// Partial or final totals of one of the scanned directory's children;
+    /// final once `complete` is set.
+    Progress(DirStats),
+    /// An entry the scan couldn't read. Only the first few are reported
+    /// one by one; the rest show up in the counts and a last summary.
+    Unreadable(String),
+    /// A problem with the scan's setup, such as a bad ignore file, that it
+    /// carries on without.
+    Warning(String),
+    /// The tree so far, every [`Scanner::checkpoint`]. Directories not read
+    /// yet have no mtime, so as the `previous` tree of a later scan it
+    /// spares that scan only the ones that were.
+    Checkpoint(DirTree),
+}
+
+/// One scan of `root`. The fields past `opts` are optional extras; set
+/// them on the value [`Scanner::new`] returns before calling
+/// [`Scanner::run`].
+pub struct Scanner {
+    pub root: PathBuf,
+    pub opts: ScanOptions,
+    /// A child whose subtree the caller already has and wants to keep.
+    pub skip: Option<PathBuf>,
+    /// The last scan of `root`; directories whose mtime hasn't changed
+    /// since then reuse its file totals instead of stat-ing every file.
+    pub previous: Option<DirTree>,
+    /// Set from another thread to abandon the scan.
+    pub cancel: Arc<AtomicBool>,
+    /// Running totals to read from another thread while the scan runs.
+    pub counters: Arc<ScanCounters>,
+    /// Set from another thread to hold the scan where it is.
+    pub pause: Arc<Pause>,
+    /// Where `root` is; the local filesystem unless set.
+    pub vfs: Arc<dyn Vfs>,
+    /// How often to hand over the tree so far as a
+    /// [`ScanEvent::Checkpoint`], to pick up from should the scan never
+    /// finish. None never does.
+    pub checkpoint: Option<Duration>,
+}
+
+/// Holds a scan's threads while it's paused, so the disk is free for
+/// something else without losing what the scan has so far.
+#[derive(Debug, Default)]
+pub struct Pause {
+    paused: AtomicBool,
+    lock: Mutex<()>,
+    resumed: Condvar,
+}
+
+impl Pause {
+    pub fn set(&self, paused: bool) {
+        let guard = self.lock.lock().unwrap();
+        self.paused.store(paused, Ordering::Release);
+        drop(guard);
+        self.resumed.notify_all();
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.paused.load(Ordering::Acquire)
+    }
+
+    /// Parks the calling thread for as long as the scan is paused.
+    fn wait(&self) {
+        if !self.is_set() {
+            return;
+        }
+        let guard = self.lock.lock().unwrap();
+        let _guard = self.resumed.wait_while(guard, |_| self.is_set()).unwrap();
+    }
+}
+
+/// Token bucket holding a scan's threads to a number of operations per
+/// second, with up to a second's worth let through at once.
+#[derive(Debug)]
+struct Throttle {
+    rate: f64,
+    bucket: Mutex<(f64, Instant)>, // tokens left, possibly owed, and when last topped up
+}
+
+impl Throttle {
+    fn new(rate: u64) -> Self {
+        let rate = rate.max(1) as f64;
+        Self {
+            rate,
+            bucket: Mutex::new((rate, Instant::now())),
+        }
+    }
+
+    /// Takes `ops` tokens, first waiting for as long as it takes to earn
+    /// the ones missing, unless `cancel` is set meanwhile.
+    fn take(&self, ops: u64, cancel: &AtomicBool) {
+        if ops == 0 {
+            return;
+        }
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let (tokens, last) = &mut *bucket;
+            let now = Instant::now();
+            *tokens =
+                (*tokens + now.duration_since(*last).as_secs_f64() * self.rate).min(self.rate);
+            *last = now;
+            *tokens -= ops as f64;
+            // Whoever comes next waits for this debt on top of their own.
+            Duration::from_secs_f64((-*tokens).max(0.0) / self.rate)
+        };
+        let until = Instant::now() + wait;
+        while !cancel.load(Ordering::Relaxed) {
+            let left = until.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            thread::sleep(left.min(Duration::from_millis(100)));
+        }
+    }
+}
+
+/// Live totals of a scan in flight, for the UI to read as it draws.
+#[derive(Debug, Default)]
+pub struct ScanCounters {
+    pub entries: AtomicU64,      // directory entries read so far
+    pub bytes: AtomicU64,        // apparent size of the files counted so far
+    pub current: Mutex<PathBuf>, // directory read most recently
+    /// Files not looked up one by one, as they were taken over from the
+    /// previous scan or left out of a sample.
+    pub skipped: AtomicU64,
+}
+
+impl ScanCounters {
+    /// Notes that `dir` is being read. Skipped when another thread is at
+    /// it, as any recent directory will do.
+    fn reading(&self, dir: &Path) {
+        if let Ok(mut current) = self.current.try_lock() {
+            current.as_mut_os_string().clear();
+            current.push(dir);
+        }
+    }
+
+    fn add(&self, entries: u64, files: &DirStats) {
+        self.entries.fetch_add(entries, Ordering::Relaxed);
+        let bytes = u64::try_from(files.total_bytes).unwrap_or(u64::MAX);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+/// A previous scan indexed by path.
+struct Previous<'a> {
+    tree: &'a DirTree,
+    index: HashMap<PathBuf, NodeId>,
+    largest: HashMap<NodeId, Vec<&'a DirStats>>, // by containing directory
+}
+
+impl<'a> Previous<'a> {
+    fn new(tree: &'a DirTree) -> Self {
+        let mut index = HashMap::new();
+        let mut stack = vec![(tree.root(), tree.root_path().to_path_buf())];
+        while let Some((id, path)) = stack.pop() {
+            for &c in tree.children(id) {
+                stack.push((c, path.join(tree.name(c))));
+            }
+            index.insert(path, id);
+        }
+        let mut largest: HashMap<_, Vec<_>> = HashMap::new();
+        for (dir, file) in tree.largest_by_dir() {
+            largest.entry(dir).or_default().push(file);
+        }
+        Self {
+            tree,
+            index,
+            largest,
+        }
+    }
+
+    /// The big files `dir` had last time, to carry over along with
+    /// [`Previous::unchanged_files`].
+    fn largest_in(&self, dir: &Path) -> impl Iterator<Item = &'a DirStats> + '_ {
+        self.index
+            .get(dir)
+            .and_then(|id| self.largest.get(id))
+            .into_iter()
+            .flatten()
+            .copied()
+    }
+
+    /// The file types `dir` had last time, to carry over along with
+    /// [`Previous::unchanged_files`].
+    fn types_in(&self, dir: &Path) -> TypeMap {
+        self.index
+            .get(dir)
+            .map(|&id| self.tree.own_types(id).clone())
+            .unwrap_or_default()
+    }
+
+    /// The file owners `dir` had last time, like [`Previous::types_in`].
+    fn owners_in(&self, dir: &Path) -> OwnerMap {
+        self.index
+            .get(dir)
+            .map(|&id| self.tree.own_owners(id).clone())
+            .unwrap_or_default()
+    }
+
+    /// The hard-linked files `dir` carried last time, like
+    /// [`Previous::types_in`].
+    fn links_in(&self, dir: &Path) -> Vec<HardLink> {
+        self.index
+            .get(dir)
+            .map(|&id| self.tree.own_links(id).to_vec())
+            .unwrap_or_default()
+    }
+
+    /// The file totals `dir` had last time, if its mtime says nothing was
+    /// added, removed or renamed in it since. Files that grew in place don't
+    /// touch the directory mtime; a full rescan picks those up.
+    fn unchanged_files(&self, dir: &Path, mtime: Option<SystemTime>) -> Option<DirStats> {
+        let id = *self.index.get(dir)?;
+        let old = self.tree.stats(id);
+        // Folded directories' own totals have their subdirectories' in them.
+        if mtime.is_none() || old.mtime != mtime || old.stale || old.folded {
+            return None;
+        }
+        let mut own = self.tree.own_stats(id);
+        // Sampled or unread last time, so there's nothing exact to keep.
+        if own.estimated > 0 {
+            return None;
+        }
+        own.dir_count = 0;
+        Some(own)
+    }
+}
+
+/// Memory the process takes, resident in RAM.
+pub fn resident_bytes() -> Option<u64> {
+    use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, System};
+    let pid = sysinfo::get_current_pid().ok()?;
+    let mut system = System::new();
+    system.refresh_processes_specifics(
+        ProcessesToUpdate::Some(&[pid]),
+        false,
+        ProcessRefreshKind::nothing().with_memory(),
+    );
+    system.process(pid).map(|process| process.memory())
+}
+
+/// Device id of the filesystem holding `path`, where the platform has one.
+pub fn device_of(path: &Path) -> Option<u64> {
+    fs::symlink_metadata(path).ok().as_ref().and_then(device)
+}
+
+#[cfg(unix)]
+pub(crate) fn device(md: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(md.dev())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn device(_md: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+/// The node for a link to a directory that isn't followed, leading to
+/// `target`.
+fn link_stats(path: PathBuf, target: Option<PathBuf>) -> DirStats {
+    let mut stats = DirStats::new(path);
+    stats.dir_count = 0;
+    stats.complete = true;
+    stats.link = true;
+    stats.target = target;
+    stats
+}
+
+/// Builds the exclude matcher for a scan rooted at `root` from `~/.dmignore`,
+/// `root/.dmignore` and the `--exclude` patterns, in increasing precedence.
+/// Pseudo filesystems mounted below `root` are excluded too unless
+/// `include_pseudo` is set. Problems with the ignore files are returned
+/// rather than aborting the scan.
+pub fn build_excludes(root: &Path, opts: &ScanOptions) -> (Gitignore, Vec<String>) {
+    let mut builder = GitignoreBuilder::new(root);
+    let mut problems = Vec::new();
+
+    let ignore_files = dirs::home_dir()
+        .map(|home| home.join(IGNORE_FILE))
+        .into_iter()
+        .chain(Some(root.join(IGNORE_FILE)));
+    for file in ignore_files.filter(|f| f.is_file()) {
+        if let Some(e) = builder.add(&file) {
+            problems.push(format!("{}: {e}", file.display()));
+        }
+    }
+    if !opts.include_pseudo {
+        for mount in mounts::pseudo() {
+            let Ok(rel) = mount.strip_prefix(root) else {
+                continue;
+            };
+            if rel.as_os_str().is_empty() {
+                continue; // asked for by name
+            }
+            let pattern = format!("/{}/", glob_escape(&rel.to_string_lossy()));
+            if let Err(e) = builder.add_line(None, &pattern) {
+                problems.push(format!("{}: {e}", mount.display()));
+            }
+        }
+    }
+    for pattern in &opts.excludes {
+        if let Err(e) = builder.add_line(None, pattern) {
+            problems.push(format!("--exclude {pattern}: {e}"));
+        }
+    }
+
+    match builder.build() {
+        Ok(matcher) => (matcher, problems),
+        Err(e) => {
+            problems.push(format!("excludes disabled: {e}"));
+            (Gitignore::empty(), problems)
+        }
+    }
+}
+
+/// `text` as a gitignore pattern matching only itself.
+fn glob_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\' | '!' | '#') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Bytes actually allocated for a file, as opposed to its length.
+#[cfg(unix)]
+pub fn allocated_size(md: &fs::Metadata, _path: &Path) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    // st_blocks is always in 512-byte units, regardless of st_blksize.
+    md.blocks().saturating_mul(512)
+}
+
+#[cfg(windows)]
+pub fn allocated_size(md: &fs::Metadata, path: &Path) -> u64 {
+    use std::os::windows::{ffi::OsStrExt, fs::MetadataExt};
+    use windows_sys::Win32::Storage::FileSystem::{
+        GetCompressedFileSizeW, FILE_ATTRIBUTE_COMPRESSED, FILE_ATTRIBUTE_SPARSE_FILE,
+        INVALID_FILE_SIZE,
+    };
+
+    // Only compressed, sparse and cloud files take other than their length,
+    // and the attributes come with the directory listing, so the others
+    // don't cost another call.
+    let takes_less = FILE_ATTRIBUTE_COMPRESSED | FILE_ATTRIBUTE_SPARSE_FILE;
+    if md.file_attributes() & takes_less == 0 && !is_placeholder(md) {
+        return md.len();
+    }
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut high: u32 = 0;
+    let low = unsafe { GetCompressedFileSizeW(wide.as_ptr(), &mut high) };
+    if low == INVALID_FILE_SIZE && std::io::Error::last_os_error().raw_os_error() != Some(0) {
+        return md.len();
+    }
+    ((high as u64) << 32) | low as u64
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn allocated_size(md: &fs::Metadata, _path: &Path) -> u64 {
+    md.len()
+}
+
+/// Whether a file is a cloud placeholder, with its contents kept online:
+/// one marked to be fetched when opened or read on Windows, or a dataless
+/// File Provider file on macOS.
+#[cfg(windows)]
+pub fn is_placeholder(md: &fs::Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    use windows_sys::Win32::Storage::FileSystem::{
+        FILE_ATTRIBUTE_OFFLINE, FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS, FILE_ATTRIBUTE_RECALL_ON_OPEN,
+    };
+    let online = FILE_ATTRIBUTE_OFFLINE
+        | FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS
+        | FILE_ATTRIBUTE_RECALL_ON_OPEN;
+    md.file_attributes() & online != 0
+}
+
+#[cfg(target_os = "macos")]
+pub fn is_placeholder(md: &fs::Metadata) -> bool {
+    use std::os::macos::fs::MetadataExt;
+    const SF_DATALESS: u32 = 0x4000_0000;
+    md.st_flags() & SF_DATALESS != 0
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+pub fn is_placeholder(_md: &fs::Metadata) -> bool {
+    false
+}
+
+/// Bytes of the file at `path` in extents it shares with other files:
+/// reflinked copies on btrfs and XFS, and snapshots. Read with FIEMAP,
+/// so 0 wherever that isn't supported, as on ZFS, whose block counts
+/// already leave compression out.
+#[cfg(all(
+    target_os = "linux",
+    any(
+        target_arch = "x86_64",
+        target_arch = "x86",
+        target_arch = "aarch64",
+        target_arch = "arm",
+        target_arch = "riscv64"
+    )
+))]
+pub fn reflinked_bytes(path: &Path) -> u64 {
+    use std::os::fd::AsRawFd;
+
+    // _IOWR('f', 11, struct fiemap) in the generic ioctl encoding
+    const FS_IOC_FIEMAP: libc::c_ulong = 0xC020_660B;
+    const FIEMAP_EXTENT_LAST: u32 = 0x0001;
+    const FIEMAP_EXTENT_SHARED: u32 = 0x2000;
+    const EXTENTS: usize = 64;
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct Extent {
+        logical: u64,
+        physical: u64,
+        length: u64,
+        reserved64: [u64; 2],
+        flags: u32,
+        reserved: [u32; 3],
+    }
+
+    #[repr(C)]
+    struct Fiemap {
+        start: u64,
+        length: u64,
+        flags: u32,
+        mapped_extents: u32,
+        extent_count: u32,
+        reserved: u32,
+        extents: [Extent; EXTENTS],
+    }
+
+    let Ok(file) = fs::File::open(path) else {
+        return 0;
+    };
+    let mut map = Fiemap {
+        start: 0,
+        length: u64::MAX,
+        flags: 0,
+        mapped_extents: 0,
+        extent_count: EXTENTS as u32,
+        reserved: 0,
+        extents: [Extent::default(); EXTENTS],
+    };
+    let mut shared = 0;
+    loop {
+        if unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_FIEMAP as _, &mut map) } != 0 {
+            return shared;
+        }
+        let mapped = &map.extents[..(map.mapped_extents as usize).min(EXTENTS)];
+        let Some(last) = mapped.last() else {
+            return shared;
+        };
+        shared += mapped
+            .iter()
+            .filter(|e| e.flags & FIEMAP_EXTENT_SHARED != 0)
+            .map(|e| e.length)
+            .sum::<u64>();
+        if last.flags & FIEMAP_EXTENT_LAST != 0 {
+            return shared;
+        }
+        map.start = last.logical + last.length;
+        map.length = u64::MAX - map.start;
+        map.mapped_extents = 0;
+    }
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(
+        target_arch = "x86_64",
+        target_arch = "x86",
+        target_arch = "aarch64",
+        target_arch = "arm",
+        target_arch = "riscv64"
+    )
+)))]
+pub fn reflinked_bytes(_path: &Path) -> u64 {
+    0
+}
+
+/// User and group id of a file's owner.
+#[cfg(unix)]
+pub fn owner_of(md: &fs::Metadata) -> Option<(u32, u32)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((md.uid(), md.gid()))
+}
+
+#[cfg(not(unix))]
+pub fn owner_of(_md: &fs::Metadata) -> Option<(u32, u32)> {
+    None
+}
+
+/// (device, inode) of files seen so far with more than one hard link, or of
+/// directories walked so far when following links.
+type SeenInodes = Mutex<HashSet<(u64, u64)>>;
+
+/// Identity of a file with several hard links, `None` for ordinary files.
+#[cfg(unix)]
+pub fn hardlink_key(md: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    (md.nlink() > 1).then(|| (md.dev(), md.ino()))
+}
+
+#[cfg(not(unix))]
+pub fn hardlink_key(_md: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(unix)]
+pub(crate) fn inode(md: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(md.ino())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn inode(_md: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+/// What the file at `path` on `vfs` contributes to its directory's totals.
+fn file_stats(
+    vfs: &dyn Vfs,
+    path: &Path,
+    meta: &FileMeta,
+    opts: &ScanOptions,
+    seen: &SeenInodes,
+) -> DirStats {
+    let mut stats = DirStats::new(PathBuf::new());
+    stats.dir_count = 0;
+    stats.file_count = 1;
+    stats.newest = meta.mtime;
+    stats.owner = meta.owner;
+    stats.placeholders = meta.placeholder.into();
+    if let Some(key) = meta.hardlink {
+        // Only the first link we come across carries the size.
+        if opts.dedup_hardlinks && !seen.lock().unwrap().insert(key) {
+            return stats;
+        }
+        stats.shared_bytes = meta.len as u128;
+    }
+    stats.total_bytes = meta.len as u128;
+    stats.disk_bytes = meta.allocated as u128;
+    if let Some((family, private)) = meta.clone {
+        stats.reflinked_bytes = meta.allocated.saturating_sub(private).into();
+        // Unchanged clones hold the same blocks, counted once like hard
+        // links; no device id is all ones, so their keys stay apart.
+        if opts.dedup_hardlinks && !seen.lock().unwrap().insert((u64::MAX, family)) {
+            stats.disk_bytes = private.into();
+        }
+    } else if opts.reflinks {
+        stats.reflinked_bytes = vfs.reflinked(path).into();
+    }
+    stats
+}
+
+/// Turns the counted `stats` of a file into a listing entry of its own.
+fn file_entry(mut stats: DirStats, path: PathBuf, mtime: Option<SystemTime>) -> DirStats {
+    stats.path = path;
+    stats.mtime = mtime;
+    stats.complete = true;
+    stats
+}
+
+/// The regular files directly inside `dir` on `vfs`, counted the way a scan
+/// with `excludes` from [`build_excludes`] would count them but without
+/// sharing hard links across files. Unreadable entries are left out; the
+/// scan already reports them.
+pub fn list_files(
+    vfs: &dyn Vfs,
+    dir: &Path,
+    excludes: &Gitignore,
+    opts: &ScanOptions,
+) -> Vec<DirStats> {
+    let Ok(listing) = vfs.read(dir, true) else {
+        return Vec::new();
+    };
+    let seen = SeenInodes::default();
+    listing
+        .into_iter()
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            if excludes.matched(&entry.path, false).is_ignore() {
+                return None;
+            }
+            let meta = entry.meta?.ok()?;
+            let stats = file_stats(vfs, &entry.path, &meta, opts, &seen);
+            Some(file_entry(stats, entry.path, meta.mtime))
+        })
+        .collect()
+}
+
+/// Puts the calling thread at the back of the queue for CPU and disk, so a
+/// scan doesn't hold up everything else on the machine. Best effort: where
+/// it isn't allowed, the thread keeps the priority it had.
+#[cfg(target_os = "linux")]
+fn lower_priority() {
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+    const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+    // On Linux both take a thread id, so 0 means this thread only.
+    unsafe {
+        libc::setpriority(libc::PRIO_PROCESS, 0, 19);
+        libc::syscall(
+            libc::SYS_ioprio_set,
+            IOPRIO_WHO_PROCESS,
+            0,
+            IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+        );
+    }
+}
+
+/// Background QoS throttles disk access along with the CPU.
+#[cfg(target_os = "macos")]
+fn lower_priority() {
+    unsafe {
+        libc::pthread_set_qos_class_self_np(libc::qos_class_t::QOS_CLASS_BACKGROUND, 0);
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn lower_priority() {}
+
+/// The whole tree below `root` from the NTFS Master File Table, without
+/// walking it.
+#[cfg(windows)]
+fn read_mft(
+    root: &Path,
+    opts: &ScanOptions,
+    excludes: &Gitignore,
+    skip: Option<&Path>,
+    cancel: &AtomicBool,
+) -> io::Result<DirTree> {
+    crate::mft::scan(root, opts, excludes, skip, cancel)
+}
+
+#[cfg(not(windows))]
+fn read_mft(
+    _root: &Path,
+    _opts: &ScanOptions,
+    _excludes: &Gitignore,
+    _skip: Option<&Path>,
+    _cancel: &AtomicBool,
+) -> io::Result<DirTree> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "only NTFS volumes on Windows have one",
+    ))
+}
+
+/// What reading a directory gives.
+type Listing = io::Result<Vec<io::Result<Entry>>>;
+
+/// A directory read handed to [`Readers`]: the directory, whether to look
+/// up its entries, its device, and where the listing goes. The flag is set
+/// once the read is given up on.
+type ReadJob = (
+    PathBuf,
+    bool,
+    Option<u64>,
+    mpsc::Sender<Listing>,
+    Arc<AtomicBool>,
+);
+
+/// Threads reading directories for a scan with `dir_timeout`. A read stuck
+/// in the kernel can't be interrupted, so it holds one of these until it
+/// finishes, or forever, rather than a scan thread. There's one for each
+/// scan thread and for each read that may be stuck, so a read only waits
+/// for a free one when more than that are.
+struct Readers {
+    jobs: Mutex<mpsc::Sender<ReadJob>>,
+    timeout: Duration,
+    stuck: Arc<Mutex<HashMap<Option<u64>, usize>>>, // reads given up on, by device
+}
+
+impl Readers {
+    fn new(vfs: &Arc<dyn Vfs>, scan_threads: usize, timeout: Duration) -> Self {
+        let (tx, rx) = mpsc::channel::<ReadJob>();
+        let rx = Arc::new(Mutex::new(rx));
+        let stuck: Arc<Mutex<HashMap<Option<u64>, usize>>> = Arc::default();
+        for i in 0..scan_threads + MAX_STUCK {
+            let (vfs, rx, stuck) = (vfs.clone(), rx.clone(), stuck.clone());
+            let spawned = thread::Builder::new()
+                .name(format!("scan-read-{i}"))
+                .spawn(move || loop {
+                    // Ends with the scan, once it drops the sender.
+                    let Ok((dir, meta, dev, reply, abandoned)) = rx.lock().unwrap().recv() else {
+                        return;
+                    };
+                    let listing = vfs.read(&dir, meta);
+                    let mut stuck = stuck.lock().unwrap();
+                    if abandoned.load(Ordering::Relaxed) {
+                        // Came unstuck after all.
+                        if let Some(n) = stuck.get_mut(&dev) {
+                            *n = n.saturating_sub(1);
+                        }
+                    } else {
+                        let _ = reply.send(listing);
+                    }
+                });
+            if spawned.is_err() {
+                break;
+            }
+        }
+        Self {
+            jobs: Mutex::new(tx),
+            timeout,
+            stuck,
+        }
+    }
+
+    /// The entries of `dir` on device `dev`, or why there are none: the
+    /// read didn't finish in time, or too many on `dev` haven't.
+    fn read(&self, dir: &Path, meta: bool, dev: Option<u64>) -> Result<Listing, String> {
+        let secs = self.timeout.as_secs();
+        if self.stuck.lock().unwrap().get(&dev).copied().unwrap_or(0) >= MAX_STUCK {
+            return Err(format!(
+                "not read: {MAX_STUCK} reads on this device went over {secs}s already"
+            ));
+        }
+        let (tx, rx) = mpsc::channel();
+        let abandoned = Arc::new(AtomicBool::new(false));
+        let job = (dir.to_path_buf(), meta, dev, tx, abandoned.clone());
+        if self.jobs.lock().unwrap().send(job).is_err() {
+            return Err("not read: no threads to read with".to_string());
+        }
+        if let Ok(listing) = rx.recv_timeout(self.timeout) {
+            return Ok(listing);
+        }
+        let mut stuck = self.stuck.lock().unwrap();
+        // It may have come in while taking the lock.
+        if let Ok(listing) = rx.try_recv() {
+            return Ok(listing);
+        }
+        abandoned.store(true, Ordering::Relaxed);
+        *stuck.entry(dev).or_default() += 1;
+        Err(format!("gave up after {secs}s without a listing"))
+    }
+}
+
+/// One scan's walk, shared by the threads reading its directories.
+struct Walk<'a> {
+    opts: ScanOptions,
+    excludes: Gitignore,
+    skip: Option<PathBuf>,
+    previous: Option<Previous<'a>>,
+    cancel: Arc<AtomicBool>,
+    on_event: &'a (dyn Fn(ScanEvent) + Sync),
+    unreadable: AtomicUsize, // entries the scan couldn't read so far
+    outer: PathBuf,          // the scanned directory, canonical
+    root_dev: Option<u64>,
+    seen: SeenInodes,
+    seen_dirs: SeenInodes, // only filled when following links
+    vfs: Arc<dyn Vfs>,
+    counters: Arc<ScanCounters>,
+    pause: Arc<Pause>,
+    tree: Mutex<DirTree>,
+    checkpoint: Option<(Duration, Mutex<Instant>)>, // interval, last one
+    unread: Mutex<HashSet<NodeId>>,                 // directories queued; only kept for checkpoints
+    keep: AtomicUsize, // levels kept as directories, lowered past the memory budget
+    throttle: Option<Throttle>,
+    readers: Option<Readers>, // with `opts.dir_timeout`
+    memory_checked: Mutex<Instant>,
+    over_budget: AtomicBool,
+}
+
+/// Running totals of one of the scanned directory's children, sent to the
+/// UI while the directories below it are read.
+struct Progress {
+    totals: Mutex<DirStats>,
+    walked: AtomicU64,
+    pending: AtomicUsize, // its directories queued or being read
+}
+
+impl Walk<'_> {
+    /// Reads directory `id` at `dir` and queues its subdirectories on
+    /// `scope`, so idle threads take them over wherever they are in the
+    /// tree. `reused` says its files were taken over from the previous scan;
+    /// `dev` is the device it's on and `depth` its level below the scanned
+    /// directory. Waits first while the scan is paused.
+    #[allow(clippy::too_many_arguments)]
+    fn dir<'s>(
+        &'s self,
+        scope: &rayon::Scope<'s>,
+        id: NodeId,
+        dir: PathBuf,
+        dev: Option<u64>,
+        depth: usize,
+        reused: bool,
+        progress: Option<Arc<Progress>>,
+    ) {
+        self.pause.wait();
+        if self.cancel.load(Ordering::Relaxed) {
+            return;
+        }
+        self.read(scope, id, &dir, dev, depth, reused, progress.as_ref());
+        if self.checkpoint.is_some() {
+            self.unread.lock().unwrap().remove(&id);
+            self.maybe_checkpoint();
+        }
+        if let Some(p) = progress {
+            if p.pending.fetch_sub(1, Ordering::AcqRel) == 1 {
+                let mut totals = p.totals.lock().unwrap();
+                totals.complete = true;
+                self.send_progress(&totals);
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn read<'s>(
+        &'s self,
+        scope: &rayon::Scope<'s>,
+        id: NodeId,
+        dir: &Path,
+        dev: Option<u64>,
+        depth: usize,
+        reused: bool,
+        progress: Option<&Arc<Progress>>,
+    ) {
+        let mut own = DirStats::new(PathBuf::new());
+        own.dir_count = 0;
+        let mut files = Vec::new();
+        let mut links = Vec::new();
+        let mut subdirs = Vec::new();
+        let mut unsampled = Vec::new();
+        let mut entries: u64 = 0;
+        let sample = self.opts.sample.filter(|_| !reused);
+        self.counters.reading(dir);
+        self.throttle(1);
+        let lookups = !reused && sample.is_none();
+        match self.list(dir, lookups, dev) {
+            Err(why) => {
+                own.errors += 1;
+                self.report(dir, why);
+                self.tree.lock().unwrap().stats_mut(id).timed_out = true;
+            }
+            Ok(Err(err)) => {
+                own.errors += 1;
+                self.report(dir, err);
+            }
+            Ok(Ok(listing)) => {
+                for entry in listing {
+                    entries += 1;
+                    let entry = match entry {
+                        Ok(entry) => entry,
+                        Err(err) => {
+                            own.errors += 1;
+                            self.report(dir, err);
+                            continue;
+                        }
+                    };
+                    let path = entry.path;
+                    let is_dir = entry.kind == Kind::Dir;
+                    if self.skip.as_ref() == Some(&path)
+                        || self.excludes.matched(&path, is_dir).is_ignore()
+                    {
+                        continue;
+                    }
+                    // Links to files aren't counted, followed or not.
+                    let link = entry.kind == Kind::Symlink;
+                    if is_dir || link && self.vfs.stat(&path).is_ok_and(|s| s.kind == Kind::Dir) {
+                        subdirs.push((path, link));
+                        continue;
+                    }
+                    match entry.meta {
+                        Some(Ok(meta)) => {
+                            let stats =
+                                file_stats(&*self.vfs, &path, &meta, &self.opts, &self.seen);
+                            if let Some(key) = meta.hardlink.filter(|_| stats.shared_bytes > 0) {
+                                links.push(HardLink {
+                                    key,
+                                    bytes: stats.total_bytes,
+                                    disk_bytes: stats.disk_bytes,
+                                });
+                            }
+                            own.add(&stats);
+                            files.push((path, stats, meta.mtime));
+                        }
+                        Some(Err(err)) => {
+                            own.errors += 1;
+                            self.report(&path, err);
+                        }
+                        None if sample.is_some() && entry.kind == Kind::File => {
+                            unsampled.push(path);
+                        }
+                        None => {}
+                    }
+                }
+            }
+        }
+        // Each entry looked up as it was listed, and each subdirectory.
+        self.throttle(if lookups { entries } else { 0 } + subdirs.len() as u64);
+        if reused {
+            let skipped = entries.saturating_sub(subdirs.len() as u64);
+            self.counters.skipped.fetch_add(skipped, Ordering::Relaxed);
+        }
+        if let Some(limit) = sample {
+            self.sample(unsampled, limit, &mut own, &mut files);
+        }
+        let children: Vec<_> = subdirs
+            .into_iter()
+            .map(|(path, link)| self.child(path, link, dev, depth + 1))
+            .collect();
+
+        self.check_memory(depth);
+        let fold = depth >= self.keep.load(Ordering::Relaxed);
+        let mut counted = own.clone();
+        let mut queued = Vec::new();
+        let mut tree = self.tree.lock().unwrap();
+        tree.stats_mut(id).add(&own);
+        tree.add_own_links(id, links);
+        for (path, stats, mtime) in files {
+            tree.count_type(id, &path, &stats);
+            if tree.wants_file(stats.total_bytes) {
+                tree.note_file(id, file_entry(stats, path, mtime));
+            }
+        }
+        for (stats, walk, child_dev) in children {
+            if fold {
+                // Walked as part of this directory rather than kept.
+                tree.stats_mut(id).add(&stats);
+                tree.stats_mut(id).folded = true;
+                counted.add(&stats);
+                if walk {
+                    queued.push((id, stats.path, child_dev, false, progress.cloned()));
+                }
+                continue;
+            }
+            let path = stats.path.clone();
+            let child = tree.push(id, stats);
+            let reused = walk && self.reuse(&mut tree, child);
+            let stats = tree.stats(child);
+            match progress {
+                // Below the scanned directory's children, everything adds
+                // to the totals of the one it's in.
+                Some(p) => {
+                    counted.add(stats);
+                    if walk {
+                        queued.push((child, path, child_dev, reused, Some(p.clone())));
+                    }
+                }
+                None if walk => {
+                    let p = Progress {
+                        totals: Mutex::new(DirStats {
+                            path: path.clone(),
+                            ..stats.clone()
+                        }),
+                        walked: AtomicU64::new(0),
+                        pending: AtomicUsize::new(1),
+                    };
+                    queued.push((child, path, child_dev, reused, Some(Arc::new(p))));
+                }
+                None => self.send_progress(&DirStats {
+                    path,
+                    ..stats.clone()
+                }),
+            }
+        }
+        if self.checkpoint.is_some() {
+            let mut unread = self.unread.lock().unwrap();
+            unread.extend(queued.iter().map(|(child, ..)| *child));
+        }
+        drop(tree);
+        self.counters.add(entries, &own);
+
+        if let Some(p) = progress {
+            p.pending.fetch_add(queued.len(), Ordering::AcqRel);
+            let mut totals = p.totals.lock().unwrap();
+            totals.add(&counted);
+            let before = p.walked.fetch_add(entries, Ordering::Relaxed);
+            if before / PROGRESS_BATCH != (before + entries) / PROGRESS_BATCH {
+                self.send_progress(&totals);
+            }
+        }
+        for (child, path, dev, reused, progress) in queued {
+            scope
+                .spawn(move |scope| self.dir(scope, child, path, dev, depth + 1, reused, progress));
+        }
+    }
+
+    /// Looks up at most `limit` of the files at `paths`, spread evenly, and
+    /// counts the rest in `own` as if they were like them. The ones looked
+    /// up go to `files`.
+    fn sample(
+        &self,
+        paths: Vec<PathBuf>,
+        limit: usize,
+        own: &mut DirStats,
+        files: &mut Vec<(PathBuf, DirStats, Option<SystemTime>)>,
+    ) {
+        let total = paths.len() as u64;
+        let step = paths.len().div_ceil(limit.max(1)).max(1);
+        let mut sampled = DirStats::new(PathBuf::new());
+        sampled.dir_count = 0;
+        let mut looked_up = 0;
+        for path in paths.into_iter().step_by(step) {
+            looked_up += 1;
+            self.throttle(1);
+            match self.vfs.file_meta(&path) {
+                Ok(meta) => {
+                    let stats = file_stats(&*self.vfs, &path, &meta, &self.opts, &self.seen);
+                    sampled.add(&stats);
+                    files.push((path, stats, meta.mtime));
+                }
+                Err(err) => {
+                    own.errors += 1;
+                    self.report(&path, err);
+                }
+            }
+        }
+        own.add(&sampled);
+        let seen = sampled.file_count;
+        let rest = total - looked_up;
+        self.counters.skipped.fetch_add(rest, Ordering::Relaxed);
+        if seen == 0 || rest == 0 {
+            return;
+        }
+        let scale = |bytes: u128| bytes * u128::from(rest) / u128::from(seen);
+        let mut others = DirStats::new(PathBuf::new());
+        others.dir_count = 0;
+        others.file_count = rest;
+        others.total_bytes = scale(sampled.total_bytes);
+        others.disk_bytes = scale(sampled.disk_bytes);
+        others.shared_bytes = scale(sampled.shared_bytes);
+        others.estimated = 1;
+        own.add(&others);
+    }
+
+    /// The entries of `dir` on device `dev`, like `Vfs::read`, or why not
+    /// if they don't come within `opts.dir_timeout`; see [`Readers`].
+    fn list(&self, dir: &Path, meta: bool, dev: Option<u64>) -> Result<Listing, String> {
+        match &self.readers {
+            Some(readers) => readers.read(dir, meta, dev),
+            None => Ok(self.vfs.read(dir, meta)),
+        }
+    }
+
+    /// The node for subdirectory `path` of a directory being read, whether
+    /// to walk it, and the device it's on. `link` says it's reached through
+    /// a link; `parent_dev` is the device of the directory being read and
+    /// `depth` its level below the scanned directory.
+    fn child(
+        &self,
+        path: PathBuf,
+        link: bool,
+        parent_dev: Option<u64>,
+        depth: usize,
+    ) -> (DirStats, bool, Option<u64>) {
+        if link && (!self.opts.follow_links || self.leads_into(&path)) {
+            let target = self.vfs.read_link(&path).ok();
+            return (link_stats(path, target), false, None);
+        }
+        let md = self.vfs.stat(&path).ok();
+        let dev = md.as_ref().and_then(|md| md.device);
+        if self.opts.one_file_system && md.is_some() && dev != self.root_dev {
+            let mut stats = DirStats::new(path);
+            stats.complete = true;
+            stats.other_fs = true;
+            return (stats, false, dev);
+        }
+        let mut stats = DirStats::new(path);
+        stats.mount = dev.is_some() && parent_dev.is_some() && dev != parent_dev;
+        stats.mtime = md.as_ref().and_then(|md| md.mtime);
+        stats.newest = stats.mtime;
+        if self.opts.max_depth.is_some_and(|max| depth > max) {
+            stats.complete = true;
+            stats.cut = true;
+            stats.estimated = 1;
+            return (stats, false, dev);
+        }
+        if self.opts.follow_links && !self.first_visit(&stats.path, md.as_ref()) {
+            let target = self.vfs.read_link(&stats.path).ok();
+            return (link_stats(stats.path, target), false, dev);
+        }
+        if link {
+            stats.target = self.vfs.read_link(&stats.path).ok();
+        }
+        (stats, true, dev)
+    }
+
+    /// Whether the directory at `path` hasn't been walked yet in this scan,
+    /// noting that it now has.
+    fn first_visit(&self, path: &Path, md: Option<&Stat>) -> bool {
+        let Some(md) = md else {
+            return true;
+        };
+        // Identity of the directory, to catch one reached through several
+        // links or a loop of them. Without inode numbers, the canonical
+        // path stands in for one.
+        let key = match (md.device, md.inode) {
+            (Some(dev), Some(ino)) => Some((dev, ino)),
+            _ => self.vfs.canonicalize(path).ok().map(|path| {
+                let mut hasher = DefaultHasher::new();
+                path.hash(&mut hasher);
+                (0, hasher.finish())
+            }),
+        };
+        key.is_none_or(|key| self.seen_dirs.lock().unwrap().insert(key))
+    }
+
+    /// Whether the link at `path` leads to the scanned directory, one above
+    /// it or one inside it. Following it would walk those again; the ones
+    /// inside are counted under their own paths.
+    fn leads_into(&self, path: &Path) -> bool {
+        self.vfs
+            .canonicalize(path)
+            .is_ok_and(|target| self.outer.starts_with(&target) || target.starts_with(&self.outer))
+    }
+
+    /// Takes the files of directory `id` over from the previous scan if
+    /// they're unchanged since, returning whether it did.
+    fn reuse(&self, tree: &mut DirTree, id: NodeId) -> bool {
+        let Some(p) = &self.previous else {
+            return false;
+        };
+        let path = tree.path(id);
+        let Some(mut own) = p.unchanged_files(&path, tree.stats(id).mtime) else {
+            return false;
+        };
+        // Other links to the files it carries the size of are met
+        // elsewhere in this scan, before or after getting here.
+        let mut links = p.links_in(&path);
+        if self.opts.dedup_hardlinks {
+            let mut seen = self.seen.lock().unwrap();
+            links.retain(|link| {
+                let first = seen.insert(link.key);
+                if !first {
+                    own.total_bytes = own.total_bytes.saturating_sub(link.bytes);
+                    own.disk_bytes = own.disk_bytes.saturating_sub(link.disk_bytes);
+                    own.shared_bytes = own.shared_bytes.saturating_sub(link.bytes);
+                }
+                first
+            });
+        }
+        tree.add_own_links(id, links);
+        tree.stats_mut(id).add(&own);
+        self.counters.add(0, &own);
+        tree.set_own_types(id, p.types_in(&path));
+        tree.set_own_owners(id, p.owners_in(&path));
+        for file in p.largest_in(&path) {
+            tree.note_file(id, file.clone());
+        }
+        true
+    }
+
+    /// Holds the calling thread until `ops` more operations fit the I/O
+    /// limit, if there's one.
+    fn throttle(&self, ops: u64) {
+        if let Some(throttle) = &self.throttle {
+            throttle.take(ops, &self.cancel);
+        }
+    }
+
+    /// Stops keeping directories below `depth`, where the calling thread
+    /// is, the first time the process is found past the memory budget.
+    fn check_memory(&self, depth: usize) {
+        let Some(budget) = self.opts.memory_budget else {
+            return;
+        };
+        if self.over_budget.load(Ordering::Relaxed) {
+            return;
+        }
+        match self.memory_checked.try_lock() {
+            Ok(mut last) if last.elapsed() >= MEMORY_CHECK => *last = Instant::now(),
+            _ => return,
+        }
+        if resident_bytes().is_none_or(|used| used <= budget)
+            || self.over_budget.swap(true, Ordering::Relaxed)
+        {
+            return;
+        }
+        let depth = depth.max(1);
+        if self.keep.fetch_min(depth, Ordering::Relaxed) > depth {
+            (self.on_event)(ScanEvent::Warning(format!(
+                "Past the memory budget: directories more than {depth} levels down are only counted into the ones above"
+            )));
+        }
+    }
+
+    /// Hands over the tree so far if the next checkpoint is due. The
+    /// directories still to read lose their mtime, so a scan resuming from
+    /// it reads them rather than taking over files they don't have yet.
+    fn maybe_checkpoint(&self) {
+        let Some((every, last)) = &self.checkpoint else {
+            return;
+        };
+        {
+            let mut last = last.lock().unwrap();
+            if last.elapsed() < *every {
+                return;
+            }
+            *last = Instant::now();
+        }
+        let tree = self.tree.lock().unwrap();
+        let mut snapshot = tree.clone();
+        for &id in self.unread.lock().unwrap().iter() {
+            snapshot.stats_mut(id).mtime = None;
+        }
+        drop(tree);
+        snapshot.finish();
+        (self.on_event)(ScanEvent::Checkpoint(snapshot));
+    }
+
+    /// Passes the first `MAX_REPORTED_ERRORS` unreadable entries on to
+    /// the caller.
+    fn report(&self, path: &Path, err: impl std::fmt::Display) {
+        if self.unreadable.fetch_add(1, Ordering::Relaxed) < MAX_REPORTED_ERRORS {
+            let msg = format!("{}: {err}", path.display());
+            (self.on_event)(ScanEvent::Unreadable(msg));
+        }
+    }
+
+    fn send_progress(&self, stats: &DirStats) {
+        (self.on_event)(ScanEvent::Progress(stats.clone()));
+    }
+}
+
+impl Scanner {
+    /// A scan of `root` with nothing to reuse, skip or share.
+    pub fn new(root: impl Into<PathBuf>, opts: ScanOptions) -> Self {
+        Self {
+            root: root.into(),
+            opts,
+            skip: None,
+            previous: None,
+            cancel: Arc::default(),
+            counters: Arc::default(),
+            pause: Arc::default(),
+            vfs: vfs::local(),
+            checkpoint: None,
+        }
+    }
+
+    /// Scans `root` into a fresh tree on the calling thread, handing
+    /// [`ScanEvent`]s to `on_event` as they come up, among them the totals
+    /// of the immediate subdirectories as they come in. Directories are
+    /// read in parallel at every depth, on `opts.threads` threads, unless
+    /// `opts.mft` has the whole tree read from the volume's file table
+    /// instead. `None` if the scan was cancelled or its threads couldn't
+    /// start, which is reported as a warning.
+    pub fn run(self, on_event: impl Fn(ScanEvent) + Sync) -> Option<DirTree> {
+        let Scanner {
+            root: target,
+            opts,
+            skip,
+            previous,
+            cancel,
+            counters,
+            pause,
+            vfs,
+            checkpoint,
+        } = self;
+        let (excludes, problems) = build_excludes(&target, &opts);
+        for problem in problems {
+            on_event(ScanEvent::Warning(problem));
+        }
+        // Followed links may lead off the volume, so those scans walk.
+        if opts.mft && !opts.follow_links {
+            match read_mft(&target, &opts, &excludes, skip.as_deref(), &cancel) {
+                Ok(tree) => return Some(tree),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => return None,
+                Err(e) => {
+                    let msg = format!("Walking instead of reading the MFT: {e}");
+                    on_event(ScanEvent::Warning(msg));
+                }
+            }
+        }
+        let low_priority = opts.low_priority;
+        let pool = match ThreadPoolBuilder::new()
+            .num_threads(opts.threads)
+            .thread_name(|i| format!("scan-{i}"))
+            .start_handler(move |_| {
+                if low_priority {
+                    lower_priority();
+                }
+            })
+            .build()
+        {
+            Ok(pool) => pool,
+            Err(e) => {
+                let msg = format!("Couldn't start the scan threads: {e}");
+                on_event(ScanEvent::Warning(msg));
+                return None;
+            }
+        };
+
+        let md = vfs.stat(&target).ok();
+        let mut tree = DirTree::new(target.clone());
+        let root = tree.root();
+        let mtime = md.as_ref().and_then(|md| md.mtime);
+        tree.stats_mut(root).mtime = mtime;
+        tree.stats_mut(root).newest = mtime;
+        let readers = opts
+            .dir_timeout
+            .map(|secs| Readers::new(&vfs, pool.current_num_threads(), Duration::from_secs(secs)));
+        let walk = Walk {
+            excludes,
+            skip,
+            previous: previous.as_ref().map(Previous::new),
+            cancel,
+            on_event: &on_event,
+            unreadable: AtomicUsize::new(0),
+            outer: vfs.canonicalize(&target).unwrap_or_else(|_| target.clone()),
+            root_dev: md.as_ref().and_then(|md| md.device),
+            seen: SeenInodes::default(),
+            seen_dirs: SeenInodes::default(),
+            vfs,
+            counters,
+            pause,
+            tree: Mutex::new(tree),
+            checkpoint: checkpoint.map(|every| (every, Mutex::new(Instant::now()))),
+            unread: Mutex::default(),
+            keep: AtomicUsize::new(opts.keep_depth.map_or(usize::MAX, |keep| keep.max(1))),
+            throttle: opts.io_limit.map(Throttle::new),
+            readers,
+            memory_checked: Mutex::new(Instant::now()),
+            over_budget: AtomicBool::new(false),
+            opts,
+        };
+        if walk.opts.follow_links {
+            walk.first_visit(&target, md.as_ref());
+        }
+        let reused = walk.reuse(&mut walk.tree.lock().unwrap(), root);
+        let dev = walk.root_dev;
+        pool.scope(|scope| walk.dir(scope, root, target.clone(), dev, 0, reused, None));
+
+        if walk.cancel.load(Ordering::Relaxed) {
+            return None;
+        }
+        let unreported = walk
+            .unreadable
+            .load(Ordering::Relaxed)
+            .saturating_sub(MAX_REPORTED_ERRORS);
+        if unreported > 0 {
+            let msg = format!("…and {unreported} more unreadable entries");
+            on_event(ScanEvent::Unreadable(msg));
+        }
+        let mut tree = walk.tree.into_inner().unwrap();
+        tree.finish();
+        Some(tree)
+    }
+}
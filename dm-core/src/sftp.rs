@@ -0,0 +1,553 @@
+//! Reading another machine's filesystem over SFTP, through the `ssh`
+//! command: nothing has to be installed there, and the user's SSH config,
+//! keys and agent apply as for any other connection. Much slower than a
+//! local scan, as every directory is a round trip, though the scan's
+//! threads keep several of them in flight.
+//!
+//! Only as much of version 3 of the protocol as listing and deleting need
+//! is spoken.
+//! It has no block counts, inodes or devices, so disk usage is taken to be
+//! the apparent size, hard links are counted every time, and the scan
+//! can't tell filesystems apart.
+
+use std::{
+    collections::HashMap,
+    io::{self, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    meta::{Entry, FileMeta, Kind},
+    vfs::{Stat, Vfs},
+};
+
+// Packet types.
+const FXP_INIT: u8 = 1;
+const FXP_VERSION: u8 = 2;
+const FXP_CLOSE: u8 = 4;
+const FXP_LSTAT: u8 = 7;
+const FXP_OPENDIR: u8 = 11;
+const FXP_READDIR: u8 = 12;
+const FXP_REMOVE: u8 = 13;
+const FXP_RMDIR: u8 = 15;
+const FXP_REALPATH: u8 = 16;
+const FXP_STAT: u8 = 17;
+const FXP_READLINK: u8 = 19;
+const FXP_STATUS: u8 = 101;
+const FXP_HANDLE: u8 = 102;
+const FXP_NAME: u8 = 104;
+const FXP_ATTRS: u8 = 105;
+
+// Status codes.
+const FX_OK: u32 = 0;
+const FX_EOF: u32 = 1;
+const FX_NO_SUCH_FILE: u32 = 2;
+const FX_PERMISSION_DENIED: u32 = 3;
+
+// Which attributes are present.
+const ATTR_SIZE: u32 = 0x1;
+const ATTR_UIDGID: u32 = 0x2;
+const ATTR_PERMISSIONS: u32 = 0x4;
+const ATTR_ACMODTIME: u32 = 0x8;
+const ATTR_EXTENDED: u32 = 0x8000_0000;
+
+/// Largest packet taken from the server; anything bigger means the stream
+/// is out of step.
+const MAX_PACKET: u32 = 1 << 24;
+
+/// Where `sftp://[user@]host[:port]/path` points.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    /// What to hand `ssh`: `host` or `user@host`.
+    pub dest: String,
+    pub port: Option<u16>,
+    /// The path on the server; the login directory when empty.
+    pub path: PathBuf,
+}
+
+impl Url {
+    /// Parses `text` if it's an `sftp://` URL.
+    pub fn parse(text: &str) -> Option<Self> {
+        let rest = text.strip_prefix("sftp://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, ""),
+        };
+        let (dest, port) = match authority.rsplit_once(':') {
+            Some((dest, port)) => (dest, Some(port.parse().ok()?)),
+            None => (authority, None),
+        };
+        if dest.is_empty() || dest.ends_with('@') {
+            return None;
+        }
+        Some(Self {
+            dest: dest.to_string(),
+            port,
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+/// A reply, minus its request id.
+struct Packet {
+    kind: u8,
+    body: Vec<u8>,
+}
+
+/// An SFTP session over `ssh`.
+pub struct Sftp {
+    host: String,
+    child: Mutex<Child>,
+    stdin: Mutex<ChildStdin>,
+    /// Requests waiting for their replies, by id.
+    pending: Arc<Mutex<HashMap<u32, mpsc::Sender<Packet>>>>,
+    closed: Arc<AtomicBool>,
+    next_id: AtomicU32,
+    /// What `ssh` said, for when it goes away.
+    stderr: Arc<Mutex<String>>,
+}
+
+impl Sftp {
+    /// Starts `ssh` for `url` and the SFTP session in it. Any password or
+    /// host key question is asked on the terminal.
+    pub fn connect(url: &Url) -> io::Result<Arc<Self>> {
+        let mut cmd = Command::new("ssh");
+        if let Some(port) = url.port {
+            cmd.arg("-p").arg(port.to_string());
+        }
+        // `--` so a host like `-oProxyCommand=...` isn't taken as an option.
+        let mut child = cmd
+            .arg("-s")
+            .arg("--")
+            .arg(&url.dest)
+            .arg("sftp")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot run ssh: {e}")))?;
+        let mut stdin = child.stdin.take().expect("piped");
+        let mut stdout = BufReader::new(child.stdout.take().expect("piped"));
+        let mut errors = child.stderr.take().expect("piped");
+        let stderr = Arc::new(Mutex::new(String::new()));
+        let collector = {
+            let stderr = stderr.clone();
+            thread::spawn(move || {
+                let mut text = String::new();
+                let _ = errors.read_to_string(&mut text);
+                stderr.lock().unwrap().push_str(&text);
+            })
+        };
+
+        let mut init = Vec::new();
+        put_u32(&mut init, 3);
+        let hello =
+            send_packet(&mut stdin, FXP_INIT, &init).and_then(|()| read_packet(&mut stdout));
+        let failed = match hello {
+            Ok((FXP_VERSION, _)) => None,
+            Ok(_) => Some(bad_reply()),
+            Err(e) => Some(e),
+        };
+        if let Some(e) = failed {
+            let _ = child.kill();
+            let _ = child.wait();
+            let _ = collector.join();
+            let said = last_line(&stderr.lock().unwrap());
+            return Err(io::Error::new(
+                e.kind(),
+                format!(
+                    "no SFTP session with {}: {}",
+                    url.dest,
+                    said.unwrap_or(e.to_string())
+                ),
+            ));
+        }
+
+        let sftp = Arc::new(Self {
+            host: url.dest.clone(),
+            child: Mutex::new(child),
+            stdin: Mutex::new(stdin),
+            pending: Arc::default(),
+            closed: Arc::default(),
+            next_id: AtomicU32::new(1),
+            stderr,
+        });
+        let (pending, closed) = (sftp.pending.clone(), sftp.closed.clone());
+        thread::spawn(move || dispatch(stdout, &pending, &closed));
+        Ok(sftp)
+    }
+
+    /// Sends a request and waits for its reply.
+    fn request(&self, kind: u8, payload: &[u8]) -> io::Result<Packet> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+        if self.closed.load(Ordering::Acquire) {
+            return Err(self.gone());
+        }
+        let mut body = Vec::with_capacity(payload.len() + 4);
+        put_u32(&mut body, id);
+        body.extend_from_slice(payload);
+        send_packet(&mut *self.stdin.lock().unwrap(), kind, &body)?;
+        rx.recv_timeout(Duration::from_secs(120))
+            .map_err(|_| self.gone())
+    }
+
+    /// The error for a session that ended, with what `ssh` had to say.
+    fn gone(&self) -> io::Error {
+        let said = last_line(&self.stderr.lock().unwrap());
+        io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            match said {
+                Some(said) => format!("SFTP session with {} ended: {said}", self.host),
+                None => format!("SFTP session with {} ended", self.host),
+            },
+        )
+    }
+
+    fn path_request(&self, kind: u8, path: &Path) -> io::Result<Packet> {
+        let mut payload = Vec::new();
+        put_bytes(&mut payload, &path_bytes(path));
+        self.request(kind, &payload)
+    }
+
+    fn attrs(&self, kind: u8, path: &Path) -> io::Result<Attrs> {
+        let reply = self.path_request(kind, path)?;
+        match reply.kind {
+            FXP_ATTRS => Attrs::parse(&mut Cursor(&reply.body)),
+            _ => Err(status_error(&reply)),
+        }
+    }
+
+    /// The single name a READLINK or REALPATH comes back with.
+    fn one_name(&self, kind: u8, path: &Path) -> io::Result<PathBuf> {
+        let reply = self.path_request(kind, path)?;
+        if reply.kind != FXP_NAME {
+            return Err(status_error(&reply));
+        }
+        let mut body = Cursor(&reply.body);
+        if body.u32()? == 0 {
+            return Err(bad_reply());
+        }
+        Ok(path_from(body.bytes()?))
+    }
+}
+
+impl Drop for Sftp {
+    fn drop(&mut self) {
+        let child = self.child.get_mut().unwrap();
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+impl Vfs for Sftp {
+    fn read(&self, dir: &Path, meta: bool) -> io::Result<Vec<io::Result<Entry>>> {
+        let reply = self.path_request(FXP_OPENDIR, dir)?;
+        if reply.kind != FXP_HANDLE {
+            return Err(status_error(&reply));
+        }
+        let mut handle = Vec::new();
+        put_bytes(&mut handle, Cursor(&reply.body).bytes()?);
+
+        let mut entries = Vec::new();
+        let listed = loop {
+            let reply = match self.request(FXP_READDIR, &handle) {
+                Ok(reply) => reply,
+                Err(e) => break Err(e),
+            };
+            if reply.kind != FXP_NAME {
+                break match status_of(&reply) {
+                    Some((FX_EOF, _)) => Ok(()),
+                    _ => Err(status_error(&reply)),
+                };
+            }
+            let mut body = Cursor(&reply.body);
+            let parsed = (|| {
+                for _ in 0..body.u32()? {
+                    let name = body.bytes()?.to_vec();
+                    body.bytes()?; // the `ls -l` line
+                    let attrs = Attrs::parse(&mut body)?;
+                    if name == b"." || name == b".." {
+                        continue;
+                    }
+                    let kind = attrs.kind();
+                    entries.push(Ok(Entry {
+                        path: dir.join(path_from(&name)),
+                        kind,
+                        meta: (meta && kind == Kind::File).then(|| Ok(attrs.file_meta())),
+                    }));
+                }
+                Ok(())
+            })();
+            if let Err(e) = parsed {
+                break Err(e);
+            }
+        };
+        let _ = self.request(FXP_CLOSE, &handle);
+        listed.map(|()| entries)
+    }
+
+    fn stat(&self, path: &Path) -> io::Result<Stat> {
+        let attrs = self.attrs(FXP_STAT, path)?;
+        Ok(Stat {
+            kind: attrs.kind(),
+            mtime: attrs.mtime(),
+            device: None,
+            inode: None,
+        })
+    }
+
+    fn file_meta(&self, path: &Path) -> io::Result<FileMeta> {
+        Ok(self.attrs(FXP_LSTAT, path)?.file_meta())
+    }
+
+    fn kind(&self, path: &Path) -> io::Result<Kind> {
+        Ok(self.attrs(FXP_LSTAT, path)?.kind())
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        self.one_name(FXP_READLINK, path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        // An empty path is the login directory.
+        let path = if path.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            path
+        };
+        self.one_name(FXP_REALPATH, path)
+    }
+
+    fn remove(&self, path: &Path, dir: bool) -> io::Result<()> {
+        let reply = self.path_request(if dir { FXP_RMDIR } else { FXP_REMOVE }, path)?;
+        match status_of(&reply) {
+            Some((FX_OK, _)) => Ok(()),
+            _ => Err(status_error(&reply)),
+        }
+    }
+
+    fn host(&self) -> Option<&str> {
+        Some(&self.host)
+    }
+}
+
+/// Hands the replies coming from the server to the requests waiting for
+/// them, until the session ends. Then the requests still waiting, and any
+/// made later, fail.
+fn dispatch(
+    mut stdout: BufReader<ChildStdout>,
+    pending: &Mutex<HashMap<u32, mpsc::Sender<Packet>>>,
+    closed: &AtomicBool,
+) {
+    while let Ok((kind, body)) = read_packet(&mut stdout) {
+        let Some(id) = body
+            .get(..4)
+            .map(|id| u32::from_be_bytes(id.try_into().unwrap()))
+        else {
+            break;
+        };
+        if let Some(tx) = pending.lock().unwrap().remove(&id) {
+            let _ = tx.send(Packet {
+                kind,
+                body: body[4..].to_vec(),
+            });
+        }
+    }
+    closed.store(true, Ordering::Release);
+    pending.lock().unwrap().clear();
+}
+
+/// The attributes that come with a name or a STAT.
+#[derive(Debug, Default)]
+struct Attrs {
+    size: Option<u64>,
+    permissions: Option<u32>,
+    mtime: Option<u32>,
+}
+
+impl Attrs {
+    fn parse(body: &mut Cursor) -> io::Result<Self> {
+        let flags = body.u32()?;
+        let mut attrs = Attrs::default();
+        if flags & ATTR_SIZE != 0 {
+            attrs.size = Some(body.u64()?);
+        }
+        if flags & ATTR_UIDGID != 0 {
+            // The other machine's users, meaningless here.
+            body.u32()?;
+            body.u32()?;
+        }
+        if flags & ATTR_PERMISSIONS != 0 {
+            attrs.permissions = Some(body.u32()?);
+        }
+        if flags & ATTR_ACMODTIME != 0 {
+            body.u32()?;
+            attrs.mtime = Some(body.u32()?);
+        }
+        if flags & ATTR_EXTENDED != 0 {
+            for _ in 0..body.u32()? {
+                body.bytes()?;
+                body.bytes()?;
+            }
+        }
+        Ok(attrs)
+    }
+
+    fn kind(&self) -> Kind {
+        match self.permissions.map(|p| p & 0o170000) {
+            Some(0o100000) => Kind::File,
+            Some(0o040000) => Kind::Dir,
+            Some(0o120000) => Kind::Symlink,
+            _ => Kind::Other,
+        }
+    }
+
+    fn mtime(&self) -> Option<SystemTime> {
+        self.mtime
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs.into()))
+    }
+
+    fn file_meta(&self) -> FileMeta {
+        let len = self.size.unwrap_or(0);
+        FileMeta {
+            len,
+            allocated: len,
+            mtime: self.mtime(),
+            hardlink: None,
+            owner: None,
+            placeholder: false,
+            clone: None,
+        }
+    }
+}
+
+/// Reads the fields of a packet in order.
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(bad_reply());
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+}
+
+fn put_u32(out: &mut Vec<u8>, n: u32) {
+    out.extend_from_slice(&n.to_be_bytes());
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_u32(out, bytes.len() as u32);
+    out.extend_from_slice(bytes);
+}
+
+fn send_packet(out: &mut impl Write, kind: u8, body: &[u8]) -> io::Result<()> {
+    let mut packet = Vec::with_capacity(body.len() + 5);
+    put_u32(&mut packet, body.len() as u32 + 1);
+    packet.push(kind);
+    packet.extend_from_slice(body);
+    out.write_all(&packet)?;
+    out.flush()
+}
+
+fn read_packet(input: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+    let mut len = [0; 4];
+    input.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len);
+    if len == 0 || len > MAX_PACKET {
+        return Err(bad_reply());
+    }
+    let mut packet = vec![0; len as usize];
+    input.read_exact(&mut packet)?;
+    let body = packet.split_off(1);
+    Ok((packet[0], body))
+}
+
+/// The code and message of a STATUS reply.
+fn status_of(reply: &Packet) -> Option<(u32, String)> {
+    if reply.kind != FXP_STATUS {
+        return None;
+    }
+    let mut body = Cursor(&reply.body);
+    let code = body.u32().ok()?;
+    let message = body.bytes().map_or_else(
+        |_| String::new(),
+        |m| String::from_utf8_lossy(m).into_owned(),
+    );
+    Some((code, message))
+}
+
+/// A reply that isn't the one hoped for as an error.
+fn status_error(reply: &Packet) -> io::Error {
+    let Some((code, message)) = status_of(reply) else {
+        return bad_reply();
+    };
+    let kind = match code {
+        FX_NO_SUCH_FILE => io::ErrorKind::NotFound,
+        FX_PERMISSION_DENIED => io::ErrorKind::PermissionDenied,
+        _ => io::ErrorKind::Other,
+    };
+    if message.is_empty() {
+        io::Error::new(kind, format!("SFTP error {code}"))
+    } else {
+        io::Error::new(kind, message)
+    }
+}
+
+fn bad_reply() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "garbled SFTP reply")
+}
+
+fn last_line(text: &str) -> Option<String> {
+    text.lines()
+        .rev()
+        .find(|l| !l.trim().is_empty())
+        .map(|l| l.trim().to_string())
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().replace('\\', "/").into_bytes()
+}
+
+#[cfg(unix)]
+fn path_from(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
@@ -0,0 +1,948 @@
+//! In-memory model of a scanned directory hierarchy.
+//!
+//! Nodes live in a flat arena and refer to each other by index, so a whole
+//! scan can be navigated without touching the disk again. Every node carries
+//! the totals of its entire subtree; mutations keep the ancestors in sync.
+//! Nodes keep only their own name, not their whole path: see
+//! [`DirTree::path`].
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+
+pub type NodeId = usize;
+
+/// How many of the biggest files a tree remembers.
+pub const LARGEST_FILES: usize = 1_000;
+
+/// Apparent size is what `ls -l` reports; disk usage is the space actually
+/// allocated (sparse files shrink, small files round up to a block).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum SizeMode {
+    Apparent,
+    Disk,
+}
+
+impl SizeMode {
+    pub fn toggled(self) -> Self {
+        match self {
+            SizeMode::Apparent => SizeMode::Disk,
+            SizeMode::Disk => SizeMode::Apparent,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SizeMode::Apparent => "apparent size",
+            SizeMode::Disk => "disk usage",
+        }
+    }
+}
+
+/// Totals of a directory's whole subtree, or of a single file where files
+/// are listed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirStats {
+    /// Empty for the nodes of a [`DirTree`], which keep only their name.
+    #[serde(default, skip_serializing_if = "is_empty")]
+    pub path: PathBuf,
+    pub total_bytes: u128,
+    pub disk_bytes: u128,
+    pub shared_bytes: u128, // apparent bytes in files with more than one hard link
+    /// Apparent bytes in extents files share with others, like reflinked
+    /// copies and snapshots, so deleting them frees less. APFS tells with
+    /// every scan; elsewhere they're only looked up when scanning with
+    /// [`crate::ScanOptions::reflinks`].
+    #[serde(default)]
+    pub reflinked_bytes: u128,
+    pub file_count: u64,
+    pub dir_count: u64,
+    #[serde(default)]
+    pub errors: u64, // entries that couldn't be read, so totals are a lower bound
+    #[serde(default)]
+    pub estimated: u64, // directories sampled or left unwalked, so totals are rough
+    #[serde(default)]
+    pub placeholders: u64, // cloud files whose contents are kept online, not all here
+    // last_scanned: Instant,
+    pub complete: bool, // false while the walk of this directory is still running
+    pub other_fs: bool, // mount point left unscanned because of --one-file-system
+    #[serde(default)]
+    pub mount: bool, // on another device than its parent, walked into anyway
+    #[serde(default)]
+    pub link: bool, // symlink or junction to a directory, left unscanned
+    #[serde(default)]
+    pub cut: bool, // left unwalked below the depth limit
+    #[serde(default)]
+    pub timed_out: bool, // didn't list within the directory timeout, so left unwalked
+    #[serde(default)]
+    pub folded: bool, // subdirectories walked but counted into it, not kept
+    /// Where a symlink or junction leads, for links and followed links.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<PathBuf>,
+    #[serde(skip)]
+    pub stale: bool, // loaded from the cache and not rescanned yet
+    #[serde(default)]
+    pub mtime: Option<SystemTime>, // of the directory itself, for incremental rescans
+    /// Latest mtime of anything in the subtree. Deletions don't lower it
+    /// until the next scan.
+    #[serde(default)]
+    pub newest: Option<SystemTime>,
+    /// User and group id of a file, where the platform has them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<(u32, u32)>,
+}
+
+impl DirStats {
+    /// Stats for a directory that has not been walked yet; it counts itself.
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            total_bytes: 0,
+            disk_bytes: 0,
+            shared_bytes: 0,
+            reflinked_bytes: 0,
+            file_count: 0,
+            dir_count: 1,
+            errors: 0,
+            estimated: 0,
+            placeholders: 0,
+            complete: false,
+            other_fs: false,
+            mount: false,
+            link: false,
+            cut: false,
+            timed_out: false,
+            folded: false,
+            target: None,
+            stale: false,
+            mtime: None,
+            newest: None,
+            owner: None,
+        }
+    }
+
+    pub fn bytes(&self, mode: SizeMode) -> u128 {
+        match mode {
+            SizeMode::Apparent => self.total_bytes,
+            SizeMode::Disk => self.disk_bytes,
+        }
+    }
+
+    /// Adds the counters of `other` (but not its flags) to `self`.
+    pub fn add(&mut self, other: &DirStats) {
+        self.newest = self.newest.max(other.newest);
+        self.total_bytes = self.total_bytes.saturating_add(other.total_bytes);
+        self.disk_bytes = self.disk_bytes.saturating_add(other.disk_bytes);
+        self.shared_bytes = self.shared_bytes.saturating_add(other.shared_bytes);
+        self.reflinked_bytes = self.reflinked_bytes.saturating_add(other.reflinked_bytes);
+        self.file_count = self.file_count.saturating_add(other.file_count);
+        self.dir_count = self.dir_count.saturating_add(other.dir_count);
+        self.errors = self.errors.saturating_add(other.errors);
+        self.estimated = self.estimated.saturating_add(other.estimated);
+        self.placeholders = self.placeholders.saturating_add(other.placeholders);
+    }
+
+    /// Inverse of [`DirStats::add`], except for `newest`.
+    pub fn sub(&mut self, other: &DirStats) {
+        self.total_bytes = self.total_bytes.saturating_sub(other.total_bytes);
+        self.disk_bytes = self.disk_bytes.saturating_sub(other.disk_bytes);
+        self.shared_bytes = self.shared_bytes.saturating_sub(other.shared_bytes);
+        self.reflinked_bytes = self.reflinked_bytes.saturating_sub(other.reflinked_bytes);
+        self.file_count = self.file_count.saturating_sub(other.file_count);
+        self.dir_count = self.dir_count.saturating_sub(other.dir_count);
+        self.errors = self.errors.saturating_sub(other.errors);
+        self.estimated = self.estimated.saturating_sub(other.estimated);
+        self.placeholders = self.placeholders.saturating_sub(other.placeholders);
+    }
+}
+
+fn is_empty(path: &Path) -> bool {
+    path.as_os_str().is_empty()
+}
+
+/// What the files of one type add up to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TypeTotals {
+    pub total_bytes: u128,
+    pub disk_bytes: u128,
+    pub file_count: u64,
+}
+
+impl TypeTotals {
+    pub fn bytes(&self, mode: SizeMode) -> u128 {
+        match mode {
+            SizeMode::Apparent => self.total_bytes,
+            SizeMode::Disk => self.disk_bytes,
+        }
+    }
+
+    pub fn add(&mut self, other: &TypeTotals) {
+        self.total_bytes = self.total_bytes.saturating_add(other.total_bytes);
+        self.disk_bytes = self.disk_bytes.saturating_add(other.disk_bytes);
+        self.file_count = self.file_count.saturating_add(other.file_count);
+    }
+
+    pub fn sub(&mut self, other: &TypeTotals) {
+        self.total_bytes = self.total_bytes.saturating_sub(other.total_bytes);
+        self.disk_bytes = self.disk_bytes.saturating_sub(other.disk_bytes);
+        self.file_count = self.file_count.saturating_sub(other.file_count);
+    }
+}
+
+impl From<&DirStats> for TypeTotals {
+    fn from(s: &DirStats) -> Self {
+        Self {
+            total_bytes: s.total_bytes,
+            disk_bytes: s.disk_bytes,
+            file_count: s.file_count,
+        }
+    }
+}
+
+/// Totals by lowercase file extension, "" for files without one.
+pub type TypeMap = HashMap<Box<str>, TypeTotals>;
+
+/// Totals by user and group id.
+pub type OwnerMap = HashMap<(u32, u32), TypeTotals>;
+
+/// A file with more than one hard link whose size a directory carries, so
+/// a rescan taking the directory over knows not to count it again.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct HardLink {
+    pub key: (u64, u64), // (device, inode)
+    pub bytes: u128,
+    pub disk_bytes: u128,
+}
+
+/// The key `path` is counted under in a [`TypeMap`].
+pub fn extension_of(path: &Path) -> Box<str> {
+    path.extension()
+        .map_or_else(String::new, |e| e.to_string_lossy().to_lowercase())
+        .into()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Node {
+    /// The last component of the path, or all of it for the root.
+    #[serde(default)]
+    name: PathBuf,
+    stats: DirStats,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    /// Only the files directly inside, unlike `stats`; see
+    /// [`DirTree::types_below`].
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    types: TypeMap,
+    /// Owners of the files directly inside, like `types`.
+    #[serde(
+        default,
+        skip_serializing_if = "HashMap::is_empty",
+        with = "owner_pairs"
+    )]
+    owners: OwnerMap,
+    /// Hard-linked files directly inside whose size is counted here.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    links: Vec<HardLink>,
+}
+
+/// JSON only takes strings as object keys, so an [`OwnerMap`] is stored
+/// as a list of `[[uid, gid], totals]` pairs instead.
+mod owner_pairs {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::{OwnerMap, TypeTotals};
+
+    pub fn serialize<S: Serializer>(owners: &OwnerMap, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(owners)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<OwnerMap, D::Error> {
+        let pairs = Vec::<((u32, u32), TypeTotals)>::deserialize(d)?;
+        Ok(pairs.into_iter().collect())
+    }
+}
+
+/// One of [`DirTree::largest_files`], kept by its directory and name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LargeFile {
+    dir: NodeId,
+    stats: DirStats, // `path` is only the name
+}
+
+/// A directory hierarchy rooted at node 0. Detached nodes stay in the arena
+/// until [`DirTree::reclaim`] finds them taking up as much of it as the
+/// rest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirTree {
+    nodes: Vec<Node>,
+    /// The biggest files anywhere in the tree by apparent size, biggest
+    /// first. Files are not nodes; these are the only ones kept.
+    #[serde(default)]
+    largest: Vec<LargeFile>,
+    /// Nodes detached since the arena was last compacted.
+    #[serde(skip)]
+    detached: usize,
+}
+
+impl DirTree {
+    /// A tree of just `root`, not walked yet.
+    pub fn new(root: PathBuf) -> Self {
+        Self::from_root(DirStats::new(root))
+    }
+
+    /// A tree of just a root with the given stats.
+    pub fn from_root(mut stats: DirStats) -> Self {
+        Self {
+            nodes: vec![Node {
+                name: std::mem::take(&mut stats.path),
+                stats,
+                parent: None,
+                children: Vec::new(),
+                types: TypeMap::new(),
+                owners: OwnerMap::new(),
+                links: Vec::new(),
+            }],
+            largest: Vec::new(),
+            detached: 0,
+        }
+    }
+
+    /// The scanned directory; every other node is below it.
+    pub fn root(&self) -> NodeId {
+        0
+    }
+
+    pub fn root_path(&self) -> &Path {
+        &self.nodes[0].name
+    }
+
+    /// The full path of `id`, put together from the names up to the root.
+    pub fn path(&self, id: NodeId) -> PathBuf {
+        let mut names = Vec::new();
+        let mut cur = Some(id);
+        while let Some(id) = cur {
+            names.push(&self.nodes[id].name);
+            cur = self.nodes[id].parent;
+        }
+        names.into_iter().rev().collect()
+    }
+
+    /// The last component of the path of `id`; the whole path for the
+    /// root.
+    pub fn name(&self, id: NodeId) -> &Path {
+        &self.nodes[id].name
+    }
+
+    /// Totals of the whole subtree at `id`. Their `path` is empty; see
+    /// [`DirTree::path`].
+    pub fn stats(&self, id: NodeId) -> &DirStats {
+        &self.nodes[id].stats
+    }
+
+    /// The directory `id` is in; `None` for the root.
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id].parent
+    }
+
+    /// Subdirectories of `id`, in the order they were read. Files aren't
+    /// nodes; see [`DirTree::largest_files`].
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        &self.nodes[id].children
+    }
+
+    /// Number of nodes in the arena, detached ones included.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// What the files directly inside `id` add up to, without its
+    /// subdirectories.
+    pub fn own_stats(&self, id: NodeId) -> DirStats {
+        let mut own = self.nodes[id].stats.clone();
+        for &c in &self.nodes[id].children {
+            own.sub(&self.nodes[c].stats);
+        }
+        own
+    }
+
+    /// Counts the file at `path` among the types and owners of `id`, which
+    /// must be its directory. Totals are not touched.
+    pub fn count_type(&mut self, id: NodeId, path: &Path, file: &DirStats) {
+        let totals = self.nodes[id].types.entry(extension_of(path)).or_default();
+        totals.add(&file.into());
+        if let Some(owner) = file.owner {
+            self.nodes[id]
+                .owners
+                .entry(owner)
+                .or_default()
+                .add(&file.into());
+        }
+    }
+
+    /// Inverse of [`DirTree::count_type`], for a file removed outside of a
+    /// scan.
+    pub fn uncount_type(&mut self, id: NodeId, file: &DirStats) {
+        let ext = extension_of(&file.path);
+        if let Some(totals) = self.nodes[id].types.get_mut(&ext) {
+            totals.sub(&file.into());
+            if totals.file_count == 0 {
+                self.nodes[id].types.remove(&ext);
+            }
+        }
+        let owners = &mut self.nodes[id].owners;
+        if let Some(totals) = file.owner.and_then(|o| owners.get_mut(&o)) {
+            totals.sub(&file.into());
+            if totals.file_count == 0 {
+                owners.retain(|_, t| t.file_count > 0);
+            }
+        }
+    }
+
+    /// The types of the files directly inside `id`.
+    pub fn own_types(&self, id: NodeId) -> &TypeMap {
+        &self.nodes[id].types
+    }
+
+    pub fn set_own_types(&mut self, id: NodeId, types: TypeMap) {
+        self.nodes[id].types = types;
+    }
+
+    /// The owners of the files directly inside `id`.
+    pub fn own_owners(&self, id: NodeId) -> &OwnerMap {
+        &self.nodes[id].owners
+    }
+
+    pub fn set_own_owners(&mut self, id: NodeId, owners: OwnerMap) {
+        self.nodes[id].owners = owners;
+    }
+
+    /// The hard-linked files directly inside `id` that it carries the size
+    /// of.
+    pub fn own_links(&self, id: NodeId) -> &[HardLink] {
+        &self.nodes[id].links
+    }
+
+    pub fn add_own_links(&mut self, id: NodeId, links: Vec<HardLink>) {
+        self.nodes[id].links.extend(links);
+    }
+
+    /// Counts the types of `id` afresh from `files`, all of the files now
+    /// directly inside it.
+    pub fn recount_types(&mut self, id: NodeId, files: &[DirStats]) {
+        self.nodes[id].types.clear();
+        self.nodes[id].owners.clear();
+        for file in files {
+            self.count_type(id, &file.path, file);
+        }
+    }
+
+    /// File types anywhere below `id`, added up.
+    pub fn types_below(&self, id: NodeId) -> TypeMap {
+        let mut out = TypeMap::new();
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            for (ext, totals) in &self.nodes[id].types {
+                out.entry(ext.clone()).or_default().add(totals);
+            }
+            stack.extend(&self.nodes[id].children);
+        }
+        out
+    }
+
+    /// File owners anywhere below `id`, added up.
+    pub fn owners_below(&self, id: NodeId) -> OwnerMap {
+        let mut out = OwnerMap::new();
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            for (owner, totals) in &self.nodes[id].owners {
+                out.entry(*owner).or_default().add(totals);
+            }
+            stack.extend(&self.nodes[id].children);
+        }
+        out
+    }
+
+    /// Up to `max` directories at or below `id` with the most files
+    /// directly inside them, most first: where the inodes went.
+    pub fn most_files(&self, id: NodeId, max: usize) -> Vec<NodeId> {
+        let own = |id: NodeId| {
+            let below: u64 = self.nodes[id]
+                .children
+                .iter()
+                .map(|&c| self.nodes[c].stats.file_count)
+                .sum();
+            self.nodes[id].stats.file_count.saturating_sub(below)
+        };
+        let mut found = Vec::new();
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            let files = own(id);
+            if files > 0 {
+                found.push((files, id));
+            }
+            stack.extend(&self.nodes[id].children);
+        }
+        found.sort_unstable_by(|a, b| b.cmp(a));
+        found.truncate(max);
+        found.into_iter().map(|(_, id)| id).collect()
+    }
+
+    /// The biggest files anywhere in the tree by apparent size, biggest
+    /// first, each with its full path.
+    pub fn largest_files(&self) -> Vec<DirStats> {
+        self.largest
+            .iter()
+            .map(|f| DirStats {
+                path: self.path(f.dir).join(&f.stats.path),
+                ..f.stats.clone()
+            })
+            .collect()
+    }
+
+    /// [`DirTree::largest_files`] with the directory each is in, their
+    /// `path` only the name.
+    pub fn largest_by_dir(&self) -> impl Iterator<Item = (NodeId, &DirStats)> {
+        self.largest.iter().map(|f| (f.dir, &f.stats))
+    }
+
+    /// Whether a file of `bytes` would make it into [`DirTree::largest_files`].
+    pub fn wants_file(&self, bytes: u128) -> bool {
+        self.largest.len() < LARGEST_FILES
+            || self
+                .largest
+                .last()
+                .is_some_and(|f| f.stats.total_bytes < bytes)
+    }
+
+    /// Offers a file directly inside `dir` for [`DirTree::largest_files`];
+    /// it's kept only if it is among the biggest.
+    pub fn note_file(&mut self, dir: NodeId, mut file: DirStats) {
+        if !self.wants_file(file.total_bytes) {
+            return;
+        }
+        if let Some(name) = file.path.file_name() {
+            file.path = name.into();
+        }
+        let at = self
+            .largest
+            .partition_point(|f| f.stats.total_bytes >= file.total_bytes);
+        self.largest.insert(at, LargeFile { dir, stats: file });
+        self.largest.truncate(LARGEST_FILES);
+    }
+
+    /// Drops the remembered file at `path`, if it's one.
+    pub fn forget_file(&mut self, path: &Path) {
+        let (Some(dir), Some(name)) = (path.parent().and_then(|p| self.find(p)), path.file_name())
+        else {
+            return;
+        };
+        self.largest
+            .retain(|f| f.dir != dir || f.stats.path.as_os_str() != name);
+    }
+
+    /// Drops the remembered files directly inside `dir`.
+    pub fn forget_files_in(&mut self, dir: NodeId) {
+        self.largest.retain(|f| f.dir != dir);
+    }
+
+    /// Looks a path up by walking down from the root one component at a time.
+    pub fn find(&self, path: &Path) -> Option<NodeId> {
+        let rel = path.strip_prefix(self.root_path()).ok()?;
+        let mut id = self.root();
+        for comp in rel.components() {
+            id = *self.nodes[id]
+                .children
+                .iter()
+                .find(|&&c| self.nodes[c].name.as_os_str() == comp.as_os_str())?;
+        }
+        Some(id)
+    }
+
+    /// Appends a leaf without touching any totals; used while building a
+    /// tree bottom-up (see [`DirTree::finish`]). Only the last component of
+    /// `stats.path` is kept.
+    pub fn push(&mut self, parent: NodeId, mut stats: DirStats) -> NodeId {
+        let path = std::mem::take(&mut stats.path);
+        let name = match path.file_name() {
+            Some(name) => name.into(),
+            None => path,
+        };
+        self.push_named(parent, name, stats)
+    }
+
+    fn push_named(&mut self, parent: NodeId, name: PathBuf, stats: DirStats) -> NodeId {
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            name,
+            stats,
+            parent: Some(parent),
+            children: Vec::new(),
+            types: TypeMap::new(),
+            owners: OwnerMap::new(),
+            links: Vec::new(),
+        });
+        self.nodes[parent].children.push(id);
+        id
+    }
+
+    pub fn stats_mut(&mut self, id: NodeId) -> &mut DirStats {
+        &mut self.nodes[id].stats
+    }
+
+    /// Folds every node's counters into its ancestors and marks the tree
+    /// complete. Only valid once, on a freshly built tree whose nodes hold
+    /// their own (non-recursive) counts and whose parents precede their
+    /// children in the arena.
+    pub fn finish(&mut self) {
+        for id in (0..self.nodes.len()).rev() {
+            self.nodes[id].stats.complete = true;
+            if let Some(parent) = self.nodes[id].parent {
+                let stats = self.nodes[id].stats.clone();
+                self.nodes[parent].stats.add(&stats);
+            }
+        }
+    }
+
+    /// Flags every node as last-known data rather than a fresh scan.
+    pub fn mark_stale(&mut self) {
+        for node in &mut self.nodes {
+            node.stats.stale = true;
+        }
+        for file in &mut self.largest {
+            file.stats.stale = true;
+        }
+    }
+
+    /// `id` and the nodes below it, each before its children.
+    fn subtree(&self, id: NodeId) -> Vec<NodeId> {
+        let mut order = Vec::new();
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            order.push(id);
+            stack.extend(&self.nodes[id].children);
+        }
+        order
+    }
+
+    /// Moves `subtree` under `parent` and adds its totals to the ancestors.
+    pub fn graft(&mut self, parent: NodeId, subtree: DirTree) -> NodeId {
+        let offset = self.nodes.len();
+        let totals = subtree.nodes[0].stats.clone();
+        let DirTree { nodes, largest, .. } = subtree;
+        self.nodes.extend(nodes.into_iter().map(|mut n| {
+            match n.parent {
+                Some(p) => n.parent = Some(p + offset),
+                None => {
+                    n.parent = Some(parent);
+                    if let Some(name) = n.name.file_name() {
+                        n.name = name.into();
+                    }
+                }
+            }
+            n.children.iter_mut().for_each(|c| *c += offset);
+            n
+        }));
+        for file in largest {
+            self.note_file(file.dir + offset, file.stats);
+        }
+        self.nodes[parent].children.push(offset);
+        self.for_ancestors(parent, |s| s.add(&totals));
+        offset
+    }
+
+    /// Unlinks `id` from its parent and subtracts its totals from the
+    /// ancestors. The root cannot be detached.
+    pub fn detach(&mut self, id: NodeId) {
+        let Some(parent) = self.nodes[id].parent else {
+            return;
+        };
+        self.nodes[parent].children.retain(|&c| c != id);
+        let totals = self.nodes[id].stats.clone();
+        self.for_ancestors(parent, |s| s.sub(&totals));
+        let gone: HashSet<NodeId> = self.subtree(id).into_iter().collect();
+        self.largest.retain(|f| !gone.contains(&f.dir));
+        self.detached += gone.len();
+    }
+
+    /// Drops detached nodes from the arena once there are as many of them
+    /// as attached ones, renumbering the rest; ids from before are no good
+    /// after. Keeps a tree that's rescanned and watched piece by piece
+    /// from growing without end.
+    pub fn reclaim(&mut self) {
+        if self.detached * 2 < self.nodes.len() {
+            return;
+        }
+        let order = self.subtree(self.root());
+        let mut new_ids = vec![NodeId::MAX; self.nodes.len()];
+        for (new, &old) in order.iter().enumerate() {
+            new_ids[old] = new;
+        }
+        let mut old: Vec<Option<Node>> = std::mem::take(&mut self.nodes)
+            .into_iter()
+            .map(Some)
+            .collect();
+        self.nodes = order
+            .iter()
+            .map(|&id| {
+                let mut node = old[id].take().expect("each node once");
+                node.parent = node.parent.map(|p| new_ids[p]);
+                node.children.iter_mut().for_each(|c| *c = new_ids[*c]);
+                node
+            })
+            .collect();
+        for file in &mut self.largest {
+            file.dir = new_ids[file.dir];
+        }
+        self.detached = 0;
+    }
+
+    /// Copies the subtree below `id` out into a standalone tree.
+    pub fn extract(&self, id: NodeId) -> DirTree {
+        let mut out = DirTree::from_root(DirStats {
+            path: self.path(id),
+            ..self.nodes[id].stats.clone()
+        });
+        let mut new_ids = HashMap::from([(id, out.root())]);
+        let mut stack = vec![(id, out.root())];
+        while let Some((src, dst)) = stack.pop() {
+            out.nodes[dst].types = self.nodes[src].types.clone();
+            out.nodes[dst].owners = self.nodes[src].owners.clone();
+            out.nodes[dst].links = self.nodes[src].links.clone();
+            for &c in &self.nodes[src].children {
+                let node = &self.nodes[c];
+                let new = out.push_named(dst, node.name.clone(), node.stats.clone());
+                new_ids.insert(c, new);
+                stack.push((c, new));
+            }
+        }
+        out.largest = self
+            .largest
+            .iter()
+            .filter_map(|f| {
+                Some(LargeFile {
+                    dir: *new_ids.get(&f.dir)?,
+                    stats: f.stats.clone(),
+                })
+            })
+            .collect();
+        out
+    }
+
+    /// Replaces the node at `subtree`'s root path with `subtree`. Returns
+    /// false if the path is not part of this tree. Node ids from before may
+    /// not hold after; see [`DirTree::reclaim`].
+    pub fn replace(&mut self, subtree: DirTree) -> bool {
+        let path = subtree.root_path().to_path_buf();
+        if path == self.root_path() {
+            *self = subtree;
+            return true;
+        }
+        let Some(id) = self.find(&path) else {
+            return false;
+        };
+        let parent = self.nodes[id].parent.expect("non-root node has a parent");
+        // Only a scan of the parent can tell.
+        let mount = self.nodes[id].stats.mount;
+        self.detach(id);
+        let id = self.graft(parent, subtree);
+        self.nodes[id].stats.mount = mount;
+        self.reclaim();
+        true
+    }
+
+    /// Sets the totals of `stats.path`, a direct child of `parent`, keeping
+    /// whatever is already known about its own children. Used for partial
+    /// results that arrive while a scan is running.
+    pub fn update_child(&mut self, parent: NodeId, mut stats: DirStats) {
+        let name = stats.path.file_name().map(Path::new);
+        let existing = self.nodes[parent]
+            .children
+            .iter()
+            .copied()
+            .find(|&c| Some(self.nodes[c].name.as_path()) == name);
+        match existing {
+            Some(id) => {
+                stats.path = PathBuf::new();
+                let old = self.nodes[id].stats.clone();
+                self.for_ancestors(parent, |s| {
+                    s.sub(&old);
+                    s.add(&stats);
+                });
+                self.nodes[id].stats = stats;
+            }
+            None => {
+                let totals = stats.clone();
+                self.push(parent, stats);
+                self.for_ancestors(parent, |s| s.add(&totals));
+            }
+        }
+    }
+
+    /// Takes `stats` off `id` and its ancestors, for files removed outside
+    /// of a scan.
+    pub fn discount(&mut self, id: NodeId, stats: &DirStats) {
+        self.for_ancestors(id, |s| s.sub(stats));
+    }
+
+    /// Replaces what the files directly inside `id` add up to with `files`,
+    /// leaving its subdirectories and error count alone.
+    pub fn set_own_files(&mut self, id: NodeId, files: &DirStats) {
+        let mut old = self.own_stats(id);
+        old.dir_count = 0;
+        old.errors = 0;
+        self.for_ancestors(id, |s| {
+            s.sub(&old);
+            s.add(files);
+        });
+    }
+
+    fn for_ancestors(&mut self, start: NodeId, mut f: impl FnMut(&mut DirStats)) {
+        let mut cur = Some(start);
+        while let Some(id) = cur {
+            f(&mut self.nodes[id].stats);
+            cur = self.nodes[id].parent;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory with `bytes` in one file of its own.
+    fn dir(path: &str, bytes: u128) -> DirStats {
+        DirStats {
+            total_bytes: bytes,
+            file_count: 1,
+            ..DirStats::new(PathBuf::from(path))
+        }
+    }
+
+    /// /r with a/b and c/{0..n}, finished, and a big file in a/b.
+    fn sample(n: usize) -> DirTree {
+        let mut tree = DirTree::from_root(dir("/r", 1));
+        let a = tree.push(tree.root(), dir("/r/a", 10));
+        let b = tree.push(a, dir("/r/a/b", 100));
+        let c = tree.push(tree.root(), dir("/r/c", 1000));
+        for i in 0..n {
+            tree.push(c, dir(&format!("/r/c/{i}"), 1));
+        }
+        tree.finish();
+        tree.note_file(b, dir("/r/a/b/big", 90));
+        tree
+    }
+
+    /// Every attached node's path, looked up again, with its totals.
+    fn walk(tree: &DirTree) -> Vec<(PathBuf, u128, u64)> {
+        let mut out = Vec::new();
+        let mut stack = vec![tree.root()];
+        while let Some(id) = stack.pop() {
+            let path = tree.path(id);
+            assert_eq!(tree.find(&path), Some(id), "{}", path.display());
+            for &c in tree.children(id) {
+                assert_eq!(tree.parent(c), Some(id));
+            }
+            let stats = tree.stats(id);
+            out.push((path, stats.total_bytes, stats.dir_count));
+            stack.extend(tree.children(id));
+        }
+        out.sort();
+        out
+    }
+
+    #[test]
+    fn detach_and_reclaim_keep_the_rest() {
+        let mut tree = sample(10);
+        assert_eq!(tree.stats(tree.root()).total_bytes, 1121);
+        let c = tree.find(Path::new("/r/c")).unwrap();
+        tree.detach(c);
+        assert_eq!(tree.stats(tree.root()).total_bytes, 111);
+        assert_eq!(tree.find(Path::new("/r/c/0")), None);
+        let before = walk(&tree);
+
+        tree.reclaim();
+        assert_eq!(tree.node_count(), 3);
+        assert_eq!(walk(&tree), before);
+        let files = tree.largest_files();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, Path::new("/r/a/b/big"));
+    }
+
+    #[test]
+    fn reclaim_waits_for_enough_detached_nodes() {
+        let mut tree = sample(10);
+        let a = tree.find(Path::new("/r/a")).unwrap();
+        tree.detach(a);
+        assert!(tree.largest_files().is_empty());
+        let before = walk(&tree);
+
+        tree.reclaim();
+        assert_eq!(tree.node_count(), 14);
+        assert_eq!(walk(&tree), before);
+        // Ids from before still hold.
+        assert_eq!(
+            tree.path(tree.find(Path::new("/r/c/9")).unwrap()),
+            Path::new("/r/c/9")
+        );
+    }
+
+    #[test]
+    fn graft_names_the_subtree_under_its_parent() {
+        let mut tree = sample(0);
+        let a = tree.find(Path::new("/r/a")).unwrap();
+        let mut sub = DirTree::from_root(dir("/elsewhere/d", 5));
+        sub.push(sub.root(), dir("/elsewhere/d/e", 7));
+        sub.finish();
+        let d = tree.graft(a, sub);
+        assert_eq!(tree.name(d), Path::new("d"));
+        assert_eq!(tree.stats(a).total_bytes, 122);
+        assert_eq!(tree.stats(tree.root()).total_bytes, 1123);
+        assert_eq!(
+            tree.find(Path::new("/r/a/d/e")).map(|e| tree.path(e)),
+            Some("/r/a/d/e".into())
+        );
+        walk(&tree);
+    }
+
+    #[test]
+    fn replace_below_the_root() {
+        let mut tree = sample(10);
+        let mut sub = DirTree::from_root(dir("/r/c", 2000));
+        let x = sub.push(sub.root(), dir("/r/c/x", 3));
+        sub.finish();
+        sub.note_file(x, dir("/r/c/x/huge", 500));
+
+        assert!(tree.replace(sub));
+        assert_eq!(tree.stats(tree.root()).total_bytes, 2114);
+        assert_eq!(tree.find(Path::new("/r/c/0")), None);
+        assert!(tree.find(Path::new("/r/c/x")).is_some());
+        // The old subtree was half the arena, so it's gone from it too.
+        assert_eq!(tree.node_count(), 5);
+        let files: Vec<_> = tree.largest_files().into_iter().map(|f| f.path).collect();
+        assert_eq!(files, [Path::new("/r/c/x/huge"), Path::new("/r/a/b/big")]);
+        walk(&tree);
+
+        assert!(!tree.replace(DirTree::new("/r/nowhere".into())));
+        assert!(!tree.replace(DirTree::new("/other".into())));
+    }
+
+    #[test]
+    fn replace_the_root() {
+        let mut tree = sample(2);
+        let mut fresh = DirTree::from_root(dir("/r", 4));
+        fresh.push(fresh.root(), dir("/r/z", 6));
+        fresh.finish();
+
+        assert!(tree.replace(fresh));
+        assert_eq!(tree.stats(tree.root()).total_bytes, 10);
+        assert_eq!(tree.find(Path::new("/r/a")), None);
+        assert!(tree.largest_files().is_empty());
+        assert_eq!(walk(&tree), [("/r".into(), 10, 2), ("/r/z".into(), 6, 1)]);
+    }
+}
@@ -0,0 +1,131 @@
+//! What scans read and deletes go through: the local filesystem, or
+//! another machine's reached some other way, like [`crate::sftp`].
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use crate::{
+    meta::{self, DirReader, Entry, FileMeta, Kind},
+    mounts,
+    scan::{device, inode, reflinked_bytes},
+};
+
+/// The parts of an entry's metadata that scans look at.
+#[derive(Debug, Clone)]
+pub struct Stat {
+    pub kind: Kind,
+    pub mtime: Option<SystemTime>,
+    /// Filesystem it's on, for staying on one.
+    pub device: Option<u64>,
+    pub inode: Option<u64>,
+}
+
+/// A filesystem to scan.
+pub trait Vfs: Send + Sync {
+    /// The entries of `dir`, in no particular order, with metadata for the
+    /// regular files if `meta` is set. Entries that couldn't be read are
+    /// errors in the list; `dir` itself failing to open is the outer error.
+    fn read(&self, dir: &Path, meta: bool) -> io::Result<Vec<io::Result<Entry>>>;
+
+    /// What's at `path`, links followed.
+    fn stat(&self, path: &Path) -> io::Result<Stat>;
+
+    /// The metadata of the file at `path`, links not followed.
+    fn file_meta(&self, path: &Path) -> io::Result<FileMeta>;
+
+    /// What's at `path`, links not followed.
+    fn kind(&self, path: &Path) -> io::Result<Kind>;
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// `path` made absolute, with links and `..` resolved.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// Removes the empty directory at `path` if `dir` is set, or else the
+    /// file, link or other entry there.
+    fn remove(&self, path: &Path, dir: bool) -> io::Result<()>;
+
+    /// Bytes of the file at `path` in extents it shares with other files,
+    /// where the filesystem tells.
+    fn reflinked(&self, _path: &Path) -> u64 {
+        0
+    }
+
+    /// The machine the paths are on, unless it's this one.
+    fn host(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// This machine's filesystem, listed through the fastest reader it has.
+pub struct Local {
+    reader: Box<dyn DirReader>,
+}
+
+impl Vfs for Local {
+    fn read(&self, dir: &Path, meta: bool) -> io::Result<Vec<io::Result<Entry>>> {
+        self.reader.read(dir, meta)
+    }
+
+    fn stat(&self, path: &Path) -> io::Result<Stat> {
+        let md = fs::metadata(path)?;
+        Ok(Stat {
+            kind: Kind::from(md.file_type()),
+            mtime: md.modified().ok(),
+            device: device(&md),
+            inode: inode(&md),
+        })
+    }
+
+    fn file_meta(&self, path: &Path) -> io::Result<FileMeta> {
+        fs::symlink_metadata(path).map(|md| FileMeta::from_std(&md, path))
+    }
+
+    fn kind(&self, path: &Path) -> io::Result<Kind> {
+        fs::symlink_metadata(path).map(|md| Kind::from(md.file_type()))
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path).map(mounts::plain)
+    }
+
+    fn remove(&self, path: &Path, dir: bool) -> io::Result<()> {
+        if dir || is_dir_link(path) {
+            fs::remove_dir(path)
+        } else {
+            fs::remove_file(path)
+        }
+    }
+
+    fn reflinked(&self, path: &Path) -> u64 {
+        reflinked_bytes(path)
+    }
+}
+
+/// Whether `path` is a link to a directory that has to be removed like
+/// one: a directory symlink or junction on Windows.
+#[cfg(windows)]
+fn is_dir_link(path: &Path) -> bool {
+    use std::os::windows::fs::FileTypeExt;
+    fs::symlink_metadata(path).is_ok_and(|md| md.file_type().is_symlink_dir())
+}
+
+#[cfg(not(windows))]
+fn is_dir_link(_path: &Path) -> bool {
+    false
+}
+
+/// The local filesystem.
+pub fn local() -> Arc<dyn Vfs> {
+    Arc::new(Local {
+        reader: meta::reader(),
+    })
+}
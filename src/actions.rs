@@ -0,0 +1,75 @@
+//! Custom commands from the config, run on the selected entry.
+
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::mpsc::Sender,
+    thread,
+};
+
+use crate::{config::Action, wsl, Msg};
+
+/// The command line of `action` for `path`, with `{path}` replaced by the
+/// path quoted for the shell, and `{windows_path}` by the path Windows
+/// programs take in WSL.
+pub fn command_line(action: &Action, path: &Path) -> String {
+    let mut line = action
+        .command
+        .replace("{path}", &quote(&path.to_string_lossy()));
+    if line.contains("{windows_path}") {
+        let windows = wsl::detected()
+            .then(|| wsl::windows_path(path).ok())
+            .flatten()
+            .unwrap_or_else(|| path.to_string_lossy().into_owned());
+        line = line.replace("{windows_path}", &quote(&windows));
+    }
+    line
+}
+
+/// `text` as a single shell word.
+pub fn quote(text: &str) -> String {
+    if cfg!(windows) {
+        format!("\"{text}\"")
+    } else {
+        format!("'{}'", text.replace('\'', r"'\''"))
+    }
+}
+
+/// Runs `line` with the platform's shell.
+pub fn shell_command(line: &str) -> Command {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C");
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c");
+        cmd
+    };
+    cmd.arg(line);
+    cmd
+}
+
+/// Runs `line` in `dir` on a background thread and logs how it went. The
+/// command may well have changed what's on disk, so a rescan follows.
+pub fn spawn_action(name: String, line: String, dir: PathBuf, tx: Sender<Msg>) {
+    thread::spawn(move || {
+        let output = shell_command(&line)
+            .current_dir(&dir)
+            .stdin(Stdio::null())
+            .output();
+        let msg = match output {
+            Ok(out) if out.status.success() => Msg::Info(format!("{name}: done")),
+            Ok(out) => {
+                let stderr = String::from_utf8_lossy(&out.stderr);
+                match stderr.lines().rev().find(|l| !l.trim().is_empty()) {
+                    Some(last) => Msg::Error(format!("{name}: {} ({})", last.trim(), out.status)),
+                    None => Msg::Error(format!("{name}: {}", out.status)),
+                }
+            }
+            Err(e) => Msg::Error(format!("{name}: cannot run it: {e}")),
+        };
+        let _ = tx.send(msg);
+        let _ = tx.send(Msg::RecomputeNow);
+    });
+}
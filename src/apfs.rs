@@ -0,0 +1,34 @@
+//! Local Time Machine snapshots on APFS. They keep whatever was deleted
+//! since they were taken, so deleting frees next to nothing until they're
+//! thinned, and a walk never sees them. Listed and thinned with `tmutil`.
+
+use std::{path::Path, process::Command};
+
+use anyhow::{bail, Context, Result};
+
+/// `tmutil thinlocalsnapshots` with this many bytes to free and the
+/// highest urgency removes every snapshot it's allowed to.
+pub const THIN_ALL: &str = "999999999999999 4";
+
+/// Names of the local snapshots of the volume mounted at `mount`, oldest
+/// first, like `com.apple.TimeMachine.2024-05-01-101010.local`.
+pub fn local_snapshots(mount: &Path) -> Result<Vec<String>> {
+    let out = Command::new("tmutil")
+        .arg("listlocalsnapshots")
+        .arg(mount)
+        .output()
+        .context("Cannot run tmutil")?;
+    if !out.status.success() {
+        bail!(
+            "tmutil listlocalsnapshots: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .map(str::trim)
+        // Older versions start with a "Snapshots for volume group ..." line.
+        .filter(|line| line.starts_with("com.apple."))
+        .map(str::to_string)
+        .collect())
+}
@@ -0,0 +1,249 @@
+//! Looking inside zip and tar archives without unpacking them. The members
+//! become a tree like an imported export, rooted at the archive, with their
+//! uncompressed sizes as the apparent size and their compressed ones as
+//! disk usage, so 'a' switches between the two.
+//!
+//! Members of a compressed tar aren't compressed one by one; what each
+//! took is how far the compressed stream moved while reading it, which is
+//! close for big members and rough for small ones.
+
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader, Read},
+    path::{Component, Path, PathBuf},
+    rc::Rc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use chrono::{Local, NaiveDate};
+use flate2::bufread::MultiGzDecoder;
+
+use dm_core::tree::{DirStats, DirTree, NodeId};
+
+use crate::ncdu::Import;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl Format {
+    fn of(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        let ends = |suffixes: &[&str]| suffixes.iter().any(|s| name.ends_with(s));
+        Some(if ends(&[".zip", ".jar"]) {
+            Format::Zip
+        } else if ends(&[".tar"]) {
+            Format::Tar
+        } else if ends(&[".tar.gz", ".tgz"]) {
+            Format::TarGz
+        } else if ends(&[".tar.zst", ".tzst"]) {
+            Format::TarZst
+        } else {
+            return None;
+        })
+    }
+}
+
+/// Whether `path` is named like an archive `read` can list.
+pub fn is_archive(path: &Path) -> bool {
+    Format::of(path).is_some()
+}
+
+/// Lists the members of the archive at `path`. A compressed tar has to be
+/// decompressed all the way through for that, a zip only has its index
+/// read.
+pub fn read(path: &Path) -> Result<Import> {
+    let format = Format::of(path).context("Not an archive")?;
+    let file = File::open(path).with_context(|| format!("Cannot open {}", path.display()))?;
+    let packed_size = file.metadata().map_or(0, |md| md.len());
+    let mut members = Members {
+        tree: DirTree::new(path.to_path_buf()),
+        files: HashMap::new(),
+        dirs: HashMap::new(),
+    };
+    let read = match format {
+        Format::Zip => read_zip(file, &mut members),
+        Format::Tar => read_tar(BufReader::new(file), None, &mut members),
+        Format::TarGz | Format::TarZst => {
+            let consumed = Rc::new(Cell::new(0));
+            let counted = Counted {
+                inner: file,
+                read: consumed.clone(),
+            };
+            // Kept small, as the decoder is only ever this far ahead.
+            let counted = BufReader::with_capacity(8 * 1024, counted);
+            let stream: Box<dyn Read> = match format {
+                Format::TarGz => Box::new(MultiGzDecoder::new(counted)),
+                _ => Box::new(zstd::Decoder::with_buffer(counted)?),
+            };
+            read_tar(stream, Some((&consumed, packed_size)), &mut members)
+        }
+    };
+    read.with_context(|| format!("Cannot read {}", path.display()))?;
+    let Members {
+        mut tree, files, ..
+    } = members;
+    tree.finish();
+    Ok(Import { tree, files })
+}
+
+fn read_zip(file: File, members: &mut Members) -> io::Result<()> {
+    let mut archive = zip::ZipArchive::new(BufReader::new(file))?;
+    for i in 0..archive.len() {
+        let member = archive.by_index_raw(i)?;
+        let mtime = member.last_modified().and_then(|t| {
+            NaiveDate::from_ymd_opt(t.year().into(), t.month().into(), t.day().into())?
+                .and_hms_opt(t.hour().into(), t.minute().into(), t.second().into())?
+                .and_local_timezone(Local)
+                .earliest()
+                .map(SystemTime::from)
+        });
+        let name = Path::new(member.name());
+        if member.is_dir() {
+            members.dir(name, mtime);
+        } else {
+            members.file(name, member.size(), member.compressed_size(), mtime);
+        }
+    }
+    Ok(())
+}
+
+/// Lists a tar read from `stream`. Given a count of the compressed bytes
+/// read so far and the archive's size, each file counts as having
+/// compressed to how far the count moved while it was read.
+fn read_tar(
+    stream: impl Read,
+    compressed: Option<(&Cell<u64>, u64)>,
+    members: &mut Members,
+) -> io::Result<()> {
+    // The last file read, waiting to learn where the next one starts.
+    let mut last: Option<(PathBuf, u64, Option<SystemTime>, u64)> = None;
+    let mut first = true;
+    let mut archive = tar::Archive::new(stream);
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let at = match compressed {
+            // The first file takes what came before, so they add up.
+            Some(_) if first => 0,
+            Some((read, _)) => read.get(),
+            None => 0,
+        };
+        if let Some((path, size, mtime, start)) = last.take() {
+            members.file(&path, size, at.saturating_sub(start), mtime);
+        }
+        let path = entry.path()?.into_owned();
+        let mtime = entry
+            .header()
+            .mtime()
+            .ok()
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+        if entry.header().entry_type().is_dir() {
+            members.dir(&path, mtime);
+        } else if compressed.is_some() {
+            last = Some((path, entry.size(), mtime, at));
+            first = false;
+        } else {
+            members.file(&path, entry.size(), entry.size(), mtime);
+        }
+    }
+    if let (Some((path, size, mtime, start)), Some((_, total))) = (last, compressed) {
+        members.file(&path, size, total.saturating_sub(start), mtime);
+    }
+    Ok(())
+}
+
+/// The tree being built from an archive's members.
+struct Members {
+    tree: DirTree,
+    files: HashMap<PathBuf, Vec<DirStats>>,
+    dirs: HashMap<PathBuf, NodeId>, // by path in the archive
+}
+
+impl Members {
+    /// The node of directory `name` in the archive, added with the ones
+    /// above it unless they're there already.
+    fn dir(&mut self, name: &Path, mtime: Option<SystemTime>) -> NodeId {
+        let name = clean(name);
+        let id = self.node(&name);
+        if mtime.is_some() {
+            let stats = self.tree.stats_mut(id);
+            stats.mtime = mtime;
+            stats.newest = stats.newest.max(mtime);
+        }
+        id
+    }
+
+    fn node(&mut self, name: &Path) -> NodeId {
+        if name.as_os_str().is_empty() {
+            return self.tree.root();
+        }
+        if let Some(&id) = self.dirs.get(name) {
+            return id;
+        }
+        let parent = self.node(name.parent().unwrap_or(Path::new("")));
+        let path = self.tree.root_path().join(name);
+        let id = self.tree.push(parent, DirStats::new(path));
+        self.dirs.insert(name.to_path_buf(), id);
+        id
+    }
+
+    /// Adds file `name`, `size` bytes big and `packed` compressed.
+    fn file(&mut self, name: &Path, size: u64, packed: u64, mtime: Option<SystemTime>) {
+        let name = clean(name);
+        let Some(file_name) = name.file_name() else {
+            return;
+        };
+        let id = self.node(name.parent().unwrap_or(Path::new("")));
+        let dir = self.tree.path(id);
+
+        let mut stats = DirStats::new(PathBuf::new());
+        stats.dir_count = 0;
+        stats.file_count = 1;
+        stats.newest = mtime;
+        stats.total_bytes = size.into();
+        stats.disk_bytes = packed.into();
+        self.tree.stats_mut(id).add(&stats);
+
+        stats.path = dir.join(file_name);
+        self.tree.count_type(id, &stats.path, &stats);
+        stats.mtime = mtime;
+        stats.complete = true;
+        if self.tree.wants_file(stats.total_bytes) {
+            self.tree.note_file(id, stats.clone());
+        }
+        self.files.entry(dir).or_default().push(stats);
+    }
+}
+
+/// A member's path with anything that would lead outside the archive, like
+/// a leading `/` or `..`, left out.
+fn clean(name: &Path) -> PathBuf {
+    name.components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Counts what's read through it, for how far into a compressed stream a
+/// decompressor has got.
+struct Counted<R> {
+    inner: R,
+    read: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read.set(self.read.get() + n as u64);
+        Ok(n)
+    }
+}
@@ -0,0 +1,199 @@
+//! `dm bench`: scans one directory several ways and compares how long each
+//! takes and what it costs, for tuning `scan.threads` and the like to a
+//! disk, and for telling whether a new version got slower.
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use clap::Args;
+use humansize::{format_size, DECIMAL};
+use thousands::Separable;
+
+use dm_core::{
+    scan::{resident_bytes, ScanCounters},
+    DirTree, Profile, ScanEvent, ScanOptions, Scanner,
+};
+
+/// How often a run's memory is looked at for its peak.
+const MEMORY_SAMPLE: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Args)]
+pub struct BenchArgs {
+    /// Directory to scan (defaults to the current directory).
+    #[arg(value_name = "PATH")]
+    pub path: Option<PathBuf>,
+
+    /// Thread counts to walk with, like 1,4,16; powers of two up to one per
+    /// CPU by default.
+    #[arg(long, value_name = "N,...", value_delimiter = ',')]
+    pub thread_counts: Vec<usize>,
+
+    /// Scans of each kind, of which the fastest counts.
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    pub runs: usize,
+}
+
+/// What one kind of scan did, at its fastest.
+struct Run {
+    label: String,
+    time: Duration,
+    files: u64,
+    skipped: u64,
+    peak: Option<u64>,
+}
+
+/// Scans `root` with `opts` once to warm up and as the previous scan for
+/// an incremental one, then with each thread count in `args`, the quick
+/// profile and, on Windows, the file table, writing a row for each.
+pub fn run(root: &Path, opts: &ScanOptions, args: &BenchArgs, out: &mut impl Write) -> Result<()> {
+    let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+    let counts = if args.thread_counts.is_empty() {
+        let mut counts: Vec<_> = (0..)
+            .map(|p| 1 << p)
+            .take_while(|&n| n < cpus)
+            .chain([cpus])
+            .collect();
+        counts.dedup();
+        counts
+    } else {
+        args.thread_counts.clone()
+    };
+    let threads = |n: usize| format!("{n} thread{}", if n == 1 { "" } else { "s" });
+    let walk = ScanOptions {
+        mft: false,
+        ..opts.clone()
+    };
+
+    let plural = if cpus == 1 { "" } else { "s" };
+    writeln!(out, "Benchmarking {} on {cpus} CPU{plural}", root.display())?;
+    // Also fills the OS cache, so the runs after it compare fairly.
+    let (warm_up, tree) = measure("first walk", root, &walk, None, 1);
+    let Some(tree) = tree else {
+        anyhow::bail!("The scan of {} failed", root.display());
+    };
+    let stats = tree.stats(tree.root());
+    writeln!(
+        out,
+        "{} files in {} directories\n",
+        stats.file_count.separate_with_spaces(),
+        stats.dir_count.separate_with_spaces()
+    )?;
+    writeln!(
+        out,
+        "{:<28} {:>9} {:>12} {:>14} {:>12}",
+        "scan", "time", "files/s", "lookups saved", "peak memory"
+    )?;
+    write_run(out, &warm_up)?;
+    let incremental = ScanOptions {
+        threads: 0,
+        ..walk.clone()
+    };
+    let label = format!("incremental, {}", threads(cpus));
+    write_run(
+        out,
+        &measure(&label, root, &incremental, Some(&tree), args.runs).0,
+    )?;
+    drop(tree);
+    for &n in &counts {
+        let opts = ScanOptions {
+            threads: n,
+            ..walk.clone()
+        };
+        let label = format!("walk, {}", threads(n));
+        write_run(out, &measure(&label, root, &opts, None, args.runs).0)?;
+    }
+    let mut quick = ScanOptions {
+        threads: 0,
+        ..walk.clone()
+    };
+    Profile::Quick.apply(&mut quick);
+    let label = format!("quick profile, {}", threads(cpus));
+    write_run(out, &measure(&label, root, &quick, None, args.runs).0)?;
+    if cfg!(windows) {
+        let mft = ScanOptions {
+            mft: true,
+            ..walk.clone()
+        };
+        write_run(out, &measure("file table", root, &mft, None, args.runs).0)?;
+    }
+    Ok(())
+}
+
+/// Scans `root` `runs` times, keeping the fastest and the tree it made.
+fn measure(
+    label: &str,
+    root: &Path,
+    opts: &ScanOptions,
+    previous: Option<&DirTree>,
+    runs: usize,
+) -> (Run, Option<DirTree>) {
+    let mut best: Option<(Run, Option<DirTree>)> = None;
+    for _ in 0..runs.max(1) {
+        let counters = Arc::new(ScanCounters::default());
+        let scanner = Scanner {
+            previous: previous.cloned(),
+            counters: counters.clone(),
+            ..Scanner::new(root, opts.clone())
+        };
+        let done = Arc::new(AtomicBool::new(false));
+        let sampler = {
+            let done = done.clone();
+            thread::spawn(move || {
+                let mut peak = resident_bytes();
+                while !done.load(Ordering::Relaxed) {
+                    peak = peak.max(resident_bytes());
+                    thread::sleep(MEMORY_SAMPLE);
+                }
+                peak
+            })
+        };
+        let started = Instant::now();
+        let tree = scanner.run(|event| {
+            if let ScanEvent::Warning(e) = event {
+                eprintln!("dm: {e}");
+            }
+        });
+        let time = started.elapsed();
+        done.store(true, Ordering::Relaxed);
+        let run = Run {
+            label: label.to_string(),
+            time,
+            files: tree.as_ref().map_or(0, |t| t.stats(t.root()).file_count),
+            skipped: counters.skipped.load(Ordering::Relaxed),
+            peak: sampler.join().ok().flatten(),
+        };
+        if best.as_ref().is_none_or(|(b, _)| run.time < b.time) {
+            best = Some((run, tree));
+        }
+    }
+    best.expect("at least one run")
+}
+
+fn write_run(out: &mut impl Write, run: &Run) -> Result<()> {
+    let secs = run.time.as_secs_f64();
+    let rate = if secs > 0.0 {
+        (run.files as f64 / secs) as u64
+    } else {
+        0
+    };
+    writeln!(
+        out,
+        "{:<28} {:>8.2}s {:>12} {:>14} {:>12}",
+        run.label,
+        secs,
+        rate.separate_with_spaces(),
+        run.skipped.separate_with_spaces(),
+        run.peak
+            .map_or_else(|| "?".to_string(), |b| format_size(b, DECIMAL))
+    )?;
+    Ok(())
+}
@@ -0,0 +1,103 @@
+//! Btrfs subvolumes and snapshots. A walk can't tell them apart from
+//! ordinary directories, when they're mounted where it goes at all, yet a
+//! snapshot holds on to everything deleted since it was taken. Listed with
+//! the `btrfs` tool, so this needs what it needs: usually root, and quota
+//! groups enabled for how much each one takes.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{bail, Context, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subvolume {
+    pub id: u64,
+    pub path: PathBuf, // from the filesystem's top level, not the mount point
+    pub snapshot: bool,
+    /// Referenced and exclusive bytes from its quota group; None without
+    /// quotas. Exclusive is what deleting it would free.
+    pub usage: Option<(u64, u64)>,
+}
+
+impl Subvolume {
+    /// Bytes it shares with other subvolumes and snapshots.
+    pub fn shared(&self) -> Option<u64> {
+        self.usage
+            .map(|(referenced, exclusive)| referenced.saturating_sub(exclusive))
+    }
+}
+
+/// The subvolumes and snapshots of the btrfs filesystem mounted at `mount`,
+/// the ones taking the most exclusively first.
+pub fn list(mount: &Path) -> Result<Vec<Subvolume>> {
+    let all = run(&["subvolume", "list"], mount)?;
+    let snapshots: HashSet<u64> = run(&["subvolume", "list", "-s"], mount)?
+        .lines()
+        .filter_map(|line| parse_subvolume(line).map(|(id, _)| id))
+        .collect();
+    // Fails when quotas are off; the list is still worth showing.
+    let usage: HashMap<u64, (u64, u64)> = run(&["qgroup", "show", "--raw"], mount)
+        .map(|out| out.lines().filter_map(parse_qgroup).collect())
+        .unwrap_or_default();
+    let mut subvolumes: Vec<Subvolume> = all
+        .lines()
+        .filter_map(parse_subvolume)
+        .map(|(id, path)| Subvolume {
+            id,
+            path,
+            snapshot: snapshots.contains(&id),
+            usage: usage.get(&id).copied(),
+        })
+        .collect();
+    subvolumes.sort_by(|a, b| {
+        let exclusive = |s: &Subvolume| s.usage.map(|(_, e)| e);
+        exclusive(b)
+            .cmp(&exclusive(a))
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    Ok(subvolumes)
+}
+
+fn run(args: &[&str], mount: &Path) -> Result<String> {
+    let out = Command::new("btrfs")
+        .args(args)
+        .arg(mount)
+        .output()
+        .context("Cannot run btrfs (is btrfs-progs installed?)")?;
+    if !out.status.success() {
+        bail!(
+            "btrfs {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+/// Id and path of a `btrfs subvolume list` line, like
+/// `ID 257 gen 8 cgen 8 top level 5 otime 2024-05-01 10:00:00 path snaps/home`.
+fn parse_subvolume(line: &str) -> Option<(u64, PathBuf)> {
+    let id = line
+        .strip_prefix("ID ")?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()?;
+    let (_, path) = line.split_once(" path ")?;
+    let path = path.strip_prefix("<FS_TREE>/").unwrap_or(path);
+    Some((id, PathBuf::from(path)))
+}
+
+/// Subvolume id, referenced and exclusive bytes of a `btrfs qgroup show
+/// --raw` line, like `0/257  1638400  16384`. Higher-level groups are left
+/// out.
+fn parse_qgroup(line: &str) -> Option<(u64, (u64, u64))> {
+    let mut fields = line.split_whitespace();
+    let id = fields.next()?.strip_prefix("0/")?.parse().ok()?;
+    let referenced = fields.next()?.parse().ok()?;
+    let exclusive = fields.next()?.parse().ok()?;
+    Some((id, (referenced, exclusive)))
+}
@@ -0,0 +1,214 @@
+//! On-disk cache of the last scan of each root, so a restart shows the
+//! last-known sizes immediately while a fresh scan runs.
+
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+
+use dm_core::{scan::device_of, tree::DirTree};
+
+/// Bumped whenever the serialized tree layout changes; older files are ignored.
+const CACHE_VERSION: u32 = 3;
+
+/// How a tree file is encoded, told by its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Gzipped JSON, for reading with other tools.
+    Json,
+    /// Zstandard-compressed MessagePack: smaller, and quicker to load.
+    Binary,
+}
+
+impl Format {
+    pub const ALL: [Format; 2] = [Format::Json, Format::Binary];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Json => "json.gz",
+            Format::Binary => "msgpack.zst",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Binary => "binary",
+        }
+    }
+
+    fn of(path: &Path) -> Self {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.ends_with(Format::Binary.extension()) {
+            Format::Binary
+        } else {
+            Format::Json
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    saved_at: u64, // seconds since the Unix epoch
+    tree: DirTree,
+}
+
+/// 64-bit FNV-1a; stable across builds, unlike `DefaultHasher`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| {
+        (h ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Cache file for `root`, keyed by device and path so a different
+/// filesystem mounted at the same place doesn't pick up stale sizes.
+fn cache_file(root: &Path) -> Option<PathBuf> {
+    Some(cache_dir()?.join(format!("{}.{}", key(root), Format::Json.extension())))
+}
+
+/// Checkpoint of an unfinished scan of `root`, next to its cache file.
+/// Binary, as it's written over and over for the biggest trees.
+fn checkpoint_file(root: &Path) -> Option<PathBuf> {
+    let name = format!("{}.checkpoint.{}", key(root), Format::Binary.extension());
+    Some(cache_dir()?.join(name))
+}
+
+fn cache_dir() -> Option<PathBuf> {
+    Some(dirs::cache_dir()?.join("dm"))
+}
+
+fn key(root: &Path) -> String {
+    let key = format!("{}\0{}", device_of(root).unwrap_or(0), root.display());
+    format!("{:016x}", fnv1a(key.as_bytes()))
+}
+
+/// Loads the cached tree for `root` and when it was saved. A missing or
+/// outdated cache is not an error.
+pub fn load(root: &Path) -> Result<Option<(DirTree, SystemTime)>> {
+    let Some(path) = cache_file(root) else {
+        return Ok(None);
+    };
+    match read_file(&path)? {
+        Some((mut tree, saved)) if tree.root_path() == root => {
+            tree.mark_stale();
+            Ok(Some((tree, saved)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Loads the checkpoint an unfinished scan of `root` left and when it was
+/// written. Not marked stale: the directories it has are what a scan
+/// resuming from it takes over.
+pub fn load_checkpoint(root: &Path) -> Result<Option<(DirTree, SystemTime)>> {
+    let Some(path) = checkpoint_file(root) else {
+        return Ok(None);
+    };
+    Ok(read_file(&path)?.filter(|(tree, _)| tree.root_path() == root))
+}
+
+/// Writes `tree`, from a scan still in flight, as the checkpoint for its
+/// root.
+pub fn save_checkpoint(tree: DirTree) -> Result<()> {
+    match checkpoint_file(tree.root_path()) {
+        Some(path) => write_file(&path, tree),
+        None => Ok(()),
+    }
+}
+
+/// Removes the checkpoint for `root` once its scan is done.
+pub fn clear_checkpoint(root: &Path) -> Result<()> {
+    let Some(path) = checkpoint_file(root) else {
+        return Ok(());
+    };
+    match fs::remove_file(&path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("Cannot remove {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Reads a tree written by [`write_file`] and when it was written; `None`
+/// if the file is missing or from another version.
+pub fn read_file(path: &Path) -> Result<Option<(DirTree, SystemTime)>> {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Cannot open {}", path.display())),
+    };
+    let file = BufReader::new(file);
+    let cached: CacheFile = match Format::of(path) {
+        Format::Json => serde_json::from_reader(GzDecoder::new(file)).map_err(anyhow::Error::from),
+        Format::Binary => decode(file),
+    }
+    .with_context(|| format!("Corrupt cache file {}", path.display()))?;
+    if cached.version != CACHE_VERSION {
+        return Ok(None);
+    }
+    Ok(Some((
+        cached.tree,
+        UNIX_EPOCH + Duration::from_secs(cached.saved_at),
+    )))
+}
+
+/// Writes `tree` as the cache for its root, replacing the previous one
+/// atomically.
+pub fn save(tree: DirTree) -> Result<()> {
+    match cache_file(tree.root_path()) {
+        Some(path) => write_file(&path, tree),
+        None => Ok(()),
+    }
+}
+
+/// Writes `tree` to `path` in the [`Format`] its extension calls for,
+/// replacing any previous file atomically.
+pub fn write_file(path: &Path, tree: DirTree) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+    }
+    let saved_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let cached = CacheFile {
+        version: CACHE_VERSION,
+        saved_at,
+        tree,
+    };
+
+    let tmp = path.with_extension("tmp");
+    let file = File::create(&tmp).with_context(|| format!("Cannot write {}", tmp.display()))?;
+    let file = BufWriter::new(file);
+    let file = match Format::of(path) {
+        Format::Json => {
+            let mut out = GzEncoder::new(file, Compression::fast());
+            serde_json::to_writer(&mut out, &cached).context("Cannot serialize scan tree")?;
+            out.finish()?
+        }
+        Format::Binary => encode(file, &cached)?,
+    };
+    file.into_inner().map_err(|e| e.into_error())?;
+    fs::rename(&tmp, path).with_context(|| format!("Cannot replace {}", path.display()))?;
+    Ok(())
+}
+
+/// Field names are kept, so like the JSON the layout tolerates fields
+/// being added or left out.
+fn encode<W: Write>(out: W, cached: &CacheFile) -> Result<W> {
+    let mut out = zstd::Encoder::new(out, 3)?;
+    rmp_serde::encode::write_named(&mut out, cached).context("Cannot serialize scan tree")?;
+    Ok(out.finish()?)
+}
+
+fn decode(input: impl Read) -> Result<CacheFile> {
+    Ok(rmp_serde::from_read(zstd::Decoder::new(input)?)?)
+}
@@ -0,0 +1,156 @@
+//! `dm check`: scans headlessly and fails when a directory has outgrown its
+//! limits, for gating CI caches and artifact directories.
+
+use std::{io::Write, path::PathBuf};
+
+use anyhow::Result;
+use clap::{ArgGroup, Args};
+use humansize::{format_size, DECIMAL};
+use thousands::Separable;
+
+use dm_core::tree::{DirStats, DirTree, SizeMode};
+
+#[derive(Debug, Args)]
+#[command(group(ArgGroup::new("limits").required(true).multiple(true)))]
+pub struct CheckArgs {
+    /// Directory to scan (defaults to the current directory).
+    #[arg(value_name = "PATH")]
+    pub path: Option<PathBuf>,
+
+    /// Most the directory may take, like 50GB, 1.5TiB or 800M.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, group = "limits")]
+    pub max_size: Option<u128>,
+
+    /// Most files the directory may hold, like 250000 or 1e6.
+    #[arg(long, value_name = "N", value_parser = parse_count, group = "limits")]
+    pub max_files: Option<u64>,
+
+    /// How many of the largest subdirectories to list when over a limit.
+    #[arg(short = 'n', long, value_name = "N", default_value_t = 10)]
+    pub top: usize,
+}
+
+/// Prints how `tree` measures up against the limits in `args`, and with
+/// any exceeded, the subdirectories taking the most. Returns whether it's
+/// within all of them.
+pub fn run(tree: &DirTree, args: &CheckArgs, mode: SizeMode, out: &mut impl Write) -> Result<bool> {
+    let root = tree.stats(tree.root());
+    let rough = if root.estimated > 0 { "~" } else { "" };
+    let size = |bytes: u128| format!("{rough}{}", format_size(bytes as u64, DECIMAL));
+    let mut over = Vec::new();
+    let mut within = Vec::new();
+    if let Some(max) = args.max_size {
+        let used = root.bytes(mode);
+        let line = format!("{} of {} ({})", size(used), size(max), mode.label());
+        if used > max {
+            over.push(format!("{line}, {} over", size(used - max)));
+        } else {
+            within.push(line);
+        }
+    }
+    if let Some(max) = args.max_files {
+        let line = format!(
+            "{}{} of {} files",
+            rough,
+            root.file_count.separate_with_spaces(),
+            max.separate_with_spaces()
+        );
+        if root.file_count > max {
+            over.push(format!(
+                "{line}, {} over",
+                (root.file_count - max).separate_with_spaces()
+            ));
+        } else {
+            within.push(line);
+        }
+    }
+
+    let path = tree.root_path().display();
+    if over.is_empty() {
+        writeln!(out, "ok: {path}: {}", within.join("; "))?;
+        return Ok(true);
+    }
+    for line in &over {
+        writeln!(out, "over: {path}: {line}")?;
+    }
+    for line in &within {
+        writeln!(out, "ok: {path}: {line}")?;
+    }
+    // What to look at first: by size when that's the problem, else by files.
+    let key = |s: &DirStats| match args.max_size {
+        Some(max) if root.bytes(mode) > max => s.bytes(mode),
+        _ => u128::from(s.file_count),
+    };
+    let mut dirs = Vec::new();
+    let mut stack = tree.children(tree.root()).to_vec();
+    while let Some(id) = stack.pop() {
+        dirs.push(id);
+        stack.extend(tree.children(id));
+    }
+    dirs.sort_by_key(|&id| std::cmp::Reverse(key(tree.stats(id))));
+    if args.top > 0 && !dirs.is_empty() {
+        writeln!(out, "largest subdirectories:")?;
+        for id in dirs.into_iter().take(args.top) {
+            let s = tree.stats(id);
+            writeln!(
+                out,
+                "{:>10} {:>12}  {}",
+                format_size(s.bytes(mode) as u64, DECIMAL),
+                s.file_count.separate_with_spaces(),
+                tree.path(id).display()
+            )?;
+        }
+    }
+    Ok(false)
+}
+
+/// A size like "50GB", "1.5TiB", "800M" or "4096": decimal units by
+/// default, binary ones with an `i`.
+pub fn parse_size(text: &str) -> Result<u128, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number
+        .trim()
+        .replace('_', "")
+        .parse()
+        .map_err(|_| format!("not a size: {text:?}"))?;
+    let unit = unit.trim().to_ascii_lowercase();
+    let unit = unit.strip_suffix('b').unwrap_or(&unit);
+    let (prefix, base) = match unit.strip_suffix('i') {
+        Some(prefix) => (prefix, 1024_f64),
+        None => (unit, 1000_f64),
+    };
+    let power = match prefix {
+        "" => 0,
+        "k" => 1,
+        "m" => 2,
+        "g" => 3,
+        "t" => 4,
+        "p" => 5,
+        _ => {
+            return Err(format!(
+                "unknown unit in {text:?}; use B, KB, MB, GB, TB, PB or KiB…PiB"
+            ))
+        }
+    };
+    if !number.is_finite() || number < 0.0 {
+        return Err(format!("not a size: {text:?}"));
+    }
+    Ok((number * base.powi(power)).round() as u128)
+}
+
+/// A count like "250000", "1_000_000" or "1e6".
+fn parse_count(text: &str) -> Result<u64, String> {
+    let number: f64 = text
+        .trim()
+        .replace('_', "")
+        .parse()
+        .map_err(|_| format!("not a number: {text:?}"))?;
+    if !number.is_finite() || number < 0.0 || number.fract() != 0.0 {
+        return Err(format!("not a whole number: {text:?}"));
+    }
+    Ok(number as u64)
+}
@@ -0,0 +1,77 @@
+//! Copying text to the system clipboard. Local sessions use the platform's
+//! clipboard tool; over SSH, or when there is none, the terminal is asked to
+//! do it with an OSC 52 escape sequence.
+
+use std::{
+    env,
+    io::{self, Write},
+    process::{Command, Stdio},
+};
+
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// How the text reached the clipboard, for the log.
+pub enum Via {
+    Tool(&'static str),
+    Terminal,
+}
+
+pub fn copy(text: &str) -> Result<Via> {
+    let remote = env::var_os("SSH_CONNECTION").is_some() || env::var_os("SSH_TTY").is_some();
+    if !remote {
+        for (program, args) in tools() {
+            if pipe_to(program, args, text).is_ok() {
+                return Ok(Via::Tool(program));
+            }
+        }
+    }
+    osc52(text)?;
+    Ok(Via::Terminal)
+}
+
+/// Clipboard tools to try on this platform, best first.
+fn tools() -> Vec<(&'static str, &'static [&'static str])> {
+    if cfg!(target_os = "macos") {
+        vec![("pbcopy", &[])]
+    } else if cfg!(windows) {
+        vec![("clip", &[])]
+    } else {
+        let mut tools = Vec::new();
+        if env::var_os("WAYLAND_DISPLAY").is_some() {
+            tools.push(("wl-copy", &[][..]));
+        }
+        if env::var_os("DISPLAY").is_some() {
+            tools.push(("xclip", &["-selection", "clipboard"][..]));
+            tools.push(("xsel", &["--clipboard", "--input"][..]));
+        }
+        tools
+    }
+}
+
+fn pipe_to(program: &str, args: &[&str], text: &str) -> Result<()> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .context("no stdin")?
+        .write_all(text.as_bytes())?;
+    if !child.wait()?.success() {
+        bail!("{program} failed");
+    }
+    Ok(())
+}
+
+/// Asks the terminal to set its clipboard. Whether it does can't be known;
+/// some terminals ignore the request or need it enabled.
+fn osc52(text: &str) -> Result<()> {
+    let mut out = io::stdout();
+    write!(out, "\x1b]52;c;{}\x07", STANDARD.encode(text))?;
+    out.flush()?;
+    Ok(())
+}
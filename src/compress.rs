@@ -0,0 +1,413 @@
+//! Packing a directory into a compressed tarball next to it, checking the
+//! archive by reading it back, and optionally removing the original. And
+//! guessing beforehand whether that's worth it, from samples of the files.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::Deserialize;
+use walkdir::WalkDir;
+
+use crate::Msg;
+
+/// How often progress is reported.
+const PROGRESS_EVERY: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    Zstd,
+    Gzip,
+}
+
+impl Codec {
+    pub fn toggled(self) -> Self {
+        match self {
+            Codec::Zstd => Codec::Gzip,
+            Codec::Gzip => Codec::Zstd,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Codec::Zstd => "tar.zst",
+            Codec::Gzip => "tar.gz",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Packing,
+    Verifying,
+}
+
+/// Bytes read from a file at a time when sampling it, at its start, middle
+/// and end.
+const SAMPLE_BLOCK: u64 = 64 * 1024;
+
+/// Most bytes sampled for an estimate; files are skipped evenly to stay
+/// under it.
+const SAMPLE_BUDGET: u64 = 256 * 1024 * 1024;
+
+/// What a finished compression left behind.
+#[derive(Debug)]
+pub struct Packed {
+    pub archive: PathBuf,
+    pub size: u64,
+    pub removed: bool, // the original directory was deleted
+}
+
+/// Where the archive of `dir` goes: next to it, named after it.
+pub fn archive_path(dir: &Path, codec: Codec) -> PathBuf {
+    let name = dir.file_name().unwrap_or_default().to_string_lossy();
+    dir.with_file_name(format!("{name}.{}", codec.extension()))
+}
+
+/// A guess at how well a directory would compress.
+#[derive(Debug)]
+pub struct Estimate {
+    pub total: u64,   // apparent size of all the files
+    pub files: u64,   // number of them
+    pub sampled: u64, // bytes read from them
+    pub packed: u64,  // projected size of all of them once compressed
+}
+
+impl Estimate {
+    pub fn saved(&self) -> u64 {
+        self.total.saturating_sub(self.packed)
+    }
+}
+
+/// Estimates how far `dir` would compress on a background thread, sending
+/// the result unless `cancel` is set first.
+pub fn spawn_estimate_thread(dir: PathBuf, cancel: Arc<AtomicBool>, tx: Sender<Msg>) {
+    thread::spawn(move || {
+        let res = estimate(&dir, &cancel).map_err(|e| format!("{e:#}"));
+        if !cancel.load(Ordering::Relaxed) {
+            let _ = tx.send(Msg::EstimateFinished(dir, res));
+        }
+    });
+}
+
+/// Compresses a few blocks of the files below `dir` with fast zstd, and
+/// projects each file's ratio onto all of it. Files not sampled count as
+/// compressing like the sampled ones on average.
+fn estimate(dir: &Path, cancel: &AtomicBool) -> Result<Estimate> {
+    let files: Vec<(PathBuf, u64)> = WalkDir::new(dir)
+        .follow_links(false)
+        .into_iter()
+        .flatten()
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| Some((e.path().to_path_buf(), e.metadata().ok()?.len())))
+        .filter(|&(_, len)| len > 0)
+        .collect();
+    let total: u64 = files.iter().map(|&(_, len)| len).sum();
+    let per_file = |len: u64| len.min(3 * SAMPLE_BLOCK);
+    let wanted: u64 = files.iter().map(|&(_, len)| per_file(len)).sum();
+    let every = wanted.div_ceil(SAMPLE_BUDGET).max(1) as usize;
+
+    let (mut sampled, mut covered, mut projected) = (0, 0, 0.0);
+    let mut buf = vec![0; SAMPLE_BLOCK as usize];
+    for (path, len) in files.iter().step_by(every) {
+        if cancel.load(Ordering::Relaxed) {
+            bail!("stopped");
+        }
+        let Ok(mut file) = File::open(path) else {
+            continue;
+        };
+        let (mut read, mut packed) = (0, 0);
+        let starts = if *len <= 3 * SAMPLE_BLOCK {
+            vec![0]
+        } else {
+            vec![0, len / 2, len - SAMPLE_BLOCK]
+        };
+        for start in starts {
+            let n = read_block(&mut file, start, &mut buf).unwrap_or(0);
+            if n == 0 {
+                continue;
+            }
+            read += n as u64;
+            packed += zstd::bulk::compress(&buf[..n], 1)?.len() as u64;
+        }
+        if read > 0 {
+            sampled += read;
+            covered += len;
+            // Data that doesn't compress would be stored as it is.
+            projected += *len as f64 * (packed.min(read) as f64 / read as f64);
+        }
+    }
+    let packed = if covered > 0 {
+        projected * total as f64 / covered as f64
+    } else {
+        total as f64
+    };
+    Ok(Estimate {
+        total,
+        files: files.len() as u64,
+        sampled,
+        packed: packed as u64,
+    })
+}
+
+/// Reads up to a block of `file` from `start`, fewer bytes only at its end.
+fn read_block(file: &mut File, start: u64, buf: &mut [u8]) -> io::Result<usize> {
+    file.seek(SeekFrom::Start(start))?;
+    let mut n = 0;
+    while n < buf.len() {
+        match file.read(&mut buf[n..])? {
+            0 => break,
+            m => n += m,
+        }
+    }
+    Ok(n)
+}
+
+/// Compresses `dir` on a background thread, sending progress and then the
+/// result. Stops early, leaving nothing behind, once `cancel` is set.
+pub fn spawn_compress_thread(
+    dir: PathBuf,
+    codec: Codec,
+    remove: bool,
+    cancel: Arc<AtomicBool>,
+    tx: Sender<Msg>,
+) {
+    thread::spawn(move || {
+        let res = compress(&dir, codec, remove, &cancel, &tx).map_err(|e| format!("{e:#}"));
+        let _ = tx.send(Msg::CompressFinished(dir, res));
+        let _ = tx.send(Msg::RecomputeNow);
+    });
+}
+
+fn compress(
+    dir: &Path,
+    codec: Codec,
+    remove: bool,
+    cancel: &AtomicBool,
+    tx: &Sender<Msg>,
+) -> Result<Packed> {
+    let archive = archive_path(dir, codec);
+    if archive.exists() {
+        bail!("{} already exists", archive.display());
+    }
+    // Written under a temporary name, so a half-written or unverified
+    // archive never looks like a good one.
+    let name = archive.file_name().unwrap_or_default().to_string_lossy();
+    let part = archive.with_file_name(format!(".{name}.dm-part"));
+    let res = pack(dir, &part, codec, cancel, tx)
+        .and_then(|written| verify(&part, codec, written, cancel, tx))
+        .and_then(|()| Ok(fs::rename(&part, &archive)?));
+    if let Err(e) = res {
+        let _ = fs::remove_file(&part);
+        if cancel.load(Ordering::Relaxed) {
+            bail!("stopped");
+        }
+        return Err(e);
+    }
+
+    let size = fs::metadata(&archive)?.len();
+    if remove {
+        fs::remove_dir_all(dir).with_context(|| {
+            format!(
+                "Archive {} is fine, but removing the original failed",
+                archive.display()
+            )
+        })?;
+    }
+    Ok(Packed {
+        archive,
+        size,
+        removed: remove,
+    })
+}
+
+/// Entries and file bytes in an archive, to compare the written and the
+/// read-back archive by.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Contents {
+    entries: u64,
+    bytes: u64,
+}
+
+fn pack(
+    dir: &Path,
+    part: &Path,
+    codec: Codec,
+    cancel: &AtomicBool,
+    tx: &Sender<Msg>,
+) -> Result<Contents> {
+    let out = BufWriter::new(
+        File::create(part).with_context(|| format!("Cannot create {}", part.display()))?,
+    );
+    let out = match codec {
+        Codec::Zstd => Encoder::Zstd(zstd::Encoder::new(out, 0)?),
+        Codec::Gzip => Encoder::Gzip(GzEncoder::new(out, Compression::default())),
+    };
+    let mut tar = tar::Builder::new(out);
+    tar.follow_symlinks(false);
+
+    let base = dir.parent().unwrap_or(dir);
+    let mut progress = Reporter::new(Phase::Packing, tx);
+    let mut contents = Contents::default();
+    for entry in WalkDir::new(dir).follow_links(false) {
+        let entry = entry?;
+        let name = entry.path().strip_prefix(base).unwrap_or(entry.path());
+        if entry.file_type().is_file() {
+            let md = entry.metadata()?;
+            let mut header = tar::Header::new_gnu();
+            header.set_metadata(&md);
+            let file = File::open(entry.path())
+                .with_context(|| format!("Cannot read {}", entry.path().display()))?;
+            let reader = Counting {
+                inner: file,
+                cancel,
+                progress: &mut progress,
+            };
+            tar.append_data(&mut header, name, reader)
+                .with_context(|| format!("Cannot add {}", entry.path().display()))?;
+            contents.bytes += md.len();
+        } else {
+            if cancel.load(Ordering::Relaxed) {
+                bail!("stopped");
+            }
+            tar.append_path_with_name(entry.path(), name)
+                .with_context(|| format!("Cannot add {}", entry.path().display()))?;
+        }
+        contents.entries += 1;
+    }
+    tar.into_inner()?.finish()?;
+    Ok(contents)
+}
+
+enum Encoder {
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl Encoder {
+    /// Ends the compressed stream. Unlike dropping the encoder, this
+    /// reports failures to write the last of it.
+    fn finish(self) -> io::Result<()> {
+        let mut out = match self {
+            Encoder::Zstd(e) => e.finish()?,
+            Encoder::Gzip(e) => e.finish()?,
+        };
+        out.flush()
+    }
+}
+
+impl Write for Encoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Zstd(e) => e.write(buf),
+            Encoder::Gzip(e) => e.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Zstd(e) => e.flush(),
+            Encoder::Gzip(e) => e.flush(),
+        }
+    }
+}
+
+/// Reads the archive back in full and checks it holds what was written.
+fn verify(
+    part: &Path,
+    codec: Codec,
+    written: Contents,
+    cancel: &AtomicBool,
+    tx: &Sender<Msg>,
+) -> Result<()> {
+    let file = File::open(part)?;
+    let input: Box<dyn Read> = match codec {
+        Codec::Zstd => Box::new(zstd::Decoder::new(file)?),
+        Codec::Gzip => Box::new(GzDecoder::new(file)),
+    };
+    let mut progress = Reporter::new(Phase::Verifying, tx);
+    let mut read = Contents::default();
+    let mut tar = tar::Archive::new(input);
+    for entry in tar.entries().context("Archive is unreadable")? {
+        let entry = entry.context("Archive is damaged")?;
+        let is_file = entry.header().entry_type().is_file();
+        let mut reader = Counting {
+            inner: entry,
+            cancel,
+            progress: &mut progress,
+        };
+        let bytes = io::copy(&mut reader, &mut io::sink()).context("Archive is damaged")?;
+        if is_file {
+            read.bytes += bytes;
+        }
+        read.entries += 1;
+    }
+    if read != written {
+        bail!(
+            "Archive check failed: wrote {} entries ({} bytes), read back {} ({} bytes)",
+            written.entries,
+            written.bytes,
+            read.entries,
+            read.bytes
+        );
+    }
+    Ok(())
+}
+
+/// Sends the bytes gone through so far, now and then.
+struct Reporter<'a> {
+    phase: Phase,
+    bytes: u64,
+    reported: Instant,
+    tx: &'a Sender<Msg>,
+}
+
+impl<'a> Reporter<'a> {
+    fn new(phase: Phase, tx: &'a Sender<Msg>) -> Self {
+        let _ = tx.send(Msg::CompressProgress(phase, 0));
+        Self {
+            phase,
+            bytes: 0,
+            reported: Instant::now(),
+            tx,
+        }
+    }
+
+    fn add(&mut self, n: usize) {
+        self.bytes += n as u64;
+        if self.reported.elapsed() >= PROGRESS_EVERY {
+            self.reported = Instant::now();
+            let _ = self.tx.send(Msg::CompressProgress(self.phase, self.bytes));
+        }
+    }
+}
+
+/// A reader that reports progress and fails once `cancel` is set.
+struct Counting<'a, 'b, R> {
+    inner: R,
+    cancel: &'a AtomicBool,
+    progress: &'a mut Reporter<'b>,
+}
+
+impl<R: Read> Read for Counting<'_, '_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.cancel.load(Ordering::Relaxed) {
+            return Err(io::Error::other("stopped"));
+        }
+        let n = self.inner.read(buf)?;
+        self.progress.add(n);
+        Ok(n)
+    }
+}
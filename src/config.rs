@@ -0,0 +1,542 @@
+//! User settings from `config.toml` in the platform config directory
+//! (`~/.config/dm/config.toml` on Linux). Every key is optional.
+
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Deserializer};
+
+use dm_core::scan::Profile;
+
+use crate::{
+    cache::Format,
+    check,
+    compress::Codec,
+    schedule::{self, ScheduleAction},
+};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Minutes between automatic rescans of the current directory; 0 turns
+    /// them off. `[[schedule]]` tables can rescan at set times instead.
+    pub auto_rescan_minutes: u64,
+    /// On battery, scan on one thread and hold automatic rescans until
+    /// back on mains power.
+    pub battery_saver: bool,
+    /// Follow changes on disk as they happen. Worth turning off on network
+    /// filesystems, where change events are unreliable or costly.
+    pub watch: bool,
+    /// Record the sizes from each finished scan in a local database for the
+    /// history pane.
+    pub history: bool,
+    /// Browse only: no deleting or linking from the UI.
+    pub read_only: bool,
+    /// Directories to jump to from the bookmarks menu ('B'); 'b' adds to
+    /// this list.
+    pub bookmarks: Vec<PathBuf>,
+    pub bar: BarConfig,
+    pub age: AgeConfig,
+    pub old_files: OldFilesConfig,
+    pub delete: DeleteConfig,
+    pub compress: CompressConfig,
+    pub scan: ScanConfig,
+    pub snapshot: SnapshotConfig,
+    pub daemon: DaemonConfig,
+    pub keys: KeysConfig,
+    /// `[[action]]` tables, in the order they're listed.
+    #[serde(rename = "action")]
+    pub actions: Vec<Action>,
+    /// `[[rule]]` tables: limits to alert on while watching or in
+    /// `--daemon`.
+    #[serde(rename = "rule")]
+    pub rules: Vec<Rule>,
+    /// `[[schedule]]` tables: things to do at set times.
+    #[serde(rename = "schedule")]
+    pub schedules: Vec<Schedule>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            auto_rescan_minutes: 15,
+            battery_saver: true,
+            watch: true,
+            history: true,
+            read_only: false,
+            bookmarks: Vec::new(),
+            bar: BarConfig::default(),
+            age: AgeConfig::default(),
+            old_files: OldFilesConfig::default(),
+            delete: DeleteConfig::default(),
+            compress: CompressConfig::default(),
+            scan: ScanConfig::default(),
+            snapshot: SnapshotConfig::default(),
+            daemon: DaemonConfig::default(),
+            keys: KeysConfig::default(),
+            actions: Vec::new(),
+            rules: Vec::new(),
+            schedules: Vec::new(),
+        }
+    }
+}
+
+/// The usage bar drawn next to each row's size.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BarConfig {
+    /// Characters between the brackets; 0 leaves only the percentage.
+    pub width: u16,
+    pub style: BarStyle,
+}
+
+impl Default for BarConfig {
+    fn default() -> Self {
+        Self {
+            width: 10,
+            style: BarStyle::Hash,
+        }
+    }
+}
+
+/// Coloring entries by how long nothing in them has changed.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgeConfig {
+    /// Start with the coloring on; 'o' toggles it either way.
+    pub color: bool,
+    /// Entries untouched for longer than this are dimmed red.
+    pub old_after_days: u64,
+}
+
+impl Default for AgeConfig {
+    fn default() -> Self {
+        Self {
+            color: false,
+            old_after_days: 365,
+        }
+    }
+}
+
+/// Which files the old large files report ('F') lists.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OldFilesConfig {
+    /// Files smaller than this many MB are left out.
+    pub min_size_mb: u64,
+    /// Files read or changed within this many days are left out.
+    pub unused_days: u64,
+}
+
+impl Default for OldFilesConfig {
+    fn default() -> Self {
+        Self {
+            min_size_mb: 100,
+            unused_days: 180,
+        }
+    }
+}
+
+/// Extra care before permanent deletions.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeleteConfig {
+    /// Deleting more than this many GB asks for the target's name to be
+    /// typed instead of 'Y'; 0 turns that off.
+    pub type_name_above_gb: u64,
+    /// Globs like `/srv/*/data`: deleting a match, or a directory holding
+    /// one, always asks for the name.
+    pub type_name_paths: Vec<String>,
+    /// Globs that can't be deleted from the UI at all, nor can directories
+    /// holding a match. Replaces the defaults below when set.
+    pub protected: Vec<String>,
+}
+
+impl Default for DeleteConfig {
+    fn default() -> Self {
+        Self {
+            type_name_above_gb: 10,
+            type_name_paths: Vec::new(),
+            protected: [
+                // The top level and home directories themselves...
+                "/",
+                "/*",
+                "/home/*",
+                "/Users/*",
+                "~",
+                // ...and anything inside system directories.
+                "/bin/**",
+                "/boot/**",
+                "/etc/**",
+                "/lib*/**",
+                "/sbin/**",
+                "/usr/**",
+                "/System/**",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+/// Starting choices for compressing a directory ('z').
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressConfig {
+    pub codec: Codec,
+    /// Delete the directory once its archive has been checked.
+    pub delete_original: bool,
+}
+
+impl Default for CompressConfig {
+    fn default() -> Self {
+        Self {
+            codec: Codec::Zstd,
+            delete_original: false,
+        }
+    }
+}
+
+/// How hard scans work the machine.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScanConfig {
+    /// Threads reading directories; 0 starts one per CPU.
+    pub threads: usize,
+    /// Scan at the lowest CPU and disk priority, so everything else on the
+    /// machine gets to the disk first. Linux and macOS only.
+    pub low_priority: bool,
+    /// Read local NTFS volumes' Master File Table instead of walking them.
+    /// Windows only, and only from an elevated prompt.
+    pub mft: bool,
+    /// Look up which parts of files are shared with reflinked copies and
+    /// snapshots; see `--reflinks`.
+    pub reflinks: bool,
+    /// Seconds before giving up on a directory that doesn't list; see
+    /// `--dir-timeout`. 0 waits forever, and so does unset but on network
+    /// filesystems.
+    pub dir_timeout: Option<u64>,
+    /// Levels below the scanned directory kept as directories; see
+    /// `--keep-depth`. 0 or unset keeps them all.
+    pub keep_depth: Option<usize>,
+    /// Memory dm may take, like "2GB"; see `--memory-budget`.
+    #[serde(default, deserialize_with = "size")]
+    pub memory_budget: Option<u128>,
+    /// Filesystem operations per second scans may do; see `--io-limit`.
+    /// 0 or unset doesn't hold them back.
+    pub io_limit: Option<u64>,
+    /// "quick" or "thorough"; see `--profile`.
+    pub profile: Option<Profile>,
+}
+
+/// Saving snapshots from the snapshots menu ('S').
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SnapshotConfig {
+    /// "json" for gzipped JSON other tools can read, or "binary" for
+    /// smaller files that load faster.
+    pub format: Format,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            format: Format::Json,
+        }
+    }
+}
+
+/// What `--daemon` scans, how often, and where it serves the metrics.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// Directories to scan when none are given on the command line.
+    pub paths: Vec<PathBuf>,
+    /// Minutes from the start of one round of scans to the next.
+    pub interval_minutes: u64,
+    /// Address to serve Prometheus metrics on, at `/metrics`.
+    pub listen: String,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            paths: Vec::new(),
+            interval_minutes: 15,
+            listen: "127.0.0.1:9478".to_string(),
+        }
+    }
+}
+
+/// Keys for going back and forth through the directories visited, like
+/// in a browser. They are written like "alt+left", "ctrl+o" or "backspace".
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeysConfig {
+    #[serde(deserialize_with = "key")]
+    pub back: Key,
+    #[serde(deserialize_with = "key")]
+    pub forward: Key,
+}
+
+impl Default for KeysConfig {
+    fn default() -> Self {
+        Self {
+            back: Key(KeyCode::Left, KeyModifiers::ALT),
+            forward: Key(KeyCode::Right, KeyModifiers::ALT),
+        }
+    }
+}
+
+/// A key with the modifiers held down with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key(pub KeyCode, pub KeyModifiers);
+
+impl Key {
+    /// Whether `event` is this key. Shift is left out for characters,
+    /// which come with it or without depending on the terminal.
+    pub fn matches(&self, event: &KeyEvent) -> bool {
+        let ignored = match event.code {
+            KeyCode::Char(_) => KeyModifiers::SHIFT,
+            _ => KeyModifiers::NONE,
+        };
+        event.code == self.0 && event.modifiers - ignored == self.1 - ignored
+    }
+}
+
+impl std::str::FromStr for Key {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut parts: Vec<_> = text.split('+').collect();
+        // "ctrl++" is the plus key.
+        if text.ends_with("++") {
+            parts.truncate(parts.len() - 2);
+            parts.push("+");
+        }
+        let (name, held) = parts.split_last().ok_or("an empty key")?;
+        for m in held {
+            modifiers |= match m.to_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" | "meta" | "option" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return Err(format!("unknown modifier {m:?} in {text:?}")),
+            };
+        }
+        let mut chars = name.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match name.to_lowercase().as_str() {
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                "backspace" => KeyCode::Backspace,
+                "delete" => KeyCode::Delete,
+                "insert" => KeyCode::Insert,
+                "enter" => KeyCode::Enter,
+                "tab" => KeyCode::Tab,
+                "esc" => KeyCode::Esc,
+                "space" => KeyCode::Char(' '),
+                f => match f.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                    Some(n @ 1..=24) => KeyCode::F(n),
+                    _ => return Err(format!("unknown key {name:?} in {text:?}")),
+                },
+            },
+        };
+        Ok(Key(code, modifiers))
+    }
+}
+
+fn key<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Key, D::Error> {
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(serde::de::Error::custom)
+}
+
+/// A command for the actions menu ('A').
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Action {
+    pub name: String,
+    /// Runs the action straight from the menu.
+    #[serde(default)]
+    pub key: Option<char>,
+    /// A shell command; `{path}` becomes the selected path, quoted, and
+    /// `{windows_path}` the same as a Windows program takes it in WSL.
+    pub command: String,
+    /// Ask before running it.
+    #[serde(default)]
+    pub confirm: bool,
+    /// Hand the terminal over until it exits, for interactive programs.
+    /// Otherwise it runs in the background and only its result is logged.
+    #[serde(default)]
+    pub foreground: bool,
+}
+
+/// An alert for when a directory outgrows a limit.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub path: PathBuf,
+    /// Most the directory may take, like "20GB" or "1.5TiB".
+    #[serde(default, deserialize_with = "size")]
+    pub max_size: Option<u128>,
+    /// Most files the directory may hold.
+    #[serde(default)]
+    pub max_files: Option<u64>,
+    /// URL to POST a JSON description of the breach to. It has a `text`
+    /// field, so Slack and Mattermost webhooks take it as it is.
+    #[serde(default)]
+    pub webhook: Option<String>,
+    /// A shell command; `{path}`, `{size}` (bytes), `{files}` and
+    /// `{message}` are replaced, quoted.
+    #[serde(default)]
+    pub command: Option<String>,
+    /// Minutes to stay quiet after alerting, however often the limit is
+    /// found broken in the meantime.
+    #[serde(default = "default_cooldown")]
+    pub cooldown_minutes: u64,
+}
+
+/// Something to do at set times, in the UI or `--daemon`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schedule {
+    /// When, in cron syntax: minute, hour, day of month, month and day of
+    /// week, like "0 3 * * *" for 3 am every day.
+    pub cron: String,
+    /// The directory to act on; the scanned root (each of them, in
+    /// `--daemon`) when left out.
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    /// "rescan", "snapshot" or "rules".
+    #[serde(default)]
+    pub action: ScheduleAction,
+}
+
+fn default_cooldown() -> u64 {
+    60
+}
+
+fn size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u128>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    check::parse_size(&text)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BarStyle {
+    Hash,  // [####      ], like ncdu
+    Block, // eighth-width Unicode blocks for a smoother bar
+}
+
+/// Where the config is read from unless `--config` says otherwise.
+pub fn default_path() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("dm").join("config.toml"))
+}
+
+/// Reads the config at `path`, or the default location. A missing default
+/// config is not an error; a missing explicit one is.
+pub fn load(path: Option<PathBuf>) -> Result<Config> {
+    let explicit = path.is_some();
+    let Some(path) = path.or_else(default_path) else {
+        return Ok(Config::default());
+    };
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound && !explicit => return Ok(Config::default()),
+        Err(e) => return Err(e).with_context(|| format!("Cannot read {}", path.display())),
+    };
+    let config: Config =
+        toml::from_str(&text).with_context(|| format!("Invalid config {}", path.display()))?;
+    for patterns in [&config.delete.type_name_paths, &config.delete.protected] {
+        glob_set(patterns).with_context(|| format!("Invalid config {}", path.display()))?;
+    }
+    for rule in &config.rules {
+        let problem = if rule.max_size.is_none() && rule.max_files.is_none() {
+            "needs max_size or max_files"
+        } else if rule.webhook.is_none() && rule.command.is_none() {
+            "needs a webhook or a command"
+        } else {
+            continue;
+        };
+        bail!(
+            "Invalid config {}: the rule for {} {problem}",
+            path.display(),
+            rule.path.display()
+        );
+    }
+    for entry in &config.schedules {
+        schedule::parse(&entry.cron)
+            .with_context(|| format!("Invalid config {}", path.display()))?;
+    }
+    if config.daemon.interval_minutes == 0 {
+        bail!(
+            "Invalid config {}: daemon.interval_minutes must be at least 1",
+            path.display()
+        );
+    }
+    Ok(config)
+}
+
+/// Writes `bookmarks` into the config at `path`, leaving the rest of it,
+/// comments included, as it is.
+pub fn save_bookmarks(path: &Path, bookmarks: &[PathBuf]) -> Result<()> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("Cannot read {}", path.display())),
+    };
+    let mut doc: toml_edit::DocumentMut = text
+        .parse()
+        .with_context(|| format!("Invalid config {}", path.display()))?;
+    let mut list = toml_edit::Array::new();
+    for bookmark in bookmarks {
+        let text = bookmark
+            .to_str()
+            .with_context(|| format!("{} is not valid UTF-8", bookmark.display()))?;
+        list.push_formatted(toml_edit::Value::from(text).decorated("\n    ", ""));
+    }
+    list.set_trailing_comma(true);
+    list.set_trailing(if bookmarks.is_empty() { "" } else { "\n" });
+    doc["bookmarks"] = toml_edit::value(list);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+    }
+    fs::write(path, doc.to_string()).with_context(|| format!("Cannot write {}", path.display()))
+}
+
+/// Compiles path globs from the config. `*` stays within one path
+/// component; `**` crosses them. A leading `~` is the home directory.
+pub fn glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let home = dirs::home_dir();
+    let mut set = GlobSetBuilder::new();
+    for pattern in patterns {
+        let expanded = match (pattern.strip_prefix('~'), &home) {
+            (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+                format!("{}{rest}", home.display())
+            }
+            _ => pattern.clone(),
+        };
+        let glob = GlobBuilder::new(&expanded)
+            .literal_separator(true)
+            .build()
+            .with_context(|| format!("Bad path pattern {pattern:?}"))?;
+        set.add(glob);
+    }
+    Ok(set.build()?)
+}
@@ -0,0 +1,306 @@
+//! `--daemon`: rescans a set of directories on a schedule and serves their
+//! sizes as Prometheus metrics, so disk growth can be graphed and alerted
+//! on. The config's `[[schedule]]`s run alongside, and its `[[rule]]`s are
+//! checked after every scan.
+
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::Local;
+use humansize::{format_size, DECIMAL};
+
+use dm_core::{mounts, DirStats, DirTree, ScanEvent, ScanOptions, Scanner, SizeMode};
+
+use crate::{
+    cache::Format,
+    config::{Config, Schedule},
+    rules::Rules,
+    schedule::{self, ScheduleAction, Schedules},
+    snapshot,
+};
+
+/// Most bytes of request line and headers read before giving up on it.
+const MAX_REQUEST: u64 = 16 * 1024;
+
+/// What the last scan of a path found.
+struct Sample {
+    stats: DirStats,
+    duration: Duration,
+    finished: SystemTime,
+}
+
+/// Latest samples by path, plus how many scans each has had.
+#[derive(Default)]
+struct Metrics {
+    samples: HashMap<PathBuf, Sample>,
+    scans: HashMap<PathBuf, u64>,
+}
+
+/// Scans `paths` every `[daemon] interval_minutes`, one after another, and
+/// serves the results on `listen` at `/metrics` until the process is
+/// stopped. The config's `[[schedule]]`s run in between, and limits in its
+/// `[[rule]]`s are measured in `mode`.
+pub fn run(
+    paths: Vec<PathBuf>,
+    opts: ScanOptions,
+    mode: SizeMode,
+    config: &Config,
+    listen: &str,
+) -> Result<()> {
+    let interval = Duration::from_secs(60 * config.daemon.interval_minutes);
+    let listener =
+        TcpListener::bind(listen).with_context(|| format!("Cannot listen on {listen}"))?;
+    log(format!(
+        "serving metrics for {} paths on http://{}/metrics, rescanning every {}m",
+        paths.len(),
+        listener.local_addr()?,
+        interval.as_secs() / 60
+    ));
+    let metrics = Arc::new(Mutex::new(Metrics::default()));
+    let mut scans = Scans {
+        opts,
+        mode,
+        rules: Rules::new(config.rules.clone()),
+        format: config.snapshot.format,
+        metrics: metrics.clone(),
+    };
+    let mut schedules = Schedules::new(&config.schedules);
+    {
+        let paths = paths.clone();
+        thread::spawn(move || {
+            let mut next_round = Instant::now();
+            loop {
+                if Instant::now() >= next_round {
+                    next_round = Instant::now() + interval;
+                    for path in &paths {
+                        scans.scan(path);
+                    }
+                }
+                for schedule in schedules.due() {
+                    scans.run_schedule(&schedule, &paths);
+                }
+                let mut wait = next_round.saturating_duration_since(Instant::now());
+                if let Some(until) = schedules.until_next() {
+                    wait = wait.min(until);
+                }
+                // The clock may be changed, so look again now and then.
+                thread::sleep(wait.min(Duration::from_secs(60)));
+            }
+        });
+    }
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        if let Err(e) = serve(stream, &paths, &metrics) {
+            log(format!("request failed: {e}"));
+        }
+    }
+    Ok(())
+}
+
+/// What the scan thread needs to scan a path and act on the result.
+struct Scans {
+    opts: ScanOptions,
+    mode: SizeMode,
+    rules: Rules,
+    format: Format, // of scheduled snapshots
+    metrics: Arc<Mutex<Metrics>>,
+}
+
+impl Scans {
+    /// Scans `path`, records it in the metrics and checks the rules for
+    /// it. Returns the tree unless the scan failed.
+    fn scan(&mut self, path: &Path) -> Option<DirTree> {
+        let started = Instant::now();
+        let tree = Scanner::new(path, self.opts.clone()).run(|event| match event {
+            ScanEvent::Warning(e) => log(e),
+            ScanEvent::Unreadable(_) | ScanEvent::Progress(_) | ScanEvent::Checkpoint(_) => {}
+        })?;
+        let stats = tree.stats(tree.root()).clone();
+        let duration = started.elapsed();
+        log(format!(
+            "scanned {} in {:.1}s: {}, {} files{}",
+            path.display(),
+            duration.as_secs_f64(),
+            format_size(stats.disk_bytes as u64, DECIMAL),
+            stats.file_count,
+            match stats.errors {
+                0 => String::new(),
+                n => format!(", {n} unreadable"),
+            }
+        ));
+        for alert in self.rules.check(&tree, path, self.mode) {
+            log(format!("alert: {}", alert.message()));
+            if let Err(e) = alert.send() {
+                log(format!("alert failed: {e:#}"));
+            }
+        }
+        let mut metrics = self.metrics.lock().unwrap();
+        *metrics.scans.entry(path.to_path_buf()).or_default() += 1;
+        metrics.samples.insert(
+            path.to_path_buf(),
+            Sample {
+                stats,
+                duration,
+                finished: SystemTime::now(),
+            },
+        );
+        Some(tree)
+    }
+
+    /// Carries out `schedule` for its path, or each of `paths`. No trees
+    /// are kept between scans, so every action starts with one, and a rule
+    /// check is no more than that.
+    fn run_schedule(&mut self, schedule: &Schedule, paths: &[PathBuf]) {
+        let targets = match &schedule.path {
+            Some(path) => vec![fs::canonicalize(path).map_or_else(|_| path.clone(), mounts::plain)],
+            None => paths.to_vec(),
+        };
+        log(format!(
+            "scheduled {} ({})",
+            schedule.action.label(),
+            schedule.cron
+        ));
+        for path in targets {
+            let Some(tree) = self.scan(&path) else {
+                continue;
+            };
+            if schedule.action == ScheduleAction::Snapshot {
+                let name = schedule::snapshot_name(&path);
+                match snapshot::save(&name, tree, self.format) {
+                    Ok(()) => log(format!("saved snapshot {name}")),
+                    Err(e) => log(format!("cannot save snapshot {name}: {e:#}")),
+                }
+            }
+        }
+    }
+}
+
+/// Answers one HTTP request: the metrics at `/metrics`, a pointer to them
+/// at `/`, and 404 for anything else.
+fn serve(mut stream: TcpStream, paths: &[PathBuf], metrics: &Mutex<Metrics>) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream).take(MAX_REQUEST);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // The headers go unused, but closing with them unread resets the
+    // connection, which can lose the reply on the client's end.
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            bail!("request cut off or over {MAX_REQUEST} bytes");
+        }
+        if line.trim_end().is_empty() {
+            break;
+        }
+    }
+    let target = request.split_whitespace().nth(1).unwrap_or("");
+    let (status, kind, body) = match target.split('?').next() {
+        Some("/metrics") => (
+            "200 OK",
+            "text/plain; version=0.0.4",
+            render(paths, &metrics.lock().unwrap()),
+        ),
+        Some("/") => (
+            "200 OK",
+            "text/html",
+            "<html><body><a href=\"/metrics\">Metrics</a></body></html>\n".to_string(),
+        ),
+        _ => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {kind}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    Ok(())
+}
+
+/// The metrics in Prometheus' text format. Paths not scanned yet are left
+/// out rather than reported as empty.
+fn render(paths: &[PathBuf], metrics: &Metrics) -> String {
+    type Value = fn(&Sample) -> f64;
+    let gauges: [(&str, &str, Value); 7] = [
+        (
+            "dm_path_disk_bytes",
+            "Disk usage of the path, hard links counted once.",
+            |s| s.stats.disk_bytes as f64,
+        ),
+        (
+            "dm_path_apparent_bytes",
+            "Apparent size of the files under the path.",
+            |s| s.stats.total_bytes as f64,
+        ),
+        ("dm_path_files", "Files under the path.", |s| {
+            s.stats.file_count as f64
+        }),
+        (
+            "dm_path_directories",
+            "Directories under the path, itself included.",
+            |s| s.stats.dir_count as f64,
+        ),
+        (
+            "dm_path_unreadable_entries",
+            "Entries the last scan couldn't read.",
+            |s| s.stats.errors as f64,
+        ),
+        (
+            "dm_scan_duration_seconds",
+            "How long the last scan of the path took.",
+            |s| s.duration.as_secs_f64(),
+        ),
+        (
+            "dm_scan_last_success_timestamp_seconds",
+            "When the last scan of the path finished.",
+            |s| {
+                s.finished
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0.0, |d| d.as_secs_f64())
+            },
+        ),
+    ];
+    let mut out = String::new();
+    for (name, help, value) in gauges {
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} gauge");
+        for path in paths {
+            if let Some(sample) = metrics.samples.get(path) {
+                let _ = writeln!(out, "{name}{{path=\"{}\"}} {}", label(path), value(sample));
+            }
+        }
+    }
+    let name = "dm_scans_total";
+    let _ = writeln!(
+        out,
+        "# HELP {name} Scans of the path finished since the daemon started."
+    );
+    let _ = writeln!(out, "# TYPE {name} counter");
+    for path in paths {
+        let count = metrics.scans.get(path).copied().unwrap_or(0);
+        let _ = writeln!(out, "{name}{{path=\"{}\"}} {count}", label(path));
+    }
+    out
+}
+
+/// `path` as a label value, with what the format gives a meaning escaped.
+fn label(path: &Path) -> String {
+    path.to_string_lossy()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn log(msg: impl std::fmt::Display) {
+    eprintln!("{} dm: {msg}", Local::now().format("%Y-%m-%d %H:%M:%S"));
+}
@@ -0,0 +1,170 @@
+//! Sizes from past scans in a local SQLite database, for seeing how
+//! directories grow over weeks rather than since the last scan.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use dm_core::tree::{DirTree, NodeId, SizeMode};
+
+/// Levels below the scanned directory that are recorded. Deeper directories
+/// are left out to keep the database small; scanning a deeper directory
+/// records its own levels.
+const DEPTH: usize = 3;
+
+/// A scan of a directory finishing sooner than this after the last recorded
+/// one is not recorded, so automatic rescans don't flood the database.
+const MIN_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Points returned for one directory, the most recent ones.
+const MAX_POINTS: usize = 500;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS scans (
+        id INTEGER PRIMARY KEY,
+        root TEXT NOT NULL,
+        finished INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS scans_root ON scans (root, finished);
+    CREATE TABLE IF NOT EXISTS sizes (
+        scan INTEGER NOT NULL REFERENCES scans (id),
+        path TEXT NOT NULL,
+        total_bytes INTEGER NOT NULL,
+        disk_bytes INTEGER NOT NULL,
+        file_count INTEGER NOT NULL,
+        PRIMARY KEY (path, scan)
+    ) WITHOUT ROWID;
+";
+
+/// The size of a directory as of one scan.
+#[derive(Debug, Clone)]
+pub struct Point {
+    pub at: SystemTime,
+    pub total_bytes: u128,
+    pub disk_bytes: u128,
+}
+
+impl Point {
+    pub fn bytes(&self, mode: SizeMode) -> u128 {
+        match mode {
+            SizeMode::Apparent => self.total_bytes,
+            SizeMode::Disk => self.disk_bytes,
+        }
+    }
+}
+
+pub fn path() -> Option<PathBuf> {
+    Some(dirs::data_dir()?.join("dm").join("history.sqlite"))
+}
+
+pub struct History {
+    conn: Connection,
+}
+
+impl History {
+    /// Opens the database, creating it if needed.
+    pub fn open() -> Result<Self> {
+        let path = path().context("No data directory to keep scan history in")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let conn =
+            Connection::open(&path).with_context(|| format!("Cannot open {}", path.display()))?;
+        // The UI reads while a background thread records a scan.
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.execute_batch(SCHEMA)
+            .with_context(|| format!("Cannot set up {}", path.display()))?;
+        Ok(Self { conn })
+    }
+
+    /// Records the sizes of `dir` and the directories up to [`DEPTH`]
+    /// levels below it, as found in `tree`. Returns false if the last
+    /// record of `dir` is too recent to add another.
+    pub fn record(&mut self, tree: &DirTree, dir: &Path, finished: SystemTime) -> Result<bool> {
+        let Some(top) = tree.find(dir) else {
+            return Ok(false);
+        };
+        let root = dir.to_string_lossy();
+        let finished = secs(finished);
+        let last: Option<i64> = self
+            .conn
+            .query_row(
+                "SELECT MAX(finished) FROM scans WHERE root = ?1",
+                params![root],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten();
+        if last.is_some_and(|t| finished - t < MIN_INTERVAL.as_secs() as i64) {
+            return Ok(false);
+        }
+
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT INTO scans (root, finished) VALUES (?1, ?2)",
+            params![root, finished],
+        )?;
+        let scan = tx.last_insert_rowid();
+        {
+            let mut insert = tx.prepare(
+                "INSERT OR REPLACE INTO sizes (scan, path, total_bytes, disk_bytes, file_count)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            let mut stack: Vec<(NodeId, PathBuf, usize)> = vec![(top, tree.path(top), 0)];
+            while let Some((id, path, depth)) = stack.pop() {
+                let s = tree.stats(id);
+                insert.execute(params![
+                    scan,
+                    path.to_string_lossy(),
+                    clamp(s.total_bytes),
+                    clamp(s.disk_bytes),
+                    clamp(s.file_count as u128),
+                ])?;
+                if depth < DEPTH {
+                    stack.extend(
+                        tree.children(id)
+                            .iter()
+                            .map(|&c| (c, path.join(tree.name(c)), depth + 1)),
+                    );
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// The recorded sizes of `dir`, oldest first.
+    pub fn sizes(&self, dir: &Path) -> Result<Vec<Point>> {
+        let mut query = self.conn.prepare_cached(
+            "SELECT finished, total_bytes, disk_bytes FROM (
+                 SELECT scans.finished, total_bytes, disk_bytes
+                 FROM sizes JOIN scans ON scans.id = sizes.scan
+                 WHERE sizes.path = ?1
+                 ORDER BY scans.finished DESC LIMIT ?2
+             ) ORDER BY finished",
+        )?;
+        let rows = query.query_map(params![dir.to_string_lossy(), MAX_POINTS], |row| {
+            Ok(Point {
+                at: UNIX_EPOCH + Duration::from_secs(row.get::<_, i64>(0)?.max(0) as u64),
+                total_bytes: row.get::<_, i64>(1)?.max(0) as u128,
+                disk_bytes: row.get::<_, i64>(2)?.max(0) as u128,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+fn secs(t: SystemTime) -> i64 {
+    t.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+/// SQLite integers are 64-bit signed.
+fn clamp(n: u128) -> i64 {
+    n.min(i64::MAX as u128) as i64
+}
@@ -0,0 +1,343 @@
+//! `dm report --format html`: one self-contained page with a sortable table
+//! of the largest directories and a treemap of the scan, to attach to a
+//! ticket or mail to whoever owns the space.
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use humansize::{format_size, DECIMAL};
+use thousands::Separable;
+
+use dm_core::tree::{DirStats, DirTree, NodeId, SizeMode};
+
+/// Size of the treemap's drawing area, scaled to the page width.
+const MAP_WIDTH: f64 = 1200.0;
+const MAP_HEIGHT: f64 = 720.0;
+
+/// Levels the treemap nests when the report doesn't limit the depth.
+const MAP_DEPTH: usize = 3;
+
+/// Height of the name strip above a directory's nested children.
+const LABEL_HEIGHT: f64 = 16.0;
+
+const STYLE: &str = "
+body { font: 14px system-ui, sans-serif; margin: 2em; color: #222; }
+h1 { font-size: 1.4em; margin-bottom: 0.2em; }
+.meta { color: #666; margin-top: 0; }
+svg { width: 100%; height: auto; border: 1px solid #ccc; }
+svg rect { stroke: #fff; stroke-width: 1; }
+svg text { font-size: 11px; fill: #fff; pointer-events: none; }
+table { border-collapse: collapse; margin-top: 2em; width: 100%; }
+th, td { padding: 0.25em 0.75em; border-bottom: 1px solid #eee; }
+th { cursor: pointer; text-align: left; background: #f4f4f4; user-select: none; }
+th.sorted::after { content: ' \\25BE'; }
+th.sorted.asc::after { content: ' \\25B4'; }
+td.num, th.num { text-align: right; font-variant-numeric: tabular-nums; }
+tr.total td { font-weight: bold; }
+";
+
+/// Sorts the table by the clicked column, by the raw number in `data-v`
+/// where cells have one.
+const SCRIPT: &str = "
+document.querySelectorAll('th').forEach((th, col) => th.addEventListener('click', () => {
+  const body = th.closest('table').tBodies[0];
+  const asc = th.classList.contains('sorted') && !th.classList.contains('asc');
+  document.querySelectorAll('th').forEach(h => h.classList.remove('sorted', 'asc'));
+  th.classList.add('sorted');
+  if (asc) th.classList.add('asc');
+  const key = td => td.dataset.v !== undefined ? Number(td.dataset.v) : td.textContent;
+  const rows = Array.from(body.rows);
+  rows.sort((a, b) => {
+    const x = key(a.cells[col]), y = key(b.cells[col]);
+    const c = typeof x === 'number' ? x - y : x.localeCompare(y);
+    return asc ? c : -c;
+  });
+  rows.forEach(r => body.appendChild(r));
+}));
+";
+
+/// Writes the page for `tree`: `dirs` in the table, biggest first by
+/// `mode`, then `root`'s totals, and a treemap `depth` levels deep.
+pub fn write(
+    tree: &DirTree,
+    dirs: &[(PathBuf, &DirStats)],
+    mode: SizeMode,
+    depth: Option<usize>,
+    out: &mut impl Write,
+) -> Result<()> {
+    let root = tree.stats(tree.root());
+    let title = format!("Disk usage of {}", tree.root_path().display());
+    let generated = DateTime::<Local>::from(SystemTime::now()).format("%Y-%m-%d %H:%M");
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html lang=\"en\"><head><meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>{}</title>", escape(&title))?;
+    writeln!(out, "<style>{STYLE}</style></head><body>")?;
+    writeln!(out, "<h1>{}</h1>", escape(&title))?;
+    writeln!(
+        out,
+        "<p class=\"meta\">{} ({}), {} files in {} directories{}. Generated by dm {} on {generated}.</p>",
+        format_size(root.bytes(mode) as u64, DECIMAL),
+        mode.label(),
+        root.file_count.separate_with_spaces(),
+        root.dir_count.separate_with_spaces(),
+        match (root.errors, root.estimated) {
+            (0, 0) => String::new(),
+            (0, _) => "; sizes are rough, some directories were sampled or not read".into(),
+            (n, _) => format!("; {} entries couldn't be read", n.separate_with_spaces()),
+        },
+        env!("CARGO_PKG_VERSION"),
+    )?;
+
+    write_treemap(tree, mode, depth.unwrap_or(MAP_DEPTH), out)?;
+
+    writeln!(out, "<table><thead><tr>")?;
+    writeln!(
+        out,
+        "<th>Directory</th><th class=\"num sorted\">Size</th><th class=\"num\">Apparent</th>\
+         <th class=\"num\">On disk</th><th class=\"num\">Files</th><th class=\"num\">Dirs</th>"
+    )?;
+    writeln!(out, "</tr></thead><tbody>")?;
+    for (path, s) in dirs {
+        write_row(out, path, s, mode, "")?;
+    }
+    writeln!(out, "</tbody><tfoot>")?;
+    write_row(out, tree.root_path(), root, mode, " class=\"total\"")?;
+    writeln!(out, "</tfoot></table>")?;
+    writeln!(out, "<script>{SCRIPT}</script></body></html>")?;
+    Ok(())
+}
+
+fn write_row(
+    out: &mut impl Write,
+    path: &Path,
+    s: &DirStats,
+    mode: SizeMode,
+    class: &str,
+) -> Result<()> {
+    let size = |bytes: u128| {
+        format!(
+            "<td class=\"num\" data-v=\"{bytes}\">{}</td>",
+            format_size(bytes as u64, DECIMAL)
+        )
+    };
+    let count = |n: u64| {
+        format!(
+            "<td class=\"num\" data-v=\"{n}\">{}</td>",
+            n.separate_with_spaces()
+        )
+    };
+    writeln!(
+        out,
+        "<tr{class}><td>{}{}</td>{}{}{}{}{}</tr>",
+        escape(&path.to_string_lossy()),
+        if s.estimated > 0 { " ~" } else { "" },
+        size(s.bytes(mode)),
+        size(s.total_bytes),
+        size(s.disk_bytes),
+        count(s.file_count),
+        count(s.dir_count),
+    )?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Area {
+    x: f64,
+    y: f64,
+    w: f64,
+    h: f64,
+}
+
+fn write_treemap(tree: &DirTree, mode: SizeMode, depth: usize, out: &mut impl Write) -> Result<()> {
+    writeln!(
+        out,
+        "<svg viewBox=\"0 0 {MAP_WIDTH} {MAP_HEIGHT}\" xmlns=\"http://www.w3.org/2000/svg\">"
+    )?;
+    let area = Area {
+        x: 0.0,
+        y: 0.0,
+        w: MAP_WIDTH,
+        h: MAP_HEIGHT,
+    };
+    let children = tree.children(tree.root());
+    for (hue, (id, area)) in squarify(tree, tree.root(), children, mode, area)
+        .into_iter()
+        .enumerate()
+    {
+        // Each top-level directory keeps its color all the way down.
+        draw(tree, id, mode, area, 1, depth, (hue * 47 % 360) as u32, out)?;
+    }
+    writeln!(out, "</svg>")?;
+    Ok(())
+}
+
+/// Draws directory `id` into `area`, then its children inside it while
+/// `level` is within `depth` and there's room for them.
+#[allow(clippy::too_many_arguments)]
+fn draw(
+    tree: &DirTree,
+    id: NodeId,
+    mode: SizeMode,
+    area: Area,
+    level: usize,
+    depth: usize,
+    hue: u32,
+    out: &mut impl Write,
+) -> Result<()> {
+    // Too small to see or hover, and so would be anything inside.
+    if area.w * area.h < 4.0 {
+        return Ok(());
+    }
+    let s = tree.stats(id);
+    let path = tree.path(id);
+    let name = file_name(&path);
+    let light = 30 + 10 * level.min(4);
+    writeln!(
+        out,
+        "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"hsl({hue},45%,{light}%)\">\
+         <title>{} ({})</title></rect>",
+        area.x,
+        area.y,
+        area.w,
+        area.h,
+        escape(&path.to_string_lossy()),
+        format_size(s.bytes(mode) as u64, DECIMAL),
+    )?;
+    // Roughly 6.5 units per character at this font size.
+    let fits = (area.w / 6.5) as usize;
+    if area.h >= 14.0 && fits >= 4 {
+        let label: String = if name.chars().count() > fits {
+            name.chars().take(fits - 1).chain(['…']).collect()
+        } else {
+            name
+        };
+        writeln!(
+            out,
+            "<text x=\"{:.1}\" y=\"{:.1}\">{}</text>",
+            area.x + 3.0,
+            area.y + 12.0,
+            escape(&label)
+        )?;
+    }
+    if level >= depth || area.w < 40.0 || area.h < LABEL_HEIGHT + 20.0 {
+        return Ok(());
+    }
+    let inner = Area {
+        x: area.x + 2.0,
+        y: area.y + LABEL_HEIGHT,
+        w: area.w - 4.0,
+        h: area.h - LABEL_HEIGHT - 2.0,
+    };
+    for (child, area) in squarify(tree, id, tree.children(id), mode, inner) {
+        draw(tree, child, mode, area, level + 1, depth, hue, out)?;
+    }
+    Ok(())
+}
+
+/// Lays `children` of `parent` out in `area` as close to square as they
+/// go, each taking its share of the parent's size. What the parent's own
+/// files take is left empty.
+fn squarify(
+    tree: &DirTree,
+    parent: NodeId,
+    children: &[NodeId],
+    mode: SizeMode,
+    area: Area,
+) -> Vec<(NodeId, Area)> {
+    let total = tree.stats(parent).bytes(mode) as f64;
+    let mut items: Vec<(Option<NodeId>, f64)> = children
+        .iter()
+        .map(|&c| (Some(c), tree.stats(c).bytes(mode) as f64))
+        .filter(|&(_, bytes)| bytes > 0.0)
+        .collect();
+    let counted: f64 = items.iter().map(|&(_, bytes)| bytes).sum();
+    if total <= 0.0 || counted <= 0.0 {
+        return Vec::new();
+    }
+    items.push((None, (total - counted).max(0.0)));
+    items.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let scale = area.w * area.h / total.max(counted);
+    let sizes: Vec<f64> = items.iter().map(|&(_, bytes)| bytes * scale).collect();
+
+    let mut laid = Vec::new();
+    let mut rest = area;
+    let mut i = 0;
+    while i < sizes.len() && sizes[i] > 0.0 {
+        let side = rest.w.min(rest.h);
+        let mut j = i + 1;
+        while j < sizes.len() && worst(&sizes[i..=j], side) <= worst(&sizes[i..j], side) {
+            j += 1;
+        }
+        let row: f64 = sizes[i..j].iter().sum();
+        let thickness = row / side;
+        let mut offset = 0.0;
+        for k in i..j {
+            let length = sizes[k] / thickness;
+            let cell = if rest.w >= rest.h {
+                Area {
+                    x: rest.x,
+                    y: rest.y + offset,
+                    w: thickness,
+                    h: length,
+                }
+            } else {
+                Area {
+                    x: rest.x + offset,
+                    y: rest.y,
+                    w: length,
+                    h: thickness,
+                }
+            };
+            offset += length;
+            if let Some(id) = items[k].0 {
+                laid.push((id, cell));
+            }
+        }
+        if rest.w >= rest.h {
+            rest.x += thickness;
+            rest.w -= thickness;
+        } else {
+            rest.y += thickness;
+            rest.h -= thickness;
+        }
+        i = j;
+    }
+    laid
+}
+
+/// The worst aspect ratio among `row` laid along a side of length `side`.
+fn worst(row: &[f64], side: f64) -> f64 {
+    let sum: f64 = row.iter().sum();
+    let max = row.iter().copied().fold(f64::MIN, f64::max);
+    let min = row.iter().copied().fold(f64::MAX, f64::min);
+    let side2 = side * side;
+    (side2 * max / (sum * sum)).max(sum * sum / (side2 * min))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map_or_else(
+        || path.display().to_string(),
+        |n| n.to_string_lossy().into_owned(),
+    )
+}
+
+/// `text` with the characters HTML gives a meaning escaped.
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,166 @@
+//! Background threads running the engine's scans, searches and fixes for
+//! the UI, each reporting back through the event loop's channel.
+
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use humansize::{format_size, DECIMAL};
+
+use dm_core::{
+    audit::find_flagged,
+    broken::find_broken,
+    dupes::{find_duplicate_dirs, find_duplicates, link_copy, link_dir, Link},
+    empty::{find_empty, prune},
+    old::find_old_files,
+    ScanOptions, Scanner,
+};
+
+use crate::{DupesOf, Msg};
+
+/// Runs scan `id` to completion, forwarding its events and then the tree,
+/// unless it's cancelled first.
+pub fn spawn_scan_thread(id: u64, scanner: Scanner, tx: Sender<Msg>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let tree = scanner.run(|event| {
+            let _ = tx.send(Msg::Scan(id, event));
+        });
+        if let Some(tree) = tree {
+            let _ = tx.send(Msg::ScanFinished(id, tree));
+        }
+    })
+}
+
+/// Searches `root` for duplicate files or directories on a background
+/// thread and sends the result, unless `cancel` is set first.
+pub fn spawn_dupes_thread(
+    root: PathBuf,
+    of: DupesOf,
+    opts: ScanOptions,
+    cancel: Arc<AtomicBool>,
+    tx: Sender<Msg>,
+) {
+    thread::spawn(move || {
+        let groups = match of {
+            DupesOf::Files => find_duplicates(&root, &opts, &cancel),
+            DupesOf::Dirs => find_duplicate_dirs(&root, &opts, false, &cancel),
+            DupesOf::DirContents => find_duplicate_dirs(&root, &opts, true, &cancel),
+        };
+        if !cancel.load(Ordering::Relaxed) {
+            let _ = tx.send(Msg::DupesFinished(root, of, groups));
+        }
+    });
+}
+
+/// Replaces each copy in `links` with a hard link to the one kept, or for
+/// directories their files with links to those of the one kept, reporting
+/// each separately and then what it all freed. A rescan follows so the
+/// totals count the links once.
+pub fn spawn_link_thread(links: Vec<Link>, tx: Sender<Msg>) {
+    thread::spawn(move || {
+        let (mut linked, mut freed) = (0, 0);
+        for link in &links {
+            let res = if link.keep.is_dir() {
+                link_dir(&link.keep, &link.copy).map(|_| ())
+            } else {
+                link_copy(&link.keep, &link.copy)
+            };
+            if res.is_ok() {
+                linked += 1;
+                freed += link.bytes;
+            }
+            let res = res.map_err(|e| format!("{e}"));
+            let _ = tx.send(Msg::LinkFinished(link.copy.clone(), res));
+        }
+        let _ = tx.send(Msg::Info(format!(
+            "Hard links freed about {} ({linked} of {} copies linked)",
+            format_size(freed as u64, DECIMAL),
+            links.len()
+        )));
+        let _ = tx.send(Msg::RecomputeNow);
+    });
+}
+
+/// Searches `root` for empty directories and files on a background thread
+/// and sends the result, unless `cancel` is set first.
+pub fn spawn_empty_thread(
+    root: PathBuf,
+    opts: ScanOptions,
+    cancel: Arc<AtomicBool>,
+    tx: Sender<Msg>,
+) {
+    thread::spawn(move || {
+        let found = find_empty(&root, &opts, &cancel);
+        if !cancel.load(Ordering::Relaxed) {
+            let _ = tx.send(Msg::EmptyFinished(root, found));
+        }
+    });
+}
+
+/// Searches `root` for big files left unused for long on a background
+/// thread and sends the result, unless `cancel` is set first.
+pub fn spawn_old_files_thread(
+    root: PathBuf,
+    opts: ScanOptions,
+    min_bytes: u64,
+    unused_for: Duration,
+    cancel: Arc<AtomicBool>,
+    tx: Sender<Msg>,
+) {
+    thread::spawn(move || {
+        let found = find_old_files(&root, &opts, min_bytes, unused_for, &cancel);
+        if !cancel.load(Ordering::Relaxed) {
+            let _ = tx.send(Msg::OldFilesFinished(root, found));
+        }
+    });
+}
+
+/// Removes each of `dirs` with the empty directories below it, reporting
+/// each separately with the number of directories removed.
+pub fn spawn_prune_thread(dirs: Vec<PathBuf>, tx: Sender<Msg>) {
+    thread::spawn(move || {
+        for dir in dirs {
+            let res = prune(&dir).map_err(|e| format!("{e}"));
+            let _ = tx.send(Msg::PruneFinished(dir, res));
+        }
+    });
+}
+
+/// Searches `root` for broken links on a background thread and sends the
+/// result, unless `cancel` is set first.
+pub fn spawn_broken_thread(
+    root: PathBuf,
+    opts: ScanOptions,
+    cancel: Arc<AtomicBool>,
+    tx: Sender<Msg>,
+) {
+    thread::spawn(move || {
+        let found = find_broken(&root, &opts, &cancel);
+        if !cancel.load(Ordering::Relaxed) {
+            let _ = tx.send(Msg::BrokenFinished(root, found));
+        }
+    });
+}
+
+/// Sweeps `root` for loose permissions and owners gone on a background
+/// thread and sends what it flagged, unless `cancel` is set first.
+pub fn spawn_audit_thread(
+    root: PathBuf,
+    opts: ScanOptions,
+    cancel: Arc<AtomicBool>,
+    tx: Sender<Msg>,
+) {
+    thread::spawn(move || {
+        let found = find_flagged(&root, &opts, &cancel);
+        if !cancel.load(Ordering::Relaxed) {
+            let _ = tx.send(Msg::AuditFinished(root, found));
+        }
+    });
+}
@@ -1,83 +1,915 @@
+mod actions;
+mod apfs;
+mod archive;
+mod bench;
+mod btrfs;
+mod cache;
+mod check;
+mod clipboard;
+mod compress;
+mod config;
+mod daemon;
+mod history;
+mod html;
+mod jobs;
+mod ncdu;
+mod recycle;
+mod remote;
+mod report;
+mod rules;
+mod schedule;
+mod snapshot;
+mod table;
+mod term;
+mod watch;
+mod wsl;
+
 use std::{
-    collections::VecDeque,
+    cell::Cell,
+    collections::{BTreeSet, HashMap, VecDeque},
+    env,
+    ffi::{OsStr, OsString},
     fs, io,
     path::{Path, PathBuf},
-    sync::mpsc::{self, Receiver, Sender},
+    process::{self, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
     thread,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
 
 use anyhow::{Context, Result};
 use chrono::{Local, Timelike};
-use crossterm::{
-    event::{self, Event as CEvent, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
-    execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+use clap::{Parser, Subcommand};
+use crossterm::event::{
+    self, Event as CEvent, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseButton, MouseEvent,
+    MouseEventKind,
 };
+use globset::GlobSet;
 use humansize::{format_size, DECIMAL};
+use ignore::gitignore::GitignoreBuilder;
 use ratatui::{
     backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout, Rect},
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, Clear, List, ListItem, Paragraph, Wrap},
+    widgets::{
+        Block, Borders, Clear, List, ListItem, ListState, Paragraph, Scrollbar,
+        ScrollbarOrientation, ScrollbarState, Sparkline, Wrap,
+    },
     Frame, Terminal,
 };
-use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use thousands::Separable;
-use walkdir::WalkDir;
 
-// ====== Data types ======
+use dm_core::{
+    audit::{Flagged, Problem},
+    broken::BrokenLink,
+    cleanup::{self, Cache},
+    dupes::{DupGroup, Link},
+    filetype::{self, Grouping},
+    meta::Kind,
+    mounts::{self, Mount, Owner, Quota},
+    owners::{self, Names, OwnerKey},
+    power,
+    s3::{self, S3},
+    scan::{
+        build_excludes, list_files, owner_of, resident_bytes, Pause, Profile, ScanCounters,
+        ScanEvent, ScanOptions, Scanner,
+    },
+    sftp::{self, Sftp},
+    tree::{DirStats, DirTree, NodeId, SizeMode, TypeTotals},
+    vfs::{self, Vfs},
+};
+
+use crate::{
+    actions::{command_line, quote, shell_command, spawn_action},
+    bench::BenchArgs,
+    btrfs::Subvolume,
+    check::CheckArgs,
+    compress::{
+        archive_path, spawn_compress_thread, spawn_estimate_thread, Codec, Estimate, Packed, Phase,
+    },
+    config::{BarConfig, BarStyle, Config, ScanConfig},
+    history::{History, Point},
+    jobs::{
+        spawn_audit_thread, spawn_broken_thread, spawn_dupes_thread, spawn_empty_thread,
+        spawn_link_thread, spawn_old_files_thread, spawn_prune_thread, spawn_scan_thread,
+    },
+    ncdu::Import,
+    remote::{AgentArgs, Remote},
+    report::ReportArgs,
+    rules::Rules,
+    schedule::{ScheduleAction, Schedules},
+    snapshot::Saved,
+    watch::{spawn_watcher, DirUpdate},
+};
+
+// ====== CLI ======
+
+/// Interactive disk usage browser.
+#[derive(Debug, Parser)]
+#[command(name = "dm", version, about, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Directories to browse (defaults to the current directory). With
+    /// several paths, Tab cycles between them. A single
+    /// `sftp://[user@]host[:port]/path` browses another machine over SSH,
+    /// and `s3://bucket/prefix` a bucket. On Windows, UNC paths like
+    /// `\\server\share\dir` work too.
+    #[arg(value_name = "PATH")]
+    paths: Vec<PathBuf>,
+
+    /// Which size to show and sort by; toggle at runtime with 'a'. Apparent
+    /// size unless the scan profile says otherwise.
+    #[arg(long, global = true, value_enum)]
+    size_mode: Option<SizeMode>,
+
+    /// Scan with a preset: `quick` reads a few levels deep and samples big
+    /// directories, for a rough picture in moments; `thorough` reads
+    /// everything, shows disk usage and counts hard links once. Switch at
+    /// runtime with 'Q'. Overrides `scan.profile` in the config.
+    #[arg(long, global = true, value_enum)]
+    profile: Option<Profile>,
+
+    /// Read directories at most N levels below the scanned one. Deeper ones
+    /// are listed but not read, so their sizes are left out of the totals;
+    /// 'r' in one of them reads N levels further. Overrides the depth of
+    /// the scan profile.
+    #[arg(long, global = true, value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// Count the size of hard-linked files every time they are seen
+    /// instead of only once.
+    #[arg(short = 'l', long, global = true)]
+    count_links: bool,
+
+    /// Don't descend into directories on other filesystems (toggle with 'x').
+    #[arg(short = 'x', long, global = true)]
+    one_file_system: bool,
+
+    /// Follow symlinks (and on Windows junctions) to directories instead of
+    /// listing them unscanned. Each directory is counted once however many
+    /// links lead to it, so links into the scanned tree or back above it
+    /// are still left alone.
+    #[arg(short = 'L', long, global = true)]
+    follow_links: bool,
+
+    /// Walk pseudo filesystems like /proc, /sys and /dev too. They are
+    /// skipped by default, as their sizes are made up and some of their
+    /// files never finish reading.
+    #[arg(long, global = true)]
+    include_pseudo: bool,
+
+    /// Threads reading directories during a scan; 0 starts one per CPU.
+    /// Overrides `scan.threads` in the config.
+    #[arg(short = 'j', long, global = true, value_name = "N")]
+    threads: Option<usize>,
+
+    /// Scan at the lowest CPU and disk priority (ionice idle on Linux,
+    /// background QoS on macOS), e.g. to spare a busy disk. Overrides
+    /// `scan.low_priority` in the config.
+    #[arg(long, global = true)]
+    low_priority: bool,
+
+    /// On Windows, read the whole tree of a local NTFS volume from its
+    /// Master File Table instead of walking it: seconds rather than minutes
+    /// for a full drive. Needs an elevated prompt; without one, or on other
+    /// filesystems, the scan walks as usual. Overrides `scan.mft` in the
+    /// config.
+    #[arg(long, global = true)]
+    mft: bool,
+
+    /// Look up each file's extents to tell how much of it is shared with
+    /// reflinked copies (`cp --reflink`) or snapshots on btrfs and XFS, so
+    /// deleting it frees less than its size. Opens every file, so scans
+    /// take longer. Linux only. Overrides `scan.reflinks` in the config.
+    #[arg(long, global = true)]
+    reflinks: bool,
+
+    /// Give up on a directory that doesn't list within this many seconds,
+    /// like one on a hung network mount, and on everything below it. It's
+    /// marked as timed out and the scan goes on without it. After four on
+    /// one filesystem, the rest of that filesystem is given up on too.
+    /// Defaults to 60 on network filesystems, 0 waits forever. Overrides
+    /// `scan.dir_timeout` in the config.
+    #[arg(long, global = true, value_name = "SECS")]
+    dir_timeout: Option<u64>,
+
+    /// Keep directories only down to N levels below the scanned one. Deeper
+    /// ones are still walked and counted, but into the one they're in at
+    /// level N, which then can't be opened further; 'r' in it scans N
+    /// levels below it. For trees too big to keep whole in memory.
+    /// Overrides `scan.keep_depth` in the config.
+    #[arg(long, global = true, value_name = "N")]
+    keep_depth: Option<usize>,
+
+    /// Memory dm may take, like "2GB". Once a scan goes past it, it stops
+    /// keeping directories below the level it has got to, as if with
+    /// `--keep-depth`. Overrides `scan.memory_budget` in the config.
+    #[arg(long, global = true, value_name = "SIZE", value_parser = check::parse_size)]
+    memory_budget: Option<u128>,
+
+    /// Hold scans to this many filesystem operations per second, each
+    /// directory listed and each file looked up counting as one, so a scan
+    /// of a busy server's disk doesn't slow everything else on it down.
+    /// Overrides `scan.io_limit` in the config.
+    #[arg(long, global = true, value_name = "OPS")]
+    io_limit: Option<u64>,
+
+    /// Skip paths matching this gitignore-style glob (repeatable). Patterns
+    /// from `.dmignore` in the scanned directory and in $HOME apply as well.
+    #[arg(long, global = true, value_name = "PATTERN")]
+    exclude: Vec<String>,
+
+    /// Don't show or update the cached results of previous runs, nor
+    /// resume a scan that didn't finish from its checkpoint.
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Read settings from this file instead of the default
+    /// `<config dir>/dm/config.toml`.
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Minutes between automatic rescans, 0 for none (pause with 'p').
+    /// Overrides `auto_rescan_minutes` in the config.
+    #[arg(long, value_name = "MINUTES")]
+    auto_rescan: Option<u64>,
+
+    /// Compare against snapshot NAME (saved with 'S') in the changes view
+    /// instead of the previous scan.
+    #[arg(long, value_name = "NAME")]
+    compare: Option<String>,
+
+    /// Don't watch the scanned tree for changes (toggle with 'w'), e.g. on
+    /// network filesystems. Overrides `watch` in the config.
+    #[arg(long)]
+    no_watch: bool,
+
+    /// Disable deleting and linking, for looking around safely. Overrides
+    /// `read_only` in the config.
+    #[arg(long, global = true)]
+    read_only: bool,
+
+    /// Start by picking a mounted filesystem to scan (also 'M') instead of
+    /// scanning PATH.
+    #[arg(long, conflicts_with = "import")]
+    mounts: bool,
+
+    /// Leave the mouse to the terminal, e.g. to keep its copy and paste.
+    #[arg(long)]
+    no_mouse: bool,
+
+    /// Scan PATH and write it to FILE ("-" for stdout) in ncdu's JSON
+    /// export format instead of starting the browser.
+    #[arg(short = 'o', long = "export", value_name = "FILE")]
+    export: Option<PathBuf>,
+
+    /// Scan PATH and write every directory to FILE ("-" for stdout) as
+    /// CSV: path, depth, bytes on disk, apparent bytes, files, dirs, newest
+    /// mtime and errors.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["export", "export_tsv", "import"])]
+    export_csv: Option<PathBuf>,
+
+    /// Like --export-csv, but tab-separated.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["export", "import"])]
+    export_tsv: Option<PathBuf>,
+
+    /// Browse an ncdu JSON export ("-" for stdin) instead of scanning.
+    /// Rescans and deletes are disabled.
+    #[arg(
+        short = 'f',
+        long = "import",
+        value_name = "FILE",
+        conflicts_with_all = ["paths", "export"]
+    )]
+    import: Option<PathBuf>,
+
+    /// Rescan PATHs (or `daemon.paths` from the config) on a schedule and
+    /// serve their sizes as Prometheus metrics instead of starting the
+    /// browser.
+    #[arg(long, conflicts_with_all = ["import", "export", "mounts", "snapshot"])]
+    daemon: bool,
+
+    /// Address for --daemon to serve metrics on. Overrides `daemon.listen`
+    /// in the config.
+    #[arg(long, value_name = "ADDR", requires = "daemon")]
+    listen: Option<String>,
+
+    /// Browse snapshot NAME (saved with 'S') instead of scanning. Rescans
+    /// and deletes are disabled until 'b' in the snapshots menu goes back
+    /// to scanning PATH.
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["import", "export", "mounts"])]
+    snapshot: Option<String>,
+
+    /// Browse the machine `dm agent` runs on, scanning and deleting through
+    /// the agent at ADDR, e.g. a port forwarded with `ssh -L`. PATHs are on
+    /// that machine; without one, the agent's working directory is scanned.
+    #[arg(
+        long,
+        value_name = "ADDR",
+        conflicts_with_all = ["import", "export", "export_csv", "export_tsv", "mounts", "daemon"]
+    )]
+    connect: Option<String>,
+}
+
+/// A PATH on something other than this machine's filesystem.
+enum Foreign {
+    Sftp(sftp::Url),
+    S3(s3::Url),
+}
+
+impl Foreign {
+    /// Connects to where the path is.
+    fn open(&self) -> io::Result<Arc<dyn Vfs>> {
+        Ok(match self {
+            Foreign::Sftp(url) => Sftp::connect(url)?,
+            Foreign::S3(url) => Arc::new(S3::open(url)?),
+        })
+    }
+
+    fn path(&self) -> &Path {
+        match self {
+            Foreign::Sftp(url) => &url.path,
+            Foreign::S3(url) => &url.path,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Scan a directory and print its largest subdirectories, no TUI.
+    Report(ReportArgs),
+    /// Scan a directory and exit with status 1 if it's over a size or file
+    /// count limit, listing the subdirectories taking the most; status 2
+    /// if some of it couldn't be read, so the totals can't be trusted.
+    Check(CheckArgs),
+    /// Scan a directory with different thread counts and methods and
+    /// compare their speed, lookups saved and peak memory.
+    Bench(BenchArgs),
+    /// Let `dm --connect` on another machine scan and delete on this one.
+    Agent(AgentArgs),
+}
+
+impl Cli {
+    /// Resolves the given paths to absolute directories, failing with a
+    /// readable message for anything that isn't one.
+    fn start_dirs(&self) -> Result<Vec<PathBuf>> {
+        let paths = match &self.command {
+            Some(Command::Report(args)) => args.path.iter().cloned().collect(),
+            Some(Command::Check(args)) => args.path.iter().cloned().collect(),
+            Some(Command::Bench(args)) => args.path.iter().cloned().collect(),
+            Some(Command::Agent(_)) | None => self.paths.clone(),
+        };
+        if paths.is_empty() {
+            let cwd = std::env::current_dir().context("Unable to get current directory")?;
+            return Ok(vec![cwd]);
+        }
+        paths
+            .iter()
+            .map(|p| {
+                let md =
+                    fs::metadata(p).with_context(|| format!("Cannot open '{}'", p.display()))?;
+                if !md.is_dir() {
+                    anyhow::bail!("'{}' is not a directory", p.display());
+                }
+                fs::canonicalize(p)
+                    .map(mounts::plain)
+                    .with_context(|| format!("Cannot resolve '{}'", p.display()))
+            })
+            .collect()
+    }
+
+    /// The `sftp://` or `s3://` URL given as PATH, if there's one. It has
+    /// to be the only PATH, and it can only be browsed.
+    fn foreign_url(&self) -> Result<Option<Foreign>> {
+        let Some(text) = self
+            .paths
+            .iter()
+            .filter_map(|p| p.to_str())
+            .find(|p| p.starts_with("sftp://") || p.starts_with("s3://"))
+        else {
+            return Ok(None);
+        };
+        let url = if text.starts_with("s3://") {
+            s3::Url::parse(text)
+                .map(Foreign::S3)
+                .with_context(|| format!("Bad URL '{text}', expected s3://bucket/prefix"))?
+        } else {
+            sftp::Url::parse(text).map(Foreign::Sftp).with_context(|| {
+                format!("Bad URL '{text}', expected sftp://[user@]host[:port]/path")
+            })?
+        };
+        if self.paths.len() > 1 {
+            anyhow::bail!("An sftp:// or s3:// PATH has to be the only one");
+        }
+        let headless = self.daemon
+            || self.mounts
+            || self.export.is_some()
+            || self.export_csv.is_some()
+            || self.export_tsv.is_some();
+        if headless || self.connect.is_some() || self.import.is_some() || self.snapshot.is_some() {
+            anyhow::bail!("sftp:// and s3:// PATHs can only be browsed");
+        }
+        Ok(Some(url))
+    }
 
-#[derive(Debug, Clone)]
-struct DirStats {
-    path: PathBuf,
-    total_bytes: u128,
-    file_count: u64,
-    dir_count: u64,
-    // last_scanned: Instant,
+    fn scan_options(&self, config: &ScanConfig) -> Result<ScanOptions> {
+        let mut check = GitignoreBuilder::new("/");
+        for pattern in &self.exclude {
+            check
+                .add_line(None, pattern)
+                .with_context(|| format!("Invalid --exclude pattern '{pattern}'"))?;
+        }
+        let mut opts = ScanOptions {
+            dedup_hardlinks: !self.count_links,
+            one_file_system: self.one_file_system,
+            follow_links: self.follow_links,
+            include_pseudo: self.include_pseudo,
+            threads: self.threads.unwrap_or(config.threads),
+            low_priority: self.low_priority || config.low_priority,
+            mft: self.mft || config.mft,
+            max_depth: None,
+            sample: None,
+            reflinks: self.reflinks || config.reflinks,
+            dir_timeout: self
+                .dir_timeout
+                .or(config.dir_timeout)
+                .filter(|&secs| secs > 0),
+            keep_depth: self.keep_depth.or(config.keep_depth).filter(|&n| n > 0),
+            memory_budget: self
+                .memory_budget
+                .or(config.memory_budget)
+                .map(|bytes| u64::try_from(bytes).unwrap_or(u64::MAX)),
+            io_limit: self.io_limit.or(config.io_limit).filter(|&ops| ops > 0),
+            excludes: self.exclude.clone(),
+        };
+        if let Some(profile) = self.profile.or(config.profile) {
+            profile.apply(&mut opts);
+        }
+        if self.max_depth.is_some() {
+            opts.max_depth = self.max_depth;
+        }
+        Ok(opts)
+    }
+
+    fn size_mode(&self, config: &ScanConfig) -> SizeMode {
+        let profile = self.profile.or(config.profile);
+        self.size_mode
+            .or(profile.map(Profile::size_mode))
+            .unwrap_or(SizeMode::Apparent)
+    }
 }
 
+// ====== Data types ======
+
 #[derive(Debug)]
 enum Msg {
-    RecomputeNow, // manual or scheduled refresh
-    Tick,         // UI timer tick
-    #[allow(dead_code)]
-    Error(String), // error message for the log pane
-    ScanFinished(Vec<DirStats>), // new results
-    DeleteFinished(PathBuf, Result<(), String>),
+    RecomputeNow,                // manual or scheduled refresh
+    Tick,                        // UI timer tick
+    Info(String),                // note for the log pane from a background thread
+    Error(String),               // error message for the log pane
+    Scan(u64, ScanEvent),        // progress or a problem from a scan in flight
+    ScanFinished(u64, DirTree),  // complete tree of the scanned dir
+    ScanFailed(u64, String),     // a scan that gave up without a tree
+    WatchUpdate(Vec<DirUpdate>), // directories that changed on disk
+    DeleteProgress(u64, u128),   // files removed and bytes freed so far by a permanent deletion
+    DeleteFinished(PathBuf, DeleteKind, Result<(), String>),
+    CompressProgress(Phase, u64), // bytes packed or checked so far
+    CompressFinished(PathBuf, Result<Packed, String>),
+    EstimateFinished(PathBuf, Result<Estimate, String>), // how far a directory would compress
+    DupesFinished(PathBuf, DupesOf, Vec<DupGroup>),      // duplicates found under a directory
+    LinkFinished(PathBuf, Result<(), String>),           // a copy replaced by a hard link
+    EmptyFinished(PathBuf, Vec<DirStats>), // empty directories and files found under a directory
+    OldFilesFinished(PathBuf, Vec<DirStats>), // big files left unused found under a directory
+    PruneFinished(PathBuf, Result<u64, String>), // an empty directory removed, with those below it
+    BrokenFinished(PathBuf, Vec<BrokenLink>), // links to nowhere found under a directory
+    AuditFinished(PathBuf, Vec<Flagged>),  // loose permissions and owners gone under a directory
+    HistoryRecorded,                       // a scan was added to the history database
+    TrashSized(Vec<DirStats>),             // the trash folders holding anything
+    TrashEmptied(Result<(), String>),
+    ArchiveRead(PathBuf, Result<Import, String>), // the members of an archive to look inside
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum DeleteKind {
+    Trash,     // system trash / recycle bin, recoverable
+    Permanent, // remove_dir_all / remove_file
+}
+
+/// A row of the listing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Entry {
+    Dir(NodeId),
+    File(usize),             // index into `App::files`
+    Largest(usize),          // index into `App::largest`
+    Duplicate(usize, usize), // group and file in `App::dupes`
+    Empty(usize),            // index into `App::empties`
+    Old(usize),              // index into `App::old_files`
+    Broken(usize),           // index into `App::broken`
+    Flagged(usize),          // index into `App::flagged`
+    Gone(usize),             // index into `App::gone`
+    Trash(usize),            // index into `App::trash`
+}
+
+/// What the left pane lists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum View {
+    Contents,     // subdirectories and files of `cwd`
+    LargestFiles, // biggest files anywhere below `cwd`
+    Duplicates,   // files or directories with identical contents below `cwd`
+    Changes,      // subdirectories of `cwd` compared with the baseline
+    Cleanup,      // build artifacts and package caches below `cwd`
+    Empty,        // empty directories and zero-byte files below `cwd`
+    OldFiles,     // big files below `cwd` unused for long
+    ManyFiles,    // directories below `cwd` holding the most files
+    Broken,       // links below `cwd` whose targets are gone
+    Audit,        // entries below `cwd` with loose permissions or no owner
+}
+
+/// Column the listing is ordered by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortKey {
+    Size,
+    Name,
+    Files,
+    Modified,
+}
+
+impl SortKey {
+    /// Direction a column starts out in: biggest/newest first, names A-Z.
+    fn default_desc(self) -> bool {
+        self != SortKey::Name
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum Mode {
     Normal,
-    ConfirmDelete(PathBuf),
+    Filter, // typing into the filter prompt
+    ConfirmDelete(Vec<PathBuf>, DeleteKind, Option<NameCheck>),
+    ConfirmLink(Vec<Link>, Vec<PathBuf>, usize), // links to make, copies that can't be; scrolled rows
+    Help,
+    SaveSnapshot(String),               // typing the name to save the tree under
+    Snapshots(Vec<Saved>, usize, bool), // the snapshots menu; the selected row, deleting it
+    Mounts(Vec<Mount>, usize),          // picking a filesystem to scan; the selected row
+    Subvolumes(Vec<Subvolume>, usize),  // btrfs subvolumes and snapshots; the selected row
+    LocalSnapshots(Vec<String>, bool),  // Time Machine snapshots on APFS; thinning them
+    Actions(usize),                     // the custom actions menu; the selected row
+    Bookmarks(usize),                   // the bookmarks menu; the selected row
+    ConfirmAction(usize, PathBuf),
+    ConfirmClean(PathBuf, &'static Cache), // package-manager cache to clear with its own command
+    ConfirmCompress(PathBuf, Codec, bool), // directory, codec, delete it afterwards
+    ConfirmPrune(Vec<PathBuf>),            // empty directories to remove
+    ConfirmEmptyTrash,
+    Owners(OwnerKey, Vec<(String, TypeTotals)>), // who owns the whole tree
+    Extensions(Vec<ExtRow>, usize),              // extensions of the whole tree; the selected row
+}
+
+/// A row of the extensions list: an extension across the whole tree, or
+/// one of the directories adding the most to the extension above it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ExtRow {
+    Ext(Box<str>, TypeTotals),
+    Dir(PathBuf, TypeTotals),
+}
+
+/// How many directories an extension opens up to.
+const TOP_DIRS: usize = 10;
+
+/// How many directories the most-files view ranks.
+const MANY_FILES: usize = 500;
+
+/// Threads scanning a network filesystem, unless `--threads` or
+/// `scan.threads` set a number. One per CPU swamps the server.
+const NETWORK_THREADS: usize = 2;
+
+/// Seconds a directory on a network filesystem gets to list, unless
+/// `--dir-timeout` or `scan.dir_timeout` are given. Without one a hung
+/// server holds the scan up for good.
+const NETWORK_DIR_TIMEOUT: u64 = 60;
+
+/// How often to look whether the machine went on or off battery.
+const POWER_CHECK: Duration = Duration::from_secs(30);
+
+/// How often the screen is drawn when nothing is going on, for the
+/// countdowns and "5m ago"s on it.
+const IDLE_REDRAW: Duration = Duration::from_secs(1);
+
+/// How long quitting waits for a deletion or compression to stop at the
+/// end of its current file, before leaving it halfway.
+const QUIT_WAIT: Duration = Duration::from_secs(5);
+
+/// How often a scan of the whole tree leaves a checkpoint to resume from,
+/// should it never finish.
+const CHECKPOINT_EVERY: Duration = Duration::from_secs(300);
+
+/// What the duplicates view lists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DupesOf {
+    Files,
+    Dirs,        // by the names and sizes of what's in them
+    DirContents, // and by the contents of their files as well
+}
+
+/// A program that takes over the terminal until it exits.
+enum Foreground {
+    Shell(PathBuf),                  // interactive shell in this directory
+    Action(String, String, PathBuf), // custom action: name, command line, directory
+}
+
+/// A name to type before a big or sensitive deletion goes ahead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct NameCheck {
+    name: String,
+    typed: String,
+}
+
+/// A permanent deletion in progress, for the progress bar.
+struct Deletion {
+    left: usize, // targets not finished yet
+    total_files: u64,
+    total_bytes: u128, // on disk
+    files: u64,
+    bytes: u128,
+    cancel: Arc<AtomicBool>,
+}
+
+/// A directory being compressed, for the progress bar.
+struct Compressing {
+    dir: PathBuf,
+    codec: Codec,
+    total: u128, // apparent size of the directory
+    phase: Phase,
+    bytes: u64,
+    cancel: Arc<AtomicBool>,
+}
+
+/// The tree set aside while browsing inside an archive in it.
+struct Outside {
+    archive: PathBuf,
+    tree: DirTree,
+    imported: Option<HashMap<PathBuf, Vec<DirStats>>>,
+    snapshot: Option<String>,
+}
+
+/// The earlier tree the changes view compares against.
+struct Baseline {
+    tree: DirTree,
+    name: Option<String>, // snapshot name; `None` for the previous scan
+    taken: SystemTime,
 }
 
 // ====== App state ======
 
+/// Two clicks on the same row within this long open it.
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+
+/// How many directories going back can return to.
+const HISTORY_LEN: usize = 100;
+
+/// A directory left, with the entry that was selected in it.
+struct Visit {
+    dir: PathBuf,
+    selected: Option<PathBuf>,
+}
+
+/// Where the last frame put things, so mouse events can be mapped back.
+#[derive(Debug, Default, Clone, Copy)]
+struct ScreenLayout {
+    list: Rect,       // rows of the listing, below the column header
+    breadcrumb: Rect, // the path segments in the listing's title
+    messages: Rect,
+}
+
 struct App {
     cwd: PathBuf,
+    roots: Vec<PathBuf>,
+    root_idx: usize,
+    tree: DirTree,
     selected: usize,
-    entries: Vec<DirStats>,
+    selected_path: Option<PathBuf>, // to find the selection again in a rebuilt listing
+    back: Vec<Visit>,               // directories to go back to, the last one first
+    forward: Vec<Visit>,            // and to go forward to again after going back
+    entries: Vec<Entry>,            // contents of `cwd`, sorted for display
+    files: Vec<DirStats>,           // files directly in `cwd`, read when entering it
+    largest: Vec<DirStats>,         // `DirTree::largest_files` as of the last refresh
+    show_files: bool,
+    view: View,
+    filter: String,            // case-insensitive substring the listing is narrowed to
+    marked: BTreeSet<PathBuf>, // entries picked with Space, kept across directories
     messages: VecDeque<String>,
     last_error: Option<String>,
     last_scan_started: Option<Instant>,
     is_scanning: bool,
+    size_mode: SizeMode,
+    sort_key: SortKey,
+    sort_desc: bool,
+    scan_opts: ScanOptions,
+    profile: Option<Profile>, // None for the options as given
+    scan_id: u64,
+    scan_dir: PathBuf,
+    scan_skip: Option<PathBuf>,
+    scan_cancel: Option<Arc<AtomicBool>>,
+    scan_counters: Arc<ScanCounters>,
+    scan_pause: Arc<Pause>,
+    scan_sample: (Instant, u64), // when the entry count was last sampled, and what it was
+    scan_rate: u64,              // entries per second as of the last sample
+    scan_moved: Instant,         // when the entry count last went up
+    memory: Option<u64>,         // resident bytes as of the last sample
+    on_battery: bool,            // and `battery_saver` on
+    power_checked: Option<Instant>,
+    use_cache: bool,
+    cached_at: Option<SystemTime>, // set while showing a cached tree
+    resume: Option<DirTree>,       // checkpoint for the next scan of the root to go on from
+    checkpoints_done: Arc<Mutex<u64>>, // scans up to this id have no checkpoint to write any more
     mode: Mode,
+    config: Config,
+    config_path: Option<PathBuf>, // where bookmarks are saved
+    list_offset: Cell<usize>,     // first visible row, kept between frames
+    msg_scroll: u16,
+    layout: Cell<ScreenLayout>,
+    last_click: Option<(Instant, usize)>,
+    pending_g: bool,               // first half of `gg`
+    auto_rescan: Option<Duration>, // None if turned off in the config
+    auto_rescan_paused: bool,
+    next_rescan: Instant,
+    imported: Option<HashMap<PathBuf, Vec<DirStats>>>, // files by directory; read-only
+    snapshot: Option<String>, // name of the snapshot being browsed instead of a scan
+    watching: bool,
+    watch_stop: Option<Arc<AtomicBool>>, // stops the watcher of the current root
+    watch_backlog: Vec<DirUpdate>,       // changes that arrived during a scan
+    pending_scans: Vec<PathBuf>,         // new directories seen by the watcher
+    dupes: Vec<DupGroup>,
+    dupes_root: Option<PathBuf>, // where `dupes` were searched for
+    dupes_of: DupesOf,
+    dupes_cancel: Option<Arc<AtomicBool>>, // set while a search is running
+    empties: Vec<DirStats>,
+    empties_root: Option<PathBuf>,
+    empties_cancel: Option<Arc<AtomicBool>>,
+    old_files: Vec<DirStats>,
+    old_root: Option<PathBuf>,
+    old_cancel: Option<Arc<AtomicBool>>,
+    broken: Vec<BrokenLink>,
+    broken_root: Option<PathBuf>,
+    broken_cancel: Option<Arc<AtomicBool>>,
+    flagged: Vec<Flagged>,
+    audit_root: Option<PathBuf>,
+    audit_cancel: Option<Arc<AtomicBool>>,
+    trash: Vec<DirStats>, // the platform's trash folders that aren't empty
+    /// The mount point of the root's filesystem and, in WSL, the Windows
+    /// drive it is.
+    windows_drive: Option<(PathBuf, Option<String>)>,
+    trash_sizing: bool,
+    grouping: Grouping,
+    by_owner: Option<OwnerKey>, // the types pane shows owners instead
+    owner_names: Names,
+    types: Option<(PathBuf, Vec<(String, TypeTotals)>)>, // breakdown of the directory in focus
+    age_colors: bool,
+    placeholders_noted: bool, // said once that cloud files count at their online size
+    baseline: Option<Baseline>,
+    scanned_at: Option<SystemTime>, // when the whole tree was last scanned
+    gone: Vec<DirStats>,            // baseline subdirectories of `cwd` that no longer exist
+    history: Option<History>,       // None if turned off or the database can't be opened
+    trend: Option<(PathBuf, Vec<Point>)>, // recorded sizes of the directory in focus
+    disk: Option<Mount>,            // the filesystem of the current root
+    network_below: bool,            // a network filesystem mounted inside the root
+    timeout_set: bool,              // `--dir-timeout` or `scan.dir_timeout` given, even 0
+    deleting: Option<Deletion>,
+    name_paths: GlobSet,            // `delete.type_name_paths` from the config
+    protected: GlobSet,             // `delete.protected`
+    foreground: Option<Foreground>, // for the event loop to run
+    compressing: Option<Compressing>,
+    quitting: Option<(i32, Instant)>, // status to exit with, and until when to wait for work to stop
+    estimating: Option<(PathBuf, Arc<AtomicBool>)>, // directory sampled for compression, and its stop
+    rules: Rules,                                   // `[[rule]]` alerts from the config
+    schedules: Schedules,                           // `[[schedule]]`s from the config
+    remote: Option<Remote>, // agent scanning another machine, with `--connect`
+    outside: Option<Outside>, // the tree left to look inside an archive
+    vfs: Arc<dyn Vfs>,      // what local scans read: SFTP, a bucket, or this machine
 }
 
 impl App {
-    fn new(cwd: PathBuf) -> Self {
+    fn new(
+        roots: Vec<PathBuf>,
+        size_mode: SizeMode,
+        scan_opts: ScanOptions,
+        use_cache: bool,
+        config: Config,
+    ) -> Self {
+        let auto_rescan = (config.auto_rescan_minutes > 0)
+            .then(|| Duration::from_secs(60 * config.auto_rescan_minutes));
         Self {
-            cwd,
+            cwd: roots[0].clone(),
+            tree: DirTree::new(roots[0].clone()),
+            roots,
+            root_idx: 0,
             selected: 0,
+            selected_path: None,
+            back: Vec::new(),
+            forward: Vec::new(),
             entries: Vec::new(),
+            files: Vec::new(),
+            largest: Vec::new(),
+            show_files: true,
+            view: View::Contents,
+            filter: String::new(),
+            marked: BTreeSet::new(),
             messages: VecDeque::with_capacity(200),
             last_error: None,
             last_scan_started: None,
             is_scanning: false,
+            size_mode,
+            sort_key: SortKey::Size,
+            sort_desc: true,
+            scan_opts,
+            scan_id: 0,
+            scan_dir: PathBuf::new(),
+            scan_skip: None,
+            profile: None,
+            scan_cancel: None,
+            scan_counters: Arc::default(),
+            scan_pause: Arc::default(),
+            scan_sample: (Instant::now(), 0),
+            scan_rate: 0,
+            scan_moved: Instant::now(),
+            memory: None,
+            on_battery: false,
+            power_checked: None,
+            use_cache,
+            cached_at: None,
+            resume: None,
+            checkpoints_done: Arc::default(),
             mode: Mode::Normal,
+            config_path: None,
+            list_offset: Cell::new(0),
+            msg_scroll: 0,
+            layout: Cell::new(ScreenLayout::default()),
+            last_click: None,
+            pending_g: false,
+            auto_rescan,
+            auto_rescan_paused: false,
+            next_rescan: Instant::now() + auto_rescan.unwrap_or_default(),
+            name_paths: config::glob_set(&config.delete.type_name_paths)
+                .unwrap_or_else(|_| GlobSet::empty()),
+            protected: config::glob_set(&config.delete.protected)
+                .unwrap_or_else(|_| GlobSet::empty()),
+            watching: config.watch,
+            age_colors: config.age.color,
+            placeholders_noted: false,
+            rules: Rules::new(config.rules.clone()),
+            schedules: Schedules::new(&config.schedules),
+            remote: None,
+            outside: None,
+            vfs: vfs::local(),
+            config,
+            imported: None,
+            snapshot: None,
+            watch_stop: None,
+            watch_backlog: Vec::new(),
+            pending_scans: Vec::new(),
+            dupes: Vec::new(),
+            dupes_root: None,
+            dupes_of: DupesOf::Files,
+            dupes_cancel: None,
+            empties: Vec::new(),
+            empties_root: None,
+            empties_cancel: None,
+            old_files: Vec::new(),
+            old_root: None,
+            old_cancel: None,
+            broken: Vec::new(),
+            broken_root: None,
+            broken_cancel: None,
+            flagged: Vec::new(),
+            audit_root: None,
+            audit_cancel: None,
+            trash: Vec::new(),
+            windows_drive: None,
+            trash_sizing: false,
+            grouping: Grouping::Category,
+            by_owner: None,
+            owner_names: Names::load(),
+            types: None,
+            baseline: None,
+            scanned_at: None,
+            gone: Vec::new(),
+            history: None,
+            trend: None,
+            disk: None,
+            network_below: false,
+            timeout_set: false,
+            deleting: None,
+            foreground: None,
+            compressing: None,
+            quitting: None,
+            estimating: None,
         }
     }
 
@@ -88,292 +920,4550 @@ impl App {
         self.messages.push_back(s.into());
     }
 
+    fn entry_stats(&self, entry: Entry) -> &DirStats {
+        match entry {
+            Entry::Dir(id) => self.tree.stats(id),
+            Entry::File(i) => &self.files[i],
+            Entry::Largest(i) => &self.largest[i],
+            Entry::Duplicate(g, i) => &self.dupes[g].files[i],
+            Entry::Empty(i) => &self.empties[i],
+            Entry::Old(i) => &self.old_files[i],
+            Entry::Broken(i) => &self.broken[i].link,
+            Entry::Flagged(i) => &self.flagged[i].entry,
+            Entry::Gone(i) => &self.gone[i],
+            Entry::Trash(i) => &self.trash[i],
+        }
+    }
+
     fn selected_entry(&self) -> Option<&DirStats> {
-        self.entries.get(self.selected)
+        self.entries
+            .get(self.selected)
+            .map(|&e| self.entry_stats(e))
     }
 
-    fn set_entries(&mut self, mut list: Vec<DirStats>) {
-        list.sort_by(|a, b| b.total_bytes.cmp(&a.total_bytes));
-        self.entries = list;
-        if self.selected >= self.entries.len() && !self.entries.is_empty() {
-            self.selected = self.entries.len() - 1;
-        } else if self.entries.is_empty() {
-            self.selected = 0;
+    /// The full path of `entry`. Directories in the tree only know their
+    /// name, so theirs is put together.
+    fn entry_path(&self, entry: Entry) -> PathBuf {
+        match entry {
+            Entry::Dir(id) => self.tree.path(id),
+            _ => self.entry_stats(entry).path.clone(),
         }
     }
-}
 
-// ====== Scanning logic ======
+    /// The last component of the path of `entry`.
+    fn entry_name(&self, entry: Entry) -> Option<&OsStr> {
+        match entry {
+            Entry::Dir(id) => self.tree.name(id).file_name(),
+            _ => self.entry_stats(entry).path.file_name(),
+        }
+    }
 
-fn immediate_subdirs(root: &Path) -> Vec<PathBuf> {
-    std::fs::read_dir(root)
-        .map(|it| {
-            it.filter_map(|e| e.ok())
-                .filter(|e| e.file_type().map(|ft| ft.is_dir()).unwrap_or(false))
-                .map(|e| e.path())
-                .collect::<Vec<_>>()
-        })
-        .unwrap_or_default()
-}
+    fn selected_entry_path(&self) -> Option<PathBuf> {
+        self.entries.get(self.selected).map(|&e| self.entry_path(e))
+    }
 
-fn compute_stats_for_dir(dir: &Path) -> DirStats {
-    let mut total_bytes: u128 = 0;
-    let mut file_count: u64 = 0;
-    let mut dir_count: u64 = 0;
+    /// Where in the listing the entry for `path` is.
+    fn position_of(&self, path: &Path) -> Option<usize> {
+        let dir = self.tree.find(path);
+        self.entries.iter().position(|&e| match e {
+            Entry::Dir(id) => Some(id) == dir,
+            _ => self.entry_stats(e).path == path,
+        })
+    }
 
-    for entry in WalkDir::new(dir)
-        .follow_links(false)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        if entry.file_type().is_file() {
-            if let Ok(md) = entry.metadata() {
-                total_bytes = total_bytes.saturating_add(md.len() as u128);
-                file_count = file_count.saturating_add(1);
-            }
-        } else if entry.file_type().is_dir() {
-            dir_count = dir_count.saturating_add(1);
+    /// Whatever is known about `path`, directory or file.
+    fn stats_of(&self, path: &Path) -> Option<&DirStats> {
+        match self.tree.find(path) {
+            Some(id) => Some(self.tree.stats(id)),
+            None => self
+                .files
+                .iter()
+                .chain(&self.largest)
+                .chain(self.dupes.iter().flat_map(|g| &g.files))
+                .chain(&self.empties)
+                .chain(&self.old_files)
+                .chain(self.broken.iter().map(|b| &b.link))
+                .chain(self.flagged.iter().map(|f| &f.entry))
+                .find(|f| f.path == path),
         }
     }
 
-    DirStats {
-        path: dir.to_path_buf(),
-        total_bytes,
-        file_count,
-        dir_count,
-        // last_scanned: Instant::now(),
+    /// The marked entries, or the selected one if nothing is marked. Marks
+    /// inside another marked directory are dropped; they go with it. Among
+    /// duplicates, the selected file is the one copy that stays.
+    fn delete_targets(&self) -> Vec<PathBuf> {
+        if self.marked.is_empty() && self.view == View::Duplicates {
+            return self.other_copies();
+        }
+        if self.marked.is_empty() {
+            return self.selected_entry_path().into_iter().collect();
+        }
+        self.marked
+            .iter()
+            .filter(|p| !self.marked.iter().any(|m| m != *p && p.starts_with(m)))
+            .cloned()
+            .collect()
     }
-}
 
-fn spawn_scan_thread(cwd: PathBuf, tx: Sender<Msg>) -> thread::JoinHandle<()> {
-    thread::spawn(move || {
-        let child_dirs = immediate_subdirs(&cwd);
-        let results: Vec<DirStats> = child_dirs
-            .par_iter()
-            .map(|d| compute_stats_for_dir(d))
+    /// `path` itself, or the first directory in the tree below it, if it
+    /// matches `set`.
+    fn match_below(&self, set: &GlobSet, path: &Path) -> Option<PathBuf> {
+        if set.is_empty() {
+            return None;
+        }
+        if set.is_match(path) {
+            return Some(path.to_path_buf());
+        }
+        let mut stack: Vec<(NodeId, PathBuf)> = self
+            .tree
+            .find(path)
+            .map(|id| (id, path.to_path_buf()))
+            .into_iter()
             .collect();
-        let _ = tx.send(Msg::ScanFinished(results));
-    })
-}
+        while let Some((id, dir)) = stack.pop() {
+            if set.is_match(&dir) {
+                return Some(dir);
+            }
+            stack.extend(
+                self.tree
+                    .children(id)
+                    .iter()
+                    .map(|&c| (c, dir.join(self.tree.name(c)))),
+            );
+        }
+        None
+    }
 
-fn spawn_delete_thread(target: PathBuf, tx: Sender<Msg>) {
-    thread::spawn(move || {
-        // Safety: attempt to delete recursively; report back
-        let res = match fs::remove_dir_all(&target) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(format!("{e}")),
-        };
-        let _ = tx.send(Msg::DeleteFinished(target, res));
-        // Afterwards, trigger a rescan so UI updates
-        let _ = tx.send(Msg::RecomputeNow);
-    });
-}
+    /// The name to type before deleting `targets` for good, if they are
+    /// over the size limit or hold a path from `type_name_paths`.
+    fn name_check(&self, targets: &[PathBuf], kind: DeleteKind) -> Option<NameCheck> {
+        if kind != DeleteKind::Permanent {
+            return None;
+        }
+        let limit = self.config.delete.type_name_above_gb as u128 * 1_000_000_000;
+        let target = targets
+            .iter()
+            .find(|t| self.match_below(&self.name_paths, t).is_some())
+            .or_else(|| {
+                (limit > 0 && self.total_bytes(targets) > limit)
+                    .then(|| {
+                        targets
+                            .iter()
+                            .max_by_key(|t| self.stats_of(t).map_or(0, |s| s.bytes(self.size_mode)))
+                    })
+                    .flatten()
+            })?;
+        let name = target.file_name().map_or_else(
+            || target.display().to_string(),
+            |n| n.to_string_lossy().into_owned(),
+        );
+        Some(NameCheck {
+            name,
+            typed: String::new(),
+        })
+    }
 
-// ====== UI ======
+    /// Why `targets` can't be deleted or replaced, if one of them is or
+    /// holds a protected path.
+    fn protection(&self, targets: &[PathBuf]) -> Option<String> {
+        targets.iter().find_map(|target| {
+            let hit = self.match_below(&self.protected, target)?;
+            let pattern = &self.config.delete.protected[self.protected.matches(&hit)[0]];
+            Some(if hit == *target {
+                format!(
+                    "{} is protected by {pattern:?} in delete.protected",
+                    target.display()
+                )
+            } else {
+                format!(
+                    "{} holds {}, which is protected by {pattern:?} in delete.protected",
+                    target.display(),
+                    hit.display()
+                )
+            })
+        })
+    }
 
-fn draw_ui(f: &mut Frame, app: &App) {
-    let root_chunks = Layout::default()
-        .direction(Direction::Horizontal)
-        .constraints([Constraint::Percentage(70), Constraint::Percentage(30)])
-        .split(f.size());
+    /// Lists the hard links about to replace copies, to be confirmed,
+    /// unless one of the copies is protected.
+    fn preview_links(&mut self, links: Vec<Link>, skipped: Vec<PathBuf>) {
+        let copies: Vec<PathBuf> = links.iter().map(|l| l.copy.clone()).collect();
+        if let Some(why) = self.protection(&copies) {
+            self.log(format!("Error: {why}"));
+            self.last_error = Some(why);
+        } else {
+            self.mode = Mode::ConfirmLink(links, skipped, 0);
+        }
+    }
 
-    let left = root_chunks[0];
-    let right = root_chunks[1];
+    /// Asks before deleting whatever `d` or `D` applies to, unless it's
+    /// protected.
+    fn confirm_delete(&mut self, kind: DeleteKind) {
+        if self.marked.is_empty()
+            && matches!(self.entries.get(self.selected), Some(Entry::Trash(_)))
+        {
+            self.confirm_empty_trash();
+            return;
+        }
+        if self.view == View::Duplicates && self.dupes_of == DupesOf::Dirs {
+            // Same names and sizes say nothing about what's in the files.
+            let why = "These directories only look alike; compare their contents with 'i' before deleting any";
+            self.log(format!("Error: {why}"));
+            self.last_error = Some(why.to_string());
+            return;
+        }
+        let targets = self.delete_targets();
+        if let Some(why) = self.protection(&targets) {
+            self.log(format!("Error: {why}"));
+            self.last_error = Some(why);
+        } else if !targets.is_empty() {
+            let check = self.name_check(&targets, kind);
+            self.mode = Mode::ConfirmDelete(targets, kind, check);
+        }
+    }
 
-    draw_left(f, app, left);
-    draw_right(f, app, right);
+    /// Whether nothing in `stats` has changed for `old_after_days`.
+    fn is_old(&self, stats: &DirStats) -> bool {
+        let limit = Duration::from_secs(86_400 * self.config.age.old_after_days);
+        stats
+            .newest
+            .and_then(|t| t.elapsed().ok())
+            .is_some_and(|age| age > limit)
+    }
 
-    // Modal confirm for deletion
-    if let Mode::ConfirmDelete(path) = &app.mode {
-        draw_confirm_modal(f, path);
+    /// What the baseline knows about `path`.
+    fn baseline_stats(&self, path: &Path) -> Option<&DirStats> {
+        let baseline = self.baseline.as_ref()?;
+        let id = baseline.tree.find(path)?;
+        Some(baseline.tree.stats(id))
     }
-}
 
-fn draw_left(f: &mut Frame, app: &App, area: Rect) {
-    let title = format!(
-        "Directories under {}{}",
-        app.cwd.display(),
-        if app.is_scanning {
-            "  [scanning…]"
-        } else {
-            ""
+    /// When the project holding build artifact `entry` was last used.
+    fn project_used(&self, entry: Entry) -> Option<SystemTime> {
+        match entry {
+            Entry::Dir(id) => cleanup::project_used(&self.tree, id),
+            _ => None,
         }
-    );
+    }
 
-    let items: Vec<ListItem> = app
-        .entries
-        .iter()
-        .map(|ds| {
-            let name = ds
-                .path
-                .file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("<unknown>");
-            let size = format_size(ds.total_bytes as u64, DECIMAL);
-            let files = ds.file_count.separate_with_spaces();
-            let line = format!("{name:<30}  {size:>10}  ({files} files)");
-            ListItem::new(Line::from(Span::raw(line)))
-        })
-        .collect();
+    /// How much `entry` grew since the baseline. New directories grew by
+    /// all of their size, deleted ones shrank by all of theirs.
+    fn growth(&self, entry: Entry) -> i128 {
+        let stats = self.entry_stats(entry);
+        let now = match entry {
+            Entry::Gone(_) => 0,
+            _ => stats.bytes(self.size_mode) as i128,
+        };
+        let before = self
+            .baseline_stats(&self.entry_path(entry))
+            .map_or(0, |s| s.bytes(self.size_mode) as i128);
+        now - before
+    }
 
-    let list = List::new(items)
-        .block(Block::default().borders(Borders::ALL).title(title))
-        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    /// The duplicates of the selected file, if it has any.
+    fn other_copies(&self) -> Vec<PathBuf> {
+        let Some(&Entry::Duplicate(g, i)) = self.entries.get(self.selected) else {
+            return Vec::new();
+        };
+        self.dupes[g]
+            .files
+            .iter()
+            .enumerate()
+            .filter(|&(j, _)| j != i)
+            .map(|(_, f)| f.path.clone())
+            .collect()
+    }
 
-    f.render_stateful_widget(list, area, &mut list_state(app));
-}
+    /// Combined size of `paths`, which must not contain one another.
+    fn total_bytes(&self, paths: &[PathBuf]) -> u128 {
+        paths
+            .iter()
+            .filter_map(|p| self.stats_of(p))
+            .map(|s| s.bytes(self.size_mode))
+            .sum()
+    }
 
-fn list_state(app: &App) -> ratatui::widgets::ListState {
-    let mut st = ratatui::widgets::ListState::default();
-    if !app.entries.is_empty() {
-        st.select(Some(app.selected));
+    /// Moves the selection by `delta` rows, stopping at either end.
+    fn move_selection(&mut self, delta: isize) {
+        let last = self.entries.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
     }
-    st
-}
 
-fn convert_bytes(bytes: u128) -> (f64, String) {
-    const KB: f64 = 1024.0;
-    const MB: f64 = KB * 1024.0;
-    const GB: f64 = MB * 1024.0;
-    const TB: f64 = GB * 1024.0;
+    /// Rows visible in the listing, for PageUp/PageDown.
+    fn page(&self) -> isize {
+        (self.layout.get().list.height as isize).max(1)
+    }
 
-    let bytes_f64 = bytes as f64;
+    /// Half the visible height of the listing, for Ctrl-d/Ctrl-u.
+    fn half_page(&self) -> isize {
+        (self.page() / 2).max(1)
+    }
 
-    if bytes_f64 >= TB {
-        (bytes_f64 / TB, "TB".to_string())
-    } else if bytes_f64 >= GB {
-        (bytes_f64 / GB, "GB".to_string())
-    } else if bytes_f64 >= MB {
-        (bytes_f64 / MB, "MB".to_string())
-    } else if bytes_f64 >= KB {
-        (bytes_f64 / KB, "KB".to_string())
-    } else {
-        (bytes_f64, "Bytes".to_string())
+    /// Marks or unmarks the selected entry and moves on to the next one.
+    fn toggle_mark(&mut self) {
+        if let Some(Entry::Trash(_)) = self.entries.get(self.selected) {
+            self.log("The trash is emptied as a whole (T)");
+            return;
+        }
+        let Some(path) = self.selected_entry_path() else {
+            return;
+        };
+        if !self.marked.remove(&path) {
+            self.marked.insert(path);
+        }
+        self.move_selection(1);
     }
-}
 
-fn draw_right(f: &mut Frame, app: &App, area: Rect) {
-    let right_chunks = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints([
-            Constraint::Length(9), // Info
-            Constraint::Min(6),    // Messages (grows with vertical space)
-            Constraint::Length(9), // Help
-        ])
-        .split(area);
+    /// Marks everything listed, or unmarks it if it's all marked already.
+    fn toggle_mark_all(&mut self) {
+        let paths: Vec<PathBuf> = self
+            .entries
+            .iter()
+            .filter(|e| !matches!(e, Entry::Trash(_)))
+            .map(|&e| self.entry_path(e))
+            .collect();
+        if paths.iter().all(|p| self.marked.contains(p)) {
+            for path in &paths {
+                self.marked.remove(path);
+            }
+        } else {
+            self.marked.extend(paths);
+        }
+    }
 
-    // Info about selected directory
-    let info = if let Some(sel) = app.selected_entry() {
-        let name = sel
-            .path
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("<unknown>");
-        // let size = format_size(sel.total_bytes as u64, DECIMAL);
-        let size = convert_bytes(sel.total_bytes).0.round();
-        let size_end = convert_bytes(sel.total_bytes).1;
-        let info_lines = vec![
-            Line::from(vec![
-                Span::raw("Selected: "),
-                Span::styled(name, Style::default().add_modifier(Modifier::BOLD)),
-            ]),
-            Line::from(format!("Path: {}", sel.path.display())),
-            Line::from(format!("Total size: {size} {size_end}")),
-            Line::from(format!("Files: {}", sel.file_count.separate_with_spaces())),
-            Line::from(format!("Dirs: {}", sel.dir_count.separate_with_spaces())),
-            Line::from(""),
-        ];
-        Paragraph::new(info_lines)
-            .block(Block::default().borders(Borders::ALL).title("Info"))
-            .wrap(Wrap { trim: true })
-    } else if app.is_scanning {
-        Paragraph::new("Scanning.").block(Block::default().borders(Borders::ALL).title("Info"))
-    } else {
-        Paragraph::new("No subdirectories in this location.")
-            .block(Block::default().borders(Borders::ALL).title("Info"))
-    };
-    f.render_widget(info, right_chunks[0]);
+    /// Re-reads the files of `cwd` from disk.
+    fn reload_files(&mut self) {
+        self.files = match (&self.imported, &self.remote) {
+            (Some(files), _) => files.get(&self.cwd).cloned().unwrap_or_default(),
+            (None, Some(remote)) => {
+                let root = self.tree.root_path().to_path_buf();
+                match remote.files(self.cwd.clone(), root, self.scan_opts.clone()) {
+                    Ok(files) => files,
+                    Err(e) => {
+                        self.log(format!("Error: cannot list files: {e:#}"));
+                        Vec::new()
+                    }
+                }
+            }
+            (None, None) => {
+                let (excludes, _) = build_excludes(self.tree.root_path(), &self.scan_opts);
+                list_files(&*self.vfs, &self.cwd, &excludes, &self.scan_opts)
+            }
+        };
+        self.refresh_view();
+    }
+
+    /// Reads the members of the archive at `path` in the background, to
+    /// look inside it once they're in.
+    fn open_archive(&mut self, path: PathBuf, tx: &Sender<Msg>) {
+        if self.outside.is_some() {
+            self.log("Archives inside archives can't be opened");
+            return;
+        }
+        if self.host().is_some() {
+            self.log("Only archives on this machine can be opened");
+            return;
+        }
+        if self.is_scanning {
+            self.log("Archives can be opened once the scan is done");
+            return;
+        }
+        self.log(format!("Reading {}…", path.display()));
+        let tx = tx.clone();
+        thread::spawn(move || {
+            let members = archive::read(&path).map_err(|e| format!("{e:#}"));
+            let _ = tx.send(Msg::ArchiveRead(path, members));
+        });
+    }
+
+    /// Browses inside the archive at `path`, setting the tree aside until
+    /// going up from the archive's top leaves it.
+    fn enter_archive(&mut self, path: PathBuf, members: Import, tx: &Sender<Msg>) {
+        let stats = members.tree.stats(members.tree.root());
+        self.log(format!(
+            "Inside {}: {} in {} files, {} compressed ('a' switches)",
+            path.display(),
+            format_size(stats.total_bytes as u64, DECIMAL),
+            stats.file_count.separate_with_spaces(),
+            format_size(stats.disk_bytes as u64, DECIMAL),
+        ));
+        self.outside = Some(Outside {
+            archive: path.clone(),
+            tree: std::mem::replace(&mut self.tree, members.tree),
+            imported: self.imported.replace(members.files),
+            snapshot: self.snapshot.take(),
+        });
+        self.marked.clear();
+        self.view = View::Contents;
+        self.restart_watch(tx);
+        self.update_disk();
+        self.change_dir(path);
+    }
+
+    /// Goes back from inside an archive to the tree it's in, with the
+    /// archive selected.
+    fn leave_archive(&mut self, tx: &Sender<Msg>) {
+        let Some(outside) = self.outside.take() else {
+            return;
+        };
+        self.tree = outside.tree;
+        self.imported = outside.imported;
+        self.snapshot = outside.snapshot;
+        self.restart_watch(tx);
+        self.update_disk();
+        let dir = outside.archive.parent().unwrap_or(&outside.archive);
+        self.change_dir(dir.to_path_buf());
+        self.selected = self.position_of(&outside.archive).unwrap_or(0);
+    }
+
+    /// Browses an imported export instead of scanning.
+    fn open_import(&mut self, import: Import) {
+        let root = import.tree.root_path().to_path_buf();
+        self.tree = import.tree;
+        self.imported = Some(import.files);
+        self.roots = vec![root.clone()];
+        self.change_dir(root);
+    }
+
+    /// Rebuilds the listing of `cwd` from the tree after it changed.
+    fn refresh_view(&mut self) {
+        let mode = self.size_mode;
+        self.gone.clear();
+        if self.view == View::Changes {
+            if let Some(baseline) = &self.baseline {
+                let before = baseline
+                    .tree
+                    .find(&self.cwd)
+                    .map_or(&[][..], |id| baseline.tree.children(id));
+                self.gone = before
+                    .iter()
+                    .map(|&c| DirStats {
+                        path: baseline.tree.path(c),
+                        ..baseline.tree.stats(c).clone()
+                    })
+                    .filter(|s| self.tree.find(&s.path).is_none())
+                    .collect();
+            }
+        }
+        self.largest = self.tree.largest_files();
+        let mut entries: Vec<Entry> = match self.view {
+            View::Contents => {
+                let mut entries: Vec<Entry> = match self.tree.find(&self.cwd) {
+                    Some(id) => self
+                        .tree
+                        .children(id)
+                        .iter()
+                        .map(|&c| Entry::Dir(c))
+                        .collect(),
+                    None => Vec::new(),
+                };
+                if self.show_files {
+                    entries.extend((0..self.files.len()).map(Entry::File));
+                }
+                entries
+            }
+            View::LargestFiles => self
+                .largest
+                .iter()
+                .enumerate()
+                .filter(|(_, f)| f.path.starts_with(&self.cwd))
+                .map(|(i, _)| Entry::Largest(i))
+                .collect(),
+            View::Duplicates => self
+                .dupes
+                .iter()
+                .enumerate()
+                .flat_map(|(g, group)| (0..group.files.len()).map(move |i| Entry::Duplicate(g, i)))
+                .collect(),
+            View::Changes => {
+                let mut entries: Vec<Entry> = match self.tree.find(&self.cwd) {
+                    Some(id) if self.baseline.is_some() => self
+                        .tree
+                        .children(id)
+                        .iter()
+                        .map(|&c| Entry::Dir(c))
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                entries.extend((0..self.gone.len()).map(Entry::Gone));
+                entries
+            }
+            View::Empty => (0..self.empties.len()).map(Entry::Empty).collect(),
+            View::OldFiles => (0..self.old_files.len()).map(Entry::Old).collect(),
+            View::Broken => (0..self.broken.len()).map(Entry::Broken).collect(),
+            View::Audit => (0..self.flagged.len()).map(Entry::Flagged).collect(),
+            View::ManyFiles => match self.tree.find(&self.cwd) {
+                Some(id) => self
+                    .tree
+                    .most_files(id, MANY_FILES)
+                    .into_iter()
+                    .map(Entry::Dir)
+                    .collect(),
+                None => Vec::new(),
+            },
+            View::Cleanup => match self.tree.find(&self.cwd) {
+                Some(id) => cleanup::find(&self.tree, id)
+                    .into_iter()
+                    .map(Entry::Dir)
+                    .collect(),
+                None => Vec::new(),
+            },
+        };
+        if !self.filter.is_empty() {
+            let needle = self.filter.to_lowercase();
+            entries.retain(|&e| {
+                let path = &self.entry_path(e);
+                let shown = match self.view {
+                    View::Contents | View::Changes => path.file_name().map(Path::new),
+                    View::LargestFiles
+                    | View::Duplicates
+                    | View::Cleanup
+                    | View::Empty
+                    | View::OldFiles
+                    | View::ManyFiles
+                    | View::Broken
+                    | View::Audit => path.strip_prefix(&self.cwd).ok(),
+                };
+                shown.is_some_and(|p| p.to_string_lossy().to_lowercase().contains(&needle))
+            });
+        }
+        entries.sort_by(|&a, &b| {
+            match self.view {
+                // Duplicates stay in their groups, most reclaimable first.
+                View::Duplicates => return std::cmp::Ordering::Equal,
+                // Ranked by the files directly inside, which no key sorts by.
+                View::ManyFiles => return std::cmp::Ordering::Equal,
+                View::Changes => return self.growth(b).cmp(&self.growth(a)),
+                _ => {}
+            }
+            let name = |e| {
+                self.entry_name(e)
+                    .map(|n| n.to_string_lossy().to_lowercase())
+            };
+            let (sa, sb) = (self.entry_stats(a), self.entry_stats(b));
+            let ord = match self.sort_key {
+                SortKey::Size => sa.bytes(mode).cmp(&sb.bytes(mode)),
+                SortKey::Name => name(a).cmp(&name(b)),
+                SortKey::Files => sa.file_count.cmp(&sb.file_count),
+                SortKey::Modified if self.view == View::Cleanup => {
+                    self.project_used(a).cmp(&self.project_used(b))
+                }
+                SortKey::Modified if self.view == View::OldFiles => sa.newest.cmp(&sb.newest),
+                SortKey::Modified => sa.mtime.cmp(&sb.mtime),
+            };
+            if self.sort_desc {
+                ord.reverse()
+            } else {
+                ord
+            }
+        });
+        if self.view == View::Contents && self.filter.is_empty() && self.shows_trash() {
+            entries.splice(0..0, (0..self.trash.len()).map(Entry::Trash));
+        }
+        self.entries = entries;
+
+        // Follow the selected entry to its new position; if it's gone, the
+        // index now points at a neighbour.
+        let moved = self
+            .selected_path
+            .as_ref()
+            .and_then(|path| self.position_of(path));
+        if let Some(i) = moved {
+            self.selected = i;
+        } else if self.selected >= self.entries.len() && !self.entries.is_empty() {
+            self.selected = self.entries.len() - 1;
+        } else if self.entries.is_empty() {
+            self.selected = 0;
+        }
+        self.remember_selection();
+        self.types = None;
+        self.update_focus();
+    }
+
+    /// Records which entry is selected, by path. Entries only make sense
+    /// against the tree they were built from, so this has to happen before
+    /// the tree changes under them.
+    fn remember_selection(&mut self) {
+        self.selected_path = self.selected_entry_path();
+    }
+
+    /// The directory the side panes describe: the selected one, or `cwd`
+    /// when a file is selected.
+    fn focus(&self) -> Option<NodeId> {
+        match self.entries.get(self.selected) {
+            Some(&Entry::Dir(id)) => Some(id),
+            _ => self.tree.find(&self.cwd),
+        }
+    }
+
+    /// Updates the side panes if the focus moved to another directory.
+    fn update_focus(&mut self) {
+        self.update_types();
+        self.update_trend();
+    }
+
+    fn update_types(&mut self) {
+        let Some(id) = self.focus() else {
+            self.types = None;
+            return;
+        };
+        let path = self.tree.path(id);
+        if self.types.as_ref().is_some_and(|(p, _)| *p == path) {
+            return;
+        }
+        let rows = match self.by_owner {
+            Some(key) => owners::breakdown(
+                self.tree.owners_below(id),
+                key,
+                &self.owner_names,
+                self.size_mode,
+            ),
+            None => filetype::breakdown(self.tree.types_below(id), self.grouping, self.size_mode),
+        };
+        self.types = Some((path, rows));
+    }
+
+    fn update_trend(&mut self) {
+        let (Some(history), Some(id)) = (&self.history, self.focus()) else {
+            self.trend = None;
+            return;
+        };
+        let path = self.tree.path(id);
+        if self.trend.as_ref().is_some_and(|(p, _)| *p == path) {
+            return;
+        }
+        match history.sizes(&path) {
+            Ok(points) => self.trend = Some((path, points)),
+            Err(e) => {
+                self.trend = None;
+                self.history = None;
+                self.log(format!("Scan history is off: {e:#}"));
+            }
+        }
+    }
+
+    /// Orders the listing by `key`; picking the active column again flips
+    /// the direction.
+    fn sort_by(&mut self, key: SortKey) {
+        if self.sort_key == key {
+            self.sort_desc = !self.sort_desc;
+        } else {
+            self.sort_key = key;
+            self.sort_desc = key.default_desc();
+        }
+        self.refresh_view();
+    }
+
+    /// Forgets a file that was deleted, along with its share of the
+    /// directory totals.
+    fn remove_file(&mut self, path: &Path) {
+        let stats = match self.files.iter().position(|f| f.path == path) {
+            Some(i) => Some(self.files.remove(i)),
+            None => self.stats_of(path).cloned(),
+        };
+        self.tree.forget_file(path);
+        self.forget_duplicate(path);
+        let dir = path.parent().and_then(|p| self.tree.find(p));
+        if let (Some(stats), Some(id)) = (stats, dir) {
+            self.tree.discount(id, &stats);
+            self.tree.uncount_type(id, &stats);
+        }
+        self.refresh_view();
+    }
+
+    /// Drills into the selected directory, or jumps to the selected file's
+    /// directory in the largest-files view (and to an artifact's project in
+    /// the cleanup view).
+    fn open_selected(&mut self, tx: &Sender<Msg>) {
+        match self.entries.get(self.selected) {
+            Some(&Entry::File(i)) if archive::is_archive(&self.files[i].path) => {
+                self.open_archive(self.files[i].path.clone(), tx);
+            }
+            Some(Entry::Dir(_)) if matches!(self.view, View::Cleanup | View::ManyFiles) => {
+                self.jump_to_file();
+                self.log(format!("Entered {}", self.cwd.display()));
+            }
+            Some(&Entry::Dir(id)) => {
+                self.change_dir(self.tree.path(id));
+                self.log(format!("Entered {}", self.cwd.display()));
+            }
+            Some(&Entry::Trash(i)) => self.pick_root(self.trash[i].path.clone(), tx),
+            Some(
+                Entry::Largest(_)
+                | Entry::Duplicate(..)
+                | Entry::Empty(_)
+                | Entry::Old(_)
+                | Entry::Broken(_)
+                | Entry::Flagged(_),
+            ) => {
+                self.jump_to_file();
+                self.log(format!("Entered {}", self.cwd.display()));
+            }
+            _ => {}
+        }
+    }
+
+    /// The selected directory, or the one holding the selected file.
+    fn selected_dir(&self) -> Option<PathBuf> {
+        let &entry = self.entries.get(self.selected)?;
+        let path = self.entry_path(entry);
+        Some(match entry {
+            Entry::Dir(_) | Entry::Gone(_) | Entry::Trash(_) => path,
+            Entry::Empty(i) if self.empties[i].dir_count > 0 => path,
+            _ => path.parent().unwrap_or(&path).to_path_buf(),
+        })
+    }
+
+    /// Asks how to compress the selected directory.
+    fn confirm_compress(&mut self) {
+        if self.compressing.is_some() {
+            self.log("A compression is still running (Esc stops it)");
+            return;
+        }
+        let Some(&Entry::Dir(id)) = self
+            .entries
+            .get(self.selected)
+            .filter(|&&e| !self.entry_stats(e).link)
+        else {
+            self.log("Select a directory to compress");
+            return;
+        };
+        let dir = self.tree.path(id);
+        let compress = &self.config.compress;
+        self.mode = Mode::ConfirmCompress(dir, compress.codec, compress.delete_original);
+    }
+
+    /// Samples the selected directory to guess what compressing it saves.
+    fn estimate_compression(&mut self, tx: &Sender<Msg>) {
+        let Some(&Entry::Dir(id)) = self
+            .entries
+            .get(self.selected)
+            .filter(|&&e| !self.entry_stats(e).link)
+        else {
+            self.log("Select a directory to estimate its compression");
+            return;
+        };
+        if let Some((_, cancel)) = self.estimating.take() {
+            cancel.store(true, Ordering::Relaxed);
+        }
+        let dir = self.tree.path(id);
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.log(format!(
+            "Sampling {} to see how far it compresses",
+            dir.display()
+        ));
+        self.estimating = Some((dir.clone(), cancel.clone()));
+        spawn_estimate_thread(dir, cancel, tx.clone());
+    }
+
+    fn estimate_finished(&mut self, dir: PathBuf, res: Result<Estimate, String>) {
+        if self.estimating.as_ref().map(|(d, _)| d) != Some(&dir) {
+            return;
+        }
+        self.estimating = None;
+        match res {
+            Ok(e) if e.sampled == 0 => {
+                self.log(format!("Nothing readable in {} to compress", dir.display()))
+            }
+            Ok(e) => self.log(format!(
+                "~{} could be saved by compressing {} (zstd -1 packs samples to {:.0}%; {} read from {} files)",
+                format_size(e.saved(), DECIMAL),
+                dir.display(),
+                e.packed as f64 * 100.0 / e.total as f64,
+                format_size(e.sampled, DECIMAL),
+                e.files.separate_with_spaces()
+            )),
+            Err(e) => {
+                let msg = format!("Cannot estimate the compression of {}: {e}", dir.display());
+                self.log(format!("Error: {msg}"));
+                self.last_error = Some(msg);
+            }
+        }
+    }
+
+    fn start_compress(&mut self, dir: PathBuf, codec: Codec, remove: bool, tx: &Sender<Msg>) {
+        if let Some(why) = remove
+            .then(|| self.protection(std::slice::from_ref(&dir)))
+            .flatten()
+        {
+            self.log(format!("Error: {why}"));
+            self.last_error = Some(why);
+            return;
+        }
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.compressing = Some(Compressing {
+            dir: dir.clone(),
+            codec,
+            total: self.stats_of(&dir).map_or(0, |s| s.total_bytes),
+            phase: Phase::Packing,
+            bytes: 0,
+            cancel: cancel.clone(),
+        });
+        spawn_compress_thread(dir, codec, remove, cancel, tx.clone());
+    }
+
+    /// Runs custom action `i` on `path`, in the background or with the
+    /// terminal handed over.
+    fn run_action(&mut self, i: usize, path: &Path, tx: &Sender<Msg>) {
+        let action = &self.config.actions[i];
+        let line = command_line(action, path);
+        let (name, dir) = (action.name.clone(), self.cwd.clone());
+        if action.foreground {
+            self.foreground = Some(Foreground::Action(name, line, dir));
+        } else {
+            self.log(format!("{name}: {line}"));
+            spawn_action(name, line, dir, tx.clone());
+        }
+    }
+
+    /// Runs action `i` on the selected entry, asking first if it says so.
+    fn pick_action(&mut self, i: usize, tx: &Sender<Msg>) {
+        self.mode = Mode::Normal;
+        let Some(path) = self.selected_entry_path() else {
+            return;
+        };
+        if self.config.actions[i].confirm {
+            self.mode = Mode::ConfirmAction(i, path);
+        } else {
+            self.run_action(i, &path, tx);
+        }
+    }
+
+    /// Asks before clearing the selected package-manager cache with the
+    /// command made for it.
+    fn clean_cache(&mut self) {
+        let Some(path) = self.selected_entry_path() else {
+            return;
+        };
+        match cleanup::cache(&path) {
+            Some(cache) => self.mode = Mode::ConfirmClean(path, cache),
+            None => self.log(format!(
+                "{} isn't a cache dm knows a cleanup for (d/D deletes it)",
+                path.display()
+            )),
+        }
+    }
+
+    /// Clears the cache at `path` in the foreground, where the command can
+    /// ask for a password or confirmation.
+    fn run_clean(&mut self, path: &Path, cache: &Cache) {
+        let line = cache
+            .command
+            .replace("{path}", &quote(&path.to_string_lossy()));
+        let dir = path.parent().unwrap_or(path).to_path_buf();
+        self.foreground = Some(Foreground::Action(cache.label.to_string(), line, dir));
+    }
+
+    fn copy_selected_path(&mut self) {
+        let Some(path) = self.selected_entry_path() else {
+            return;
+        };
+        match clipboard::copy(&path.to_string_lossy()) {
+            Ok(clipboard::Via::Tool(tool)) => {
+                self.log(format!("Copied {} (with {tool})", path.display()));
+            }
+            Ok(clipboard::Via::Terminal) => {
+                self.log(format!(
+                    "Sent {} to the terminal's clipboard",
+                    path.display()
+                ));
+            }
+            Err(e) => {
+                self.last_error = Some(format!("Cannot copy the path: {e:#}"));
+                self.log(format!("Error: cannot copy the path: {e:#}"));
+            }
+        }
+    }
+
+    /// Leaves the largest-files view for the directory holding the selected
+    /// file, with that file selected. Works the same for directories.
+    fn jump_to_file(&mut self) {
+        let Some(file) = self.selected_entry_path() else {
+            return;
+        };
+        let Some(dir) = file.parent().filter(|d| self.tree.find(d).is_some()) else {
+            return;
+        };
+        self.view = View::Contents;
+        self.show_files = true;
+        self.change_dir(dir.to_path_buf());
+        self.selected = self.position_of(&file).unwrap_or(0);
+    }
+
+    /// Moves the listing to `dir`, which must already be part of the tree.
+    fn change_dir(&mut self, dir: PathBuf) {
+        if dir != self.cwd {
+            if self.back.len() == HISTORY_LEN {
+                self.back.remove(0);
+            }
+            self.back.push(self.visit());
+            self.forward.clear();
+        }
+        self.enter_dir(dir);
+    }
+
+    /// Where the listing is now, for going back or forward to.
+    fn visit(&self) -> Visit {
+        Visit {
+            dir: self.cwd.clone(),
+            selected: self.selected_path.clone(),
+        }
+    }
+
+    /// Goes back to the directory before, or `forward` to the one gone
+    /// back from, with the entry that was selected there. Directories no
+    /// longer in the tree are passed over.
+    fn step_history(&mut self, forward: bool) {
+        let from = if forward {
+            &mut self.forward
+        } else {
+            &mut self.back
+        };
+        let Some(i) = from.iter().rposition(|v| self.tree.find(&v.dir).is_some()) else {
+            from.clear();
+            self.log(if forward {
+                "Nothing to go forward to"
+            } else {
+                "Nothing to go back to"
+            });
+            return;
+        };
+        let visit = from.remove(i);
+        from.truncate(i);
+        let here = self.visit();
+        if forward {
+            self.back.push(here);
+        } else {
+            self.forward.push(here);
+        }
+        self.selected_path = visit.selected;
+        self.enter_dir(visit.dir);
+    }
+
+    /// Lists `dir`, with the entry at `selected_path` selected if it is
+    /// there.
+    fn enter_dir(&mut self, dir: PathBuf) {
+        self.cwd = dir;
+        self.selected = 0;
+        self.list_offset.set(0);
+        self.filter.clear();
+        self.reload_files();
+    }
+
+    /// Goes up to `dir`, an ancestor of `cwd` in the tree, in one step.
+    fn jump_up(&mut self, dir: PathBuf) {
+        if dir != self.cwd && self.tree.find(&dir).is_some() {
+            self.change_dir(dir);
+            self.log(format!("Up to {}", self.cwd.display()));
+        }
+    }
+
+    /// Goes to the parent directory. Leaving the scanned tree makes the
+    /// parent the new root: it is scanned, but the subtree we came from is
+    /// kept as it is rather than walked again.
+    fn go_up(&mut self, tx: &Sender<Msg>) {
+        if self.outside.is_some() && self.cwd == self.tree.root_path() {
+            self.leave_archive(tx);
+            return;
+        }
+        let Some(parent) = self.cwd.parent().map(Path::to_path_buf) else {
+            self.log(match mounts::share_root(&self.cwd) {
+                Some(_) => "Already at the top of the network share",
+                None => "Already at filesystem root",
+            });
+            return;
+        };
+        if self.cwd == self.tree.root_path() && self.imported.is_some() {
+            self.log("Already at the top of the imported tree");
+        } else if self.cwd == self.tree.root_path() {
+            let old = std::mem::replace(&mut self.tree, DirTree::new(parent.clone()));
+            let root = self.tree.root();
+            let kept = self.tree.graft(root, old);
+            self.change_dir(parent.clone());
+            self.start_scan(parent, Some(kept), false, tx);
+            self.restart_watch(tx);
+        } else {
+            self.change_dir(parent);
+        }
+    }
+
+    /// Lists who owns the files of the whole tree.
+    fn show_owners(&mut self, key: OwnerKey) {
+        let rows = owners::breakdown(
+            self.tree.owners_below(self.tree.root()),
+            key,
+            &self.owner_names,
+            self.size_mode,
+        );
+        self.mode = Mode::Owners(key, rows);
+    }
+
+    /// Lists the extensions of the files in the whole tree, biggest first.
+    fn show_extensions(&mut self) {
+        let rows = filetype::by_extension(self.tree.types_below(self.tree.root()), self.size_mode)
+            .into_iter()
+            .map(|(ext, totals)| ExtRow::Ext(ext, totals))
+            .collect();
+        self.mode = Mode::Extensions(rows, 0);
+    }
+
+    /// Opens extension row `i` of the list up to the directories holding
+    /// the most of it, or closes it again. On one of those directories,
+    /// goes there instead.
+    fn open_extension(&mut self, i: usize) {
+        let Mode::Extensions(rows, _) = &mut self.mode else {
+            return;
+        };
+        match &rows[i] {
+            ExtRow::Ext(..) if matches!(rows.get(i + 1), Some(ExtRow::Dir(..))) => {
+                let end = rows[i + 1..]
+                    .iter()
+                    .position(|r| matches!(r, ExtRow::Ext(..)))
+                    .map_or(rows.len(), |n| i + 1 + n);
+                rows.drain(i + 1..end);
+            }
+            ExtRow::Ext(ext, _) => {
+                let dirs =
+                    filetype::top_dirs(&self.tree, self.tree.root(), ext, self.size_mode, TOP_DIRS);
+                let dirs = dirs
+                    .into_iter()
+                    .map(|(id, totals)| ExtRow::Dir(self.tree.path(id), totals));
+                rows.splice(i + 1..i + 1, dirs);
+            }
+            ExtRow::Dir(dir, _) => {
+                let dir = dir.clone();
+                self.mode = Mode::Normal;
+                self.view = View::Contents;
+                self.show_files = true;
+                self.change_dir(dir);
+            }
+        }
+    }
+
+    /// Shows the mounted filesystems to pick one from, starting at the one
+    /// holding `cwd`. Returns false if none were found.
+    fn open_mounts(&mut self) -> bool {
+        let mounts = mounts::list();
+        if mounts.is_empty() {
+            self.log("No mounted filesystems found");
+            return false;
+        }
+        let current = mounts
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| self.cwd.starts_with(&m.path))
+            .max_by_key(|(_, m)| m.path.components().count())
+            .map_or(0, |(i, _)| i);
+        self.mode = Mode::Mounts(mounts, current);
+        true
+    }
+
+    /// Lists the subvolumes and snapshots of the btrfs filesystem holding
+    /// the tree, which a walk counts as plain directories or not at all,
+    /// or on APFS the local Time Machine snapshots.
+    fn open_subvolumes(&mut self) {
+        if self.disk.as_ref().is_some_and(|d| d.fs_type == "apfs") {
+            self.open_local_snapshots();
+            return;
+        }
+        let Some(disk) = self.disk.as_ref().filter(|d| d.fs_type == "btrfs") else {
+            self.log(match &self.disk {
+                Some(disk) => format!("{} is {}, not btrfs", disk.path.display(), disk.fs_type),
+                None => "Subvolumes are only listed for a local btrfs filesystem".to_string(),
+            });
+            return;
+        };
+        match btrfs::list(&disk.path) {
+            Ok(subvolumes) if subvolumes.is_empty() => {
+                self.log(format!("No subvolumes on {}", disk.path.display()));
+            }
+            Ok(subvolumes) => {
+                if subvolumes.iter().all(|s| s.usage.is_none()) {
+                    self.log(
+                        "Quotas are off, so usage is unknown ('btrfs quota enable' turns them on)",
+                    );
+                }
+                self.mode = Mode::Subvolumes(subvolumes, 0);
+            }
+            Err(e) => {
+                self.last_error = Some(format!("{e:#}"));
+                self.log(format!("Error: {e:#}"));
+            }
+        }
+    }
+
+    fn open_local_snapshots(&mut self) {
+        let Some(disk) = &self.disk else {
+            return;
+        };
+        match apfs::local_snapshots(&disk.path) {
+            Ok(names) if names.is_empty() => {
+                self.log(format!(
+                    "No local Time Machine snapshots on {}",
+                    disk.path.display()
+                ));
+            }
+            Ok(names) => self.mode = Mode::LocalSnapshots(names, false),
+            Err(e) => {
+                self.last_error = Some(format!("{e:#}"));
+                self.log(format!("Error: {e:#}"));
+            }
+        }
+    }
+
+    /// Has `tmutil` remove every local snapshot of the volume it can, in
+    /// the terminal, as it may ask for a password.
+    fn thin_local_snapshots(&mut self) {
+        self.mode = Mode::Normal;
+        let Some(disk) = &self.disk else {
+            return;
+        };
+        let line = format!(
+            "tmutil thinlocalsnapshots {} {}",
+            quote(&disk.path.to_string_lossy()),
+            apfs::THIN_ALL
+        );
+        self.foreground = Some(Foreground::Action(
+            "Thin local snapshots".to_string(),
+            line,
+            self.cwd.clone(),
+        ));
+    }
+
+    /// After a deletion on APFS, says when local snapshots still hold what
+    /// was deleted, as the space won't come free until they go.
+    fn note_local_snapshots(&mut self) {
+        let Some(disk) = self.disk.as_ref().filter(|d| d.fs_type == "apfs") else {
+            return;
+        };
+        let count = apfs::local_snapshots(&disk.path).map_or(0, |names| names.len());
+        if count > 0 {
+            self.log(format!(
+                "{count} local Time Machine snapshots still hold what was deleted, so less came free (V lists and thins them)"
+            ));
+        }
+    }
+
+    /// Makes `root` one of the roots Tab cycles through and scans it.
+    fn pick_root(&mut self, root: PathBuf, tx: &Sender<Msg>) {
+        self.root_idx = match self.roots.iter().position(|r| *r == root) {
+            Some(i) => i,
+            None => {
+                self.roots.push(root.clone());
+                self.roots.len() - 1
+            }
+        };
+        self.switch_root(root, tx);
+        self.log(format!("Switched to {}", self.cwd.display()));
+    }
+
+    /// Adds `cwd` to the bookmarks, or says it is there already.
+    fn add_bookmark(&mut self) {
+        if self.config.bookmarks.contains(&self.cwd) {
+            self.log(format!("Already bookmarked: {}", self.cwd.display()));
+            return;
+        }
+        self.config.bookmarks.push(self.cwd.clone());
+        if self.save_bookmarks() {
+            self.log(format!("Bookmarked {}", self.cwd.display()));
+        }
+    }
+
+    fn remove_bookmark(&mut self, i: usize) {
+        let removed = self.config.bookmarks.remove(i);
+        self.mode = Mode::Bookmarks(i.min(self.config.bookmarks.len().saturating_sub(1)));
+        if self.save_bookmarks() {
+            self.log(format!("Removed the bookmark for {}", removed.display()));
+        }
+    }
+
+    /// Writes the bookmarks back to the config; false if that failed.
+    fn save_bookmarks(&mut self) -> bool {
+        let res = match &self.config_path {
+            Some(path) => config::save_bookmarks(path, &self.config.bookmarks),
+            None => Err(anyhow::anyhow!("No config directory to keep bookmarks in")),
+        };
+        match res {
+            Ok(()) => true,
+            Err(e) => {
+                self.last_error = Some(format!("{e:#}"));
+                self.log(format!("Error: bookmarks not saved: {e:#}"));
+                false
+            }
+        }
+    }
+
+    /// Goes to bookmark `i`: straight there when it is in the tree,
+    /// otherwise by scanning it as a new root.
+    fn go_to_bookmark(&mut self, i: usize, tx: &Sender<Msg>) {
+        let dir = self.config.bookmarks[i].clone();
+        self.mode = Mode::Normal;
+        if self.tree.find(&dir).is_some() {
+            self.view = View::Contents;
+            self.change_dir(dir);
+        } else if self.vfs.kind(&dir).is_ok_and(|kind| kind == Kind::Dir) {
+            self.pick_root(dir, tx);
+        } else {
+            self.log(format!("{} is not there any more", dir.display()));
+        }
+    }
+
+    /// Rereads the size and free space of the root's filesystem.
+    fn update_disk(&mut self) {
+        self.disk = match (&self.imported, self.host()) {
+            (None, None) => mounts::containing(self.tree.root_path()),
+            _ => None, // paths from another machine
+        };
+        self.network_below = self.disk.is_some()
+            && !self.scan_opts.one_file_system
+            && mounts::network_below(self.tree.root_path());
+        let mount = self.disk.as_ref().map(|d| &d.path);
+        if mount != self.windows_drive.as_ref().map(|(m, _)| m) {
+            self.windows_drive = mount.map(|m| (m.clone(), wsl::drive(m)));
+        }
+    }
+
+    /// `path` as Windows programs take it, when running in WSL with the
+    /// tree on a Windows drive.
+    fn windows_path(&self, path: &Path) -> Option<String> {
+        let (mount, Some(drive)) = self.windows_drive.as_ref()? else {
+            return None;
+        };
+        wsl::on_drive(path, mount, drive)
+    }
+
+    /// Warns when whoever owns the root is close to their quota on its
+    /// filesystem, which then fills up for them long before the disk does.
+    fn check_quota(&mut self) {
+        let Some(disk) = &self.disk else {
+            return;
+        };
+        let owner = fs::metadata(self.tree.root_path())
+            .ok()
+            .and_then(|md| owner_of(&md));
+        let Some((uid, _)) = owner else {
+            return;
+        };
+        let quota = match disk.quota {
+            Some((Owner::User(me), quota)) if me == uid => Some(quota),
+            _ => mounts::quota(disk, Owner::User(uid)),
+        };
+        if let Some(quota) = quota.filter(Quota::nearly_full) {
+            self.log(format!(
+                "Warning: {} owns {} and has used {:.0}% of their quota there ({} of {})",
+                self.owner_names.user(uid),
+                self.tree.root_path().display(),
+                quota.percent(),
+                format_size(quota.used, DECIMAL),
+                format_size(quota.limit(), DECIMAL)
+            ));
+        }
+    }
+
+    /// Whether the root is on a network filesystem, or the scan goes into
+    /// one below it. It gets scanned on fewer threads, with a timeout, and
+    /// isn't rescanned by itself.
+    fn on_network(&self) -> bool {
+        self.disk.as_ref().is_some_and(Mount::is_network) || self.network_below
+    }
+
+    /// Notices the machine going on or off battery, at most every
+    /// `POWER_CHECK`, unless `battery_saver` is off.
+    fn check_power(&mut self) {
+        if !self.config.battery_saver
+            || self
+                .power_checked
+                .is_some_and(|at| at.elapsed() < POWER_CHECK)
+        {
+            return;
+        }
+        self.power_checked = Some(Instant::now());
+        let on_battery = power::on_battery();
+        if on_battery == self.on_battery {
+            return;
+        }
+        self.on_battery = on_battery;
+        self.log(if on_battery {
+            "On battery: scanning on one thread, without automatic rescans"
+        } else {
+            "Back on mains power: scanning at full speed again"
+        });
+    }
+
+    fn warn_network(&mut self) {
+        let Some(disk) = self.disk.as_ref().filter(|d| d.is_network()) else {
+            return;
+        };
+        let msg = format!(
+            "Warning: {} is on {} ({}), a network filesystem: scanning gently{}",
+            self.tree.root_path().display(),
+            disk.fs_type,
+            disk.device,
+            if self.auto_rescan.is_some() {
+                ", without automatic rescans"
+            } else {
+                ""
+            }
+        );
+        self.log(msg);
+    }
+
+    /// Where the tree is scanned: another machine through the agent or
+    /// over SFTP, or a bucket. None for this machine.
+    fn host(&self) -> Option<&str> {
+        match &self.remote {
+            Some(remote) => Some(&remote.addr),
+            None => self.vfs.host(),
+        }
+    }
+
+    /// Starts a fresh tree at `root`, seeded from the cache if there is
+    /// one, and scans it.
+    fn switch_root(&mut self, root: PathBuf, tx: &Sender<Msg>) {
+        self.outside = None;
+        self.imported = None;
+        self.snapshot = None;
+        self.tree = DirTree::new(root.clone());
+        self.update_disk();
+        self.check_quota();
+        self.warn_network();
+        self.cached_at = None;
+        self.scanned_at = None;
+        if self.baseline.as_ref().is_some_and(|b| b.name.is_none()) {
+            self.baseline = None;
+        }
+        if self.use_cache {
+            match cache::load(&root) {
+                Ok(Some((tree, saved))) => {
+                    self.tree = tree;
+                    self.cached_at = Some(saved);
+                    self.scanned_at = Some(saved);
+                    self.log(format!("Showing cached sizes from {}", fmt_age(saved)));
+                }
+                Ok(None) => {}
+                Err(e) => self.log(format!("Ignoring scan cache: {e:#}")),
+            }
+            self.resume = match cache::load_checkpoint(&root) {
+                Ok(Some((tree, saved))) => {
+                    self.log(format!(
+                        "Resuming the scan that didn't finish from its checkpoint of {}",
+                        fmt_age(saved)
+                    ));
+                    Some(tree)
+                }
+                Ok(None) => None,
+                Err(e) => {
+                    self.log(format!("Ignoring scan checkpoint: {e:#}"));
+                    None
+                }
+            };
+        }
+        self.change_dir(root.clone());
+        self.start_scan(root, None, false, tx);
+        self.restart_watch(tx);
+    }
+
+    /// Rescans everything from the root of the tree.
+    fn rescan_all(&mut self, tx: &Sender<Msg>) {
+        let root = self.tree.root_path().to_path_buf();
+        self.start_scan(root, None, false, tx);
+    }
+
+    /// Rescans everything with `profile`, showing the sizes that go with it.
+    fn switch_profile(&mut self, profile: Profile, tx: &Sender<Msg>) {
+        self.profile = Some(profile);
+        profile.apply(&mut self.scan_opts);
+        self.size_mode = profile.size_mode();
+        self.refresh_view();
+        self.log(format!(
+            "Switched to the {} scan profile, showing {}",
+            profile.label(),
+            self.size_mode.label()
+        ));
+        self.rescan_all(tx);
+    }
+
+    /// Starts scanning `target`, superseding any scan in flight. A `skip`
+    /// child is left out of the walk and keeps its current subtree. An
+    /// `incremental` scan only re-reads directories whose mtime changed.
+    fn start_scan(
+        &mut self,
+        target: PathBuf,
+        skip: Option<NodeId>,
+        incremental: bool,
+        tx: &Sender<Msg>,
+    ) {
+        if let Some(cancel) = self.scan_cancel.take() {
+            cancel.store(true, Ordering::Relaxed);
+        }
+        // Its threads have to be running to see they're cancelled.
+        self.scan_pause.set(false);
+        self.check_power();
+        // A scan of the whole tree makes the tree so far the one to compare
+        // with. It has to be kept now: progress updates change it as the
+        // scan goes.
+        if target == self.tree.root_path()
+            && self.baseline.as_ref().is_none_or(|b| b.name.is_none())
+        {
+            if let Some(taken) = self.scanned_at.take() {
+                self.baseline = Some(Baseline {
+                    tree: self.tree.clone(),
+                    name: None,
+                    taken,
+                });
+            }
+        }
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.scan_id += 1;
+        self.scan_dir = target.clone();
+        self.scan_skip = skip.map(|id| self.tree.path(id));
+        self.scan_cancel = Some(cancel.clone());
+        self.is_scanning = true;
+        self.last_scan_started = Some(Instant::now());
+        self.scan_counters = Arc::default();
+        self.scan_pause = Arc::default();
+        self.scan_sample = (Instant::now(), 0);
+        self.scan_rate = 0;
+        self.scan_moved = Instant::now();
+        let whole = target == self.tree.root_path();
+        let previous = if incremental {
+            self.tree.find(&target).map(|id| self.tree.extract(id))
+        } else {
+            self.resume.take().filter(|_| whole)
+        };
+        let mut opts = self.scan_opts.clone();
+        if self.on_network() && opts.threads == 0 {
+            opts.threads = NETWORK_THREADS;
+        }
+        if self.on_network() && !self.timeout_set {
+            opts.dir_timeout = Some(NETWORK_DIR_TIMEOUT);
+        }
+        if self.on_battery {
+            opts.threads = 1;
+        }
+        let scanner = Scanner {
+            skip: self.scan_skip.clone(),
+            previous,
+            cancel,
+            counters: self.scan_counters.clone(),
+            pause: self.scan_pause.clone(),
+            vfs: self.vfs.clone(),
+            checkpoint: (self.use_cache && whole).then_some(CHECKPOINT_EVERY),
+            ..Scanner::new(target, opts)
+        };
+        match &self.remote {
+            Some(remote) => {
+                remote::spawn_scan_thread(remote.clone(), self.scan_id, scanner, tx.clone())
+            }
+            None => spawn_scan_thread(self.scan_id, scanner, tx.clone()),
+        };
+    }
+
+    /// Works out how fast the scan in flight goes, about once a second.
+    fn sample_scan(&mut self) {
+        let (at, before) = self.scan_sample;
+        let elapsed = at.elapsed();
+        if !self.is_scanning || elapsed < Duration::from_secs(1) {
+            return;
+        }
+        let entries = self.scan_counters.entries.load(Ordering::Relaxed);
+        let walked = entries.saturating_sub(before);
+        self.scan_rate = (walked as f64 / elapsed.as_secs_f64()) as u64;
+        // Standing still while paused is no sign of trouble.
+        if walked > 0 || self.scan_pause.is_set() {
+            self.scan_moved = Instant::now();
+        }
+        self.scan_sample = (Instant::now(), entries);
+        self.memory = resident_bytes();
+    }
+
+    /// Whether something runs in the background whose progress is on
+    /// screen, so each tick is worth drawing.
+    fn busy(&self) -> bool {
+        self.is_scanning
+            || self.deleting.is_some()
+            || self.compressing.is_some()
+            || self.estimating.is_some()
+            || self.trash_sizing
+            || self.dupes_cancel.is_some()
+            || self.empties_cancel.is_some()
+            || self.old_cancel.is_some()
+            || self.broken_cancel.is_some()
+            || self.audit_cancel.is_some()
+    }
+
+    /// Stops everything running in the background, to exit with `status`
+    /// once deletions and compressions have finished the file they're at.
+    /// Asked again, it doesn't wait for them.
+    fn quit(&mut self, status: i32) {
+        if let Some((_, until)) = &mut self.quitting {
+            *until = Instant::now();
+            return;
+        }
+        self.quitting = Some((status, Instant::now() + QUIT_WAIT));
+        if let Some(cancel) = self.scan_cancel.take() {
+            cancel.store(true, Ordering::Relaxed);
+        }
+        self.scan_pause.set(false);
+        let searches = [
+            &self.dupes_cancel,
+            &self.empties_cancel,
+            &self.old_cancel,
+            &self.broken_cancel,
+            &self.audit_cancel,
+        ];
+        for cancel in searches.into_iter().flatten() {
+            cancel.store(true, Ordering::Relaxed);
+        }
+        if let Some((_, cancel)) = &self.estimating {
+            cancel.store(true, Ordering::Relaxed);
+        }
+        if let Some(deletion) = &self.deleting {
+            deletion.cancel.store(true, Ordering::Relaxed);
+            self.log("Quitting once the deletion has finished its current file…");
+        }
+        if let Some(c) = &self.compressing {
+            c.cancel.store(true, Ordering::Relaxed);
+            self.log("Quitting once the compression has stopped…");
+        }
+    }
+
+    /// Whether `quit` has nothing left to wait for.
+    fn can_exit(&self) -> Option<i32> {
+        let (status, until) = self.quitting?;
+        let stopped = self.deleting.is_none() && self.compressing.is_none();
+        (stopped || Instant::now() >= until).then_some(status)
+    }
+
+    /// Holds or lets go of the scan in flight.
+    fn toggle_scan_pause(&mut self) {
+        let paused = !self.scan_pause.is_set();
+        self.scan_pause.set(paused);
+        self.scan_moved = Instant::now();
+        self.log(if paused {
+            "Scan paused (p resumes)"
+        } else {
+            "Scan resumed"
+        });
+    }
+
+    fn scan_elapsed(&self) -> Duration {
+        self.last_scan_started
+            .map(|started| started.elapsed())
+            .unwrap_or_default()
+    }
+
+    /// Folds a streamed child total into the tree. Running totals don't
+    /// replace cached ones, which are closer to the truth until the walk of
+    /// that child is done.
+    fn scan_progress(&mut self, stats: DirStats) {
+        let cached = self
+            .tree
+            .find(&stats.path)
+            .map(|id| self.tree.stats(id).stale);
+        if !stats.complete && cached == Some(true) {
+            return;
+        }
+        if let Some(parent) = self.tree.find(&self.scan_dir) {
+            self.tree.update_child(parent, stats);
+            self.refresh_view();
+        }
+    }
+
+    /// Writes the checkpoint of scan `id` in the background, unless that
+    /// scan has finished in the meantime.
+    fn save_checkpoint(&self, id: u64, tree: DirTree, tx: &Sender<Msg>) {
+        let (done, tx) = (self.checkpoints_done.clone(), tx.clone());
+        thread::spawn(move || {
+            let done = done.lock().unwrap();
+            if *done >= id {
+                return;
+            }
+            if let Err(e) = cache::save_checkpoint(tree) {
+                let _ = tx.send(Msg::Error(format!(
+                    "Failed to write scan checkpoint: {e:#}"
+                )));
+            }
+        });
+    }
+
+    /// Rescans `cwd` once the automatic rescan is due, but never on top of
+    /// another scan or while a deletion is being confirmed.
+    fn maybe_auto_rescan(&mut self, tx: &Sender<Msg>) {
+        let Some(interval) = self.auto_rescan else {
+            return;
+        };
+        if self.auto_rescan_paused
+            || self.is_scanning
+            || self.mode != Mode::Normal
+            || self.imported.is_some()
+            || self.on_network()
+            || self.on_battery
+            || Instant::now() < self.next_rescan
+        {
+            return;
+        }
+        self.next_rescan = Instant::now() + interval;
+        self.log("Automatic rescan");
+        self.start_scan(self.cwd.clone(), None, true, tx);
+    }
+
+    /// Swaps the finished scan into the tree and refreshes the cache.
+    fn scan_finished(&mut self, mut fresh: DirTree, tx: &Sender<Msg>) {
+        if let Some(kept) = self.scan_skip.take().and_then(|p| self.tree.find(&p)) {
+            let root = fresh.root();
+            fresh.graft(root, self.tree.extract(kept));
+        }
+        if !self.tree.replace(fresh) {
+            self.log(format!(
+                "Discarded scan of {}: no longer in view",
+                self.scan_dir.display()
+            ));
+        }
+        if self.tree.find(&self.cwd).is_none() {
+            // The directory we were looking at is gone.
+            self.cwd = self.scan_dir.clone();
+        }
+        self.reload_files();
+
+        if self.scan_dir == self.tree.root_path() {
+            self.cached_at = None;
+            self.scanned_at = Some(SystemTime::now());
+            self.size_trash(tx);
+        }
+        // Count the interval from the last scan, whoever started it.
+        if let Some(interval) = self.auto_rescan {
+            self.next_rescan = Instant::now() + interval;
+        }
+        let placeholders = self.tree.stats(self.tree.root()).placeholders;
+        if placeholders > 0 && self.size_mode == SizeMode::Apparent && !self.placeholders_noted {
+            self.placeholders_noted = true;
+            self.log(format!(
+                "{} cloud files are kept online and count at their full size; 'a' shows what they take on this disk",
+                placeholders.separate_with_spaces()
+            ));
+        }
+        // Estimates would throw the trend off.
+        let record = self.history.is_some() && self.tree.stats(self.tree.root()).estimated == 0;
+        if self.use_cache || record {
+            let snapshot = self.tree.extract(self.tree.root());
+            let (save, dir, tx) = (self.use_cache, self.scan_dir.clone(), tx.clone());
+            let (done, id) = (self.checkpoints_done.clone(), self.scan_id);
+            thread::spawn(move || {
+                if record {
+                    let finished = SystemTime::now();
+                    match History::open().and_then(|mut h| h.record(&snapshot, &dir, finished)) {
+                        Ok(true) => {
+                            let _ = tx.send(Msg::HistoryRecorded);
+                        }
+                        Ok(false) => {}
+                        Err(e) => {
+                            let _ = tx.send(Msg::Error(format!("Failed to record history: {e:#}")));
+                        }
+                    }
+                }
+                if save {
+                    let root = snapshot.root_path().to_path_buf();
+                    if let Err(e) = cache::save(snapshot) {
+                        let _ = tx.send(Msg::Error(format!("Failed to update scan cache: {e:#}")));
+                    }
+                    if dir == root {
+                        let mut done = done.lock().unwrap();
+                        *done = id;
+                        if let Err(e) = cache::clear_checkpoint(&root) {
+                            let _ = tx.send(Msg::Error(format!("{e:#}")));
+                        }
+                    }
+                }
+            });
+        }
+        self.update_disk();
+        let backlog = std::mem::take(&mut self.watch_backlog);
+        if !backlog.is_empty() {
+            self.apply_watch(backlog);
+        }
+        let dir = self.scan_dir.clone();
+        self.check_rules(&dir, tx);
+    }
+
+    /// Sends the alerts of the `[[rule]]`s for `within` and below that the
+    /// tree now breaks, in the background. Imported trees and snapshots are
+    /// old news, remote ones are of another machine, and a tree mid-scan
+    /// isn't done counting, so none of those are checked.
+    fn check_rules(&mut self, within: &Path, tx: &Sender<Msg>) {
+        if self.imported.is_some() || self.host().is_some() || self.is_scanning {
+            return;
+        }
+        for alert in self.rules.check(&self.tree, within, self.size_mode) {
+            self.log(format!("Alert: {}", alert.message()));
+            let tx = tx.clone();
+            thread::spawn(move || {
+                if let Err(e) = alert.send() {
+                    let _ = tx.send(Msg::Error(format!("Alert failed: {e:#}")));
+                }
+            });
+        }
+    }
+
+    /// Watches the root of the tree for changes, replacing the watcher of
+    /// the previous root.
+    fn restart_watch(&mut self, tx: &Sender<Msg>) {
+        if let Some(stop) = self.watch_stop.take() {
+            stop.store(true, Ordering::Relaxed);
+        }
+        if !self.watching || self.imported.is_some() || self.host().is_some() {
+            return;
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        self.watch_stop = Some(stop.clone());
+        let root = self.tree.root_path().to_path_buf();
+        spawn_watcher(root, self.scan_opts.clone(), stop, tx.clone());
+    }
+
+    /// Brings the directories the watcher saw change up to date. Changes
+    /// that arrive during a scan wait for it to finish, as its result would
+    /// overwrite them.
+    fn apply_watch(&mut self, updates: Vec<DirUpdate>) {
+        if self.is_scanning {
+            self.watch_backlog.extend(updates);
+            return;
+        }
+        for update in updates {
+            let Some(id) = self.tree.find(&update.dir) else {
+                continue;
+            };
+            if self.tree.stats(id).other_fs || self.tree.stats(id).link {
+                continue;
+            }
+            if update.gone {
+                self.tree.detach(id);
+                continue;
+            }
+
+            let mut own = DirStats::new(PathBuf::new());
+            own.dir_count = 0;
+            own.newest = update.mtime;
+            for file in &update.files {
+                own.add(file);
+            }
+            self.tree.set_own_files(id, &own);
+            self.tree.recount_types(id, &update.files);
+            self.tree.stats_mut(id).mtime = update.mtime;
+            self.tree.forget_files_in(id);
+            for file in &update.files {
+                self.tree.note_file(id, file.clone());
+            }
+
+            for c in self.tree.children(id).to_vec() {
+                if !update.subdirs.contains(&self.tree.path(c)) {
+                    self.tree.detach(c);
+                }
+            }
+            for dir in update.subdirs {
+                if self.tree.find(&dir).is_none() {
+                    // Counted once scanned; see `scan_pending`.
+                    self.tree.update_child(id, DirStats::new(dir.clone()));
+                    self.pending_scans.push(dir);
+                }
+            }
+            if update.dir == self.cwd {
+                self.files = update.files;
+            }
+        }
+
+        self.tree.reclaim();
+        let before = self.cwd.clone();
+        while self.tree.find(&self.cwd).is_none() {
+            let Some(parent) = self.cwd.parent() else {
+                break;
+            };
+            self.cwd = parent.to_path_buf();
+        }
+        if self.cwd != before {
+            self.log(format!("{} no longer exists", before.display()));
+            self.change_dir(self.cwd.clone());
+        } else {
+            self.refresh_view();
+        }
+    }
+
+    /// Carries out the `[[schedule]]`s that have come due. Like automatic
+    /// rescans they wait for a running scan to finish and for dialogs to
+    /// close, and while an import or snapshot is being browsed there's
+    /// nothing to act on.
+    fn run_schedules(&mut self, tx: &Sender<Msg>) {
+        if self.is_scanning || self.mode != Mode::Normal || self.imported.is_some() {
+            return;
+        }
+        for schedule in self.schedules.due() {
+            let dir = match &schedule.path {
+                Some(path) => fs::canonicalize(path).map_or_else(|_| path.clone(), mounts::plain),
+                None => self.tree.root_path().to_path_buf(),
+            };
+            let Some(id) = self.tree.find(&dir) else {
+                self.log(format!(
+                    "Skipped scheduled {}: {} is not in the tree",
+                    schedule.action.label(),
+                    dir.display()
+                ));
+                continue;
+            };
+            self.log(format!(
+                "Scheduled {} of {}",
+                schedule.action.label(),
+                dir.display()
+            ));
+            match schedule.action {
+                ScheduleAction::Rescan if self.is_scanning => self.pending_scans.push(dir),
+                ScheduleAction::Rescan => self.start_scan(dir, None, true, tx),
+                ScheduleAction::Snapshot => {
+                    let name = schedule::snapshot_name(&dir);
+                    self.save_snapshot(name, id, tx);
+                }
+                ScheduleAction::Rules => self.check_rules(&dir, tx),
+            }
+        }
+    }
+
+    /// Scans the next directory the watcher saw appear, once nothing else
+    /// is being scanned.
+    fn scan_pending(&mut self, tx: &Sender<Msg>) {
+        if self.is_scanning || self.pending_scans.is_empty() {
+            return;
+        }
+        let dir = self.pending_scans.remove(0);
+        // Scanning it covers anything new further down as well.
+        self.pending_scans.retain(|p| !p.starts_with(&dir));
+        if self.tree.find(&dir).is_some() {
+            self.start_scan(dir, None, false, tx);
+        }
+    }
+
+    /// Saves the tree from `id` down as snapshot `name` in the background.
+    fn save_snapshot(&mut self, name: String, id: NodeId, tx: &Sender<Msg>) {
+        let tree = self.tree.extract(id);
+        let format = self.config.snapshot.format;
+        let tx = tx.clone();
+        thread::spawn(move || {
+            let msg = match snapshot::save(&name, tree, format) {
+                Ok(()) => Msg::Info(format!("Saved snapshot {name}")),
+                Err(e) => Msg::Error(format!("Cannot save snapshot {name}: {e:#}")),
+            };
+            let _ = tx.send(msg);
+        });
+    }
+
+    /// Shows the saved snapshots to pick one from.
+    fn open_snapshots(&mut self) {
+        match snapshot::list() {
+            Ok(saved) => self.mode = Mode::Snapshots(saved, 0, false),
+            Err(e) => {
+                self.last_error = Some(format!("{e:#}"));
+                self.log(format!("Error: {e:#}"));
+            }
+        }
+    }
+
+    /// Loads snapshot `name`, logging why if it can't be.
+    fn load_snapshot(&mut self, name: &str) -> Option<(DirTree, SystemTime)> {
+        match snapshot::load(name) {
+            Ok(snapshot) => Some(snapshot),
+            Err(e) => {
+                self.last_error = Some(format!("{e:#}"));
+                self.log(format!("Error: {e:#}"));
+                None
+            }
+        }
+    }
+
+    /// Makes snapshot `name` what the changes view compares against.
+    fn compare_with_snapshot(&mut self, name: String) {
+        let Some((tree, taken)) = self.load_snapshot(&name) else {
+            return;
+        };
+        self.log(format!(
+            "Comparing with snapshot {name} ({})",
+            fmt_age(taken)
+        ));
+        self.baseline = Some(Baseline {
+            tree,
+            name: Some(name),
+            taken,
+        });
+        self.view = View::Changes;
+        self.selected = 0;
+        self.refresh_view();
+    }
+
+    /// Browses snapshot `name`, saved at `taken`, read-only instead of the
+    /// scanned tree, stopping the scan and the watcher until a root is
+    /// scanned again.
+    fn browse_snapshot(
+        &mut self,
+        name: String,
+        tree: DirTree,
+        taken: SystemTime,
+        tx: &Sender<Msg>,
+    ) {
+        if let Some(cancel) = self.scan_cancel.take() {
+            cancel.store(true, Ordering::Relaxed);
+        }
+        self.scan_pause.set(false);
+        self.is_scanning = false;
+        self.watch_backlog.clear();
+        self.pending_scans.clear();
+        if self.baseline.as_ref().is_some_and(|b| b.name.is_none()) {
+            self.baseline = None;
+        }
+        // Only the biggest files were kept, so those are all it can list.
+        let mut files: HashMap<PathBuf, Vec<DirStats>> = HashMap::new();
+        for file in tree.largest_files() {
+            if let Some(dir) = file.path.parent() {
+                files.entry(dir.to_path_buf()).or_default().push(file);
+            }
+        }
+        let root = tree.root_path().to_path_buf();
+        self.outside = None;
+        self.tree = tree;
+        self.imported = Some(files);
+        self.cached_at = None;
+        self.scanned_at = Some(taken);
+        self.restart_watch(tx);
+        self.update_disk();
+        self.change_dir(root);
+        self.log(format!("Browsing snapshot {name} from {}", fmt_age(taken)));
+        self.snapshot = Some(name);
+    }
+
+    /// Deletes snapshot `name` and lists the ones left.
+    fn remove_snapshot(&mut self, name: &str) {
+        match snapshot::remove(name) {
+            Ok(()) => self.log(format!("Deleted snapshot {name}")),
+            Err(e) => {
+                self.last_error = Some(format!("{e:#}"));
+                self.log(format!("Error: {e:#}"));
+            }
+        }
+        self.open_snapshots();
+    }
+
+    /// Lists the build artifacts below `cwd` and what they add up to.
+    fn show_cleanup(&mut self) {
+        self.view = View::Cleanup;
+        self.selected = 0;
+        self.refresh_view();
+        let total: u128 = self
+            .entries
+            .iter()
+            .map(|&e| self.entry_stats(e).bytes(self.size_mode))
+            .sum();
+        self.log(format!(
+            "{} build artifacts and caches under {}, {} in total (* marks all, d/D deletes, X runs a cache's cleanup)",
+            self.entries.len(),
+            self.cwd.display(),
+            format_size(total as u64, DECIMAL)
+        ));
+    }
+
+    /// Lists the directories below `cwd` with the most files directly in
+    /// them, and how many of the filesystem's inodes are in use.
+    fn show_many_files(&mut self) {
+        self.view = View::ManyFiles;
+        self.selected = 0;
+        self.refresh_view();
+        let files = self
+            .tree
+            .find(&self.cwd)
+            .map_or(0, |id| self.tree.stats(id).file_count);
+        let inodes = match self.disk.as_ref().and_then(|d| d.inodes) {
+            Some(i) => format!(
+                "; {:.0}% of the filesystem's inodes are used ({} of {}, {} free)",
+                i.percent(),
+                i.used().separate_with_spaces(),
+                i.total.separate_with_spaces(),
+                i.free.separate_with_spaces()
+            ),
+            None => String::new(),
+        };
+        self.log(format!(
+            "{} files under {}, the directories holding most of them first{inodes}",
+            files.separate_with_spaces(),
+            self.cwd.display()
+        ));
+    }
+
+    /// Lists the duplicate files or directories below `cwd`, searching for
+    /// them first unless that was the last search.
+    fn show_duplicates(&mut self, of: DupesOf, tx: &Sender<Msg>) {
+        self.view = View::Duplicates;
+        self.selected = 0;
+        if self.dupes_root.as_ref() != Some(&self.cwd) || self.dupes_of != of {
+            if let Some(cancel) = self.dupes_cancel.take() {
+                cancel.store(true, Ordering::Relaxed);
+            }
+            let cancel = Arc::new(AtomicBool::new(false));
+            self.dupes.clear();
+            self.dupes_root = Some(self.cwd.clone());
+            self.dupes_of = of;
+            self.dupes_cancel = Some(cancel.clone());
+            self.log(format!(
+                "Looking for {} under {}",
+                match of {
+                    DupesOf::Files => "duplicates",
+                    DupesOf::Dirs => "duplicate directories",
+                    DupesOf::DirContents => "directories with the same contents",
+                },
+                self.cwd.display()
+            ));
+            spawn_dupes_thread(
+                self.cwd.clone(),
+                of,
+                self.scan_opts.clone(),
+                cancel,
+                tx.clone(),
+            );
+        }
+        self.refresh_view();
+    }
+
+    fn dupes_finished(&mut self, root: PathBuf, of: DupesOf, groups: Vec<DupGroup>) {
+        if self.dupes_root.as_ref() != Some(&root) || self.dupes_of != of {
+            return;
+        }
+        self.dupes_cancel = None;
+        let reclaimable: u128 = groups.iter().map(DupGroup::reclaimable).sum();
+        let (what, keys) = match of {
+            DupesOf::Files => (
+                "duplicates",
+                "d deletes, L hard-links the copies of the selected file, K those of all",
+            ),
+            DupesOf::Dirs => (
+                "duplicate directories",
+                "L / K hard-links the files of these copies / all, i compares contents so d can delete",
+            ),
+            DupesOf::DirContents => (
+                "directories with the same contents",
+                "d deletes, L / K hard-links the files of these copies / all",
+            ),
+        };
+        self.log(format!(
+            "{} sets of {what} under {}, {} reclaimable ({keys})",
+            groups.len(),
+            root.display(),
+            format_size(reclaimable as u64, DECIMAL)
+        ));
+        self.dupes = groups;
+        self.refresh_view();
+    }
+
+    /// Lists the empty directories and files below `cwd`, searching for
+    /// them first unless that was the last place searched.
+    fn show_empty(&mut self, tx: &Sender<Msg>) {
+        self.view = View::Empty;
+        self.selected = 0;
+        if self.empties_root.as_ref() != Some(&self.cwd) {
+            if let Some(cancel) = self.empties_cancel.take() {
+                cancel.store(true, Ordering::Relaxed);
+            }
+            let cancel = Arc::new(AtomicBool::new(false));
+            self.empties.clear();
+            self.empties_root = Some(self.cwd.clone());
+            self.empties_cancel = Some(cancel.clone());
+            spawn_empty_thread(self.cwd.clone(), self.scan_opts.clone(), cancel, tx.clone());
+        }
+        self.refresh_view();
+    }
+
+    fn empty_finished(&mut self, root: PathBuf, found: Vec<DirStats>) {
+        if self.empties_root.as_ref() != Some(&root) {
+            return;
+        }
+        self.empties_cancel = None;
+        let dirs: u64 = found.iter().map(|e| e.dir_count).sum();
+        let files = found.iter().filter(|e| e.dir_count == 0).count();
+        self.log(format!(
+            "{dirs} empty directories and {files} empty files under {} (P removes all the directories)",
+            root.display()
+        ));
+        self.empties = found;
+        self.refresh_view();
+    }
+
+    /// Lists the big files below `cwd` left unused for long, searching for
+    /// them first unless that was the last place searched.
+    fn show_old_files(&mut self, tx: &Sender<Msg>) {
+        self.view = View::OldFiles;
+        self.selected = 0;
+        if self.old_root.as_ref() != Some(&self.cwd) {
+            if let Some(cancel) = self.old_cancel.take() {
+                cancel.store(true, Ordering::Relaxed);
+            }
+            let cancel = Arc::new(AtomicBool::new(false));
+            let old = &self.config.old_files;
+            self.old_files.clear();
+            self.old_root = Some(self.cwd.clone());
+            self.old_cancel = Some(cancel.clone());
+            spawn_old_files_thread(
+                self.cwd.clone(),
+                self.scan_opts.clone(),
+                old.min_size_mb * 1_000_000,
+                Duration::from_secs(86_400 * old.unused_days),
+                cancel,
+                tx.clone(),
+            );
+        }
+        self.refresh_view();
+    }
+
+    fn old_files_finished(&mut self, root: PathBuf, found: Vec<DirStats>) {
+        if self.old_root.as_ref() != Some(&root) {
+            return;
+        }
+        self.old_cancel = None;
+        let total: u128 = found.iter().map(|f| f.total_bytes).sum();
+        let old = &self.config.old_files;
+        self.log(format!(
+            "{} files of {} MB or more unused for {} days under {}, {} in total (d/D deletes)",
+            found.len(),
+            old.min_size_mb,
+            old.unused_days,
+            root.display(),
+            format_size(total as u64, DECIMAL)
+        ));
+        self.old_files = found;
+        self.refresh_view();
+    }
+
+    /// The empty directories listed, to remove them all.
+    fn confirm_prune(&mut self) {
+        let dirs: Vec<PathBuf> = self
+            .empties
+            .iter()
+            .filter(|e| e.dir_count > 0)
+            .map(|e| e.path.clone())
+            .collect();
+        if dirs.is_empty() {
+            self.log("No empty directories to remove");
+        } else if let Some(why) = self.protection(&dirs) {
+            self.log(format!("Error: {why}"));
+            self.last_error = Some(why);
+        } else {
+            self.mode = Mode::ConfirmPrune(dirs);
+        }
+    }
+
+    /// Drops `path` and anything below it from the empty entries, the old
+    /// files, the broken links and the audit's findings.
+    fn forget_empty(&mut self, path: &Path) {
+        self.empties.retain(|e| !e.path.starts_with(path));
+        self.old_files.retain(|f| !f.path.starts_with(path));
+        self.broken.retain(|b| !b.link.path.starts_with(path));
+        self.flagged.retain(|f| !f.entry.path.starts_with(path));
+    }
+
+    /// Whether the trash goes at the top of the listing: at the root of a
+    /// tree of this machine, unless that's a trash folder itself.
+    fn shows_trash(&self) -> bool {
+        let root = self.tree.root_path();
+        self.cwd == root
+            && self.imported.is_none()
+            && self.host().is_none()
+            && !self.trash.iter().any(|t| root.starts_with(&t.path))
+    }
+
+    /// Sizes up the trash in the background, unless that's underway or the
+    /// tree isn't of this machine.
+    fn size_trash(&mut self, tx: &Sender<Msg>) {
+        if self.trash_sizing || self.imported.is_some() || self.host().is_some() {
+            return;
+        }
+        self.trash_sizing = true;
+        recycle::spawn_size_thread(tx.clone());
+    }
+
+    fn trash_sized(&mut self, trash: Vec<DirStats>) {
+        self.trash_sizing = false;
+        self.remember_selection();
+        self.trash = trash;
+        self.refresh_view();
+    }
+
+    /// Asks before emptying the trash for good.
+    fn confirm_empty_trash(&mut self) {
+        if self.trash.is_empty() {
+            self.log("The trash is empty");
+        } else {
+            self.mode = Mode::ConfirmEmptyTrash;
+        }
+    }
+
+    /// Lists the broken links below `cwd`, searching for them first unless
+    /// that was the last place searched.
+    fn show_broken(&mut self, tx: &Sender<Msg>) {
+        self.view = View::Broken;
+        self.selected = 0;
+        if self.broken_root.as_ref() != Some(&self.cwd) {
+            if let Some(cancel) = self.broken_cancel.take() {
+                cancel.store(true, Ordering::Relaxed);
+            }
+            let cancel = Arc::new(AtomicBool::new(false));
+            self.broken.clear();
+            self.broken_root = Some(self.cwd.clone());
+            self.broken_cancel = Some(cancel.clone());
+            spawn_broken_thread(self.cwd.clone(), self.scan_opts.clone(), cancel, tx.clone());
+        }
+        self.refresh_view();
+    }
+
+    fn broken_finished(&mut self, root: PathBuf, found: Vec<BrokenLink>) {
+        if self.broken_root.as_ref() != Some(&root) {
+            return;
+        }
+        self.broken_cancel = None;
+        self.log(format!(
+            "{} broken links under {} (* marks all, d/D deletes)",
+            found.len(),
+            root.display()
+        ));
+        self.broken = found;
+        self.refresh_view();
+    }
+
+    /// Lists what the audit flags below `cwd`, sweeping for it first unless
+    /// that was the last place swept.
+    fn show_audit(&mut self, tx: &Sender<Msg>) {
+        self.view = View::Audit;
+        self.selected = 0;
+        if self.audit_root.as_ref() != Some(&self.cwd) {
+            if let Some(cancel) = self.audit_cancel.take() {
+                cancel.store(true, Ordering::Relaxed);
+            }
+            let cancel = Arc::new(AtomicBool::new(false));
+            self.flagged.clear();
+            self.audit_root = Some(self.cwd.clone());
+            self.audit_cancel = Some(cancel.clone());
+            spawn_audit_thread(self.cwd.clone(), self.scan_opts.clone(), cancel, tx.clone());
+        }
+        self.refresh_view();
+    }
+
+    fn audit_finished(&mut self, root: PathBuf, found: Vec<Flagged>) {
+        if self.audit_root.as_ref() != Some(&root) {
+            return;
+        }
+        self.audit_cancel = None;
+        let count = |p: Problem| found.iter().filter(|f| f.problems.contains(&p)).count();
+        self.log(format!(
+            "Audit of {}: {} world-writable, {} setuid, {} setgid, {} with a deleted owner",
+            root.display(),
+            count(Problem::WorldWritable),
+            count(Problem::Setuid),
+            count(Problem::Setgid),
+            count(Problem::NoOwner)
+        ));
+        self.flagged = found;
+        self.refresh_view();
+    }
+
+    /// Drops `path` from the duplicates, and its group once it has no
+    /// other copies left.
+    fn forget_duplicate(&mut self, path: &Path) {
+        for group in &mut self.dupes {
+            group.files.retain(|f| f.path != path);
+        }
+        self.dupes.retain(|g| g.files.len() > 1);
+    }
+
+    /// Starts deleting `targets` on a background thread. Permanent
+    /// deletions are tracked for the progress bar and can be stopped.
+    fn start_delete(&mut self, targets: Vec<PathBuf>, kind: DeleteKind, tx: &Sender<Msg>) {
+        let cancel = Arc::new(AtomicBool::new(false));
+        if kind == DeleteKind::Permanent {
+            let (mut total_files, mut total_bytes) = (0, 0);
+            for stats in targets.iter().filter_map(|t| self.stats_of(t)) {
+                total_files += stats.file_count;
+                total_bytes += stats.disk_bytes;
+            }
+            self.deleting = Some(Deletion {
+                left: targets.len(),
+                total_files,
+                total_bytes,
+                files: 0,
+                bytes: 0,
+                cancel: cancel.clone(),
+            });
+        }
+        match &self.remote {
+            Some(remote) => {
+                let roots = self.roots.clone();
+                remote::spawn_delete_thread(
+                    remote.clone(),
+                    roots,
+                    targets,
+                    kind,
+                    cancel,
+                    tx.clone(),
+                )
+            }
+            None => spawn_delete_thread(self.vfs.clone(), targets, kind, cancel, tx.clone()),
+        }
+    }
+}
+
+// ====== Deletion ======
+
+/// How often a permanent deletion reports its progress.
+const DELETE_PROGRESS_EVERY: Duration = Duration::from_millis(100);
+
+/// Deletes `targets` from `vfs` one after the other, reporting each
+/// separately. Once `cancel` is set, the rest are left alone; if that stops
+/// a directory halfway, the current directory is rescanned.
+fn spawn_delete_thread(
+    vfs: Arc<dyn Vfs>,
+    targets: Vec<PathBuf>,
+    kind: DeleteKind,
+    cancel: Arc<AtomicBool>,
+    tx: Sender<Msg>,
+) {
+    thread::spawn(move || {
+        let mut progress = Removed {
+            files: 0,
+            bytes: 0,
+            reported: Instant::now(),
+            tx: tx.clone(),
+        };
+        let mut partial = false;
+        for target in targets {
+            let before = progress.files;
+            let res = if cancel.load(Ordering::Relaxed) {
+                Err("stopped before getting to it".to_string())
+            } else {
+                delete(&*vfs, &target, kind, &cancel, &mut progress)
+            };
+            partial |= res.is_err() && progress.files > before;
+            let _ = tx.send(Msg::DeleteFinished(target, kind, res));
+        }
+        if partial {
+            let _ = tx.send(Msg::RecomputeNow);
+        }
+    });
+}
+
+/// What a permanent deletion has removed so far.
+struct Removed {
+    files: u64,
+    bytes: u128,
+    reported: Instant,
+    tx: Sender<Msg>,
+}
+
+impl Removed {
+    fn add(&mut self, bytes: u64) {
+        self.files += 1;
+        self.bytes += bytes as u128;
+        if self.reported.elapsed() >= DELETE_PROGRESS_EVERY {
+            self.reported = Instant::now();
+            let _ = self.tx.send(Msg::DeleteProgress(self.files, self.bytes));
+        }
+    }
+}
+
+fn delete(
+    vfs: &dyn Vfs,
+    target: &Path,
+    kind: DeleteKind,
+    cancel: &AtomicBool,
+    progress: &mut Removed,
+) -> Result<(), String> {
+    match kind {
+        DeleteKind::Trash => match vfs.host() {
+            Some(host) => Err(format!("{host} has no trash to move to")),
+            None => trash::delete(target).map_err(|e| format!("{e}")),
+        },
+        // A link to a directory goes, not what it points to.
+        DeleteKind::Permanent if vfs.kind(target).is_ok_and(|kind| kind == Kind::Dir) => {
+            let mut failures = Failures::default();
+            remove_tree(vfs, target, cancel, progress, &mut failures);
+            if cancel.load(Ordering::Relaxed) {
+                return Err("stopped".to_string());
+            }
+            match failures.first {
+                None => Ok(()),
+                Some(e) if failures.count == 1 => Err(format!("{e}")),
+                Some(e) => Err(format!("{e} (and {} more)", failures.count - 1)),
+            }
+        }
+        DeleteKind::Permanent => remove_one(vfs, target, progress).map_err(|e| format!("{e}")),
+    }
+}
+
+/// What a deletion couldn't remove.
+#[derive(Default)]
+struct Failures {
+    first: Option<io::Error>,
+    count: usize,
+}
+
+impl Failures {
+    fn add(&mut self, path: &Path, e: io::Error) {
+        self.count += 1;
+        self.first
+            .get_or_insert_with(|| io::Error::new(e.kind(), format!("{}: {e}", path.display())));
+    }
+}
+
+/// Removes `dir` and everything below it, deepest entries first. Unlike
+/// `remove_dir_all` it reports progress, can be stopped, and keeps going
+/// past entries it cannot remove.
+fn remove_tree(
+    vfs: &dyn Vfs,
+    dir: &Path,
+    cancel: &AtomicBool,
+    progress: &mut Removed,
+    failures: &mut Failures,
+) {
+    match vfs.read(dir, false) {
+        Ok(entries) => {
+            for entry in entries {
+                if cancel.load(Ordering::Relaxed) {
+                    return;
+                }
+                match entry {
+                    Ok(entry) if entry.kind == Kind::Dir => {
+                        remove_tree(vfs, &entry.path, cancel, progress, failures)
+                    }
+                    Ok(entry) => {
+                        if let Err(e) = remove_one(vfs, &entry.path, progress) {
+                            failures.add(&entry.path, e);
+                        }
+                    }
+                    Err(e) => failures.add(dir, e),
+                }
+            }
+        }
+        Err(e) => failures.add(dir, e),
+    }
+    if !cancel.load(Ordering::Relaxed) {
+        if let Err(e) = vfs.remove(dir, true) {
+            failures.add(dir, e);
+        }
+    }
+}
+
+/// Removes a file, symlink or other non-directory, counting what it frees.
+fn remove_one(vfs: &dyn Vfs, path: &Path, progress: &mut Removed) -> io::Result<()> {
+    let size = vfs.file_meta(path).map_or(0, |meta| meta.allocated);
+    vfs.remove(path, false)?;
+    progress.add(size);
+    Ok(())
+}
+
+// ====== Other programs ======
+
+/// Opens `dir` in the platform's file manager, which is Explorer in WSL
+/// too. This waits for the opener to exit, so it runs on its own thread.
+fn spawn_file_manager(dir: PathBuf, tx: Sender<Msg>) {
+    let program = if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(windows) {
+        "explorer"
+    } else if wsl::detected() {
+        "explorer.exe"
+    } else {
+        "xdg-open"
+    };
+    thread::spawn(move || {
+        let arg = if program == "explorer.exe" {
+            match wsl::windows_path(&dir) {
+                Ok(path) => OsString::from(path),
+                Err(e) => {
+                    let _ = tx.send(Msg::Error(format!("Cannot open {}: {e:#}", dir.display())));
+                    return;
+                }
+            }
+        } else {
+            dir.clone().into_os_string()
+        };
+        // Keep the opener's chatter off the TUI.
+        let status = process::Command::new(program)
+            .arg(arg)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        let msg = match status {
+            // Explorer exits with 1 even when it worked.
+            Ok(status) if status.success() || program.starts_with("explorer") => {
+                Msg::Info(format!("Opened {}", dir.display()))
+            }
+            Ok(status) => Msg::Error(format!(
+                "{program} could not open {}: {status}",
+                dir.display()
+            )),
+            Err(e) => Msg::Error(format!("Cannot run {program}: {e}")),
+        };
+        let _ = tx.send(msg);
+    });
+}
+
+// ====== UI ======
+
+fn draw_ui(f: &mut Frame, app: &App) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(0), Constraint::Length(1)])
+        .split(f.size());
+    let root_chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(70), Constraint::Percentage(30)])
+        .split(rows[0]);
+
+    let left = root_chunks[0];
+    let right = root_chunks[1];
+
+    draw_left(f, app, left);
+    draw_right(f, app, right);
+    draw_status(f, app, rows[1]);
+
+    // Modal confirm for deletion
+    match &app.mode {
+        Mode::ConfirmDelete(targets, kind, check) => {
+            draw_confirm_modal(f, app, targets, *kind, check.as_ref())
+        }
+        Mode::ConfirmLink(links, skipped, scroll) => {
+            draw_link_modal(f, app, links, skipped, *scroll)
+        }
+        Mode::ConfirmPrune(dirs) => draw_prune_modal(f, app, dirs),
+        Mode::ConfirmEmptyTrash => draw_empty_trash_modal(f, app),
+        Mode::Help => draw_help(f),
+        Mode::Mounts(mounts, selected) => draw_mounts(f, app, mounts, *selected),
+        Mode::Subvolumes(subvolumes, selected) => draw_subvolumes(f, app, subvolumes, *selected),
+        Mode::LocalSnapshots(names, thinning) => draw_local_snapshots(f, app, names, *thinning),
+        Mode::Snapshots(saved, selected, deleting) => {
+            draw_snapshots(f, app, saved, *selected, *deleting)
+        }
+        Mode::Owners(key, rows) => draw_owners(f, app, *key, rows),
+        Mode::Extensions(rows, selected) => draw_extensions(f, app, rows, *selected),
+        Mode::Actions(selected) => draw_actions(f, app, *selected),
+        Mode::Bookmarks(selected) => draw_bookmarks(f, app, *selected),
+        Mode::ConfirmAction(i, path) => draw_action_confirm(f, app, *i, path),
+        Mode::ConfirmClean(path, cache) => draw_clean_confirm(f, path, cache),
+        Mode::ConfirmCompress(dir, codec, remove) => draw_compress_confirm(f, dir, *codec, *remove),
+        _ => {}
+    }
+}
+
+fn draw_left(f: &mut Frame, app: &App, area: Rect) {
+    let heading = match app.view {
+        View::Contents => "Contents of ",
+        View::LargestFiles => "Largest files under ",
+        View::Duplicates if app.dupes_of != DupesOf::Files => "Duplicate directories under ",
+        View::Duplicates => "Duplicate files under ",
+        View::Changes => "Changes in ",
+        View::Cleanup => "Build artifacts and caches under ",
+        View::Empty => "Empty entries under ",
+        View::OldFiles => "Old large files under ",
+        View::ManyFiles => "Directories with the most files under ",
+        View::Broken => "Broken links under ",
+        View::Audit => "Permission audit of ",
+    };
+    let crumbs = breadcrumbs(app);
+    let title = format!(
+        "  [{}]{}{}{}{}",
+        app.size_mode.label(),
+        match app.profile {
+            Some(profile) => format!("  [{} scan]", profile.label()),
+            None => String::new(),
+        },
+        match app.scan_opts.max_depth {
+            Some(depth) if app.profile != Some(Profile::Quick) => {
+                format!("  [max depth {depth}]")
+            }
+            _ => String::new(),
+        },
+        match app.cached_at {
+            Some(saved) => format!("  [stale: cached {}]", fmt_age(saved)),
+            None => String::new(),
+        },
+        if app.is_scanning {
+            let counters = &app.scan_counters;
+            format!(
+                "  [{} {} entries, {}, {}/s, {}]",
+                if app.scan_pause.is_set() {
+                    "scan paused:"
+                } else {
+                    "scanning…"
+                },
+                counters
+                    .entries
+                    .load(Ordering::Relaxed)
+                    .separate_with_spaces(),
+                format_size(counters.bytes.load(Ordering::Relaxed), DECIMAL),
+                app.scan_rate.separate_with_spaces(),
+                fmt_duration(app.scan_elapsed()),
+            )
+        } else if app.outside.is_some() {
+            "  [archive, read-only]".to_string()
+        } else if let Some(name) = &app.snapshot {
+            format!("  [snapshot {name}, read-only]")
+        } else if app.imported.is_some() {
+            "  [imported, read-only]".to_string()
+        } else if app.config.read_only {
+            "  [read-only]".to_string()
+        } else {
+            String::new()
+        },
+    );
+    let title = match (app.host(), &app.disk) {
+        (Some(host), _) if app.imported.is_none() => format!("{title}  [on {host}]"),
+        (_, Some(disk)) => format!("{title}  [{} on {}]", disk.fs_type, disk.device),
+        _ => title,
+    };
+    let title = if app.dupes_cancel.is_some() {
+        format!("{title}  [finding duplicates…]")
+    } else if app.empties_cancel.is_some() {
+        format!("{title}  [finding empty entries…]")
+    } else if app.old_cancel.is_some() {
+        format!("{title}  [finding old files…]")
+    } else if app.broken_cancel.is_some() {
+        format!("{title}  [finding broken links…]")
+    } else if app.audit_cancel.is_some() {
+        format!("{title}  [auditing permissions…]")
+    } else if app.estimating.is_some() {
+        format!("{title}  [sampling for compression…]")
+    } else {
+        title
+    };
+    let title = match app.auto_rescan {
+        _ if app.on_network() => {
+            format!("{title}  [network filesystem: gentle scan, no auto-rescan]")
+        }
+        _ if app.on_battery => format!("{title}  [on battery: one scan thread, no auto-rescan]"),
+        None => title,
+        Some(_) if app.imported.is_some() => title,
+        Some(_) if app.auto_rescan_paused => format!("{title}  [auto-rescan paused]"),
+        Some(_) => {
+            let left = app.next_rescan.saturating_duration_since(Instant::now());
+            format!("{title}  [rescan in {}]", fmt_duration(left))
+        }
+    };
+    let title = if app.marked.is_empty() {
+        title
+    } else {
+        format!(
+            "{title}  [{} marked: {}]",
+            app.marked.len(),
+            format_size(app.total_bytes(&app.delete_targets()) as u64, DECIMAL)
+        )
+    };
+
+    let parent_total = app
+        .tree
+        .find(&app.cwd)
+        .map_or(0, |id| app.tree.stats(id).bytes(app.size_mode));
+    let bar_cfg = &app.config.bar;
+
+    let items: Vec<ListItem> = app
+        .entries
+        .iter()
+        .enumerate()
+        .map(|(row, &entry)| {
+            let ds = app.entry_stats(entry);
+            let path = app.entry_path(entry);
+            let is_dir = matches!(entry, Entry::Dir(_) | Entry::Gone(_) | Entry::Trash(_))
+                || matches!(entry, Entry::Empty(_) | Entry::Duplicate(..) | Entry::Flagged(_) if ds.dir_count > 0);
+            let name = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("<unknown>");
+            // Directories get a trailing slash, followed links an @ like
+            // `ls -F`, and files don't repeat a count of 1
+            let name = if is_dir && ds.target.is_some() {
+                format!("{name}@")
+            } else if is_dir {
+                format!("{name}/")
+            } else {
+                name.to_string()
+            };
+            let rel = path.strip_prefix(&app.cwd).unwrap_or(&path);
+            let size = format_size(ds.bytes(app.size_mode) as u64, DECIMAL);
+            let bar = usage_bar(ds.bytes(app.size_mode), parent_total, bar_cfg);
+            let files = if is_dir {
+                ds.file_count.separate_with_spaces()
+            } else {
+                String::new()
+            };
+            let more = if !ds.complete {
+                "…"
+            } else if ds.estimated > 0 {
+                "~"
+            } else {
+                " "
+            };
+            let modified = ds.mtime.map_or_else(String::new, |t| {
+                chrono::DateTime::<Local>::from(t)
+                    .format("%Y-%m-%d")
+                    .to_string()
+            });
+            let mut line = if app.view == View::Changes {
+                let growth = app.growth(entry);
+                let (size, change) = match (entry, app.baseline_stats(&path)) {
+                    (Entry::Gone(_), _) => {
+                        ("-".to_string(), format!("deleted ({})", fmt_growth(growth)))
+                    }
+                    (_, None) => (size, format!("new ({})", fmt_growth(growth))),
+                    _ if growth == 0 => (size, String::new()),
+                    _ => (size, fmt_growth(growth)),
+                };
+                format!("{name:<30}  {size:>10}  {change}")
+            } else if let Entry::Duplicate(g, _) = entry {
+                // The first row of each group says how many copies it has.
+                let first =
+                    row == 0 || !matches!(app.entries[row - 1], Entry::Duplicate(p, _) if p == g);
+                let (copies, reclaimable) = if first {
+                    let group = &app.dupes[g];
+                    (
+                        format!("×{}", group.files.len()),
+                        format_size(group.reclaimable() as u64, DECIMAL),
+                    )
+                } else {
+                    Default::default()
+                };
+                let slash = if is_dir { "/" } else { "" };
+                format!(
+                    "{size:>10}  {copies:>6}  {reclaimable:>11}  {}{slash}",
+                    rel.display()
+                )
+            } else if app.view == View::LargestFiles {
+                format!("{size:>10} {bar}  {modified:>12}  {}", rel.display())
+            } else if let Entry::Empty(_) = entry {
+                let kind = match ds.dir_count {
+                    0 => "file".to_string(),
+                    1 => "dir".to_string(),
+                    n => format!("{n} dirs"),
+                };
+                let slash = if is_dir { "/" } else { "" };
+                format!("{kind:<10}  {modified:>12}  {}{slash}", rel.display())
+            } else if let Entry::Broken(i) = entry {
+                let target = app.broken[i].target.display();
+                format!("{modified:>12}  {} → {target}", rel.display())
+            } else if let Entry::Flagged(i) = entry {
+                let flagged = &app.flagged[i];
+                let problems: Vec<&str> = flagged.problems.iter().map(|p| p.label()).collect();
+                let slash = if is_dir { "/" } else { "" };
+                format!(
+                    "{:<32}  {:>4o}  {:<12}  {}{slash}",
+                    problems.join(", "),
+                    flagged.mode,
+                    app.owner_names.user(flagged.uid),
+                    rel.display()
+                )
+            } else if let Entry::Trash(_) = entry {
+                format!(
+                    "{name:<30}  {size:>10}   [trash at {}, T empties it]",
+                    path.display()
+                )
+            } else if let Entry::Old(_) = entry {
+                let used = ds.newest.map_or_else(String::new, fmt_age);
+                format!("{size:>10} {bar}  {used:>16}  {}", rel.display())
+            } else if let (View::ManyFiles, Entry::Dir(id)) = (app.view, entry) {
+                let here = app.tree.own_stats(id).file_count.separate_with_spaces();
+                format!(
+                    "{here:>11}  {files:>11}  {size:>10} {bar}  {}/",
+                    rel.display()
+                )
+            } else if app.view == View::Cleanup {
+                let used = app.project_used(entry).map_or_else(String::new, fmt_age);
+                let kind = cleanup::cache(&path).map_or("", |c| c.label);
+                format!(
+                    "{size:>10} {bar}  {used:>16}  {kind:<16}  {}/",
+                    rel.display()
+                )
+            } else if ds.other_fs {
+                format!("{name:<30}  {:>10}   [other filesystem, skipped]", "-")
+            } else if ds.link && app.scan_opts.follow_links {
+                format!("{name:<30}  {:>10}   [link, counted elsewhere]", "-")
+            } else if ds.link {
+                format!("{name:<30}  {:>10}   [link, not followed]", "-")
+            } else if ds.timed_out {
+                format!("{name:<30}  {size:>10}   [timed out, incomplete]")
+            } else if ds.cut {
+                format!(
+                    "{name:<30}  {:>10}   [below the depth limit, not read]",
+                    "-"
+                )
+            } else {
+                format!("{name:<30}  {size:>10}{more} {bar}  {files:>11}  {modified:>10}")
+            };
+            if ds.errors > 0 {
+                line.push_str(&format!("  ⚠ {} unreadable", ds.errors));
+            }
+            if !is_dir && ds.placeholders > 0 {
+                line.push_str("  [online only]");
+            }
+            if ds.mount {
+                line.push_str("  [another device]");
+            }
+            if ds.folded {
+                line.push_str("  [subdirectories counted in, not kept]");
+            }
+            if !is_dir && ds.reflinked_bytes > 0 {
+                line.push_str("  [reflinked]");
+            }
+            if let Some(cache) = cleanup::cache(&path).filter(|_| app.view == View::Contents) {
+                line.push_str(&format!("  [{}]", cache.label));
+            }
+            let style = if app.view == View::Changes {
+                match (entry, app.growth(entry)) {
+                    (Entry::Gone(_), _) => Style::default().fg(Color::DarkGray),
+                    _ if app.baseline_stats(&path).is_none() => {
+                        Style::default().fg(Color::Yellow)
+                    }
+                    (_, g) if g > 0 => Style::default().fg(Color::Red),
+                    (_, g) if g < 0 => Style::default().fg(Color::Green),
+                    _ => Style::default(),
+                }
+            } else if let Entry::Trash(_) = entry {
+                Style::default().fg(Color::Magenta)
+            } else if ds.stale {
+                Style::default().fg(Color::DarkGray)
+            } else if app.age_colors && app.is_old(ds) {
+                Style::default().fg(Color::Red).add_modifier(Modifier::DIM)
+            } else if !is_dir {
+                Style::default().fg(Color::Cyan)
+            } else {
+                Style::default()
+            };
+            let (mark, style) = if app.marked.contains(&path) {
+                ('*', style.fg(Color::Yellow).add_modifier(Modifier::BOLD))
+            } else {
+                (' ', style)
+            };
+            ListItem::new(Line::from(Span::styled(format!("{mark} {line}"), style)))
+        })
+        .collect();
+
+    let crumbs_width: usize = crumbs
+        .iter()
+        .flat_map(|(_, spans)| spans)
+        .map(Span::width)
+        .sum();
+    let mut title_spans = vec![Span::raw(heading)];
+    title_spans.extend(crumbs.into_iter().flat_map(|(_, spans)| spans));
+    title_spans.push(Span::raw(title));
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(Line::from(title_spans));
+    let inner = block.inner(area);
+    let breadcrumb_x = inner.x + heading.chars().count() as u16;
+    let breadcrumb = Rect {
+        x: breadcrumb_x,
+        y: area.y,
+        width: (crumbs_width as u16).min(inner.right().saturating_sub(breadcrumb_x)),
+        height: 1,
+    };
+    f.render_widget(block, area);
+    let filtering = app.mode == Mode::Filter || !app.filter.is_empty();
+    let naming = matches!(app.mode, Mode::SaveSnapshot(_));
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1),
+            Constraint::Min(0),
+            Constraint::Length((filtering || naming) as u16),
+        ])
+        .split(inner);
+
+    // Column header with the sort indicator
+    let arrow = if app.sort_desc { "▼" } else { "▲" };
+    let col = |key: SortKey, label: &str| {
+        if app.sort_key == key {
+            format!("{label}{arrow}")
+        } else {
+            label.to_string()
+        }
+    };
+    let bar_pad = " ".repeat(usage_bar(0, 0, bar_cfg).chars().count());
+    let header = match app.view {
+        View::Contents => format!(
+            "  {:<30}  {:>10}  {bar_pad}  {:>11}  {:>10}",
+            col(SortKey::Name, "Name (n)"),
+            col(SortKey::Size, "Size (s)"),
+            col(SortKey::Files, "Files (c)"),
+            col(SortKey::Modified, "Modified (m)"),
+        ),
+        View::LargestFiles => format!(
+            "  {:>10} {bar_pad}  {:>12}  {}",
+            col(SortKey::Size, "Size (s)"),
+            col(SortKey::Modified, "Modified (m)"),
+            col(SortKey::Name, "Path (n)"),
+        ),
+        View::Audit => format!(
+            "  {:<32}  {:>4}  {:<12}  {}",
+            "Problems",
+            "Mode",
+            "Owner",
+            col(SortKey::Name, "Path (n)"),
+        ),
+        View::Broken => format!(
+            "  {:>12}  {} → Target",
+            col(SortKey::Modified, "Modified (m)"),
+            col(SortKey::Name, "Link (n)"),
+        ),
+        View::Empty => format!(
+            "  {:<10}  {:>12}  {}",
+            "Kind",
+            col(SortKey::Modified, "Modified (m)"),
+            col(SortKey::Name, "Path (n)"),
+        ),
+        View::OldFiles => format!(
+            "  {:>10} {bar_pad}  {:>16}  {}",
+            col(SortKey::Size, "Size (s)"),
+            col(SortKey::Modified, "Last used (m)"),
+            col(SortKey::Name, "Path (n)"),
+        ),
+        View::ManyFiles => format!(
+            "  {:>11}  {:>11}  {:>10} {bar_pad}  Path",
+            "Files here", "Files below", "Size"
+        ),
+        View::Cleanup => format!(
+            "  {:>10} {bar_pad}  {:>16}  {:<16}  {}",
+            col(SortKey::Size, "Size (s)"),
+            col(SortKey::Modified, "Project used (m)"),
+            "Cache",
+            col(SortKey::Name, "Path (n)"),
+        ),
+        View::Duplicates => format!(
+            "  {:>10}  {:>6}  {:>11}  Path",
+            "Size", "Copies", "Reclaimable"
+        ),
+        View::Changes => match &app.baseline {
+            None => {
+                "  Nothing to compare with until a scan finishes (or start with --compare NAME)"
+                    .to_string()
+            }
+            Some(b) => {
+                let since = match &b.name {
+                    Some(name) => format!("snapshot {name} ({})", fmt_age(b.taken)),
+                    None => format!("the previous scan ({})", fmt_age(b.taken)),
+                };
+                if b.tree.find(&app.cwd).is_some() {
+                    format!("  {:<30}  {:>10}  Change since {since}", "Name", "Size")
+                } else {
+                    format!("  This directory is not part of {since}")
+                }
+            }
+        },
+    };
+    f.render_widget(
+        Paragraph::new(Span::styled(
+            header,
+            Style::default().add_modifier(Modifier::BOLD),
+        )),
+        rows[0],
+    );
+
+    let list = List::new(items).highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+    let mut state = ListState::default().with_offset(app.list_offset.get());
+    if !app.entries.is_empty() {
+        state.select(Some(app.selected));
+    }
+    f.render_stateful_widget(list, rows[1], &mut state);
+    app.list_offset.set(state.offset());
+
+    let visible = rows[1].height as usize;
+    if app.entries.len() > visible {
+        let mut scroll = ScrollbarState::new(app.entries.len() - visible)
+            .viewport_content_length(visible)
+            .position(state.offset());
+        f.render_stateful_widget(
+            Scrollbar::new(ScrollbarOrientation::VerticalRight)
+                .begin_symbol(None)
+                .end_symbol(None),
+            rows[1],
+            &mut scroll,
+        );
+    }
+
+    if let Mode::SaveSnapshot(name) = &app.mode {
+        f.render_widget(
+            Paragraph::new(Span::styled(
+                format!("Save snapshot as: {name}█  (Enter saves, Esc cancels)"),
+                Style::default().fg(Color::Yellow),
+            )),
+            rows[2],
+        );
+    } else if filtering {
+        let prompt = if app.mode == Mode::Filter {
+            format!("/{}█", app.filter)
+        } else {
+            format!("/{}  ({} shown, Esc clears)", app.filter, app.entries.len())
+        };
+        f.render_widget(
+            Paragraph::new(Span::styled(prompt, Style::default().fg(Color::Yellow))),
+            rows[2],
+        );
+    }
+    app.layout.set(ScreenLayout {
+        list: rows[1],
+        breadcrumb,
+        ..app.layout.get()
+    });
+}
+
+/// The way from the root of the tree down to `cwd` for the listing's title:
+/// the root as a whole, then each directory below it, each with what it
+/// takes to jump there. The nine nearest ancestors are numbered for the
+/// keys 1 to 9, counting up from the parent.
+fn breadcrumbs(app: &App) -> Vec<(PathBuf, Vec<Span<'static>>)> {
+    let root = app.tree.root_path();
+    let below = app.cwd.strip_prefix(root).unwrap_or(Path::new(""));
+    let dirs = std::iter::once(root.to_path_buf()).chain(below.iter().scan(
+        root.to_path_buf(),
+        |path, name| {
+            path.push(name);
+            Some(path.clone())
+        },
+    ));
+    let last = below.iter().count();
+    dirs.enumerate()
+        .map(|(i, path)| {
+            let name = match i {
+                0 => path.display().to_string(),
+                _ => path
+                    .file_name()
+                    .map_or(String::new(), |n| n.to_string_lossy().into_owned()),
+            };
+            let mut spans = Vec::new();
+            if i > 0 {
+                spans.push(Span::raw(" › "));
+            }
+            let up = last - i;
+            if (1..=9).contains(&up) {
+                spans.push(Span::styled(
+                    format!("{up} "),
+                    Style::default().fg(Color::DarkGray),
+                ));
+            }
+            let style = if path == app.cwd {
+                Style::default().add_modifier(Modifier::BOLD)
+            } else {
+                Style::default()
+            };
+            spans.push(Span::styled(name, style));
+            (path, spans)
+        })
+        .collect()
+}
+
+/// `[####      ]  43.2%`: the share of `part` in `whole`, with the bar
+/// drawn as configured.
+fn usage_bar(part: u128, whole: u128, cfg: &BarConfig) -> String {
+    let frac = if whole == 0 {
+        0.0
+    } else {
+        (part as f64 / whole as f64).min(1.0)
+    };
+    let pct = format!("{:>5.1}%", frac * 100.0);
+    if cfg.width == 0 {
+        return pct;
+    }
+    let width = cfg.width as usize;
+    let bar: String = match cfg.style {
+        BarStyle::Hash => {
+            let filled = (frac * width as f64).round() as usize;
+            format!("{}{}", "#".repeat(filled), " ".repeat(width - filled))
+        }
+        BarStyle::Block => {
+            const EIGHTHS: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+            let eighths = (frac * width as f64 * 8.0).round() as usize;
+            let (full, rest) = (eighths / 8, eighths % 8);
+            let mut bar = "█".repeat(full);
+            if full < width {
+                bar.push(EIGHTHS[rest]);
+                bar.push_str(&" ".repeat(width - full - 1));
+            }
+            bar
+        }
+    };
+    format!("[{bar}] {pct}")
+}
+
+fn convert_bytes(bytes: u128) -> (f64, String) {
+    const KB: f64 = 1024.0;
+    const MB: f64 = KB * 1024.0;
+    const GB: f64 = MB * 1024.0;
+    const TB: f64 = GB * 1024.0;
+
+    let bytes_f64 = bytes as f64;
+
+    if bytes_f64 >= TB {
+        (bytes_f64 / TB, "TB".to_string())
+    } else if bytes_f64 >= GB {
+        (bytes_f64 / GB, "GB".to_string())
+    } else if bytes_f64 >= MB {
+        (bytes_f64 / MB, "MB".to_string())
+    } else if bytes_f64 >= KB {
+        (bytes_f64 / KB, "KB".to_string())
+    } else {
+        (bytes_f64, "Bytes".to_string())
+    }
+}
+
+/// Coarse "how long ago" for status lines, e.g. "5m ago".
+/// `42s`, `14m` or `2h5m`: a countdown, rounded down.
+fn fmt_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m", secs / 60),
+        _ => format!("{}h{}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// A size change with its sign, like "+3.20 GB" or "−120 kB".
+fn fmt_growth(bytes: i128) -> String {
+    let size = format_size(bytes.unsigned_abs() as u64, DECIMAL);
+    match bytes.signum() {
+        1 => format!("+{size}"),
+        -1 => format!("−{size}"),
+        _ => size,
+    }
+}
+
+fn fmt_age(when: SystemTime) -> String {
+    let secs = SystemTime::now()
+        .duration_since(when)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    match secs {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86_399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86_400),
+    }
+}
+
+fn draw_right(f: &mut Frame, app: &App, area: Rect) {
+    let right_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(12),                                  // Info
+            Constraint::Length(if app.is_scanning { 7 } else { 0 }), // Scan
+            Constraint::Length(9),                                   // Types
+            Constraint::Length(6),                                   // History
+            Constraint::Min(6), // Messages (grows with vertical space)
+        ])
+        .split(area);
+
+    // Info about selected directory
+    let info = if let (Some(sel), Some(path)) = (app.selected_entry(), app.selected_entry_path()) {
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("<unknown>")
+            .to_string();
+        // let size = format_size(sel.total_bytes as u64, DECIMAL);
+        let size = convert_bytes(sel.bytes(app.size_mode)).0.round();
+        let size_end = convert_bytes(sel.bytes(app.size_mode)).1;
+        let mut info_lines = vec![
+            Line::from(vec![
+                Span::raw("Selected: "),
+                Span::styled(name, Style::default().add_modifier(Modifier::BOLD)),
+            ]),
+            Line::from(match &sel.target {
+                Some(target) => format!("Path: {} → {}", path.display(), target.display()),
+                None => format!("Path: {}", path.display()),
+            }),
+            Line::from(format!(
+                "Total size: {size} {size_end} ({})",
+                app.size_mode.label()
+            )),
+            Line::from(format!(
+                "Apparent: {}  On disk: {}",
+                format_size(sel.total_bytes as u64, DECIMAL),
+                format_size(sel.disk_bytes as u64, DECIMAL)
+            )),
+            Line::from(match sel.reflinked_bytes {
+                0 => format!(
+                    "Hard-linked: {}",
+                    format_size(sel.shared_bytes as u64, DECIMAL)
+                ),
+                shared => format!(
+                    "Hard-linked: {}  Reflinked: {}",
+                    format_size(sel.shared_bytes as u64, DECIMAL),
+                    format_size(shared as u64, DECIMAL)
+                ),
+            }),
+            Line::from(match sel.placeholders {
+                0 => format!("Files: {}", sel.file_count.separate_with_spaces()),
+                n => format!(
+                    "Files: {} ({} kept online)",
+                    sel.file_count.separate_with_spaces(),
+                    n.separate_with_spaces()
+                ),
+            }),
+            Line::from(format!("Dirs: {}", sel.dir_count.separate_with_spaces())),
+            Line::from(match sel.newest {
+                Some(t) => format!(
+                    "Last modified: {} ({})",
+                    chrono::DateTime::<Local>::from(t).format("%Y-%m-%d %H:%M"),
+                    fmt_age(t)
+                ),
+                None => "Last modified: unknown".to_string(),
+            }),
+            if sel.errors > 0 {
+                Line::from(Span::styled(
+                    format!(
+                        "⚠ {} entries unreadable; sizes are a lower bound",
+                        sel.errors.separate_with_spaces()
+                    ),
+                    Style::default().fg(Color::Yellow),
+                ))
+            } else if sel.estimated > 0 {
+                Line::from(Span::styled(
+                    format!(
+                        "~ Sampled or unread directories: {}; sizes are rough",
+                        sel.estimated.separate_with_spaces()
+                    ),
+                    Style::default().fg(Color::Yellow),
+                ))
+            } else {
+                Line::from("")
+            },
+        ];
+        if let Some(path) = app.windows_path(&path) {
+            info_lines.insert(2, Line::from(format!("Windows: {path}")));
+        }
+        Paragraph::new(info_lines)
+            .block(Block::default().borders(Borders::ALL).title("Info"))
+            .wrap(Wrap { trim: true })
+    } else if app.is_scanning {
+        Paragraph::new("Scanning.").block(Block::default().borders(Borders::ALL).title("Info"))
+    } else {
+        Paragraph::new("Nothing in this location.")
+            .block(Block::default().borders(Borders::ALL).title("Info"))
+    };
+    f.render_widget(info, right_chunks[0]);
+    if app.is_scanning {
+        draw_scan(f, app, right_chunks[1]);
+    }
+    draw_types(f, app, right_chunks[2]);
+    draw_history(f, app, right_chunks[3]);
 
     // Messages / Errors
     let mut lines: Vec<Line> = app
         .messages
         .iter()
-        .rev()
-        .take(200)
-        .map(|m| Line::from(m.as_str()))
+        .rev()
+        .take(200)
+        .map(|m| Line::from(m.as_str()))
+        .collect();
+    if let Some(err) = &app.last_error {
+        lines.insert(
+            0,
+            Line::from(Span::styled(
+                format!("ERROR: {err}"),
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            )),
+        );
+    }
+    let msg = Paragraph::new(lines)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Messages & Errors"),
+        )
+        .wrap(Wrap { trim: true })
+        .scroll((app.msg_scroll, 0));
+    f.render_widget(msg, right_chunks[4]);
+    app.layout.set(ScreenLayout {
+        messages: right_chunks[4],
+        ..app.layout.get()
+    });
+}
+
+/// The bottom line: how full the filesystem is and how much of it the
+/// directory being viewed takes up.
+fn draw_status(f: &mut Frame, app: &App, area: Rect) {
+    const HINT: &str = "? keys  q quit ";
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Min(0), Constraint::Length(HINT.len() as u16)])
+        .split(area);
+    let dim = Style::default().fg(Color::DarkGray);
+    f.render_widget(
+        Paragraph::new(Span::styled(HINT, dim)).alignment(Alignment::Right),
+        chunks[1],
+    );
+    if let Some(d) = &app.deleting {
+        let bar = BarConfig {
+            width: 20,
+            style: app.config.bar.style,
+        };
+        let (done, whole) = if d.total_bytes > 0 {
+            (d.bytes, d.total_bytes)
+        } else {
+            (d.files as u128, d.total_files as u128)
+        };
+        let line = Line::from(vec![
+            Span::styled(" Deleting ", Style::default().add_modifier(Modifier::BOLD)),
+            Span::styled(
+                usage_bar(done, whole, &bar),
+                Style::default().fg(Color::Red),
+            ),
+            Span::raw(format!(
+                "  {} of {} files, {} freed  ",
+                d.files.separate_with_spaces(),
+                d.total_files.separate_with_spaces(),
+                format_size(d.bytes as u64, DECIMAL)
+            )),
+            Span::styled("Esc: stop", dim),
+        ]);
+        f.render_widget(Paragraph::new(line), chunks[0]);
+        return;
+    }
+    if let Some(c) = &app.compressing {
+        let bar = BarConfig {
+            width: 20,
+            style: app.config.bar.style,
+        };
+        let verb = match c.phase {
+            Phase::Packing => "Packing",
+            Phase::Verifying => "Checking",
+        };
+        let archive = archive_path(&c.dir, c.codec);
+        let line = Line::from(vec![
+            Span::styled(
+                format!(" {verb} "),
+                Style::default().add_modifier(Modifier::BOLD),
+            ),
+            Span::styled(
+                usage_bar(c.bytes as u128, c.total, &bar),
+                Style::default().fg(Color::Cyan),
+            ),
+            Span::raw(format!(
+                "  {} of {}, {}  ",
+                format_size(c.bytes, DECIMAL),
+                format_size(c.total as u64, DECIMAL),
+                archive.file_name().unwrap_or_default().to_string_lossy()
+            )),
+            Span::styled("Esc: stop", dim),
+        ]);
+        f.render_widget(Paragraph::new(line), chunks[0]);
+        return;
+    }
+    let Some(disk) = &app.disk else {
+        return;
+    };
+
+    let free_style = if (disk.free as f64) < disk.total as f64 * 0.1 {
+        Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)
+    } else {
+        Style::default()
+    };
+    let mut spans = vec![
+        Span::raw(format!(
+            " {} on {} ({}): {} used of {}, ",
+            disk.device,
+            disk.path.display(),
+            disk.fs_type,
+            format_size(disk.used(), DECIMAL),
+            format_size(disk.total, DECIMAL)
+        )),
+        Span::styled(
+            format!("{} free", format_size(disk.free, DECIMAL)),
+            free_style,
+        ),
+    ];
+    // Running out of inodes fills a disk as surely as running out of bytes.
+    if let Some(inodes) = disk.inodes {
+        let style = if inodes.percent() > 90.0 {
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default()
+        };
+        spans.push(Span::raw(", "));
+        spans.push(Span::styled(
+            format!("inodes {:.0}% used", inodes.percent()),
+            style,
+        ));
+    }
+    if let Some((owner, quota)) = disk.quota {
+        let style = if quota.nearly_full() {
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default()
+        };
+        let whose = match owner {
+            Owner::User(_) => "quota",
+            Owner::Group(_) => "group quota",
+        };
+        spans.push(Span::raw(", "));
+        spans.push(Span::styled(
+            format!(
+                "{whose} {} of {} ({:.0}%)",
+                format_size(quota.used, DECIMAL),
+                format_size(quota.limit(), DECIMAL),
+                quota.percent()
+            ),
+            style,
+        ));
+    }
+    // On-disk size, whatever the size mode, to compare with what's used.
+    if let Some(id) = app.tree.find(&app.cwd) {
+        let here = app.tree.stats(id).disk_bytes;
+        let share = if disk.used() > 0 {
+            here as f64 * 100.0 / disk.used() as f64
+        } else {
+            0.0
+        };
+        spans.push(Span::styled(" │ ", dim));
+        spans.push(Span::raw(format!(
+            "{}: {} ({share:.1}% of used)",
+            app.cwd.display(),
+            format_size(here as u64, DECIMAL)
+        )));
+    }
+    f.render_widget(Paragraph::new(Line::from(spans)), chunks[0]);
+}
+
+/// Live counters of the scan in flight. A scan that stops getting anywhere,
+/// say on a stuck network mount, shows for how long, along with the
+/// directory it's in.
+fn draw_scan(f: &mut Frame, app: &App, area: Rect) {
+    // Without a new entry for this long the scan counts as stuck; longer
+    // where every directory read goes over the network.
+    let stalled = Duration::from_secs(if app.on_network() { 30 } else { 5 });
+    let counters = &app.scan_counters;
+    let current = counters.current.lock().unwrap().display().to_string();
+    let width = area.width.saturating_sub(6) as usize;
+    let mut elapsed = vec![Span::raw(format!(
+        "Elapsed: {}",
+        fmt_duration(app.scan_elapsed())
+    ))];
+    let still = app.scan_moved.elapsed();
+    if app.scan_pause.is_set() {
+        elapsed.push(Span::styled(
+            "  paused (p resumes)",
+            Style::default().fg(Color::Yellow),
+        ));
+    } else if still >= stalled {
+        elapsed.push(Span::styled(
+            format!("  no new entries for {}", fmt_duration(still)),
+            Style::default().fg(Color::Yellow),
+        ));
+    }
+    let mut memory = format!(
+        "Memory: {}",
+        app.memory
+            .map_or_else(|| "?".to_string(), |m| format_size(m, DECIMAL))
+    );
+    if let Some(budget) = app.scan_opts.memory_budget {
+        memory.push_str(&format!(" of a {} budget", format_size(budget, DECIMAL)));
+    }
+    let mut rate = format!("{}/s", app.scan_rate.separate_with_spaces());
+    if let Some(ops) = app.scan_opts.io_limit {
+        rate.push_str(&format!(", I/O held to {}/s", ops.separate_with_spaces()));
+    }
+    let lines = vec![
+        Line::from(format!(
+            "Entries: {}  ({rate})",
+            counters
+                .entries
+                .load(Ordering::Relaxed)
+                .separate_with_spaces(),
+        )),
+        Line::from(format!(
+            "Counted: {}",
+            format_size(counters.bytes.load(Ordering::Relaxed), DECIMAL)
+        )),
+        Line::from(memory),
+        Line::from(elapsed),
+        Line::from(format!("In: {}", keep_tail(&current, width))),
+    ];
+    let block = Block::default().borders(Borders::ALL).title("Scan");
+    f.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+/// `text` cut down to `width` characters from the front, so a long path
+/// keeps its last, most telling part.
+fn keep_tail(text: &str, width: usize) -> String {
+    let chars = text.chars().count();
+    if chars <= width {
+        return text.to_string();
+    }
+    let tail: String = text.chars().skip(chars - width.saturating_sub(1)).collect();
+    format!("…{tail}")
+}
+
+/// What the directory in focus is made of, by category or extension, or
+/// who owns it.
+fn draw_types(f: &mut Frame, app: &App, area: Rect) {
+    let title = match (app.by_owner, app.grouping) {
+        (Some(OwnerKey::User), _) => "Users (t: by group)",
+        (Some(OwnerKey::Group), _) => "Groups (t: by category)",
+        (None, Grouping::Category) => "Types (t: by extension)",
+        (None, Grouping::Extension) => "Extensions (t: by user)",
+    };
+    let block = Block::default().borders(Borders::ALL).title(title);
+    let Some((path, rows)) = &app.types else {
+        f.render_widget(block, area);
+        return;
+    };
+    let whole = app
+        .tree
+        .find(path)
+        .map_or(0, |id| app.tree.stats(id).bytes(app.size_mode));
+    let visible = block.inner(area).height as usize;
+    let lines: Vec<Line> = rows
+        .iter()
+        .take(visible)
+        .map(|(label, t)| {
+            let bytes = t.bytes(app.size_mode);
+            let pct = if whole > 0 {
+                bytes as f64 * 100.0 / whole as f64
+            } else {
+                0.0
+            };
+            Line::from(format!(
+                "{label:<10} {:>10} {pct:>5.1}% {:>9}",
+                format_size(bytes as u64, DECIMAL),
+                t.file_count.separate_with_spaces()
+            ))
+        })
+        .collect();
+    f.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+/// How the directory in focus grew over the recorded scans.
+fn draw_history(f: &mut Frame, app: &App, area: Rect) {
+    let points = match &app.trend {
+        Some((_, points)) => points.as_slice(),
+        None => &[],
+    };
+    let title = match points.len() {
+        0 | 1 => "History".to_string(),
+        n => format!("History ({n} scans)"),
+    };
+    let block = Block::default().borders(Borders::ALL).title(title);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        let note = if app.history.is_some() {
+            "No scans of this directory recorded yet"
+        } else {
+            "Scan history is off"
+        };
+        f.render_widget(Paragraph::new(note), inner);
+        return;
+    };
+
+    let day = |t: SystemTime| chrono::DateTime::<Local>::from(t).format("%Y-%m-%d");
+    let (start, end) = (first.bytes(app.size_mode), last.bytes(app.size_mode));
+    let summary = match last.at.duration_since(first.at) {
+        Ok(span) if points.len() > 1 && span.as_secs() > 0 => {
+            let growth = end as i128 - start as i128;
+            let weekly = growth as f64 * (7.0 * 86_400.0) / span.as_secs() as f64;
+            format!(
+                "{} since {}, {}/week",
+                fmt_growth(growth),
+                day(first.at),
+                fmt_growth(weekly as i128)
+            )
+        }
+        _ => format!("{} on {}", format_size(end as u64, DECIMAL), day(last.at)),
+    };
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(1), Constraint::Min(1)])
+        .split(inner);
+    f.render_widget(Paragraph::new(summary), rows[0]);
+
+    // Relative to the smallest size, so growth shows even when it's a
+    // small part of the total.
+    let shown = &points[points.len().saturating_sub(rows[1].width as usize)..];
+    let low = shown
+        .iter()
+        .map(|p| p.bytes(app.size_mode))
+        .min()
+        .unwrap_or(0);
+    let data: Vec<u64> = shown
+        .iter()
+        .map(|p| (p.bytes(app.size_mode) - low) as u64)
+        .collect();
+    f.render_widget(
+        Sparkline::default()
+            .data(&data)
+            .style(Style::default().fg(Color::Cyan)),
+        rows[1],
+    );
+}
+
+fn draw_help(f: &mut Frame) {
+    let help = vec![
+        Line::from("  ↑/↓ j/k   — Move selection"),
+        Line::from("  PgUp/PgDn — Page up / down (^u/^d: half a page)"),
+        Line::from("  Home/End  — Top / bottom (also gg / G)"),
+        Line::from("  Enter/l   — Drill into directory or archive / go to file's dir"),
+        Line::from("  Bksp/h    — Go to parent directory"),
+        Line::from("  Space     — Mark / unmark entry (Esc clears marks)"),
+        Line::from("  *         — Mark / unmark everything listed"),
+        Line::from("  d         — Move selected or marked entries to trash"),
+        Line::from("  D         — Delete permanently (asks first; Esc stops it)"),
+        Line::from("  r / R     — Refresh changed dirs / full rescan"),
+        Line::from("  p         — Pause / resume the scan, or else automatic rescans"),
+        Line::from("  w         — Toggle watching for changes"),
+        Line::from("  s/n/c/m   — Sort by size/name/files/mtime (again: reverse)"),
+        Line::from("  f         — Largest files below here / back"),
+        Line::from("  u         — Duplicate files (d / L: delete / hard-link the other copies)"),
+        Line::from("  K         — Hard-link the copies of every duplicate, after a dry run"),
+        Line::from("  I         — Duplicate directories by names and sizes (i: by contents too, to delete)"),
+        Line::from(
+            "  C         — Build artifacts and package caches (node_modules, .npm, …) below here",
+        ),
+        Line::from("  X         — Clear the selected package cache with its own command"),
+        Line::from("  e         — Empty directories and files (P removes the directories)"),
+        Line::from("  F         — Big files unused for long ([old_files] in the config sets both)"),
+        Line::from("  N         — Directories holding the most files, for when inodes run out"),
+        Line::from("  @         — Broken links, with the targets they point to"),
+        Line::from("  W         — Audit: world-writable, setuid/setgid, owned by deleted users"),
+        Line::from(
+            "  T         — Empty the trash (listed at the top of the root; Enter browses it)",
+        ),
+        Line::from("  t         — File types by category / extension, owners by user / group"),
+        Line::from("  U         — Users owning the most of the whole tree"),
+        Line::from("  E         — Extensions across the whole tree, and where each takes most"),
+        Line::from("  o         — Color entries untouched for a long time"),
+        Line::from("  v         — Changes since the previous scan (or --compare snapshot)"),
+        Line::from("  S         — Snapshots: save the tree by name, compare with or browse one"),
+        Line::from("  H         — Hide / show files"),
+        Line::from("  /         — Filter by name (Enter keeps, Esc clears)"),
+        Line::from(
+            "  a         — Toggle apparent size / disk usage (what cloud files really take here)",
+        ),
+        Line::from("  x         — Toggle staying on one filesystem"),
+        Line::from("  Q         — Switch between the quick and thorough scan profile"),
+        Line::from("  Tab       — Next path from the command line"),
+        Line::from("  M         — Pick a mounted filesystem, or a drive on Windows, to scan"),
+        Line::from("  V         — Btrfs subvolumes and snapshots, or local Time Machine snapshots"),
+        Line::from(
+            "  O         — Open the selected directory in the file manager (Explorer in WSL)",
+        ),
+        Line::from("  !         — Shell in the selected directory (exit to come back)"),
+        Line::from("  y         — Copy the selected path to the clipboard"),
+        Line::from("  A         — Custom actions from the config"),
+        Line::from("  b         — Bookmark this directory"),
+        Line::from("  B         — Bookmarks: jump to one, or remove it"),
+        Line::from("  z         — Compress the selected directory into a tarball"),
+        Line::from("  Z         — Estimate what compressing the selected directory would save"),
+        Line::from("  1-9       — Up that many levels, as numbered in the path in the title"),
+        Line::from("  Alt+←/→   — Back / forward through the directories visited ([keys])"),
+        Line::from("  Mouse     — Click, double-click, wheel, click the path"),
+        Line::from("  ?         — This help (any key closes it)"),
+        Line::from("  q, Ctrl+C — Quit, once a deletion finishes its current file"),
+    ];
+    let popup = centered_popup(f.size(), help.len() as u16 + 2);
+    f.render_widget(Clear, popup);
+    f.render_widget(
+        Paragraph::new(help).block(Block::default().borders(Borders::ALL).title("Keys")),
+        popup,
+    );
+}
+
+fn draw_confirm_modal(
+    f: &mut Frame,
+    app: &App,
+    targets: &[PathBuf],
+    kind: DeleteKind,
+    check: Option<&NameCheck>,
+) {
+    const MAX_LISTED: usize = 10;
+    let mut listed: Vec<Line> = targets
+        .iter()
+        .take(MAX_LISTED)
+        .map(|t| Line::from(format!("  {}", t.display())))
+        .collect();
+    if targets.len() > MAX_LISTED {
+        listed.push(Line::from(format!(
+            "  …and {} more",
+            targets.len() - MAX_LISTED
+        )));
+    }
+    let reflinked: u128 = targets
+        .iter()
+        .filter_map(|p| app.stats_of(p))
+        .map(|s| s.reflinked_bytes)
+        .sum();
+    if reflinked > 0 {
+        listed.push(Line::from(Span::styled(
+            format!(
+                "{} of it is shared with reflinked copies or snapshots and won't be freed.",
+                format_size(reflinked as u64, DECIMAL)
+            ),
+            Style::default().fg(Color::Yellow),
+        )));
+    }
+    let what = match targets.len() {
+        1 if app.view == View::Duplicates && app.marked.is_empty() => "the other copy".to_string(),
+        n if app.view == View::Duplicates && app.marked.is_empty() => format!("{n} other copies"),
+        1 => "the selected entry".to_string(),
+        n => format!("{n} marked entries"),
+    };
+    let size = format_size(app.total_bytes(targets) as u64, DECIMAL);
+
+    let extra = if check.is_some() { 1 } else { 0 };
+    let popup = centered_popup(f.size(), listed.len() as u16 + 6 + extra);
+
+    let (msg, title, border) = match kind {
+        DeleteKind::Trash => (
+            vec![
+                Line::from(Span::styled(
+                    format!("Move {what} ({size}) to the trash?"),
+                    Style::default().add_modifier(Modifier::BOLD),
+                )),
+                Line::from("Targets:"),
+            ]
+            .into_iter()
+            .chain(listed)
+            .chain([
+                Line::from(""),
+                Line::from("Press 'y' to confirm, 'n' or Esc to cancel."),
+            ])
+            .collect::<Vec<_>>(),
+            "Confirm Move to Trash",
+            Style::default(),
+        ),
+        DeleteKind::Permanent => (
+            vec![
+                Line::from(Span::styled(
+                    format!(
+                        "WARNING: This will permanently and recursively delete {what} ({size})."
+                    ),
+                    Style::default()
+                        .fg(Color::Yellow)
+                        .add_modifier(Modifier::BOLD),
+                )),
+                Line::from("Targets:"),
+            ]
+            .into_iter()
+            .chain(listed)
+            .chain([Line::from(Span::styled(
+                "It will NOT go to the trash and cannot be recovered.",
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ))])
+            .chain(match check {
+                Some(check) => vec![
+                    Line::from(format!(
+                        "Type '{}' and press Enter to delete forever, Esc to cancel:",
+                        check.name
+                    )),
+                    Line::from(Span::styled(
+                        format!("> {}▏", check.typed),
+                        Style::default().add_modifier(Modifier::BOLD),
+                    )),
+                ],
+                None => vec![Line::from(
+                    "Press 'Y' (Shift+y) to delete forever, 'n' or Esc to cancel.",
+                )],
+            })
+            .collect::<Vec<_>>(),
+            "PERMANENT DELETION",
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+        ),
+    };
+
+    f.render_widget(Clear, popup);
+    let block = Paragraph::new(msg).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_style(border)
+            .title(title),
+    );
+    f.render_widget(block, popup);
+}
+
+/// The custom actions menu, with each action's key and command.
+fn draw_actions(f: &mut Frame, app: &App, selected: usize) {
+    let dim = Style::default().fg(Color::DarkGray);
+    let items: Vec<ListItem> = app
+        .config
+        .actions
+        .iter()
+        .map(|a| {
+            let key = a.key.map_or("   ".to_string(), |k| format!("[{k}]"));
+            ListItem::new(Line::from(vec![
+                Span::raw(format!("{key} {:<24} ", a.name)),
+                Span::styled(a.command.clone(), dim),
+            ]))
+        })
         .collect();
-    if let Some(err) = &app.last_error {
-        lines.insert(
-            0,
-            Line::from(Span::styled(
-                format!("ERROR: {err}"),
-                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
-            )),
-        );
-    }
-    let msg = Paragraph::new(lines)
+    let popup = centered_popup(f.size(), items.len() as u16 + 2);
+    f.render_widget(Clear, popup);
+    let list = List::new(items)
         .block(
             Block::default()
                 .borders(Borders::ALL)
-                .title("Messages & Errors"),
+                .title("Actions (Enter or key: run, Esc: close)"),
         )
-        .wrap(Wrap { trim: true });
-    f.render_widget(msg, right_chunks[1]);
-
-    // Help / Keys
-    let help = Paragraph::new(vec![
-        Line::from("Keys:"),
-        Line::from("  ↑/↓       — Move selection"),
-        Line::from("  Enter     — Drill into selected directory"),
-        Line::from("  Backspace — Go to parent directory"),
-        Line::from("  d         — Delete selected directory (asks for confirmation)"),
-        Line::from("  r         — Refresh now"),
-        Line::from("  q         — Quit"),
-    ])
-    .block(Block::default().borders(Borders::ALL).title("Help"));
-    f.render_widget(help, right_chunks[2]);
-}
-
-fn draw_confirm_modal(f: &mut Frame, target: &Path) {
-    // Centered box
-    let area = f.size();
-    let w = (area.width as f32 * 0.7) as u16;
-    let h = 7u16;
-    let x = area.x + (area.width.saturating_sub(w)) / 2;
-    let y = area.y + (area.height.saturating_sub(h)) / 2;
-    let popup = Rect {
-        x,
-        y,
-        width: w,
-        height: h,
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    let mut state = ListState::default().with_selected(Some(selected));
+    f.render_stateful_widget(list, popup, &mut state);
+}
+
+fn draw_bookmarks(f: &mut Frame, app: &App, selected: usize) {
+    let dim = Style::default().fg(Color::DarkGray);
+    let mut items: Vec<ListItem> = app
+        .config
+        .bookmarks
+        .iter()
+        .enumerate()
+        .map(|(i, dir)| {
+            let key = if i < 9 {
+                format!("[{}]", i + 1)
+            } else {
+                "   ".to_string()
+            };
+            // The size, when the bookmark is in the tree.
+            let size = app.tree.find(dir).map_or(String::new(), |id| {
+                format_size(app.tree.stats(id).bytes(app.size_mode) as u64, DECIMAL)
+            });
+            ListItem::new(Line::from(vec![
+                Span::raw(format!("{key} {size:>10}  ")),
+                Span::raw(dir.display().to_string()),
+            ]))
+        })
+        .collect();
+    if items.is_empty() {
+        items.push(ListItem::new(Span::styled(
+            "No bookmarks yet: b bookmarks the directory being listed",
+            dim,
+        )));
+    }
+    let popup = centered_popup(f.size(), items.len() as u16 + 2);
+    f.render_widget(Clear, popup);
+    let list =
+        List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(
+                "Bookmarks (Enter or key: go, b: add this directory, d: remove, Esc: close)",
+            ))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    let selected = (!app.config.bookmarks.is_empty()).then_some(selected);
+    let mut state = ListState::default().with_selected(selected);
+    f.render_stateful_widget(list, popup, &mut state);
+}
+
+fn draw_compress_confirm(f: &mut Frame, dir: &Path, codec: Codec, remove: bool) {
+    let bold = Style::default().add_modifier(Modifier::BOLD);
+    let lines = vec![
+        Line::from(Span::styled(format!("Compress {}?", dir.display()), bold)),
+        Line::from(format!("  into {}", archive_path(dir, codec).display())),
+        Line::from(""),
+        Line::from(vec![
+            Span::raw("  Tab  format:            "),
+            Span::styled(codec.extension(), bold),
+        ]),
+        Line::from(vec![
+            Span::raw("  d    delete afterwards: "),
+            if remove {
+                Span::styled("yes, once the archive checks out", bold.fg(Color::Red))
+            } else {
+                Span::styled("no", bold)
+            },
+        ]),
+        Line::from(""),
+        Line::from("Press Enter to start, Esc to cancel."),
+    ];
+    let popup = centered_popup(f.size(), lines.len() as u16 + 2);
+    f.render_widget(Clear, popup);
+    f.render_widget(
+        Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Compress Directory"),
+        ),
+        popup,
+    );
+}
+
+fn draw_action_confirm(f: &mut Frame, app: &App, i: usize, path: &Path) {
+    let action = &app.config.actions[i];
+    let lines = vec![
+        Line::from(Span::styled(
+            format!("Run {}?", action.name),
+            Style::default().add_modifier(Modifier::BOLD),
+        )),
+        Line::from(format!("  {}", command_line(action, path))),
+        Line::from(""),
+        Line::from("Press 'y' to run it, 'n' or Esc to cancel."),
+    ];
+    let popup = centered_popup(f.size(), lines.len() as u16 + 2);
+    f.render_widget(Clear, popup);
+    f.render_widget(
+        Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Confirm Action"),
+        ),
+        popup,
+    );
+}
+
+fn draw_clean_confirm(f: &mut Frame, path: &Path, cache: &Cache) {
+    let lines = vec![
+        Line::from(Span::styled(
+            format!("Clear the {}?", cache.label),
+            Style::default().add_modifier(Modifier::BOLD),
+        )),
+        Line::from(format!("  {}", path.display())),
+        Line::from(""),
+        Line::from("It will be cleared with:"),
+        Line::from(format!(
+            "  {}",
+            cache
+                .command
+                .replace("{path}", &quote(&path.to_string_lossy()))
+        )),
+        Line::from(""),
+        Line::from("Press 'y' to run it, 'n' or Esc to cancel."),
+    ];
+    let popup = centered_popup(f.size(), lines.len() as u16 + 2);
+    f.render_widget(Clear, popup);
+    f.render_widget(
+        Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Confirm Cleanup"),
+        ),
+        popup,
+    );
+}
+
+/// The filesystem picker: one row per mount with how full it is.
+fn draw_mounts(f: &mut Frame, app: &App, mounts: &[Mount], selected: usize) {
+    const PATH_WIDTH: usize = 30;
+    let bar = BarConfig {
+        width: 10,
+        style: app.config.bar.style,
     };
+    let items: Vec<ListItem> = mounts
+        .iter()
+        .map(|m| {
+            let full = m.used() as f64 / m.total as f64;
+            let color = if full >= 0.9 {
+                Color::Red
+            } else if full >= 0.75 {
+                Color::Yellow
+            } else {
+                Color::Green
+            };
+            let path = keep_tail(&m.path.display().to_string(), PATH_WIDTH);
+            ListItem::new(Line::from(vec![
+                Span::raw(format!("{path:<PATH_WIDTH$} {:<8} ", m.fs_type)),
+                Span::styled(
+                    usage_bar(m.used() as u128, m.total as u128, &bar),
+                    Style::default().fg(color),
+                ),
+                Span::raw(format!(
+                    "  {:>10} / {:>10} {:>10} free",
+                    format_size(m.used(), DECIMAL),
+                    format_size(m.total, DECIMAL),
+                    format_size(m.free, DECIMAL)
+                )),
+            ]))
+        })
+        .collect();
+
+    let popup = centered_popup(f.size(), mounts.len() as u16 + 4);
+    f.render_widget(Clear, popup);
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(if cfg!(windows) {
+                    "Drives (Enter: scan, Esc: close)"
+                } else {
+                    "Filesystems (Enter: scan, Esc: close)"
+                }),
+        )
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    let mut state = ListState::default().with_selected(Some(selected));
+    f.render_stateful_widget(list, popup, &mut state);
+}
+
+/// Btrfs subvolumes and snapshots, with what each holds on to by itself.
+fn draw_subvolumes(f: &mut Frame, app: &App, subvolumes: &[Subvolume], selected: usize) {
+    let size = |bytes: Option<u64>| bytes.map_or("-".to_string(), |b| format_size(b, DECIMAL));
+    let mut items = vec![ListItem::new(Span::styled(
+        format!(
+            "{:>10}  {:>10}  {:<9}  Path (from the top level)",
+            "Exclusive", "Shared", "Kind"
+        ),
+        Style::default().add_modifier(Modifier::BOLD),
+    ))];
+    items.extend(subvolumes.iter().map(|s| {
+        let kind = if s.snapshot { "snapshot" } else { "subvolume" };
+        let line = format!(
+            "{:>10}  {:>10}  {kind:<9}  {}",
+            size(s.usage.map(|(_, exclusive)| exclusive)),
+            size(s.shared()),
+            s.path.display()
+        );
+        ListItem::new(if s.snapshot {
+            Span::styled(line, Style::default().fg(Color::Yellow))
+        } else {
+            Span::raw(line)
+        })
+    }));
+    let held: u64 = subvolumes
+        .iter()
+        .filter(|s| s.snapshot)
+        .filter_map(|s| s.usage.map(|(_, exclusive)| exclusive))
+        .sum();
+    let on = app.disk.as_ref().map_or(PathBuf::new(), |d| d.path.clone());
+    let title = format!(
+        "Subvolumes on {} (snapshots hold {} by themselves; Esc: close)",
+        on.display(),
+        format_size(held, DECIMAL)
+    );
+
+    let popup = centered_popup(f.size(), items.len() as u16 + 2);
+    f.render_widget(Clear, popup);
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    let mut state = ListState::default().with_selected(Some(selected + 1));
+    f.render_stateful_widget(list, popup, &mut state);
+}
 
-    let msg = vec![
+/// Local Time Machine snapshots, oldest first, and whether to thin them.
+fn draw_local_snapshots(f: &mut Frame, app: &App, names: &[String], thinning: bool) {
+    let mut lines: Vec<Line> = names.iter().map(|n| Line::from(format!("  {n}"))).collect();
+    lines.push(Line::from(""));
+    lines.push(if thinning {
         Line::from(Span::styled(
-            "WARNING: This will permanently and recursively delete the selected directory.",
+            "Remove all of them? Time Machine can't restore from them afterwards. 'y' to go ahead",
             Style::default()
                 .fg(Color::Yellow)
                 .add_modifier(Modifier::BOLD),
+        ))
+    } else {
+        Line::from("They hold on to everything deleted since they were taken. T thins them all.")
+    });
+    let on = app.disk.as_ref().map_or(PathBuf::new(), |d| d.path.clone());
+    let title = format!(
+        "Local Time Machine snapshots of {} (Esc: close)",
+        on.display()
+    );
+
+    let popup = centered_popup(f.size(), lines.len() as u16 + 2);
+    f.render_widget(Clear, popup);
+    f.render_widget(
+        Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title)),
+        popup,
+    );
+}
+
+/// The saved snapshots, newest first.
+fn draw_snapshots(f: &mut Frame, app: &App, saved: &[Saved], selected: usize, deleting: bool) {
+    let dim = Style::default().fg(Color::DarkGray);
+    let items: Vec<ListItem> = if saved.is_empty() {
+        vec![ListItem::new(Span::styled(
+            "No snapshots yet; n saves the tree as one",
+            dim,
+        ))]
+    } else {
+        saved
+            .iter()
+            .map(|s| {
+                let when = chrono::DateTime::<Local>::from(s.saved).format("%Y-%m-%d %H:%M");
+                let browsing = app.snapshot.as_ref() == Some(&s.name);
+                ListItem::new(Line::from(vec![
+                    Span::raw(format!("{:<24} {when}  {:<14}", s.name, fmt_age(s.saved))),
+                    Span::styled(
+                        format!(
+                            "{:>10}  {}{}",
+                            format_size(s.bytes, DECIMAL),
+                            s.format.label(),
+                            if browsing { "  (browsing)" } else { "" }
+                        ),
+                        dim,
+                    ),
+                ]))
+            })
+            .collect()
+    };
+    let title = match saved.get(selected) {
+        Some(s) if deleting => format!("Delete snapshot {}? (y: delete, other keys: keep)", s.name),
+        _ if app.snapshot.is_some() => {
+            "Snapshots (Enter: compare, o: browse, n: save, d: delete, b: back to scanning, Esc: close)"
+                .to_string()
+        }
+        _ => "Snapshots (Enter: compare, o: browse, n: save, d: delete, Esc: close)".to_string(),
+    };
+    let popup = centered_popup(f.size(), items.len() as u16 + 2);
+    f.render_widget(Clear, popup);
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    let mut state = ListState::default().with_selected((!saved.is_empty()).then_some(selected));
+    f.render_stateful_widget(list, popup, &mut state);
+}
+
+/// The biggest owners of the whole tree.
+fn draw_owners(f: &mut Frame, app: &App, key: OwnerKey, rows: &[(String, TypeTotals)]) {
+    let whole = app.tree.stats(app.tree.root()).bytes(app.size_mode);
+    let bar = BarConfig {
+        width: 10,
+        style: app.config.bar.style,
+    };
+    let items: Vec<ListItem> = rows
+        .iter()
+        .map(|(name, t)| {
+            let bytes = t.bytes(app.size_mode);
+            ListItem::new(Line::from(format!(
+                "{name:<16} {:>10} {}  {:>11} files",
+                format_size(bytes as u64, DECIMAL),
+                usage_bar(bytes, whole, &bar),
+                t.file_count.separate_with_spaces()
+            )))
+        })
+        .collect();
+    let (what, other) = match key {
+        OwnerKey::User => ("Users", "groups"),
+        OwnerKey::Group => ("Groups", "users"),
+    };
+    let title = format!(
+        "{what} owning {} (Tab: {other}, Esc: close)",
+        app.tree.root_path().display()
+    );
+    let popup = centered_popup(f.size(), rows.len().max(1) as u16 + 2);
+    f.render_widget(Clear, popup);
+    let block = Block::default().borders(Borders::ALL).title(title);
+    if items.is_empty() {
+        let note = if app.imported.is_some() {
+            "The export has no owners (ncdu records them with -e)"
+        } else {
+            "No owners known yet; a full rescan (R) records them"
+        };
+        f.render_widget(Paragraph::new(note).block(block), popup);
+    } else {
+        f.render_widget(List::new(items).block(block), popup);
+    }
+}
+
+/// The extensions of the whole tree, the opened ones followed by the
+/// directories holding the most of them.
+fn draw_extensions(f: &mut Frame, app: &App, rows: &[ExtRow], selected: usize) {
+    let whole = app.tree.stats(app.tree.root()).bytes(app.size_mode);
+    let bar = BarConfig {
+        width: 10,
+        style: app.config.bar.style,
+    };
+    let mut ext_bytes = whole;
+    let items: Vec<ListItem> = rows
+        .iter()
+        .map(|row| match row {
+            ExtRow::Ext(ext, t) => {
+                ext_bytes = t.bytes(app.size_mode);
+                ListItem::new(Line::from(format!(
+                    "{:<16} {:>10} {}  {:>11} files",
+                    filetype::label(ext),
+                    format_size(ext_bytes as u64, DECIMAL),
+                    usage_bar(ext_bytes, whole, &bar),
+                    t.file_count.separate_with_spaces()
+                )))
+            }
+            ExtRow::Dir(dir, t) => {
+                let bytes = t.bytes(app.size_mode);
+                let rel = dir.strip_prefix(app.tree.root_path()).unwrap_or(dir);
+                ListItem::new(Line::from(Span::styled(
+                    format!(
+                        "  {:>25} {}  {:>11} files  {}/",
+                        format_size(bytes as u64, DECIMAL),
+                        usage_bar(bytes, ext_bytes, &bar),
+                        t.file_count.separate_with_spaces(),
+                        rel.display()
+                    ),
+                    Style::default().fg(Color::Cyan),
+                )))
+            }
+        })
+        .collect();
+    let title = format!(
+        "Extensions across {} (Enter: where they are / go there, Esc: close)",
+        app.tree.root_path().display()
+    );
+    let popup = centered_popup(f.size(), rows.len().max(1) as u16 + 2);
+    f.render_widget(Clear, popup);
+    let block = Block::default().borders(Borders::ALL).title(title);
+    if items.is_empty() {
+        f.render_widget(Paragraph::new("No files counted yet").block(block), popup);
+    } else {
+        let list = List::new(items)
+            .block(block)
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut state = ListState::default().with_selected(Some(selected));
+        f.render_stateful_widget(list, popup, &mut state);
+    }
+}
+
+/// The dry run of replacing copies with hard links: each replacement, what
+/// they free together, and the copies left alone.
+fn draw_link_modal(f: &mut Frame, app: &App, links: &[Link], skipped: &[PathBuf], scroll: usize) {
+    let base = app.dupes_root.as_deref().unwrap_or(&app.cwd);
+    let rel = |p: &Path| p.strip_prefix(base).unwrap_or(p).display().to_string();
+    let freed: u128 = links.iter().map(|l| l.bytes).sum();
+    let bold = Style::default().add_modifier(Modifier::BOLD);
+    let dim = Style::default().fg(Color::DarkGray);
+
+    let mut rows: Vec<Line> = links
+        .iter()
+        .map(|l| {
+            Line::from(vec![
+                Span::raw(format!(
+                    "{:>10}  {}",
+                    format_size(l.bytes as u64, DECIMAL),
+                    rel(&l.copy)
+                )),
+                Span::styled(format!("  → {}", rel(&l.keep)), dim),
+            ])
+        })
+        .collect();
+    if !skipped.is_empty() {
+        rows.push(Line::from(""));
+        rows.push(Line::from(
+            "Left alone, on another filesystem than the copy kept:",
+        ));
+        rows.extend(skipped.iter().map(|p| Line::from(format!("  {}", rel(p)))));
+    }
+
+    let head = Line::from(Span::styled(
+        match links.len() {
+            0 => "Nothing to link".to_string(),
+            n => format!(
+                "Replace {n} copies with hard links, freeing about {} (dry run)",
+                format_size(freed as u64, DECIMAL)
+            ),
+        },
+        bold,
+    ));
+    let foot = Line::from(if links.is_empty() {
+        "Press Esc to close."
+    } else {
+        "Edits to any of them will show in all. j/k scroll, 'y' links them, 'n' or Esc cancels."
+    });
+
+    let popup = centered_popup(f.size(), rows.len() as u16 + 6);
+    f.render_widget(Clear, popup);
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title("Confirm Hard Links");
+    let inner = block.inner(popup);
+    f.render_widget(block, popup);
+    let parts = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(2),
+            Constraint::Min(1),
+            Constraint::Length(2),
+        ])
+        .split(inner);
+    f.render_widget(Paragraph::new(head), parts[0]);
+    let scroll = scroll.min(rows.len().saturating_sub(parts[1].height as usize));
+    f.render_widget(Paragraph::new(rows).scroll((scroll as u16, 0)), parts[1]);
+    f.render_widget(Paragraph::new(vec![Line::from(""), foot]), parts[2]);
+}
+
+fn draw_prune_modal(f: &mut Frame, app: &App, dirs: &[PathBuf]) {
+    const MAX_LISTED: usize = 10;
+    let total: u64 = app
+        .empties
+        .iter()
+        .filter(|e| dirs.contains(&e.path))
+        .map(|e| e.dir_count)
+        .sum();
+    let mut lines = vec![
+        Line::from(Span::styled(
+            format!("Remove {total} empty directories?"),
+            Style::default().add_modifier(Modifier::BOLD),
         )),
-        Line::from(format!("Target: {}", target.display())),
-        Line::from(""),
-        Line::from("Press 'y' to confirm, 'n' or Esc to cancel."),
+        Line::from("Outermost ones:"),
     ];
+    lines.extend(
+        dirs.iter()
+            .take(MAX_LISTED)
+            .map(|d| Line::from(format!("  {}", d.display()))),
+    );
+    if dirs.len() > MAX_LISTED {
+        lines.push(Line::from(format!(
+            "  …and {} more",
+            dirs.len() - MAX_LISTED
+        )));
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::from(
+        "Anything put in them since is left alone. Press 'y' to confirm, 'n' or Esc to cancel.",
+    ));
 
+    let popup = centered_popup(f.size(), lines.len() as u16 + 2);
     f.render_widget(Clear, popup);
-    let block = Paragraph::new(msg).block(
+    let block = Paragraph::new(lines).wrap(Wrap { trim: false }).block(
         Block::default()
             .borders(Borders::ALL)
-            .title("Confirm Deletion"),
+            .title("Remove Empty Directories"),
     );
     f.render_widget(block, popup);
 }
 
+fn draw_empty_trash_modal(f: &mut Frame, app: &App) {
+    let total: u128 = app.trash.iter().map(|t| t.bytes(app.size_mode)).sum();
+    let mut lines = vec![
+        Line::from(Span::styled(
+            format!(
+                "Empty the trash for good, freeing {}?",
+                format_size(total as u64, DECIMAL)
+            ),
+            Style::default().add_modifier(Modifier::BOLD),
+        )),
+        Line::from(""),
+    ];
+    lines.extend(app.trash.iter().map(|t| {
+        Line::from(format!(
+            "  {:>10}  {}",
+            format_size(t.bytes(app.size_mode) as u64, DECIMAL),
+            t.path.display()
+        ))
+    }));
+    lines.push(Line::from(""));
+    lines.push(Line::from(Span::styled(
+        "Nothing in it can be restored afterwards.",
+        Style::default().fg(Color::Red),
+    )));
+    lines.push(Line::from("Press 'y' to confirm, 'n' or Esc to cancel."));
+
+    let popup = centered_popup(f.size(), lines.len() as u16 + 2);
+    f.render_widget(Clear, popup);
+    let block = Paragraph::new(lines)
+        .wrap(Wrap { trim: false })
+        .block(Block::default().borders(Borders::ALL).title("Empty Trash"));
+    f.render_widget(block, popup);
+}
+
+/// A box of `height` rows, 70% as wide as `area` and centered in it.
+fn centered_popup(area: Rect, height: u16) -> Rect {
+    let w = (area.width as f32 * 0.7) as u16;
+    let h = height.min(area.height);
+    Rect {
+        x: area.x + (area.width.saturating_sub(w)) / 2,
+        y: area.y + (area.height.saturating_sub(h)) / 2,
+        width: w,
+        height: h,
+    }
+}
+
 // ====== Event loop ======
 
+/// Reports a bad command line the way clap does and exits.
+fn exit_usage(e: anyhow::Error) -> ! {
+    eprintln!("dm: {e:#}");
+    std::process::exit(2);
+}
+
+/// Scans `root` to completion for the modes without a browser, with
+/// problems going to stderr.
+fn scan_headless(root: PathBuf, opts: ScanOptions) -> Option<DirTree> {
+    Scanner::new(root, opts).run(|event| match event {
+        ScanEvent::Unreadable(e) | ScanEvent::Warning(e) => eprintln!("dm: {e}"),
+        ScanEvent::Progress(_) | ScanEvent::Checkpoint(_) => {}
+    })
+}
+
+/// The directory at `path` on the other end of an SFTP session or in a
+/// bucket, made absolute; for SFTP, the login directory if `path` is empty.
+fn foreign_root(vfs: &dyn Vfs, path: &Path) -> Result<PathBuf> {
+    let root = vfs
+        .canonicalize(path)
+        .with_context(|| format!("Cannot open '{}'", path.display()))?;
+    let stat = vfs
+        .stat(&root)
+        .with_context(|| format!("Cannot open '{}'", root.display()))?;
+    if stat.kind != Kind::Dir {
+        anyhow::bail!("'{}' is not a directory", root.display());
+    }
+    Ok(root)
+}
+
 fn main() -> Result<()> {
-    let cwd = std::env::current_dir().context("Unable to get current directory")?;
-    let mut app = App::new(cwd.clone());
+    let cli = Cli::parse();
+    if let Some(Command::Agent(args)) = &cli.command {
+        let mut config = config::load(cli.config.clone()).unwrap_or_else(|e| exit_usage(e));
+        if cli.read_only {
+            config.read_only = true;
+        }
+        if let Err(e) = remote::serve(args, &config) {
+            eprintln!("dm: {e:#}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    let remote = cli.connect.clone().map(Remote::new);
+    let foreign = cli.foreign_url().unwrap_or_else(|e| exit_usage(e));
+    let vfs: Arc<dyn Vfs> = match &foreign {
+        Some(foreign) => foreign.open().unwrap_or_else(|e| {
+            eprintln!("dm: {e}");
+            std::process::exit(1);
+        }),
+        None => vfs::local(),
+    };
+    let roots = match (&remote, &foreign) {
+        (Some(remote), _) => remote.resolve(cli.paths.clone()).unwrap_or_else(|e| {
+            eprintln!("dm: {e:#}");
+            std::process::exit(1);
+        }),
+        (None, Some(foreign)) => vec![foreign_root(&*vfs, foreign.path()).unwrap_or_else(|e| {
+            eprintln!("dm: {e:#}");
+            std::process::exit(1);
+        })],
+        (None, None) => cli.start_dirs().unwrap_or_else(|e| exit_usage(e)),
+    };
+    let mut config = config::load(cli.config.clone()).unwrap_or_else(|e| exit_usage(e));
+    let scan_opts = cli
+        .scan_options(&config.scan)
+        .unwrap_or_else(|e| exit_usage(e));
+    let size_mode = cli.size_mode(&config.scan);
+    let profile = cli.profile.or(config.scan.profile);
+    if let Some(minutes) = cli.auto_rescan {
+        config.auto_rescan_minutes = minutes;
+    }
+    if cli.no_watch {
+        config.watch = false;
+    }
+    if cli.read_only {
+        config.read_only = true;
+    }
+    if remote.is_some() || foreign.is_some() {
+        // Nothing to watch from here, and the cache and history are of
+        // this machine's paths.
+        config.watch = false;
+        config.history = false;
+    }
+    let mouse = !cli.no_mouse;
+
+    if let Some(Command::Report(args)) = &cli.command {
+        let tree = scan_headless(roots[0].clone(), scan_opts)
+            .unwrap_or_else(|| DirTree::new(roots[0].clone()));
+        let mut out = io::stdout().lock();
+        if let Err(e) = report::write(&tree, args, size_mode, &mut out) {
+            eprintln!("dm: {e:#}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if let Some(Command::Check(args)) = &cli.command {
+        let Some(tree) = scan_headless(roots[0].clone(), scan_opts) else {
+            eprintln!("dm: cannot scan {}", roots[0].display());
+            std::process::exit(2);
+        };
+        let mut out = io::stdout().lock();
+        let within = check::run(&tree, args, size_mode, &mut out);
+        let errors = tree.stats(tree.root()).errors;
+        match within {
+            Err(e) => eprintln!("dm: {e:#}"),
+            // Whatever couldn't be read may be what's over.
+            Ok(_) if errors > 0 => eprintln!(
+                "dm: {} entries under {} couldn't be read, so its totals are only a lower bound",
+                errors.separate_with_spaces(),
+                roots[0].display()
+            ),
+            Ok(true) => return Ok(()),
+            Ok(false) => std::process::exit(1),
+        }
+        std::process::exit(2);
+    }
+    if let Some(Command::Bench(args)) = &cli.command {
+        let mut out = io::stdout().lock();
+        if let Err(e) = bench::run(&roots[0], &scan_opts, args, &mut out) {
+            eprintln!("dm: {e:#}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if cli.daemon {
+        let paths = if cli.paths.is_empty() && !config.daemon.paths.is_empty() {
+            config.daemon.paths.clone()
+        } else {
+            roots
+        };
+        let listen = cli.listen.as_deref().unwrap_or(&config.daemon.listen);
+        if let Err(e) = daemon::run(paths, scan_opts, size_mode, &config, listen) {
+            eprintln!("dm: {e:#}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    let table = match (&cli.export_csv, &cli.export_tsv) {
+        (Some(out), _) => Some((out, table::Separator::Comma)),
+        (_, Some(out)) => Some((out, table::Separator::Tab)),
+        _ => None,
+    };
+    if let Some((out, sep)) = table {
+        if roots.len() > 1 {
+            exit_usage(anyhow::anyhow!(
+                "--export-csv and --export-tsv take a single PATH"
+            ));
+        }
+        let tree = scan_headless(roots[0].clone(), scan_opts)
+            .unwrap_or_else(|| DirTree::new(roots[0].clone()));
+        if let Err(e) = table::export(&tree, out, sep) {
+            eprintln!("dm: {e:#}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if let Some(out) = &cli.export {
+        if roots.len() > 1 {
+            exit_usage(anyhow::anyhow!("--export takes a single PATH"));
+        }
+        if let Err(e) = ncdu::export(&roots[0], out, &scan_opts) {
+            eprintln!("dm: {e:#}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    let import = cli
+        .import
+        .as_ref()
+        .map(|src| ncdu::import(src, scan_opts.dedup_hardlinks).unwrap_or_else(|e| exit_usage(e)));
+
+    let browse = cli.snapshot.as_ref().map(|name| {
+        let (tree, taken) = snapshot::load(name).unwrap_or_else(|e| exit_usage(e));
+        (name.clone(), tree, taken)
+    });
+    let baseline = cli.compare.as_ref().map(|name| {
+        let (tree, taken) = snapshot::load(name).unwrap_or_else(|e| exit_usage(e));
+        Baseline {
+            tree,
+            name: Some(name.clone()),
+            taken,
+        }
+    });
+
+    let use_cache = !cli.no_cache && remote.is_none() && foreign.is_none();
+    let mut app = App::new(roots, size_mode, scan_opts, use_cache, config);
+    app.remote = remote;
+    app.vfs = vfs;
+    app.config_path = cli.config.clone().or_else(config::default_path);
+    app.baseline = baseline;
+    app.profile = profile;
+    app.timeout_set = cli.dir_timeout.or(app.config.scan.dir_timeout).is_some();
+    if app.config.history {
+        match History::open() {
+            Ok(history) => app.history = Some(history),
+            Err(e) => app.log(format!("Scan history is off: {e:#}")),
+        }
+    }
 
     // Channels
     let (tx, rx): (Sender<Msg>, Receiver<Msg>) = mpsc::channel();
@@ -387,43 +5477,120 @@ fn main() -> Result<()> {
         });
     }
 
-    // Periodic rescanner (every 15 seconds)
-    {
-        let tx = tx.clone();
-        thread::spawn(move || loop {
-            thread::sleep(Duration::from_secs(60 * 15));
-            let _ = tx.send(Msg::RecomputeNow);
-        });
+    // Ctrl+C comes in as a key in raw mode; these are sent from outside.
+    let signals = Signals::default();
+    if let Err(e) = signals.register() {
+        app.log(format!("Cannot handle signals: {e}"));
     }
 
     // Kick off initial scan
-    {
-        let tx = tx.clone();
-        let _ = tx.send(Msg::RecomputeNow);
+    match (import, browse) {
+        (Some(import), _) => app.open_import(import),
+        (None, Some((name, tree, taken))) => app.browse_snapshot(name, tree, taken, &tx),
+        (None, None) if cli.mounts && app.open_mounts() => {}
+        (None, None) => app.switch_root(app.roots[0].clone(), &tx),
     }
 
     // TUI setup
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
+    let guard = term::Guard::enter(mouse)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
     terminal.clear()?;
 
     // Main loop
-    let result = run_loop(&mut terminal, &mut app, rx, tx.clone());
+    let result = run_loop(&mut terminal, &mut app, rx, tx.clone(), &signals);
 
     // Restore terminal
-    disable_raw_mode().ok();
-    execute!(terminal.backend_mut(), LeaveAlternateScreen).ok();
-    terminal.show_cursor().ok();
+    drop(guard);
 
     // Return result
-    if let Err(e) = result {
-        eprintln!("Fatal error: {e:?}");
-        std::process::exit(1);
+    match result {
+        Ok(0) => Ok(()),
+        Ok(status) => process::exit(status),
+        Err(e) => {
+            eprintln!("Fatal error: {e:?}");
+            std::process::exit(1);
+        }
     }
-    Ok(())
+}
+
+/// SIGINT and SIGTERM, noted by their handlers as they arrive. That is
+/// before a program run from dm that a Ctrl+C stops exits, so the SIGINT
+/// meant for it can be told apart.
+#[derive(Default)]
+struct Signals {
+    interrupt: Arc<AtomicBool>,
+    terminate: Arc<AtomicBool>,
+}
+
+impl Signals {
+    #[cfg(unix)]
+    fn register(&self) -> io::Result<()> {
+        use signal_hook::consts::{SIGINT, SIGTERM};
+        signal_hook::flag::register(SIGINT, self.interrupt.clone())?;
+        signal_hook::flag::register(SIGTERM, self.terminate.clone())?;
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn register(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// The status to exit with for a signal that came since the last call.
+    fn take(&self) -> Option<i32> {
+        if self.terminate.swap(false, Ordering::Relaxed) {
+            return Some(143);
+        }
+        self.interrupt.swap(false, Ordering::Relaxed).then_some(130)
+    }
+}
+
+/// Suspends the TUI for `job` and brings it back when `job` exits.
+fn run_foreground(
+    terminal: &mut Terminal<CrosstermBackend<std::io::Stdout>>,
+    job: &Foreground,
+) -> Result<()> {
+    term::give_back();
+
+    let status = match job {
+        Foreground::Shell(dir) => {
+            let shell = env::var_os("SHELL")
+                .or_else(|| env::var_os("COMSPEC"))
+                .unwrap_or_else(|| if cfg!(windows) { "cmd.exe" } else { "/bin/sh" }.into());
+            println!("Shell in {}; exit it to return to dm.", dir.display());
+            process::Command::new(&shell)
+                .current_dir(dir)
+                .status()
+                .with_context(|| {
+                    format!(
+                        "Cannot run {} in {}",
+                        shell.to_string_lossy(),
+                        dir.display()
+                    )
+                })
+                .map(|_| ())
+        }
+        Foreground::Action(name, line, dir) => {
+            println!("{name}: {line}");
+            let status = shell_command(line)
+                .current_dir(dir)
+                .status()
+                .with_context(|| format!("{name}: cannot run it"));
+            // Leave the output up until it has been read.
+            if let Ok(status) = &status {
+                println!("\n{name}: {status}. Press Enter to return to dm.");
+                let _ = io::stdin().read_line(&mut String::new());
+            }
+            match status {
+                Ok(status) if !status.success() => Err(anyhow::anyhow!("{name}: {status}")),
+                other => other.map(|_| ()),
+            }
+        }
+    };
+
+    term::take()?;
+    terminal.clear()?;
+    status
 }
 
 fn run_loop(
@@ -431,26 +5598,81 @@ fn run_loop(
     app: &mut App,
     rx: Receiver<Msg>,
     tx: Sender<Msg>,
-) -> Result<()> {
+    signals: &Signals,
+) -> Result<i32> {
+    // Drawing is skipped while nothing has changed, which over SSH and in
+    // tmux is most of the time.
+    let mut dirty = true;
+    let mut drawn = Instant::now();
     loop {
-        terminal.draw(|f| draw_ui(f, app))?;
+        if dirty || drawn.elapsed() >= IDLE_REDRAW {
+            terminal.draw(|f| draw_ui(f, app))?;
+            dirty = false;
+            drawn = Instant::now();
+        }
 
         // Poll keyboard with small timeout so we can also process messages
         if event::poll(Duration::from_millis(50))? {
-            if let CEvent::Key(key) = event::read()? {
-                if handle_key(key, app, &tx)? {
-                    // true => quit
-                    return Ok(());
+            dirty = true;
+            match event::read()? {
+                // Whatever is open, and a second time without waiting.
+                CEvent::Key(KeyEvent {
+                    code: KeyCode::Char('c'),
+                    modifiers: KeyModifiers::CONTROL,
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => app.quit(130),
+                // Nothing new starts while quitting.
+                CEvent::Key(_) | CEvent::Mouse(_) if app.quitting.is_some() => {}
+                CEvent::Key(key) => {
+                    let quit = handle_key(key, app, &tx)?;
+                    if quit {
+                        app.quit(0);
+                    }
+                }
+                CEvent::Mouse(mouse) => handle_mouse(mouse, app, &tx),
+                _ => {}
+            }
+            if let Some(job) = app.foreground.take() {
+                match run_foreground(terminal, &job) {
+                    Ok(()) => match job {
+                        Foreground::Shell(dir) => {
+                            app.log(format!("Back from the shell in {}", dir.display()))
+                        }
+                        Foreground::Action(name, ..) => app.log(format!("{name}: done")),
+                    },
+                    Err(e) => {
+                        app.last_error = Some(format!("{e:#}"));
+                        app.log(format!("Error: {e:#}"));
+                    }
                 }
+                // Ctrl+C in there was for the program; it runs in dm's
+                // process group, so dm got the SIGINT too.
+                signals.interrupt.store(false, Ordering::Relaxed);
+                // Whatever was done in there shows up in the totals.
+                let _ = tx.send(Msg::RecomputeNow);
             }
+            app.remember_selection();
+            app.update_focus();
         }
 
         // Drain messages
         while let Ok(msg) = rx.try_recv() {
             match msg {
-                Msg::Tick => { /* no-op */ }
+                // Nothing new starts while quitting.
+                Msg::Tick if app.quitting.is_some() => continue,
+                Msg::Tick => {
+                    app.sample_scan();
+                    app.check_power();
+                    app.scan_pending(&tx);
+                    app.maybe_auto_rescan(&tx);
+                    app.run_schedules(&tx);
+                    dirty |= app.busy();
+                    continue;
+                }
                 Msg::RecomputeNow => {
-                    if !app.is_scanning {
+                    // Whatever is running will update the tree anyway.
+                    if !app.is_scanning && app.imported.is_none() {
                         let now = Local::now();
 
                         // Extract hours, minutes, and seconds
@@ -460,18 +5682,57 @@ fn run_loop(
                         let now = format!("{hour}:{minute}");
 
                         app.log(format!("{now} - scan started "));
-                        app.is_scanning = true;
-                        app.last_scan_started = Some(Instant::now());
-                        let _ = spawn_scan_thread(app.cwd.clone(), tx.clone());
+                        app.start_scan(app.cwd.clone(), None, true, &tx);
+                    }
+                }
+                Msg::Info(s) => app.log(s),
+                Msg::HistoryRecorded => {
+                    app.trend = None;
+                    app.update_trend();
+                }
+                Msg::TrashSized(trash) => app.trash_sized(trash),
+                Msg::TrashEmptied(Ok(())) => {
+                    let freed: u128 = app.trash.iter().map(|t| t.bytes(app.size_mode)).sum();
+                    app.update_disk();
+                    app.log(format!(
+                        "Emptied the trash, freeing {}",
+                        format_size(freed as u64, DECIMAL)
+                    ));
+                }
+                Msg::TrashEmptied(Err(e)) => {
+                    app.last_error = Some(format!("Failed to empty the trash: {e}"));
+                    app.log(format!("Failed to empty the trash: {e}"));
+                }
+                Msg::Error(e) | Msg::Scan(_, ScanEvent::Warning(e)) => {
+                    app.last_error = Some(e.clone());
+                    app.log(format!("Error: {e}"));
+                }
+                Msg::Scan(id, _) if id != app.scan_id => {}
+                Msg::Scan(_, ScanEvent::Progress(stats)) => app.scan_progress(stats),
+                Msg::Scan(_, ScanEvent::Unreadable(e)) => app.log(format!("⚠ {e}")),
+                Msg::Scan(id, ScanEvent::Checkpoint(tree)) => app.save_checkpoint(id, tree, &tx),
+                Msg::ScanFinished(id, _) | Msg::ScanFailed(id, _) if id != app.scan_id => {}
+                Msg::ArchiveRead(path, Ok(members)) => {
+                    if app.is_scanning || app.outside.is_some() {
+                        app.log(format!("Not opening {}: busy", path.display()));
+                    } else {
+                        app.enter_archive(path, members, &tx);
                     }
                 }
-                Msg::Error(e) => {
+                Msg::ArchiveRead(_, Err(e)) => {
+                    app.last_error = Some(e.clone());
+                    app.log(format!("Error: {e}"));
+                }
+                Msg::ScanFailed(_, e) => {
+                    app.is_scanning = false;
+                    app.scan_cancel = None;
                     app.last_error = Some(e.clone());
                     app.log(format!("Error: {e}"));
                 }
-                Msg::ScanFinished(list) => {
+                Msg::ScanFinished(_, tree) => {
                     app.is_scanning = false;
-                    app.set_entries(list);
+                    app.scan_cancel = None;
+                    app.scan_finished(tree, &tx);
                     if let Some(started) = app.last_scan_started.take() {
                         let elapsed = started.elapsed().as_secs();
                         let now = Local::now();
@@ -487,15 +5748,188 @@ fn run_loop(
                         app.log("Scan completed");
                     }
                 }
-                Msg::DeleteFinished(path, res) => match res {
-                    Ok(()) => app.log(format!("Deleted: {}", path.display())),
+                Msg::WatchUpdate(updates) => {
+                    app.apply_watch(updates);
+                    let root = app.tree.root_path().to_path_buf();
+                    app.check_rules(&root, &tx);
+                }
+                Msg::DupesFinished(root, of, groups) => app.dupes_finished(root, of, groups),
+                Msg::OldFilesFinished(root, found) => app.old_files_finished(root, found),
+                Msg::EmptyFinished(root, found) => app.empty_finished(root, found),
+                Msg::BrokenFinished(root, found) => app.broken_finished(root, found),
+                Msg::AuditFinished(root, found) => app.audit_finished(root, found),
+                Msg::PruneFinished(dir, res) => match res {
+                    Ok(n) => {
+                        app.forget_empty(&dir);
+                        if let Some(id) = app.tree.find(&dir) {
+                            app.tree.detach(id);
+                        }
+                        app.refresh_view();
+                        app.log(format!("Removed {n} empty directories: {}", dir.display()));
+                    }
+                    Err(e) => {
+                        app.last_error = Some(format!("Failed to remove {}: {e}", dir.display()));
+                        app.log(format!("Failed to remove {}: {e}", dir.display()));
+                    }
+                },
+                Msg::LinkFinished(copy, res) => match res {
+                    Ok(()) => {
+                        app.forget_duplicate(&copy);
+                        app.refresh_view();
+                        app.update_disk();
+                        app.log(format!("Linked: {}", copy.display()));
+                    }
                     Err(e) => {
-                        app.last_error = Some(format!("Failed to delete {}: {e}", path.display()));
-                        app.log(format!("Failed to delete {}: {e}", path.display()));
+                        app.last_error = Some(format!("Failed to link {}: {e}", copy.display()));
+                        app.log(format!("Failed to link {}: {e}", copy.display()));
                     }
                 },
+                Msg::CompressProgress(phase, bytes) => {
+                    if let Some(c) = &mut app.compressing {
+                        c.phase = phase;
+                        c.bytes = bytes;
+                    }
+                }
+                Msg::EstimateFinished(dir, res) => app.estimate_finished(dir, res),
+                Msg::CompressFinished(dir, res) => {
+                    let total = app.compressing.take().map_or(0, |c| c.total);
+                    match res {
+                        Ok(packed) => {
+                            let ratio = if total > 0 {
+                                format!(
+                                    ", {:.0}% of the original",
+                                    packed.size as f64 * 100.0 / total as f64
+                                )
+                            } else {
+                                String::new()
+                            };
+                            app.log(format!(
+                                "Compressed {} into {} ({}{ratio}){}",
+                                dir.display(),
+                                packed.archive.display(),
+                                format_size(packed.size, DECIMAL),
+                                if packed.removed {
+                                    " and deleted it"
+                                } else {
+                                    ""
+                                }
+                            ));
+                        }
+                        Err(e) => {
+                            app.last_error =
+                                Some(format!("Failed to compress {}: {e}", dir.display()));
+                            app.log(format!("Failed to compress {}: {e}", dir.display()));
+                        }
+                    }
+                }
+                Msg::DeleteProgress(files, bytes) => {
+                    if let Some(deletion) = &mut app.deleting {
+                        deletion.files = files;
+                        deletion.bytes = bytes;
+                    }
+                }
+                Msg::DeleteFinished(path, kind, res) => {
+                    if kind == DeleteKind::Permanent {
+                        if let Some(deletion) = &mut app.deleting {
+                            deletion.left -= 1;
+                            if deletion.left == 0 {
+                                app.deleting = None;
+                                app.note_local_snapshots();
+                            }
+                        }
+                    }
+                    match res {
+                        Ok(()) => {
+                            app.forget_empty(&path);
+                            if let Some(id) = app.tree.find(&path) {
+                                app.tree.detach(id);
+                                app.refresh_view();
+                            } else {
+                                app.remove_file(&path);
+                            }
+                            app.update_disk();
+                            match kind {
+                                DeleteKind::Trash => {
+                                    app.size_trash(&tx);
+                                    app.log(format!("Moved to trash: {}", path.display()))
+                                }
+                                DeleteKind::Permanent => {
+                                    app.log(format!("Deleted: {}", path.display()))
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            app.last_error =
+                                Some(format!("Failed to delete {}: {e}", path.display()));
+                            app.log(format!("Failed to delete {}: {e}", path.display()));
+                        }
+                    }
+                }
             }
+            dirty = true;
         }
+
+        if let Some(status) = signals.take() {
+            app.quit(status);
+            dirty = true;
+        }
+        if let Some(status) = app.can_exit() {
+            return Ok(status);
+        }
+    }
+}
+
+fn handle_mouse(mouse: MouseEvent, app: &mut App, tx: &Sender<Msg>) {
+    if app.mode != Mode::Normal {
+        return;
+    }
+    let layout = app.layout.get();
+    let at = |r: Rect| {
+        mouse.column >= r.x
+            && mouse.column < r.right()
+            && mouse.row >= r.y
+            && mouse.row < r.bottom()
+    };
+    match mouse.kind {
+        MouseEventKind::Down(MouseButton::Left) if at(layout.list) => {
+            let row = app.list_offset.get() + (mouse.row - layout.list.y) as usize;
+            if row >= app.entries.len() {
+                return;
+            }
+            app.selected = row;
+            let double = app
+                .last_click
+                .is_some_and(|(when, r)| r == row && when.elapsed() < DOUBLE_CLICK);
+            if double {
+                app.last_click = None;
+                app.open_selected(tx);
+            } else {
+                app.last_click = Some((Instant::now(), row));
+            }
+        }
+
+        // Jump up to the clicked segment of the path in the title
+        MouseEventKind::Down(MouseButton::Left) if at(layout.breadcrumb) => {
+            let col = (mouse.column - layout.breadcrumb.x) as usize;
+            let mut end = 0;
+            let clicked = breadcrumbs(app).into_iter().find_map(|(path, spans)| {
+                end += spans.iter().map(Span::width).sum::<usize>();
+                (col < end).then_some(path)
+            });
+            if let Some(path) = clicked {
+                app.jump_up(path);
+            }
+        }
+
+        MouseEventKind::ScrollUp if at(layout.messages) => {
+            app.msg_scroll = app.msg_scroll.saturating_sub(1);
+        }
+        MouseEventKind::ScrollDown if at(layout.messages) => {
+            app.msg_scroll = (app.msg_scroll + 1).min(app.messages.len() as u16);
+        }
+        MouseEventKind::ScrollUp if at(layout.list) => app.move_selection(-1),
+        MouseEventKind::ScrollDown if at(layout.list) => app.move_selection(1),
+        _ => {}
     }
 }
 
@@ -503,66 +5937,710 @@ fn handle_key(key: KeyEvent, app: &mut App, tx: &Sender<Msg>) -> Result<bool> {
     if key.kind != KeyEventKind::Press {
         return Ok(false);
     }
+    let pending_g = std::mem::take(&mut app.pending_g);
     match &app.mode {
         Mode::Normal => match (key.code, key.modifiers) {
             (KeyCode::Char('q'), _) => return Ok(true),
+            _ if app.config.keys.back.matches(&key) => app.step_history(false),
+            _ if app.config.keys.forward.matches(&key) => app.step_history(true),
+
+            // Move selection, arrows or vi-style
+            (KeyCode::Up | KeyCode::Char('k'), KeyModifiers::NONE) => app.move_selection(-1),
+            (KeyCode::Down | KeyCode::Char('j'), KeyModifiers::NONE) => app.move_selection(1),
+            (KeyCode::Char('u'), KeyModifiers::CONTROL) => app.move_selection(-app.half_page()),
+            (KeyCode::Char('d'), KeyModifiers::CONTROL) => app.move_selection(app.half_page()),
+            (KeyCode::PageUp, _) => app.move_selection(-app.page()),
+            (KeyCode::PageDown, _) => app.move_selection(app.page()),
+            (KeyCode::Char('g'), _) if pending_g => app.selected = 0,
+            (KeyCode::Char('g'), _) => app.pending_g = true,
+            (KeyCode::Home, _) => app.selected = 0,
+            (KeyCode::Char('G'), _) | (KeyCode::End, _) => {
+                app.selected = app.entries.len().saturating_sub(1);
+            }
+            (KeyCode::Char('l'), _) => app.open_selected(tx),
+            (KeyCode::Char(c @ '1'..='9'), _) => {
+                let up = c as usize - '0' as usize;
+                let crumbs = breadcrumbs(app);
+                if let Some((dir, _)) = crumbs.len().checked_sub(up + 1).map(|i| &crumbs[i]) {
+                    app.jump_up(dir.clone());
+                }
+            }
+
+            // Nothing on disk to act on when browsing an export
+            (
+                KeyCode::Char(
+                    'r' | 'R' | 'x' | 'd' | 'D' | 'p' | 'w' | 'u' | 'I' | 'i' | 'e' | 'P' | '@'
+                    | 'L' | 'K' | 'M' | 'z' | 'Z' | 'Q' | 'X' | 'F' | 'W' | 'T' | 'O' | '!' | 'A',
+                ),
+                _,
+            ) if app.imported.is_some() => {
+                app.log(match app.snapshot {
+                    _ if app.outside.is_some() => {
+                        "Inside an archive: rescans and deletes are disabled (go up to leave it)"
+                    }
+                    Some(_) => {
+                        "Browsing a snapshot: rescans and deletes are disabled (S, b scans again)"
+                    }
+                    None => "Imported tree: rescans and deletes are disabled",
+                });
+            }
+            // Only scans and deletes go through the agent
+            (
+                KeyCode::Char(
+                    'w' | 'u' | 'I' | 'i' | 'e' | 'F' | 'P' | '@' | 'L' | 'K' | 'M' | 'z' | 'Z'
+                    | 'O' | '!' | 'A' | 'X' | 'W' | 'T',
+                ),
+                _,
+            ) if app.remote.is_some() => {
+                app.log("Remote tree: only rescans and deletes reach the agent");
+            }
+            (
+                KeyCode::Char(
+                    'w' | 'u' | 'I' | 'i' | 'e' | 'F' | 'd' | 'P' | '@' | 'L' | 'K' | 'M' | 'z'
+                    | 'Z' | 'O' | '!' | 'A' | 'X' | 'W' | 'T',
+                ),
+                _,
+            ) if app.vfs.host().is_some() => {
+                app.log("Not on this machine: only rescans and permanent deletes (D) work here");
+            }
+            (KeyCode::Char('p'), _) if app.remote.is_some() && app.is_scanning => {
+                app.log("Remote scans can't be paused");
+            }
+            (KeyCode::Char('d' | 'D' | 'P' | 'L' | 'K' | 'z' | 'X' | 'T'), _)
+                if app.config.read_only =>
+            {
+                app.log("Read-only: deleting, linking and compressing are disabled");
+            }
 
             // Refresh
             (KeyCode::Char('r'), _) => {
                 let _ = tx.send(Msg::RecomputeNow);
             }
+            (KeyCode::Char('p'), _) if app.is_scanning => app.toggle_scan_pause(),
+            (KeyCode::Char('p'), _) if app.auto_rescan.is_none() => {
+                app.log("Automatic rescans are off (see --auto-rescan)");
+            }
+            (KeyCode::Char('p'), _) => {
+                app.auto_rescan_paused = !app.auto_rescan_paused;
+                app.log(if app.auto_rescan_paused {
+                    "Automatic rescans paused"
+                } else {
+                    "Automatic rescans resumed"
+                });
+            }
+            (KeyCode::Char('w'), _) => {
+                app.watching = !app.watching;
+                app.restart_watch(tx);
+                app.log(if app.watching {
+                    "Watching for changes"
+                } else {
+                    "Stopped watching for changes"
+                });
+            }
+            (KeyCode::Char('R'), _) => {
+                app.log(format!("Full rescan of {}", app.cwd.display()));
+                app.start_scan(app.cwd.clone(), None, false, tx);
+            }
 
-            // Move selection
-            (KeyCode::Up, KeyModifiers::NONE) => {
-                if !app.entries.is_empty() {
-                    app.selected = app.selected.saturating_sub(1);
-                }
+            // Sort columns
+            (KeyCode::Char('s'), _) => app.sort_by(SortKey::Size),
+            (KeyCode::Char('n'), _) => app.sort_by(SortKey::Name),
+            (KeyCode::Char('c'), _) => app.sort_by(SortKey::Files),
+            (KeyCode::Char('m'), _) => app.sort_by(SortKey::Modified),
+
+            // Switch between the contents of cwd and the biggest files below it
+            (KeyCode::Char('f'), _) => {
+                app.view = match app.view {
+                    View::LargestFiles => View::Contents,
+                    _ => View::LargestFiles,
+                };
+                app.selected = 0;
+                app.refresh_view();
+            }
+
+            // What the directory in focus is made of
+            (KeyCode::Char('t'), _) => {
+                (app.grouping, app.by_owner) = match (app.grouping, app.by_owner) {
+                    (Grouping::Category, None) => (Grouping::Extension, None),
+                    (Grouping::Extension, None) => (Grouping::Category, Some(OwnerKey::User)),
+                    (_, Some(OwnerKey::User)) => (Grouping::Category, Some(OwnerKey::Group)),
+                    (_, Some(OwnerKey::Group)) => (Grouping::Category, None),
+                };
+                app.types = None;
+                app.update_types();
             }
-            (KeyCode::Down, KeyModifiers::NONE) => {
-                if !app.entries.is_empty() {
-                    app.selected = (app.selected + 1).min(app.entries.len().saturating_sub(1));
+            (KeyCode::Char('U'), _) => app.show_owners(OwnerKey::User),
+            (KeyCode::Char('E'), _) => app.show_extensions(),
+            (KeyCode::Char('?'), _) => app.mode = Mode::Help,
+            (KeyCode::Char('O'), _) => {
+                if let Some(dir) = app.selected_dir() {
+                    spawn_file_manager(dir, tx.clone());
                 }
             }
+            (KeyCode::Char('!'), _) => app.foreground = app.selected_dir().map(Foreground::Shell),
+            (KeyCode::Char('A'), _) if app.config.read_only => {
+                app.log("Read-only: custom actions are disabled");
+            }
+            (KeyCode::Char('A'), _) if app.config.actions.is_empty() => {
+                app.log("No custom actions; add [[action]] tables to the config");
+            }
+            (KeyCode::Char('A'), _) => app.mode = Mode::Actions(0),
+            (KeyCode::Char('b'), _) => app.add_bookmark(),
+            (KeyCode::Char('B'), _) => app.mode = Mode::Bookmarks(0),
+            (KeyCode::Char('y'), _) => app.copy_selected_path(),
+            (KeyCode::Char('z'), _) => app.confirm_compress(),
+            (KeyCode::Char('Z'), _) => app.estimate_compression(tx),
+            (KeyCode::Char('M'), _) => {
+                app.open_mounts();
+            }
+            (KeyCode::Char('V'), _) => app.open_subvolumes(),
 
-            // Drill in
-            (KeyCode::Enter, _) => {
-                if let Some(sel) = app.selected_entry() {
-                    app.cwd = sel.path.clone();
-                    app.selected = 0;
-                    app.log(format!("Entered {}", app.cwd.display()));
-                    let _ = tx.send(Msg::RecomputeNow);
+            // Dim what hasn't been touched in a long time
+            (KeyCode::Char('o'), _) => {
+                app.age_colors = !app.age_colors;
+                app.log(if app.age_colors {
+                    format!(
+                        "Dimming entries untouched for {} days",
+                        app.config.age.old_after_days
+                    )
+                } else {
+                    "Age coloring off".to_string()
+                });
+            }
+
+            // Compare with the previous scan or a snapshot
+            (KeyCode::Char('v'), _) => {
+                app.view = match app.view {
+                    View::Changes => View::Contents,
+                    _ => View::Changes,
+                };
+                app.selected = 0;
+                app.refresh_view();
+            }
+            (KeyCode::Char('S'), _) => app.open_snapshots(),
+
+            // Build artifacts to clear out
+            (KeyCode::Char('C'), _) if app.view == View::Cleanup => {
+                app.view = View::Contents;
+                app.selected = 0;
+                app.refresh_view();
+            }
+            (KeyCode::Char('C'), _) => app.show_cleanup(),
+            (KeyCode::Char('X'), _) => app.clean_cache(),
+
+            // What big cleanups leave behind
+            (KeyCode::Char('e'), _) if app.view == View::Empty => {
+                app.view = View::Contents;
+                app.selected = 0;
+                app.refresh_view();
+            }
+            (KeyCode::Char('e'), _) => app.show_empty(tx),
+            (KeyCode::Char('F'), _) if app.view == View::OldFiles => {
+                app.view = View::Contents;
+                app.selected = 0;
+                app.refresh_view();
+            }
+            (KeyCode::Char('F'), _) => app.show_old_files(tx),
+            (KeyCode::Char('N'), _) if app.view == View::ManyFiles => {
+                app.view = View::Contents;
+                app.selected = 0;
+                app.refresh_view();
+            }
+            (KeyCode::Char('N'), _) => app.show_many_files(),
+            (KeyCode::Char('P'), _) if app.view == View::Empty => app.confirm_prune(),
+            (KeyCode::Char('P'), _) => app.log("Empty directories are listed with 'e'"),
+
+            (KeyCode::Char('@'), _) if app.view == View::Broken => {
+                app.view = View::Contents;
+                app.selected = 0;
+                app.refresh_view();
+            }
+            (KeyCode::Char('@'), _) => app.show_broken(tx),
+            (KeyCode::Char('W'), _) if app.view == View::Audit => {
+                app.view = View::Contents;
+                app.selected = 0;
+                app.refresh_view();
+            }
+            (KeyCode::Char('W'), _) => app.show_audit(tx),
+
+            // Files with the same contents, and what to do about them
+            (KeyCode::Char('u'), _) if app.view == View::Duplicates => {
+                app.view = View::Contents;
+                app.selected = 0;
+                app.refresh_view();
+            }
+            (KeyCode::Char('u'), _) => app.show_duplicates(DupesOf::Files, tx),
+            (KeyCode::Char('I'), _)
+                if app.view == View::Duplicates && app.dupes_of != DupesOf::Files =>
+            {
+                app.view = View::Contents;
+                app.selected = 0;
+                app.refresh_view();
+            }
+            (KeyCode::Char('I'), _) => app.show_duplicates(DupesOf::Dirs, tx),
+            (KeyCode::Char('i'), _)
+                if app.view == View::Duplicates && app.dupes_of == DupesOf::Dirs =>
+            {
+                app.show_duplicates(DupesOf::DirContents, tx)
+            }
+            (KeyCode::Char('L'), _) => match app.entries.get(app.selected) {
+                Some(&Entry::Duplicate(g, i)) => {
+                    let (links, skipped) = app.dupes[g].link_plan(i);
+                    app.preview_links(links, skipped);
                 }
+                _ => app.log("Hard links replace duplicates; find them with 'u'"),
+            },
+            (KeyCode::Char('K'), _) if app.view == View::Duplicates => {
+                let (mut links, mut skipped) = (Vec::new(), Vec::new());
+                for group in &app.dupes {
+                    let (l, s) = group.link_plan(0);
+                    links.extend(l);
+                    skipped.extend(s);
+                }
+                app.preview_links(links, skipped);
+            }
+
+            // List files next to the subdirectories, or only the latter
+            (KeyCode::Char('H'), _) => {
+                app.show_files = !app.show_files;
+                app.refresh_view();
+                app.log(if app.show_files {
+                    "Showing files"
+                } else {
+                    "Hiding files"
+                });
             }
 
+            // Switch between apparent size and allocated disk usage
+            (KeyCode::Char('a'), _) => {
+                app.size_mode = app.size_mode.toggled();
+                app.refresh_view();
+                app.log(format!("Showing {}", app.size_mode.label()));
+            }
+
+            (KeyCode::Char('Q'), _) => {
+                let profile = app.profile.map_or(Profile::Quick, Profile::toggled);
+                app.switch_profile(profile, tx);
+            }
+
+            // Stay on one filesystem or cross mount points
+            (KeyCode::Char('x'), _) => {
+                app.scan_opts.one_file_system = !app.scan_opts.one_file_system;
+                app.log(if app.scan_opts.one_file_system {
+                    "Staying on one filesystem"
+                } else {
+                    "Crossing filesystem boundaries"
+                });
+                app.rescan_all(tx);
+            }
+
+            // Cycle between the paths given on the command line
+            (KeyCode::Tab, _) if app.roots.len() > 1 => {
+                app.root_idx = (app.root_idx + 1) % app.roots.len();
+                app.switch_root(app.roots[app.root_idx].clone(), tx);
+                app.log(format!("Switched to {}", app.cwd.display()));
+            }
+
+            // Drill in
+            (KeyCode::Enter, _) => app.open_selected(tx),
+
             // Go up to parent
-            (KeyCode::Backspace, _) => {
-                if let Some(parent) = app.cwd.parent() {
-                    app.cwd = parent.to_path_buf();
-                    app.selected = 0;
+            (KeyCode::Backspace | KeyCode::Char('h'), _) => {
+                let before = app.cwd.clone();
+                app.go_up(tx);
+                if app.cwd != before {
                     app.log(format!("Up to {}", app.cwd.display()));
-                    let _ = tx.send(Msg::RecomputeNow);
-                } else {
-                    app.log("Already at filesystem root");
                 }
             }
 
-            // Delete selected directory (ask confirmation)
-            (KeyCode::Char('d'), _) => {
-                if let Some(sel) = app.selected_entry() {
-                    app.mode = Mode::ConfirmDelete(sel.path.clone());
+            // Narrow the listing down by name
+            (KeyCode::Char('/'), _) => {
+                app.mode = Mode::Filter;
+            }
+            (KeyCode::Esc, _) if app.deleting.is_some() => {
+                if let Some(deletion) = &app.deleting {
+                    deletion.cancel.store(true, Ordering::Relaxed);
+                }
+                app.log("Stopping the deletion…");
+            }
+            (KeyCode::Char('d' | 'D'), _) if app.deleting.is_some() => {
+                app.log("A deletion is still running (Esc stops it)");
+            }
+            (KeyCode::Esc, _) if app.compressing.is_some() => {
+                if let Some(c) = &app.compressing {
+                    c.cancel.store(true, Ordering::Relaxed);
+                }
+                app.log("Stopping the compression…");
+            }
+            (KeyCode::Esc, _) if !app.filter.is_empty() => {
+                app.filter.clear();
+                app.refresh_view();
+            }
+            (KeyCode::Esc, _) if !app.marked.is_empty() => {
+                app.marked.clear();
+                app.log("Marks cleared");
+            }
+
+            // Trash or delete selected entry (ask confirmation)
+            (KeyCode::Char('d'), _) => app.confirm_delete(DeleteKind::Trash),
+            (KeyCode::Char('D'), _) => app.confirm_delete(DeleteKind::Permanent),
+            (KeyCode::Char('T'), _) => app.confirm_empty_trash(),
+
+            // Mark entries for a batch delete
+            (KeyCode::Char(' '), _) => app.toggle_mark(),
+            (KeyCode::Char('*'), _) => app.toggle_mark_all(),
+
+            _ => {}
+        },
+
+        Mode::Filter => match key.code {
+            KeyCode::Enter => app.mode = Mode::Normal,
+            KeyCode::Esc => {
+                app.mode = Mode::Normal;
+                app.filter.clear();
+                app.refresh_view();
+            }
+            KeyCode::Backspace => {
+                app.filter.pop();
+                app.refresh_view();
+            }
+            KeyCode::Up => app.move_selection(-1),
+            KeyCode::Down => app.move_selection(1),
+            KeyCode::Char(c) => {
+                app.filter.push(c);
+                app.selected = 0;
+                app.refresh_view();
+            }
+            _ => {}
+        },
+
+        Mode::Help => app.mode = Mode::Normal,
+
+        Mode::Actions(selected) => {
+            let selected = *selected;
+            let last = app.config.actions.len() - 1;
+            let by_key = match key.code {
+                KeyCode::Char(c) => app.config.actions.iter().position(|a| a.key == Some(c)),
+                _ => None,
+            };
+            match (by_key, key.code) {
+                (Some(i), _) => app.pick_action(i, tx),
+                (None, KeyCode::Up | KeyCode::Char('k')) => {
+                    app.mode = Mode::Actions(selected.saturating_sub(1));
+                }
+                (None, KeyCode::Down | KeyCode::Char('j')) => {
+                    app.mode = Mode::Actions((selected + 1).min(last));
+                }
+                (None, KeyCode::Enter) => app.pick_action(selected, tx),
+                (None, KeyCode::Esc | KeyCode::Char('A')) => app.mode = Mode::Normal,
+                _ => {}
+            }
+        }
+
+        Mode::Bookmarks(selected) => {
+            let selected = *selected;
+            let count = app.config.bookmarks.len();
+            match key.code {
+                KeyCode::Up | KeyCode::Char('k') => {
+                    app.mode = Mode::Bookmarks(selected.saturating_sub(1));
+                }
+                KeyCode::Down | KeyCode::Char('j') => {
+                    app.mode = Mode::Bookmarks((selected + 1).min(count.saturating_sub(1)));
+                }
+                KeyCode::Enter | KeyCode::Char('l') if count > 0 => {
+                    app.go_to_bookmark(selected, tx)
+                }
+                KeyCode::Char(c @ '1'..='9') if (c as usize - '1' as usize) < count => {
+                    app.go_to_bookmark(c as usize - '1' as usize, tx)
+                }
+                KeyCode::Char('b') => {
+                    app.add_bookmark();
+                    let at = app.config.bookmarks.iter().position(|b| *b == app.cwd);
+                    app.mode = Mode::Bookmarks(at.unwrap_or(selected));
+                }
+                KeyCode::Char('d') if count > 0 => app.remove_bookmark(selected),
+                KeyCode::Esc | KeyCode::Char('B' | 'q') => app.mode = Mode::Normal,
+                _ => {}
+            }
+        }
+
+        Mode::ConfirmCompress(dir, codec, remove) => match key.code {
+            KeyCode::Enter => {
+                let (dir, codec, remove) = (dir.clone(), *codec, *remove);
+                app.mode = Mode::Normal;
+                app.start_compress(dir, codec, remove, tx);
+            }
+            KeyCode::Tab => app.mode = Mode::ConfirmCompress(dir.clone(), codec.toggled(), *remove),
+            KeyCode::Char('d') => app.mode = Mode::ConfirmCompress(dir.clone(), *codec, !*remove),
+            KeyCode::Esc | KeyCode::Char('n') => app.mode = Mode::Normal,
+            _ => {}
+        },
+
+        Mode::ConfirmClean(path, cache) => match key.code {
+            KeyCode::Char('y') => {
+                let (path, cache) = (path.clone(), *cache);
+                app.mode = Mode::Normal;
+                app.run_clean(&path, cache);
+            }
+            KeyCode::Char('n') | KeyCode::Esc => app.mode = Mode::Normal,
+            _ => {}
+        },
+
+        Mode::ConfirmAction(i, path) => match key.code {
+            KeyCode::Char('y') => {
+                let (i, path) = (*i, path.clone());
+                app.mode = Mode::Normal;
+                app.run_action(i, &path, tx);
+            }
+            KeyCode::Char('n') | KeyCode::Esc => app.mode = Mode::Normal,
+            _ => {}
+        },
+
+        Mode::Owners(by, _) => match key.code {
+            KeyCode::Tab => app.show_owners(match by {
+                OwnerKey::User => OwnerKey::Group,
+                OwnerKey::Group => OwnerKey::User,
+            }),
+            KeyCode::Esc | KeyCode::Char('q' | 'U') => app.mode = Mode::Normal,
+            _ => {}
+        },
+
+        Mode::Extensions(rows, selected) => match key.code {
+            KeyCode::Up | KeyCode::Char('k') | KeyCode::Down | KeyCode::Char('j') => {
+                let down = matches!(key.code, KeyCode::Down | KeyCode::Char('j'));
+                let last = rows.len().saturating_sub(1);
+                if let Mode::Extensions(_, selected) = &mut app.mode {
+                    *selected = if down {
+                        (*selected + 1).min(last)
+                    } else {
+                        selected.saturating_sub(1)
+                    };
+                }
+            }
+            KeyCode::Enter | KeyCode::Char('l') | KeyCode::Right if !rows.is_empty() => {
+                app.open_extension(*selected)
+            }
+            KeyCode::Esc | KeyCode::Char('q' | 'E') => app.mode = Mode::Normal,
+            _ => {}
+        },
+
+        Mode::LocalSnapshots(_, true) => match key.code {
+            KeyCode::Char('y') => app.thin_local_snapshots(),
+            _ => {
+                if let Mode::LocalSnapshots(_, thinning) = &mut app.mode {
+                    *thinning = false;
+                }
+            }
+        },
+
+        Mode::LocalSnapshots(_, false) => match key.code {
+            KeyCode::Char('T') if app.config.read_only => {
+                app.log("Read-only: thinning snapshots is disabled");
+            }
+            KeyCode::Char('T') => {
+                if let Mode::LocalSnapshots(_, thinning) = &mut app.mode {
+                    *thinning = true;
+                }
+            }
+            KeyCode::Esc | KeyCode::Char('q' | 'V') => app.mode = Mode::Normal,
+            _ => {}
+        },
+
+        Mode::Subvolumes(subvolumes, _) => match key.code {
+            KeyCode::Up | KeyCode::Char('k') | KeyCode::Down | KeyCode::Char('j') => {
+                let down = matches!(key.code, KeyCode::Down | KeyCode::Char('j'));
+                let last = subvolumes.len() - 1;
+                if let Mode::Subvolumes(_, selected) = &mut app.mode {
+                    *selected = if down {
+                        (*selected + 1).min(last)
+                    } else {
+                        selected.saturating_sub(1)
+                    };
+                }
+            }
+            KeyCode::Esc | KeyCode::Char('q' | 'V') => app.mode = Mode::Normal,
+            _ => {}
+        },
+
+        Mode::Mounts(mounts, selected) => match key.code {
+            KeyCode::Up | KeyCode::Char('k') | KeyCode::Down | KeyCode::Char('j') => {
+                let down = matches!(key.code, KeyCode::Down | KeyCode::Char('j'));
+                if let Mode::Mounts(mounts, selected) = &mut app.mode {
+                    *selected = if down {
+                        (*selected + 1).min(mounts.len() - 1)
+                    } else {
+                        selected.saturating_sub(1)
+                    };
+                }
+            }
+            KeyCode::Enter | KeyCode::Char('l') => {
+                let root = mounts[*selected].path.clone();
+                app.mode = Mode::Normal;
+                app.pick_root(root, tx);
+            }
+            KeyCode::Esc | KeyCode::Char('M') => {
+                app.mode = Mode::Normal;
+                // Started with --mounts: fall back to PATH.
+                if app.scan_id == 0 {
+                    app.switch_root(app.roots[app.root_idx].clone(), tx);
+                }
+            }
+            _ => {}
+        },
+
+        Mode::Snapshots(saved, selected, true) => {
+            let name = saved[*selected].name.clone();
+            match key.code {
+                KeyCode::Char('y') => app.remove_snapshot(&name),
+                _ => {
+                    if let Mode::Snapshots(_, _, deleting) = &mut app.mode {
+                        *deleting = false;
+                    }
+                }
+            }
+        }
+
+        Mode::Snapshots(saved, selected, false) => match key.code {
+            KeyCode::Up | KeyCode::Char('k') | KeyCode::Down | KeyCode::Char('j') => {
+                let down = matches!(key.code, KeyCode::Down | KeyCode::Char('j'));
+                if let Mode::Snapshots(saved, selected, _) = &mut app.mode {
+                    *selected = if down {
+                        (*selected + 1).min(saved.len().saturating_sub(1))
+                    } else {
+                        selected.saturating_sub(1)
+                    };
+                }
+            }
+            KeyCode::Enter | KeyCode::Char('c') if !saved.is_empty() => {
+                let name = saved[*selected].name.clone();
+                app.mode = Mode::Normal;
+                app.compare_with_snapshot(name);
+            }
+            KeyCode::Char('o') if !saved.is_empty() => {
+                let name = saved[*selected].name.clone();
+                app.mode = Mode::Normal;
+                if let Some((tree, taken)) = app.load_snapshot(&name) {
+                    app.browse_snapshot(name, tree, taken, tx);
+                }
+            }
+            KeyCode::Char('d') if !saved.is_empty() => {
+                if let Mode::Snapshots(_, _, deleting) = &mut app.mode {
+                    *deleting = true;
+                }
+            }
+            KeyCode::Char('n') => app.mode = Mode::SaveSnapshot(String::new()),
+            KeyCode::Char('b') if app.snapshot.is_some() => {
+                app.mode = Mode::Normal;
+                app.switch_root(app.roots[app.root_idx].clone(), tx);
+            }
+            KeyCode::Esc | KeyCode::Char('q' | 'S') => app.mode = Mode::Normal,
+            _ => {}
+        },
+
+        Mode::SaveSnapshot(name) => match key.code {
+            KeyCode::Enter if !name.is_empty() => {
+                let name = name.clone();
+                app.mode = Mode::Normal;
+                app.save_snapshot(name, app.tree.root(), tx);
+            }
+            KeyCode::Esc => app.mode = Mode::Normal,
+            KeyCode::Backspace => {
+                if let Mode::SaveSnapshot(name) = &mut app.mode {
+                    name.pop();
+                }
+            }
+            KeyCode::Char(c) => {
+                if let Mode::SaveSnapshot(name) = &mut app.mode {
+                    name.push(c);
+                }
+            }
+            _ => {}
+        },
+
+        Mode::ConfirmEmptyTrash => match key.code {
+            KeyCode::Char('y') => {
+                app.trash_sizing = true;
+                recycle::spawn_empty_thread(tx.clone());
+                app.mode = Mode::Normal;
+                app.log("Emptying the trash…");
+            }
+            KeyCode::Char('n') | KeyCode::Esc => {
+                app.mode = Mode::Normal;
+                app.log("Emptying the trash cancelled");
+            }
+            _ => {}
+        },
+
+        Mode::ConfirmPrune(dirs) => match key.code {
+            KeyCode::Char('y') => {
+                spawn_prune_thread(dirs.clone(), tx.clone());
+                app.mode = Mode::Normal;
+            }
+            KeyCode::Char('n') | KeyCode::Esc => {
+                app.mode = Mode::Normal;
+                app.log("Removing empty directories cancelled");
+            }
+            _ => {}
+        },
+
+        Mode::ConfirmLink(links, skipped, scroll) => match key.code {
+            KeyCode::Char('y') if !links.is_empty() => {
+                spawn_link_thread(links.clone(), tx.clone());
+                app.mode = Mode::Normal;
+            }
+            KeyCode::Up | KeyCode::Char('k') | KeyCode::Down | KeyCode::Char('j') => {
+                let down = matches!(key.code, KeyCode::Down | KeyCode::Char('j'));
+                let scroll = if down {
+                    (*scroll + 1).min(links.len() + skipped.len())
+                } else {
+                    scroll.saturating_sub(1)
+                };
+                if let Mode::ConfirmLink(_, _, s) = &mut app.mode {
+                    *s = scroll;
                 }
             }
+            KeyCode::Char('n') | KeyCode::Esc => {
+                app.mode = Mode::Normal;
+                app.log("Linking cancelled");
+            }
+            _ => {}
+        },
 
+        Mode::ConfirmDelete(targets, kind, Some(check)) => match key.code {
+            KeyCode::Enter if check.typed == check.name => {
+                app.start_delete(targets.clone(), *kind, tx);
+                app.mode = Mode::Normal;
+                app.marked.clear();
+            }
+            KeyCode::Enter => app.log("The name doesn't match; Esc cancels"),
+            KeyCode::Esc => {
+                app.mode = Mode::Normal;
+                app.log("Deletion cancelled");
+            }
+            KeyCode::Backspace => {
+                if let Mode::ConfirmDelete(_, _, Some(check)) = &mut app.mode {
+                    check.typed.pop();
+                }
+            }
+            KeyCode::Char(c) => {
+                if let Mode::ConfirmDelete(_, _, Some(check)) = &mut app.mode {
+                    check.typed.push(c);
+                }
+            }
             _ => {}
         },
 
-        Mode::ConfirmDelete(target) => match (key.code, key.modifiers) {
-            (KeyCode::Char('y'), _) => {
-                let target = target.clone();
-                let _ = tx.send(Msg::RecomputeNow); // kick off scan after deletion completes too
-                spawn_delete_thread(target.clone(), tx.clone());
+        Mode::ConfirmDelete(targets, kind, None) => match (key.code, key.modifiers) {
+            (KeyCode::Char('y'), _) if *kind == DeleteKind::Trash => {
+                app.start_delete(targets.clone(), *kind, tx);
                 // Exit modal
                 app.mode = Mode::Normal;
+                app.marked.clear();
+            }
+            (KeyCode::Char('Y'), _) if *kind == DeleteKind::Permanent => {
+                app.start_delete(targets.clone(), *kind, tx);
+                app.mode = Mode::Normal;
+                app.marked.clear();
             }
             (KeyCode::Char('n'), _) | (KeyCode::Esc, _) => {
                 app.mode = Mode::Normal;
@@ -0,0 +1,522 @@
+//! Reading and writing ncdu's JSON export format (`ncdu -o` / `ncdu -f`),
+//! so a scan from a headless box running ncdu can be browsed here and the
+//! other way around.
+//!
+//! An export is `[1, 2, {metadata}, dir]`, where a directory is an array
+//! holding its own info object followed by its entries: objects for files,
+//! nested arrays for subdirectories.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context, Result};
+use ignore::gitignore::Gitignore;
+use serde_json::{Map, Value};
+
+use dm_core::{
+    scan::{allocated_size, build_excludes, device_of, ScanOptions},
+    tree::{DirStats, DirTree, NodeId},
+};
+
+/// Deepest JSON nesting read from an export. Paths can't be more than about
+/// 2000 directories deep anyway, and parsing, walking and dropping the JSON
+/// all recurse, so this keeps them within the stack.
+const MAX_NESTING: usize = 2100;
+
+/// A tree read from an export. Files aren't nodes, so the ones directly in
+/// each directory are kept here for the listing.
+#[derive(Debug)]
+pub struct Import {
+    pub tree: DirTree,
+    pub files: HashMap<PathBuf, Vec<DirStats>>,
+}
+
+/// Reads an export from `src`, "-" meaning stdin.
+pub fn import(src: &Path, dedup_hardlinks: bool) -> Result<Import> {
+    let reader: Box<dyn Read> = if src == Path::new("-") {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(src).with_context(|| format!("Cannot open {}", src.display()))?)
+    };
+    let mut de = serde_json::Deserializer::from_reader(BufReader::new(Nesting::new(reader)));
+    // Every directory level is one level of JSON nesting, more than
+    // serde_json allows for; `Nesting` sets the limit instead.
+    de.disable_recursion_limit();
+    let export: Value = serde::Deserialize::deserialize(&mut de)
+        .with_context(|| format!("Cannot parse {}", src.display()))?;
+
+    let root = match export.as_array().map(Vec::as_slice) {
+        Some([major, _, _, Value::Array(root), ..]) if major == 1 => root,
+        _ => bail!("{} is not an ncdu export", src.display()),
+    };
+    let Some(Value::Object(info)) = root.first() else {
+        bail!("{}: root directory has no info", src.display());
+    };
+    let root_path = PathBuf::from(info.get("name").and_then(Value::as_str).unwrap_or("/"));
+
+    let mut importer = Importer {
+        tree: DirTree::new(root_path),
+        files: HashMap::new(),
+        seen: HashSet::new(),
+        dedup_hardlinks,
+    };
+    let id = importer.tree.root();
+    importer.dir_info(id, info);
+    importer
+        .read_dir(
+            root,
+            id,
+            info.get("dev").and_then(Value::as_u64).unwrap_or(0),
+        )
+        .with_context(|| format!("Cannot import {}", src.display()))?;
+    let Importer {
+        mut tree, files, ..
+    } = importer;
+    tree.finish();
+    Ok(Import { tree, files })
+}
+
+struct Importer {
+    tree: DirTree,
+    files: HashMap<PathBuf, Vec<DirStats>>,
+    seen: HashSet<(u64, u64)>, // hard-linked (dev, ino) pairs counted already
+    dedup_hardlinks: bool,
+}
+
+impl Importer {
+    fn dir_info(&mut self, id: NodeId, info: &Map<String, Value>) {
+        let stats = self.tree.stats_mut(id);
+        stats.mtime = mtime(info);
+        stats.newest = stats.mtime;
+        if info.get("read_error").and_then(Value::as_bool) == Some(true) {
+            stats.errors += 1;
+        }
+    }
+
+    /// Adds the entries of the directory array `items` below node `id`.
+    fn read_dir(&mut self, items: &[Value], id: NodeId, dev: u64) -> Result<()> {
+        let dir = self.tree.path(id);
+        for item in items.iter().skip(1) {
+            match item {
+                Value::Array(sub) => {
+                    let Some(Value::Object(info)) = sub.first() else {
+                        continue;
+                    };
+                    let name = entry_name(info, &dir)?;
+                    let child = self.tree.push(id, DirStats::new(dir.join(name)));
+                    self.dir_info(child, info);
+                    let dev = info.get("dev").and_then(Value::as_u64).unwrap_or(dev);
+                    self.read_dir(sub, child, dev)?;
+                }
+                Value::Object(info) => self.read_file(info, id, &dir, dev)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn read_file(
+        &mut self,
+        info: &Map<String, Value>,
+        id: NodeId,
+        dir: &Path,
+        dev: u64,
+    ) -> Result<()> {
+        let name = entry_name(info, dir)?;
+        match info.get("excluded").and_then(Value::as_str) {
+            // A mount point left alone, like --one-file-system does.
+            Some("otherfs" | "othfs") => {
+                let mut stats = DirStats::new(dir.join(name));
+                stats.other_fs = true;
+                self.tree.push(id, stats);
+                return Ok(());
+            }
+            Some(_) => return Ok(()),
+            None => {}
+        }
+        if info.get("read_error").and_then(Value::as_bool) == Some(true) {
+            self.tree.stats_mut(id).errors += 1;
+            return Ok(());
+        }
+
+        let size = |key| info.get(key).and_then(Value::as_u64).unwrap_or(0) as u128;
+        let mut stats = DirStats::new(PathBuf::new());
+        stats.dir_count = 0;
+        stats.file_count = 1;
+        stats.newest = mtime(info);
+        stats.owner = owner(info);
+        stats.total_bytes = size("asize");
+        stats.disk_bytes = size("dsize");
+        if info.get("hlnkc").and_then(Value::as_bool) == Some(true) {
+            stats.shared_bytes = stats.total_bytes;
+            let ino = info.get("ino").and_then(Value::as_u64).unwrap_or(0);
+            if self.dedup_hardlinks && !self.seen.insert((dev, ino)) {
+                stats.total_bytes = 0;
+                stats.disk_bytes = 0;
+            }
+        }
+        self.tree.stats_mut(id).add(&stats);
+
+        stats.path = dir.join(name);
+        self.tree.count_type(id, &stats.path, &stats);
+        stats.mtime = stats.newest;
+        stats.complete = true;
+        if self.tree.wants_file(stats.total_bytes) {
+            self.tree.note_file(id, stats.clone());
+        }
+        self.files.entry(dir.to_path_buf()).or_default().push(stats);
+        Ok(())
+    }
+}
+
+/// The name of the entry `info` in `dir`, which has to be one path
+/// component: an export naming `../x` or `a/b` could put entries anywhere.
+fn entry_name<'a>(info: &'a Map<String, Value>, dir: &Path) -> Result<&'a str> {
+    let name = info.get("name").and_then(Value::as_str).unwrap_or("");
+    if matches!(name, "" | "." | "..") || name.contains(std::path::is_separator) {
+        bail!("bad name {name:?} in {}", dir.display());
+    }
+    Ok(name)
+}
+
+/// Passes JSON through, failing once arrays and objects nest deeper than
+/// `MAX_NESTING`.
+struct Nesting<R> {
+    inner: R,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl<R> Nesting<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            depth: 0,
+            in_string: false,
+            escaped: false,
+        }
+    }
+}
+
+impl<R: Read> Read for Nesting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        for &b in &buf[..n] {
+            if self.in_string {
+                match b {
+                    _ if self.escaped => self.escaped = false,
+                    b'\\' => self.escaped = true,
+                    b'"' => self.in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match b {
+                b'"' => self.in_string = true,
+                b'[' | b'{' => {
+                    self.depth += 1;
+                    if self.depth > MAX_NESTING {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("nested more than {MAX_NESTING} levels deep"),
+                        ));
+                    }
+                }
+                b']' | b'}' => self.depth = self.depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+        Ok(n)
+    }
+}
+
+fn owner(info: &Map<String, Value>) -> Option<(u32, u32)> {
+    let id = |key| {
+        info.get(key)
+            .and_then(Value::as_u64)
+            .and_then(|n| u32::try_from(n).ok())
+    };
+    Some((id("uid")?, id("gid")?))
+}
+
+fn mtime(info: &Map<String, Value>) -> Option<SystemTime> {
+    let secs = info.get("mtime").and_then(Value::as_u64)?;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Scans `root` and writes it to `out` ("-" meaning stdout) the way
+/// `ncdu -o` would, honouring the excludes and filesystem boundary in `opts`.
+pub fn export(root: &Path, out: &Path, opts: &ScanOptions) -> Result<()> {
+    let writer: Box<dyn Write> = if out == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(File::create(out).with_context(|| format!("Cannot create {}", out.display()))?)
+    };
+    let mut w = BufWriter::new(writer);
+    let (excludes, problems) = build_excludes(root, opts);
+    for problem in problems {
+        eprintln!("dm: {problem}");
+    }
+    let exporter = Exporter {
+        excludes,
+        root_dev: device_of(root),
+        one_file_system: opts.one_file_system,
+    };
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let meta = serde_json::json!({
+        "progname": "dm",
+        "progver": env!("CARGO_PKG_VERSION"),
+        "timestamp": timestamp,
+    });
+    write!(w, "[1,2,{meta},")?;
+    let md =
+        fs::symlink_metadata(root).with_context(|| format!("Cannot open {}", root.display()))?;
+    exporter.write_dir(&mut w, root, &root.display().to_string(), &md)?;
+    w.write_all(b"]\n")?;
+    w.flush()?;
+    Ok(())
+}
+
+struct Exporter {
+    excludes: Gitignore,
+    root_dev: Option<u64>,
+    one_file_system: bool,
+}
+
+impl Exporter {
+    fn write_dir(
+        &self,
+        w: &mut impl Write,
+        dir: &Path,
+        name: &str,
+        md: &fs::Metadata,
+    ) -> Result<()> {
+        let listing = fs::read_dir(dir);
+        let mut info = entry_info(name, md, dir);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            info.insert("dev".into(), md.dev().into());
+        }
+        if listing.is_err() {
+            info.insert("read_error".into(), true.into());
+        }
+        write!(w, "[{}", Value::Object(info))?;
+
+        for entry in listing.into_iter().flatten() {
+            w.write_all(b",")?;
+            let Ok(entry) = entry else {
+                write!(
+                    w,
+                    "{}",
+                    serde_json::json!({ "name": "", "read_error": true })
+                )?;
+                continue;
+            };
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let md = match fs::symlink_metadata(&path) {
+                Ok(md) => md,
+                Err(_) => {
+                    write!(
+                        w,
+                        "{}",
+                        serde_json::json!({ "name": name, "read_error": true })
+                    )?;
+                    continue;
+                }
+            };
+            if self.excludes.matched(&path, md.is_dir()).is_ignore() {
+                write!(
+                    w,
+                    "{}",
+                    serde_json::json!({ "name": name, "excluded": "pattern" })
+                )?;
+            } else if md.is_dir() {
+                if self.one_file_system && device_of(&path) != self.root_dev {
+                    write!(
+                        w,
+                        "{}",
+                        serde_json::json!({ "name": name, "excluded": "otherfs" })
+                    )?;
+                } else {
+                    self.write_dir(w, &path, &name, &md)?;
+                }
+            } else {
+                let mut info = entry_info(&name, &md, &path);
+                if !md.is_file() {
+                    info.insert("notreg".into(), true.into());
+                }
+                write!(w, "{}", Value::Object(info))?;
+            }
+        }
+        w.write_all(b"]")?;
+        Ok(())
+    }
+}
+
+/// The fields ncdu records for every entry.
+fn entry_info(name: &str, md: &fs::Metadata, path: &Path) -> Map<String, Value> {
+    let mut info = Map::new();
+    info.insert("name".into(), name.into());
+    info.insert("asize".into(), md.len().into());
+    info.insert("dsize".into(), allocated_size(md, path).into());
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        info.insert("ino".into(), md.ino().into());
+        info.insert("uid".into(), md.uid().into());
+        info.insert("gid".into(), md.gid().into());
+        if md.nlink() > 1 && !md.is_dir() {
+            info.insert("hlnkc".into(), true.into());
+            info.insert("nlink".into(), md.nlink().into());
+        }
+    }
+    if let Some(mtime) = md
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+    {
+        info.insert("mtime".into(), mtime.as_secs().into());
+    }
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    /// A small tree as `ncdu -o` 1.19 writes it: a hard-linked pair, an
+    /// entry excluded by pattern, a mount point left out and two unreadable
+    /// ones.
+    const FIXTURE: &str = r#"[1,2,{"progname":"ncdu","progver":"1.19","timestamp":1700000000},
+[{"name":"/data","asize":4096,"dsize":4096,"dev":2049,"ino":2,"uid":1000,"gid":1000,"mode":16877,"mtime":1699990000},
+{"name":"notes.txt","asize":1200,"dsize":4096,"ino":12,"uid":1000,"gid":1000,"mode":33188,"mtime":1699990100},
+[{"name":"photos","asize":4096,"dsize":4096,"ino":13,"mtime":1699990200},
+{"name":"a.jpg","asize":50000,"dsize":53248,"ino":14,"hlnkc":true,"nlink":2,"mtime":1699990300},
+{"name":"b.jpg","asize":50000,"dsize":53248,"ino":14,"hlnkc":true,"nlink":2,"mtime":1699990300}],
+{"name":"cache","excluded":"pattern"},
+{"name":"mnt","excluded":"othfs"},
+{"name":"secret","asize":300,"dsize":4096,"ino":16,"read_error":true},
+[{"name":"locked","asize":4096,"dsize":4096,"ino":17,"read_error":true}]]]
+"#;
+
+    /// A fresh directory for test `name`.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("dm-ncdu-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    /// Every directory's path with its apparent bytes, files and
+    /// directories, sorted.
+    fn shape(tree: &DirTree) -> Vec<(PathBuf, u128, u64, u64)> {
+        let mut out = Vec::new();
+        let mut stack = vec![tree.root()];
+        while let Some(id) = stack.pop() {
+            let s = tree.stats(id);
+            out.push((tree.path(id), s.total_bytes, s.file_count, s.dir_count));
+            stack.extend(tree.children(id));
+        }
+        out.sort();
+        out
+    }
+
+    #[test]
+    fn reads_ncdu_output() {
+        let dir = temp_dir("fixture");
+        let src = dir.join("export.json");
+        fs::write(&src, FIXTURE).unwrap();
+
+        let Import { tree, files } = import(&src, true).unwrap();
+        let root = tree.stats(tree.root());
+        assert_eq!(root.total_bytes, 51_200);
+        assert_eq!(root.disk_bytes, 57_344);
+        assert_eq!(root.shared_bytes, 100_000);
+        assert_eq!(root.errors, 2);
+        assert_eq!(
+            root.mtime,
+            Some(UNIX_EPOCH + Duration::from_secs(1_699_990_000))
+        );
+        assert_eq!(
+            shape(&tree),
+            [
+                ("/data".into(), 51_200, 3, 4),
+                ("/data/locked".into(), 0, 0, 1),
+                ("/data/mnt".into(), 0, 0, 1),
+                ("/data/photos".into(), 50_000, 2, 1),
+            ]
+        );
+        let mnt = tree.find(Path::new("/data/mnt")).unwrap();
+        assert!(tree.stats(mnt).other_fs);
+        assert_eq!(files[Path::new("/data/photos")].len(), 2);
+        assert_eq!(files[Path::new("/data")][0].owner, Some((1000, 1000)));
+        assert_eq!(
+            tree.largest_files()[0].path,
+            Path::new("/data/photos/a.jpg")
+        );
+
+        let counted = import(&src, false).unwrap().tree;
+        assert_eq!(counted.stats(counted.root()).total_bytes, 101_200);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_names_that_leave_their_directory() {
+        let dir = temp_dir("names");
+        let src = dir.join("export.json");
+        let bad = FIXTURE.replace(r#""name":"notes.txt""#, r#""name":"../notes.txt""#);
+        fs::write(&src, bad).unwrap();
+        assert!(import(&src, true).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn imports_what_it_exports() {
+        let dir = temp_dir("round-trip");
+        let root = dir.join("root");
+        fs::create_dir_all(root.join("sub/deeper")).unwrap();
+        fs::create_dir_all(root.join("empty")).unwrap();
+        fs::create_dir_all(root.join("skip")).unwrap();
+        fs::write(root.join("a.txt"), [b'a'; 100]).unwrap();
+        fs::write(root.join("sub/b.bin"), [b'b'; 2000]).unwrap();
+        fs::write(root.join("sub/deeper/c"), [b'c'; 30]).unwrap();
+        fs::write(root.join("skip/d"), [b'd'; 5000]).unwrap();
+        fs::hard_link(root.join("sub/b.bin"), root.join("sub/link")).unwrap();
+        let opts = ScanOptions {
+            excludes: vec!["skip".to_string()],
+            ..ScanOptions::default()
+        };
+        let out = dir.join("export.json");
+        export(&root, &out, &opts).unwrap();
+
+        let Import { tree, files } = import(&out, true).unwrap();
+        assert_eq!(tree.root_path(), root);
+        let p = |rel: &str| root.join(rel);
+        assert_eq!(
+            shape(&tree),
+            [
+                (root.clone(), 2130, 4, 4),
+                (p("empty"), 0, 0, 1),
+                (p("sub"), 2030, 3, 2),
+                (p("sub/deeper"), 30, 1, 1),
+            ]
+        );
+        assert_eq!(tree.stats(tree.root()).shared_bytes, 4000);
+        assert_eq!(files[&p("sub")].len(), 2);
+        assert!(!files.contains_key(&p("skip")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+//! The platform's trash: where it is, what it holds, and emptying it.
+//! What's moved there keeps taking its space, which regularly explains
+//! gigabytes gone missing.
+
+use std::{path::PathBuf, sync::mpsc::Sender, thread};
+
+use anyhow::Result;
+use walkdir::WalkDir;
+
+use dm_core::{scan::allocated_size, DirStats};
+
+use crate::Msg;
+
+/// The trash folders of this machine that exist: the home trash and those
+/// on other mounts on Linux, `~/.Trash` and each volume's `.Trashes` on
+/// macOS, and each drive's `$Recycle.Bin` on Windows.
+pub fn bins() -> Vec<PathBuf> {
+    let mut bins = platform_bins();
+    bins.retain(|b| b.is_dir());
+    bins.sort();
+    bins.dedup();
+    bins
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn platform_bins() -> Vec<PathBuf> {
+    trash::os_limited::trash_folders()
+        .map(|bins| bins.into_iter().collect())
+        .unwrap_or_default()
+}
+
+#[cfg(target_os = "macos")]
+fn platform_bins() -> Vec<PathBuf> {
+    use std::os::unix::fs::MetadataExt;
+    let Some(home) = dirs::home_dir() else {
+        return Vec::new();
+    };
+    // Other volumes keep a bin per user, named by the id that owns the home.
+    let uid = std::fs::metadata(&home).map(|md| md.uid().to_string());
+    let volumes = dm_core::mounts::list().into_iter().filter_map(|m| {
+        let uid = uid.as_ref().ok()?;
+        Some(m.path.join(".Trashes").join(uid))
+    });
+    std::iter::once(home.join(".Trash"))
+        .chain(volumes)
+        .collect()
+}
+
+#[cfg(windows)]
+fn platform_bins() -> Vec<PathBuf> {
+    dm_core::mounts::list()
+        .into_iter()
+        .map(|m| m.path.join("$Recycle.Bin"))
+        .collect()
+}
+
+/// What each bin holds, the ones without files left out, as their own
+/// folders are always there. Entries that can't be read, like other users'
+/// parts of a shared bin, are left out too.
+fn sizes() -> Vec<DirStats> {
+    bins()
+        .into_iter()
+        .filter_map(|bin| {
+            let mut stats = DirStats::new(bin.clone());
+            stats.dir_count = 0;
+            for entry in WalkDir::new(&bin).min_depth(1).into_iter().flatten() {
+                let Ok(md) = entry.metadata() else {
+                    continue;
+                };
+                if md.is_dir() {
+                    stats.dir_count += 1;
+                } else {
+                    stats.file_count += 1;
+                    stats.total_bytes += u128::from(md.len());
+                    stats.disk_bytes += u128::from(allocated_size(&md, entry.path()));
+                }
+            }
+            stats.complete = true;
+            (stats.file_count > 0).then_some(stats)
+        })
+        .collect()
+}
+
+/// Sizes up the bins on a background thread.
+pub fn spawn_size_thread(tx: Sender<Msg>) {
+    thread::spawn(move || {
+        let _ = tx.send(Msg::TrashSized(sizes()));
+    });
+}
+
+/// Empties every bin for good on a background thread, then sizes them up
+/// again.
+pub fn spawn_empty_thread(tx: Sender<Msg>) {
+    thread::spawn(move || {
+        let res = empty().map_err(|e| format!("{e:#}"));
+        let _ = tx.send(Msg::TrashEmptied(res));
+        let _ = tx.send(Msg::TrashSized(sizes()));
+    });
+}
+
+#[cfg(not(target_os = "macos"))]
+fn empty() -> Result<()> {
+    use trash::os_limited::{list, purge_all};
+    purge_all(list()?)?;
+    Ok(())
+}
+
+/// The trash crate can't list the macOS trash, so what's in the bins goes
+/// directly.
+#[cfg(target_os = "macos")]
+fn empty() -> Result<()> {
+    use anyhow::Context;
+    use std::fs;
+    for bin in bins() {
+        for entry in fs::read_dir(&bin)?.flatten() {
+            let path = entry.path();
+            let removed = if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                fs::remove_dir_all(&path)
+            } else {
+                fs::remove_file(&path)
+            };
+            removed.with_context(|| format!("Cannot remove {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
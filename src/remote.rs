@@ -0,0 +1,675 @@
+//! `dm agent` and `--connect`: the scanner runs as an agent on a server,
+//! and the browser on another machine scans, lists and deletes there
+//! through it.
+//!
+//! Each scan, listing or deletion is a TCP connection of its own, made of
+//! MessagePack values: a [`Request::Hello`] and the agent's
+//! [`Reply::Ready`], then one request and the replies to it. Closing the
+//! connection early stops whatever the agent was doing for it.
+
+use std::{
+    env,
+    io::{BufReader, BufWriter, Write},
+    net::{TcpListener, TcpStream},
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::Local;
+use clap::Args;
+use globset::GlobSet;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use dm_core::{
+    scan::{build_excludes, list_files, ScanCounters},
+    vfs, DirStats, DirTree, ScanEvent, ScanOptions, Scanner,
+};
+
+use crate::{config, config::Config, DeleteKind, Msg};
+
+/// Bumped whenever requests or replies change shape.
+const VERSION: u32 = 1;
+
+/// Environment variable with the secret the agent asks clients for; set it
+/// on both ends. An agent started without one makes one up and prints it.
+const TOKEN_VAR: &str = "DM_AGENT_TOKEN";
+
+/// How often the agent reports the totals of a scan in flight.
+const COUNTERS_EVERY: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Args)]
+pub struct AgentArgs {
+    /// Address to listen on. Whoever can connect can delete whatever the
+    /// agent's user can, so keep it local and forward a port over SSH
+    /// (`ssh -L 9479:localhost:9479 HOST`). Clients need the agent's
+    /// DM_AGENT_TOKEN, which it prints when it isn't set. Deleting is
+    /// limited by the agent's own `read_only` and `delete.protected`.
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:9479")]
+    pub listen: String,
+
+    /// Directories clients may scan and delete inside of (defaults to the
+    /// current directory). The directories themselves are never deleted.
+    #[arg(value_name = "ROOT")]
+    pub roots: Vec<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Request {
+    /// Opens every connection; `paths` are resolved for the reply, and a
+    /// deletion must stay below them. They must be in the agent's roots.
+    Hello {
+        version: u32,
+        token: Option<String>,
+        paths: Vec<PathBuf>,
+    },
+    Scan {
+        path: PathBuf,
+        skip: Option<PathBuf>,
+        opts: ScanOptions,
+    },
+    Files {
+        dir: PathBuf,
+        root: PathBuf,
+        opts: ScanOptions,
+    },
+    Delete {
+        targets: Vec<PathBuf>,
+        kind: DeleteKind,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Reply {
+    /// The paths of the hello made absolute, or the agent's roots for
+    /// none.
+    Ready(Vec<PathBuf>),
+    Event(ScanEvent),
+    Counters {
+        entries: u64,
+        bytes: u64,
+        current: PathBuf,
+    },
+    Tree(DirTree),
+    Files(Vec<DirStats>),
+    DeleteProgress(u64, u128),
+    Deleted(PathBuf, Result<(), String>),
+    Failed(String),
+}
+
+/// One end of a connection.
+struct Conn {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+}
+
+impl Conn {
+    fn new(stream: TcpStream) -> Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+        })
+    }
+
+    fn send(&mut self, msg: &impl Serialize) -> Result<()> {
+        rmp_serde::encode::write_named(&mut self.writer, msg)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn receive<T: DeserializeOwned>(&mut self) -> Result<T> {
+        Ok(rmp_serde::from_read(&mut self.reader)?)
+    }
+}
+
+// ====== Agent ======
+
+/// What the agent's own command line and config allow clients to do.
+struct Limits {
+    roots: Vec<PathBuf>, // resolved; nothing outside them is scanned or deleted
+    read_only: bool,
+    protected: GlobSet,
+    patterns: Vec<String>, // `delete.protected`, for saying which one matched
+}
+
+/// Serves clients on `args.listen` until the process is stopped, each
+/// connection on a thread of its own.
+pub fn serve(args: &AgentArgs, config: &Config) -> Result<()> {
+    let limits = Arc::new(Limits {
+        roots: resolve(args.roots.clone())?,
+        read_only: config.read_only,
+        protected: config::glob_set(&config.delete.protected)
+            .context("Bad delete.protected in the config")?,
+        patterns: config.delete.protected.clone(),
+    });
+    let token = match env::var(TOKEN_VAR).ok().filter(|t| !t.is_empty()) {
+        Some(token) => token,
+        None => {
+            let token = new_token()?;
+            log(format!(
+                "no {TOKEN_VAR} set; clients need {TOKEN_VAR}={token}"
+            ));
+            token
+        }
+    };
+    let listener = TcpListener::bind(&args.listen)
+        .with_context(|| format!("Cannot listen on {}", args.listen))?;
+    let roots: Vec<_> = limits
+        .roots
+        .iter()
+        .map(|r| r.display().to_string())
+        .collect();
+    log(format!(
+        "agent listening on {} for {}{}",
+        listener.local_addr()?,
+        roots.join(", "),
+        if limits.read_only { ", read-only" } else { "" }
+    ));
+    let token: Arc<str> = token.into();
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let token = token.clone();
+        let limits = limits.clone();
+        thread::spawn(move || {
+            let peer = stream
+                .peer_addr()
+                .map_or("?".to_string(), |a| a.to_string());
+            if let Err(e) = answer(stream, &token, &limits) {
+                log(format!("{peer}: {e:#}"));
+            }
+        });
+    }
+    Ok(())
+}
+
+/// 128 random bits, in hex.
+fn new_token() -> Result<String> {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).context("Cannot make up a token")?;
+    Ok(bytes.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+/// Compares tokens in time that depends only on their lengths, so timing
+/// replies doesn't give away how much of a guess was right.
+fn same_token(given: &[u8], token: &[u8]) -> bool {
+    given.len() == token.len() && given.iter().zip(token).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Answers the hello and the request after it.
+fn answer(stream: TcpStream, token: &str, limits: &Limits) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+    let mut conn = Conn::new(stream)?;
+    let Request::Hello {
+        version,
+        token: given,
+        paths,
+    } = conn.receive()?
+    else {
+        bail!("no hello");
+    };
+    let refused = if version != VERSION {
+        Some(format!(
+            "the agent speaks version {VERSION}, not {version}; run the same dm on both ends"
+        ))
+    } else if !same_token(given.unwrap_or_default().as_bytes(), token.as_bytes()) {
+        Some(format!("wrong or missing {TOKEN_VAR}"))
+    } else {
+        None
+    };
+    if let Some(why) = refused {
+        conn.send(&Reply::Failed(why.clone()))?;
+        bail!("refused: {why}");
+    }
+    let roots = match served(paths, &limits.roots) {
+        Ok(roots) => roots,
+        Err(e) => return conn.send(&Reply::Failed(format!("{e:#}"))),
+    };
+    conn.send(&Reply::Ready(roots.clone()))?;
+    // A client only after the paths hangs up here.
+    let Ok(request) = conn.receive() else {
+        return Ok(());
+    };
+    match request {
+        Request::Hello { .. } => conn.send(&Reply::Failed("hello again?".to_string())),
+        Request::Scan { path, .. } | Request::Files { dir: path, .. }
+            if served(vec![path.clone()], &limits.roots).is_err() =>
+        {
+            conn.send(&Reply::Failed(format!(
+                "{} isn't in the directories the agent serves",
+                path.display()
+            )))
+        }
+        Request::Scan { path, skip, opts } => scan(&mut conn, path, skip, opts),
+        Request::Files { dir, root, opts } => {
+            let (excludes, _) = build_excludes(&root, &opts);
+            let files = list_files(&*vfs::local(), &dir, &excludes, &opts);
+            conn.send(&Reply::Files(files))
+        }
+        Request::Delete { .. } if limits.read_only => {
+            conn.send(&Reply::Failed("the agent is read-only".to_string()))
+        }
+        Request::Delete { targets, kind } => {
+            let (refused, targets): (Vec<_>, Vec<_>) = targets
+                .into_iter()
+                .map(|t| match refusal(&t, &roots, limits) {
+                    Some(why) => Err((t, why)),
+                    None => Ok(t),
+                })
+                .partition(|t| t.is_err());
+            for (target, why) in refused.into_iter().filter_map(|t| t.err()) {
+                log(format!("not deleting {}: {why}", target.display()));
+                conn.send(&Reply::Deleted(target, Err(why)))?;
+            }
+            let targets: Vec<PathBuf> = targets.into_iter().filter_map(|t| t.ok()).collect();
+            if targets.is_empty() {
+                return Ok(());
+            }
+            delete(&mut conn, targets, kind)
+        }
+    }
+}
+
+/// Why the agent won't delete `target`: it isn't inside one of `roots`
+/// (symlinks on the way resolved), or it is or holds a protected path.
+fn refusal(target: &Path, roots: &[PathBuf], limits: &Limits) -> Option<String> {
+    let below = (|| {
+        if target.components().any(|c| c == Component::ParentDir) {
+            return None;
+        }
+        let parent = target.parent()?.canonicalize().ok()?;
+        let path = parent.join(target.file_name()?);
+        roots
+            .iter()
+            .any(|r| path != *r && path.starts_with(r))
+            .then_some(())
+    })();
+    if below.is_none() {
+        return Some("it isn't inside the directories asked for".to_string());
+    }
+    if limits.protected.is_empty() {
+        return None;
+    }
+    // Symlinks below are deleted, not followed, so neither is their target
+    // checked.
+    let hit = walkdir::WalkDir::new(target)
+        .follow_root_links(false)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .map(|e| e.into_path())
+        .find(|p| limits.protected.is_match(p))?;
+    let pattern = &limits.patterns[limits.protected.matches(&hit)[0]];
+    Some(if hit == target {
+        format!("protected by {pattern:?} in the agent's delete.protected")
+    } else {
+        format!(
+            "holds {}, which is protected by {pattern:?} in the agent's delete.protected",
+            hit.display()
+        )
+    })
+}
+
+/// The hello's `paths` made absolute, failing for any outside the agent's
+/// `roots`; none asks for the roots themselves.
+fn served(paths: Vec<PathBuf>, roots: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    if paths.is_empty() {
+        return Ok(roots.to_vec());
+    }
+    let paths = resolve(paths)?;
+    if let Some(outside) = paths
+        .iter()
+        .find(|p| !roots.iter().any(|r| p.starts_with(r)))
+    {
+        bail!(
+            "'{}' isn't in the directories the agent serves",
+            outside.display()
+        );
+    }
+    Ok(paths)
+}
+
+/// Makes `paths` absolute, failing for anything that isn't a directory.
+fn resolve(paths: Vec<PathBuf>) -> Result<Vec<PathBuf>> {
+    if paths.is_empty() {
+        return Ok(vec![
+            env::current_dir().context("Unable to get current directory")?
+        ]);
+    }
+    paths
+        .into_iter()
+        .map(|p| {
+            let path = p
+                .canonicalize()
+                .with_context(|| format!("Cannot open '{}'", p.display()))?;
+            if !path.is_dir() {
+                bail!("'{}' is not a directory", p.display());
+            }
+            Ok(path)
+        })
+        .collect()
+}
+
+/// Scans `path`, passing on its events and totals as it goes and then the
+/// tree. The scan is cancelled once the client is gone.
+fn scan(conn: &mut Conn, path: PathBuf, skip: Option<PathBuf>, opts: ScanOptions) -> Result<()> {
+    let cancel = Arc::new(AtomicBool::new(false));
+    let counters = Arc::new(ScanCounters::default());
+    let scanner = Scanner {
+        skip,
+        cancel: cancel.clone(),
+        counters: counters.clone(),
+        ..Scanner::new(path, opts)
+    };
+    let (events, rx) = mpsc::channel();
+    let scan = thread::spawn(move || {
+        scanner.run(move |event| {
+            let _ = events.send(event);
+        })
+    });
+    let mut reported = Instant::now();
+    let forwarded = loop {
+        let sent = match rx.recv_timeout(COUNTERS_EVERY) {
+            Ok(event) => conn.send(&Reply::Event(event)),
+            Err(RecvTimeoutError::Timeout) => Ok(()),
+            Err(RecvTimeoutError::Disconnected) => break Ok(()),
+        };
+        let sent = sent.and_then(|()| {
+            if reported.elapsed() < COUNTERS_EVERY {
+                return Ok(());
+            }
+            reported = Instant::now();
+            conn.send(&Reply::Counters {
+                entries: counters.entries.load(Ordering::Relaxed),
+                bytes: counters.bytes.load(Ordering::Relaxed),
+                current: counters.current.lock().unwrap().clone(),
+            })
+        });
+        if sent.is_err() {
+            break sent;
+        }
+    };
+    if forwarded.is_err() {
+        cancel.store(true, Ordering::Relaxed);
+    }
+    let tree = scan.join().ok().flatten();
+    forwarded?;
+    match tree {
+        Some(tree) => conn.send(&Reply::Tree(tree)),
+        None => conn.send(&Reply::Failed("the scan could not start".to_string())),
+    }
+}
+
+/// Deletes `targets` the way the browser does locally, passing on its
+/// progress and how each went. What's left is spared once the client is
+/// gone.
+fn delete(conn: &mut Conn, targets: Vec<PathBuf>, kind: DeleteKind) -> Result<()> {
+    let cancel = Arc::new(AtomicBool::new(false));
+    let (tx, rx) = mpsc::channel();
+    let mut left = targets.len();
+    crate::spawn_delete_thread(vfs::local(), targets, kind, cancel.clone(), tx);
+    while left > 0 {
+        let Ok(msg) = rx.recv() else {
+            break;
+        };
+        let sent = match msg {
+            Msg::DeleteProgress(files, bytes) => conn.send(&Reply::DeleteProgress(files, bytes)),
+            Msg::DeleteFinished(path, _, res) => {
+                left -= 1;
+                conn.send(&Reply::Deleted(path, res))
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = sent {
+            cancel.store(true, Ordering::Relaxed);
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+fn log(msg: impl std::fmt::Display) {
+    eprintln!("{} dm: {msg}", Local::now().format("%Y-%m-%d %H:%M:%S"));
+}
+
+// ====== Client ======
+
+/// An agent to scan through, as given to `--connect`.
+#[derive(Debug, Clone)]
+pub struct Remote {
+    pub addr: String,
+    token: Option<String>,
+}
+
+impl Remote {
+    pub fn new(addr: String) -> Self {
+        Self {
+            addr,
+            token: env::var(TOKEN_VAR).ok().filter(|t| !t.is_empty()),
+        }
+    }
+
+    /// Connects and says hello, getting `paths` resolved on the way.
+    fn open(&self, paths: Vec<PathBuf>) -> Result<(Conn, Vec<PathBuf>)> {
+        let stream = TcpStream::connect(&self.addr)
+            .with_context(|| format!("Cannot connect to the agent at {}", self.addr))?;
+        let mut conn = Conn::new(stream)?;
+        conn.send(&Request::Hello {
+            version: VERSION,
+            token: self.token.clone(),
+            paths,
+        })?;
+        match conn.receive()? {
+            Reply::Ready(roots) => Ok((conn, roots)),
+            Reply::Failed(e) => bail!("The agent at {} refused: {e}", self.addr),
+            _ => bail!("The agent at {} doesn't make sense", self.addr),
+        }
+    }
+
+    /// `paths` on the agent's machine made absolute, or its working
+    /// directory when there are none.
+    pub fn resolve(&self, paths: Vec<PathBuf>) -> Result<Vec<PathBuf>> {
+        Ok(self.open(paths)?.1)
+    }
+
+    /// The files directly in `dir`, as `list_files` on the agent's machine
+    /// finds them.
+    pub fn files(&self, dir: PathBuf, root: PathBuf, opts: ScanOptions) -> Result<Vec<DirStats>> {
+        let (mut conn, _) = self.open(Vec::new())?;
+        conn.send(&Request::Files { dir, root, opts })?;
+        match conn.receive()? {
+            Reply::Files(files) => Ok(files),
+            Reply::Failed(e) => bail!(e),
+            _ => bail!("The agent at {} doesn't make sense", self.addr),
+        }
+    }
+}
+
+/// Has the agent run scan `id`, reporting back like a local scan would and
+/// keeping `counters` up to date. Setting `cancel` hangs up on it.
+pub fn spawn_scan_thread(
+    remote: Remote,
+    id: u64,
+    scanner: Scanner,
+    tx: Sender<Msg>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let Scanner {
+            root,
+            opts,
+            skip,
+            cancel,
+            counters,
+            ..
+        } = scanner;
+        let tree = (|| -> Result<Option<DirTree>> {
+            let (mut conn, _) = remote.open(Vec::new())?;
+            conn.send(&Request::Scan {
+                path: root.clone(),
+                skip,
+                opts,
+            })?;
+            while !cancel.load(Ordering::Relaxed) {
+                match conn.receive()? {
+                    Reply::Event(event) => {
+                        let _ = tx.send(Msg::Scan(id, event));
+                    }
+                    Reply::Counters {
+                        entries,
+                        bytes,
+                        current,
+                    } => {
+                        counters.entries.store(entries, Ordering::Relaxed);
+                        counters.bytes.store(bytes, Ordering::Relaxed);
+                        *counters.current.lock().unwrap() = current;
+                    }
+                    Reply::Tree(tree) => return Ok(Some(tree)),
+                    Reply::Failed(e) => bail!(e),
+                    _ => bail!("The agent at {} doesn't make sense", remote.addr),
+                }
+            }
+            Ok(None)
+        })();
+        let msg = match tree {
+            Ok(Some(tree)) => Msg::ScanFinished(id, tree),
+            Ok(None) => return,
+            Err(e) => Msg::ScanFailed(id, format!("Scan of {} failed: {e:#}", root.display())),
+        };
+        let _ = tx.send(msg);
+    })
+}
+
+/// Has the agent delete `targets`, which must be below `roots`, reporting
+/// back like a local deletion would. Setting `cancel` hangs up, which
+/// stops it after the file at hand.
+pub fn spawn_delete_thread(
+    remote: Remote,
+    roots: Vec<PathBuf>,
+    targets: Vec<PathBuf>,
+    kind: DeleteKind,
+    cancel: Arc<AtomicBool>,
+    tx: Sender<Msg>,
+) {
+    thread::spawn(move || {
+        let mut left = targets.clone();
+        let mut failed = false;
+        let res = (|| -> Result<()> {
+            let (mut conn, _) = remote.open(roots)?;
+            conn.send(&Request::Delete {
+                targets: targets.clone(),
+                kind,
+            })?;
+            while !left.is_empty() && !cancel.load(Ordering::Relaxed) {
+                match conn.receive()? {
+                    Reply::DeleteProgress(files, bytes) => {
+                        let _ = tx.send(Msg::DeleteProgress(files, bytes));
+                    }
+                    Reply::Deleted(path, res) => {
+                        left.retain(|t| *t != path);
+                        failed |= res.is_err();
+                        let _ = tx.send(Msg::DeleteFinished(path, kind, res));
+                    }
+                    Reply::Failed(e) => bail!(e),
+                    _ => bail!("The agent at {} doesn't make sense", remote.addr),
+                }
+            }
+            Ok(())
+        })();
+        // Whatever is left wasn't done, or isn't known to be.
+        let why = match res {
+            Ok(()) => "stopped before getting to it".to_string(),
+            Err(e) => format!("{e:#}"),
+        };
+        failed |= !left.is_empty();
+        for target in left {
+            let _ = tx.send(Msg::DeleteFinished(target, kind, Err(why.clone())));
+        }
+        // A directory may have gone halfway.
+        if failed {
+            let _ = tx.send(Msg::RecomputeNow);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::unix::fs::symlink};
+
+    use super::*;
+
+    /// A fresh directory for test `name`, resolved like the agent's roots.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("dm-remote-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    fn limits(roots: &[PathBuf], protected: &[&str]) -> Limits {
+        let patterns: Vec<String> = protected.iter().map(|p| p.to_string()).collect();
+        Limits {
+            roots: roots.to_vec(),
+            read_only: false,
+            protected: crate::config::glob_set(&patterns).unwrap(),
+            patterns,
+        }
+    }
+
+    #[test]
+    fn refuses_outside_the_roots() {
+        let dir = temp_dir("outside");
+        let root = dir.join("root");
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::create_dir(dir.join("other")).unwrap();
+        let roots = [root.clone()];
+        let limits = limits(&roots, &[]);
+
+        assert_eq!(refusal(&root.join("a/b"), &roots, &limits), None);
+        assert!(refusal(&root.join("a/../../other"), &roots, &limits).is_some());
+        assert!(refusal(&root.join(".."), &roots, &limits).is_some());
+        assert!(refusal(&dir.join("other"), &roots, &limits).is_some());
+        assert!(refusal(&root, &roots, &limits).is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_what_holds_a_protected_path() {
+        let dir = temp_dir("protected");
+        fs::create_dir_all(dir.join("project/src/.git")).unwrap();
+        let roots = [dir.clone()];
+        let limits = limits(&roots, &["**/.git"]);
+
+        let why = refusal(&dir.join("project"), &roots, &limits).unwrap();
+        assert!(why.contains("holds") && why.contains(".git"), "{why}");
+        let why = refusal(&dir.join("project/src/.git"), &roots, &limits).unwrap();
+        assert!(why.starts_with("protected by"), "{why}");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn does_not_follow_symlinks() {
+        let dir = temp_dir("symlink");
+        let root = dir.join("root");
+        fs::create_dir_all(root.join("repo/.git")).unwrap();
+        fs::create_dir(dir.join("elsewhere")).unwrap();
+        symlink(root.join("repo"), root.join("link")).unwrap();
+        symlink(dir.join("elsewhere"), root.join("away")).unwrap();
+        let roots = [root.clone()];
+        let limits = limits(&roots, &["**/.git"]);
+
+        // Deleting the link leaves what it points to alone.
+        assert_eq!(refusal(&root.join("link"), &roots, &limits), None);
+        assert!(refusal(&root.join("repo"), &roots, &limits).is_some());
+        // A link on the way out of the roots is resolved, though.
+        fs::write(dir.join("elsewhere/file"), "x").unwrap();
+        assert!(refusal(&root.join("away/file"), &roots, &limits).is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
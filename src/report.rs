@@ -0,0 +1,177 @@
+//! `dm report`: a du-style summary of a scan on stdout, for cron jobs and
+//! terminals that can't run the browser.
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use clap::{Args, ValueEnum};
+use humansize::{format_size, DECIMAL};
+use serde::Serialize;
+use thousands::Separable;
+
+use dm_core::tree::{DirStats, DirTree, SizeMode};
+
+use crate::html;
+
+#[derive(Debug, Args)]
+pub struct ReportArgs {
+    /// Directory to scan (defaults to the current directory).
+    #[arg(value_name = "PATH")]
+    pub path: Option<PathBuf>,
+
+    /// How many of the largest directories to list; 0 lists all of them.
+    #[arg(short = 'n', long, value_name = "N", default_value_t = 20)]
+    pub top: usize,
+
+    /// Only list directories at most this many levels below PATH. Unlike
+    /// `--max-depth`, everything is still scanned.
+    #[arg(short = 'd', long, value_name = "N")]
+    pub depth: Option<usize>,
+
+    /// Output format; json and csv give sizes in bytes, html a standalone
+    /// page with a sortable table and a treemap (`-d` levels deep, 3 by
+    /// default).
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Text,
+    Json,
+    Csv,
+    Html,
+}
+
+#[derive(Serialize)]
+struct Row<'a> {
+    path: &'a Path,
+    bytes: u128,
+    disk_bytes: u128,
+    files: u64,
+    dirs: u64,
+    errors: u64,
+    estimated: u64,
+}
+
+impl<'a> From<&'a (PathBuf, &DirStats)> for Row<'a> {
+    fn from((path, s): &'a (PathBuf, &DirStats)) -> Self {
+        Self {
+            path,
+            bytes: s.total_bytes,
+            disk_bytes: s.disk_bytes,
+            files: s.file_count,
+            dirs: s.dir_count,
+            errors: s.errors,
+            estimated: s.estimated,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    size_mode: SizeMode,
+    total: Row<'a>,
+    directories: Vec<Row<'a>>,
+}
+
+/// Writes the largest directories of `tree`, biggest first by `mode`,
+/// followed by the totals of its root. Directories the scan didn't read
+/// for being below its depth limit are left out.
+pub fn write(
+    tree: &DirTree,
+    args: &ReportArgs,
+    mode: SizeMode,
+    out: &mut impl Write,
+) -> Result<()> {
+    let mut ids = Vec::new();
+    let mut stack: Vec<_> = tree.children(tree.root()).iter().map(|&c| (c, 1)).collect();
+    while let Some((id, depth)) = stack.pop() {
+        if tree.stats(id).cut {
+            continue;
+        }
+        ids.push(id);
+        if args.depth.is_none_or(|max| depth < max) {
+            stack.extend(tree.children(id).iter().map(|&c| (c, depth + 1)));
+        }
+    }
+    ids.sort_by_key(|&id| std::cmp::Reverse(tree.stats(id).bytes(mode)));
+    if args.top > 0 {
+        ids.truncate(args.top);
+    }
+    let dirs: Vec<_> = ids
+        .into_iter()
+        .map(|id| (tree.path(id), tree.stats(id)))
+        .collect();
+    let root = (tree.root_path().to_path_buf(), tree.stats(tree.root()));
+
+    match args.format {
+        Format::Text => {
+            // Like in the browser, ~ marks sizes that are estimates.
+            let rough = |s: &DirStats| if s.estimated > 0 { "~" } else { " " };
+            for (path, s) in &dirs {
+                let size = format_size(s.bytes(mode) as u64, DECIMAL);
+                let files = s.file_count.separate_with_spaces();
+                writeln!(
+                    out,
+                    "{size:>10}{} {files:>12}  {}",
+                    rough(s),
+                    path.display()
+                )?;
+            }
+            let (path, root) = &root;
+            writeln!(
+                out,
+                "{:>10}{} {:>12}  {} (total, {} directories{})",
+                format_size(root.bytes(mode) as u64, DECIMAL),
+                rough(root),
+                root.file_count.separate_with_spaces(),
+                path.display(),
+                root.dir_count.separate_with_spaces(),
+                match root.estimated {
+                    0 => String::new(),
+                    n => format!(", {} sampled or unread", n.separate_with_spaces()),
+                },
+            )?;
+        }
+        Format::Json => {
+            let report = Report {
+                size_mode: mode,
+                total: (&root).into(),
+                directories: dirs.iter().map(Row::from).collect(),
+            };
+            serde_json::to_writer_pretty(&mut *out, &report)?;
+            writeln!(out)?;
+        }
+        Format::Csv => {
+            writeln!(out, "path,bytes,disk_bytes,files,dirs,errors,estimated")?;
+            for (path, s) in dirs.iter().chain([&root]) {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{}",
+                    csv_field(&path.to_string_lossy()),
+                    s.total_bytes,
+                    s.disk_bytes,
+                    s.file_count,
+                    s.dir_count,
+                    s.errors,
+                    s.estimated
+                )?;
+            }
+        }
+        Format::Html => html::write(tree, &dirs, mode, args.depth, out)?,
+    }
+    Ok(())
+}
+
+/// Quotes a CSV field if it needs it (RFC 4180).
+pub fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
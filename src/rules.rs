@@ -0,0 +1,145 @@
+//! `[[rule]]` alerts from the config: when a directory outgrows a limit,
+//! POST to a webhook or run a command, then stay quiet for a cooldown so a
+//! directory that stays too big doesn't alert on every rescan.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use humansize::{format_size, DECIMAL};
+use serde_json::json;
+use thousands::Separable;
+
+use dm_core::{
+    mounts,
+    tree::{DirTree, SizeMode},
+};
+
+use crate::{actions, config::Rule};
+
+/// The rules, with when each last alerted.
+pub struct Rules {
+    rules: Vec<(Rule, PathBuf)>, // with the path resolved, to find it in trees
+    fired: Vec<Option<Instant>>,
+}
+
+/// A rule found broken, ready to be sent.
+pub struct Alert {
+    rule: Rule,
+    path: PathBuf,
+    bytes: u128,
+    files: u64,
+    message: String,
+}
+
+impl Rules {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        let fired = vec![None; rules.len()];
+        let rules = rules
+            .into_iter()
+            .map(|rule| {
+                let path =
+                    fs::canonicalize(&rule.path).map_or_else(|_| rule.path.clone(), mounts::plain);
+                (rule, path)
+            })
+            .collect();
+        Self { rules, fired }
+    }
+
+    /// The rules for `within` and below that `tree` breaks and that are
+    /// out of their cooldown, which starts over for each. Rules for paths
+    /// outside the tree are left for a tree that has them.
+    pub fn check(&mut self, tree: &DirTree, within: &Path, mode: SizeMode) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        for ((rule, path), fired) in self.rules.iter().zip(&mut self.fired) {
+            if !path.starts_with(within) {
+                continue;
+            }
+            let Some(id) = tree.find(path) else {
+                continue;
+            };
+            let stats = tree.stats(id);
+            if stats.estimated > 0 {
+                // Still being counted.
+                continue;
+            }
+            let (bytes, files) = (stats.bytes(mode), stats.file_count);
+            let mut over = Vec::new();
+            if let Some(max) = rule.max_size.filter(|&max| bytes > max) {
+                over.push(format!(
+                    "{} > {}",
+                    format_size(bytes as u64, DECIMAL),
+                    format_size(max as u64, DECIMAL)
+                ));
+            }
+            if let Some(max) = rule.max_files.filter(|&max| files > max) {
+                over.push(format!(
+                    "{} files > {}",
+                    files.separate_with_commas(),
+                    max.separate_with_commas()
+                ));
+            }
+            let cooldown = Duration::from_secs(60 * rule.cooldown_minutes);
+            if over.is_empty() || fired.is_some_and(|at| at.elapsed() < cooldown) {
+                continue;
+            }
+            *fired = Some(Instant::now());
+            alerts.push(Alert {
+                rule: rule.clone(),
+                path: path.clone(),
+                bytes,
+                files,
+                message: format!("{} is over its limit: {}", path.display(), over.join(", ")),
+            });
+        }
+        alerts
+    }
+}
+
+impl Alert {
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// POSTs to the rule's webhook and runs its command, waiting for both.
+    pub fn send(&self) -> Result<()> {
+        if let Some(url) = &self.rule.webhook {
+            let body = json!({
+                "text": self.message,
+                "path": self.path,
+                "bytes": self.bytes,
+                "files": self.files,
+                "max_size": self.rule.max_size,
+                "max_files": self.rule.max_files,
+            });
+            ureq::post(url)
+                .timeout(Duration::from_secs(30))
+                .set("Content-Type", "application/json")
+                .send_string(&body.to_string())
+                .with_context(|| format!("Webhook {url} failed"))?;
+        }
+        if let Some(command) = &self.rule.command {
+            let line = command
+                .replace("{path}", &actions::quote(&self.path.to_string_lossy()))
+                .replace("{size}", &self.bytes.to_string())
+                .replace("{files}", &self.files.to_string())
+                .replace("{message}", &actions::quote(&self.message));
+            let out = actions::shell_command(&line)
+                .stdin(Stdio::null())
+                .output()
+                .with_context(|| format!("Cannot run {command:?}"))?;
+            if !out.status.success() {
+                let stderr = String::from_utf8_lossy(&out.stderr);
+                match stderr.lines().rev().find(|l| !l.trim().is_empty()) {
+                    Some(last) => bail!("{command:?}: {} ({})", last.trim(), out.status),
+                    None => bail!("{command:?}: {}", out.status),
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+//! `[[schedule]]` entries from the config: cron expressions saying when to
+//! rescan, save a snapshot or check the `[[rule]]`s, in the UI and in
+//! `--daemon` alike.
+
+use std::{path::Path, time::Duration};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use croner::Cron;
+use serde::Deserialize;
+
+use crate::config::Schedule;
+
+/// What a schedule does when it comes due.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduleAction {
+    /// Rescans the path.
+    #[default]
+    Rescan,
+    /// Saves the path's tree as a snapshot named after it and the time.
+    Snapshot,
+    /// Checks the `[[rule]]`s for the path and below, so ones still broken
+    /// alert again once their cooldown is over.
+    Rules,
+}
+
+impl ScheduleAction {
+    pub fn label(self) -> &'static str {
+        match self {
+            ScheduleAction::Rescan => "rescan",
+            ScheduleAction::Snapshot => "snapshot",
+            ScheduleAction::Rules => "rule check",
+        }
+    }
+}
+
+/// The schedules, with when each is next due.
+pub struct Schedules {
+    entries: Vec<(Schedule, Cron, Option<DateTime<Local>>)>, // None once it never comes again
+}
+
+impl Schedules {
+    /// Schedules with expressions that don't parse are left out; `config::load`
+    /// has already refused those.
+    pub fn new(schedules: &[Schedule]) -> Self {
+        let now = Local::now();
+        let entries = schedules
+            .iter()
+            .filter_map(|schedule| {
+                let cron = parse(&schedule.cron).ok()?;
+                let next = cron.find_next_occurrence(&now, false).ok();
+                Some((schedule.clone(), cron, next))
+            })
+            .collect();
+        Self { entries }
+    }
+
+    /// The schedules that have come due, each moved on to its next time
+    /// after now. One that came due several times since it was last asked
+    /// for, say over a suspend, is returned once.
+    pub fn due(&mut self) -> Vec<Schedule> {
+        let now = Local::now();
+        let mut due = Vec::new();
+        for (schedule, cron, next) in &mut self.entries {
+            if next.is_some_and(|next| next <= now) {
+                due.push(schedule.clone());
+                *next = cron.find_next_occurrence(&now, false).ok();
+            }
+        }
+        due
+    }
+
+    /// How long until the next schedule comes due, if any ever does.
+    pub fn until_next(&self) -> Option<Duration> {
+        let now = Local::now();
+        self.entries
+            .iter()
+            .filter_map(|(_, _, next)| *next)
+            .min()
+            .map(|next| (next - now).to_std().unwrap_or_default())
+    }
+}
+
+/// A cron expression of five fields: minute, hour, day of month, month and
+/// day of week.
+pub fn parse(expr: &str) -> Result<Cron> {
+    Cron::new(expr)
+        .parse()
+        .with_context(|| format!("Bad cron expression {expr:?}"))
+}
+
+/// The name a scheduled snapshot of `dir` is saved under, like
+/// `var-2024-05-01-0300`.
+pub fn snapshot_name(dir: &Path) -> String {
+    let name = dir.file_name().map(|name| name.to_string_lossy());
+    let base = match name.as_deref().map(|name| name.trim_start_matches('.')) {
+        Some(base) if !base.is_empty() => base,
+        _ => "root",
+    };
+    format!("{base}-{}", Local::now().format("%Y-%m-%d-%H%M"))
+}
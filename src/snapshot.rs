@@ -0,0 +1,126 @@
+//! Named copies of a scan tree ("before-cleanup"), kept in the data
+//! directory to compare later scans against or to browse offline.
+
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{bail, Context, Result};
+
+use dm_core::tree::DirTree;
+
+use crate::cache::{self, Format};
+
+/// A snapshot on disk, as listed in the snapshots menu.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Saved {
+    pub name: String,
+    pub format: Format,
+    pub bytes: u64, // size of the file
+    pub saved: SystemTime,
+}
+
+/// Where snapshots are kept, one file per name.
+pub fn dir() -> Option<PathBuf> {
+    Some(dirs::data_dir()?.join("dm").join("snapshots"))
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        bail!("Invalid snapshot name {name:?}");
+    }
+    Ok(())
+}
+
+fn path_of(name: &str, format: Format) -> Result<PathBuf> {
+    check_name(name)?;
+    let dir = dir().context("No data directory to keep snapshots in")?;
+    Ok(dir.join(format!("{name}.{}", format.extension())))
+}
+
+/// The file snapshot `name` is in, whichever format it was saved in.
+fn find(name: &str) -> Result<Option<PathBuf>> {
+    for format in Format::ALL {
+        let path = path_of(name, format)?;
+        if path.is_file() {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+/// Saves `tree` as snapshot `name` in `format`, replacing one of the same
+/// name in either format.
+pub fn save(name: &str, tree: DirTree, format: Format) -> Result<()> {
+    let path = path_of(name, format)?;
+    cache::write_file(&path, tree)?;
+    for other in Format::ALL.into_iter().filter(|&f| f != format) {
+        remove_file(&path_of(name, other)?)?;
+    }
+    Ok(())
+}
+
+/// Loads snapshot `name` and when it was saved.
+pub fn load(name: &str) -> Result<(DirTree, SystemTime)> {
+    let snapshot = match find(name)? {
+        Some(path) => cache::read_file(&path)?,
+        None => None,
+    };
+    match snapshot {
+        Some(snapshot) => Ok(snapshot),
+        None => bail!("No snapshot named {name:?}"),
+    }
+}
+
+/// Deletes snapshot `name`.
+pub fn remove(name: &str) -> Result<()> {
+    match find(name)? {
+        Some(path) => remove_file(&path),
+        None => bail!("No snapshot named {name:?}"),
+    }
+}
+
+fn remove_file(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("Cannot remove {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The saved snapshots, newest first. No snapshot directory yet means none.
+pub fn list() -> Result<Vec<Saved>> {
+    let Some(dir) = dir() else {
+        return Ok(Vec::new());
+    };
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Cannot read {}", dir.display())),
+    };
+    let mut saved = Vec::new();
+    for entry in entries.flatten() {
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        let Some((name, format)) = Format::ALL.into_iter().find_map(|f| {
+            let name = file_name.strip_suffix(f.extension())?.strip_suffix('.')?;
+            Some((name.to_string(), f))
+        }) else {
+            continue;
+        };
+        let Ok(md) = entry.metadata() else {
+            continue;
+        };
+        saved.push(Saved {
+            name,
+            format,
+            bytes: md.len(),
+            saved: md.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+        });
+    }
+    saved.sort_by(|a, b| b.saved.cmp(&a.saved).then_with(|| a.name.cmp(&b.name)));
+    Ok(saved)
+}
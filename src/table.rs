@@ -0,0 +1,108 @@
+//! `--export-csv` and `--export-tsv`: every directory of a scan as one row,
+//! for spreadsheets and BI tools.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use dm_core::tree::{DirStats, DirTree};
+
+use crate::report::csv_field;
+
+/// How fields are told apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Separator {
+    Comma, // RFC 4180, with fields quoted where needed
+    Tab,   // tabs and line breaks in paths written as \t, \n and \r
+}
+
+impl Separator {
+    fn field(self, s: &str) -> String {
+        match self {
+            Separator::Comma => csv_field(s),
+            Separator::Tab => s
+                .replace('\\', "\\\\")
+                .replace('\t', "\\t")
+                .replace('\n', "\\n")
+                .replace('\r', "\\r"),
+        }
+    }
+
+    fn char(self) -> char {
+        match self {
+            Separator::Comma => ',',
+            Separator::Tab => '\t',
+        }
+    }
+}
+
+/// Writes every directory of `tree` to `out` ("-" for stdout), parents
+/// before their subdirectories and those by name. Sizes are in bytes and
+/// `newest` is the latest mtime in the subtree, in UTC. Directories the
+/// scan didn't read for being below its depth limit are left out.
+pub fn export(tree: &DirTree, out: &Path, sep: Separator) -> Result<()> {
+    let writer: Box<dyn Write> = if out == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(File::create(out).with_context(|| format!("Cannot create {}", out.display()))?)
+    };
+    let mut w = BufWriter::new(writer);
+    let header = [
+        "path",
+        "depth",
+        "bytes",
+        "apparent_bytes",
+        "files",
+        "dirs",
+        "newest",
+        "errors",
+    ];
+    writeln!(w, "{}", header.join(&sep.char().to_string()))?;
+    let mut stack = vec![(tree.root(), tree.root_path().to_path_buf(), 0)];
+    while let Some((id, path, depth)) = stack.pop() {
+        let s = tree.stats(id);
+        if s.cut {
+            continue;
+        }
+        write_row(&mut w, &path, s, depth, sep)?;
+        let mut children = tree.children(id).to_vec();
+        // Popped in reverse, so this comes out A-Z.
+        children.sort_by(|&a, &b| tree.name(b).cmp(tree.name(a)));
+        stack.extend(
+            children
+                .into_iter()
+                .map(|c| (c, path.join(tree.name(c)), depth + 1)),
+        );
+    }
+    w.flush()?;
+    Ok(())
+}
+
+fn write_row(
+    w: &mut impl Write,
+    path: &Path,
+    s: &DirStats,
+    depth: usize,
+    sep: Separator,
+) -> Result<()> {
+    let newest = s.newest.map_or(String::new(), |t| {
+        DateTime::<Utc>::from(t).to_rfc3339_opts(SecondsFormat::Secs, true)
+    });
+    let fields = [
+        sep.field(&path.to_string_lossy()),
+        depth.to_string(),
+        s.disk_bytes.to_string(),
+        s.total_bytes.to_string(),
+        s.file_count.to_string(),
+        s.dir_count.to_string(),
+        newest,
+        s.errors.to_string(),
+    ];
+    writeln!(w, "{}", fields.join(&sep.char().to_string()))?;
+    Ok(())
+}
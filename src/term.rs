@@ -0,0 +1,124 @@
+//! Taking the terminal over for the TUI and handing it back, however dm
+//! ends. Raw mode and the alternate screen outliving dm, as after a panic,
+//! leave the shell unusable until `reset`.
+
+use std::{
+    backtrace::Backtrace,
+    fs,
+    io::{self, Write},
+    panic::{self, PanicHookInfo},
+    path::PathBuf,
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Once,
+    },
+    thread,
+};
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use crossterm::{
+    cursor::Show,
+    event::{DisableMouseCapture, EnableMouseCapture},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+
+/// Whether the TUI has the terminal right now.
+static TAKEN: AtomicBool = AtomicBool::new(false);
+/// Whether it captures the mouse while it does.
+static MOUSE: AtomicBool = AtomicBool::new(false);
+
+/// Keeps the terminal in raw mode on the alternate screen until dropped,
+/// and until then restores it on a panic too.
+pub struct Guard(());
+
+impl Guard {
+    pub fn enter(mouse: bool) -> Result<Self> {
+        install_panic_hook();
+        MOUSE.store(mouse, Ordering::Relaxed);
+        if let Err(e) = take() {
+            give_back();
+            return Err(e.into());
+        }
+        Ok(Guard(()))
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        give_back();
+    }
+}
+
+/// Switches to raw mode and the alternate screen, again after `give_back`.
+pub fn take() -> io::Result<()> {
+    enable_raw_mode()?;
+    TAKEN.store(true, Ordering::Relaxed);
+    let mut out = io::stdout();
+    execute!(out, EnterAlternateScreen)?;
+    if MOUSE.load(Ordering::Relaxed) {
+        execute!(out, EnableMouseCapture)?;
+    }
+    Ok(())
+}
+
+/// Leaves the alternate screen and raw mode, if the TUI has them. Errors
+/// are ignored, as there is nothing better to do than try the rest.
+pub fn give_back() {
+    if !TAKEN.swap(false, Ordering::Relaxed) {
+        return;
+    }
+    let mut out = io::stdout();
+    disable_raw_mode().ok();
+    if MOUSE.load(Ordering::Relaxed) {
+        execute!(out, DisableMouseCapture).ok();
+    }
+    execute!(out, LeaveAlternateScreen, Show).ok();
+}
+
+/// Gives the terminal back before saying what panicked, which would
+/// otherwise go to the alternate screen and vanish with it, and saves a
+/// crash log with the backtrace. Panics while the TUI is away, as in a
+/// shell from it, are left to the default hook.
+fn install_panic_hook() {
+    static ONCE: Once = Once::new();
+    ONCE.call_once(|| {
+        let default = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !TAKEN.load(Ordering::Relaxed) {
+                return default(info);
+            }
+            give_back();
+            eprintln!("dm {info}");
+            match save_crash_log(info) {
+                Ok(path) => eprintln!("The crash log is in {}", path.display()),
+                Err(e) => eprintln!("dm: {e:#}"),
+            }
+            // The screen is gone; the TUI can't go on without it.
+            if thread::current().name() != Some("main") {
+                process::exit(101);
+            }
+        }));
+    });
+}
+
+/// Where crash logs go, one file per crash.
+fn crash_dir() -> Option<PathBuf> {
+    Some(dirs::data_dir()?.join("dm").join("crashes"))
+}
+
+fn save_crash_log(info: &PanicHookInfo) -> Result<PathBuf> {
+    let dir = crash_dir().context("No data directory to save a crash log in")?;
+    fs::create_dir_all(&dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+    let path = dir.join(format!("{}.log", Local::now().format("%Y-%m-%d-%H%M%S")));
+    let write = || -> io::Result<()> {
+        let mut file = fs::File::create(&path)?;
+        writeln!(file, "dm {}", env!("CARGO_PKG_VERSION"))?;
+        writeln!(file, "{info}\n")?;
+        writeln!(file, "{}", Backtrace::force_capture())
+    };
+    write().with_context(|| format!("Cannot save a crash log to {}", path.display()))?;
+    Ok(path)
+}
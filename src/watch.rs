@@ -0,0 +1,124 @@
+//! Filesystem watching: keeps the totals of a scanned tree current between
+//! scans by recounting the directories that change.
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use dm_core::{
+    scan::{build_excludes, list_files, ScanOptions},
+    tree::DirStats,
+    vfs,
+};
+use ignore::gitignore::Gitignore;
+use notify::{event::EventKind, RecursiveMode, Watcher};
+
+use crate::Msg;
+
+/// Changes are reported once things have been quiet for this long...
+const QUIET: Duration = Duration::from_millis(500);
+
+/// ...or after this long at the latest, for directories that never settle.
+const MAX_DELAY: Duration = Duration::from_secs(3);
+
+/// The current state of one directory that changed.
+#[derive(Debug)]
+pub struct DirUpdate {
+    pub dir: PathBuf,
+    pub gone: bool,
+    pub mtime: Option<SystemTime>,
+    pub files: Vec<DirStats>, // as listed by `list_files`
+    pub subdirs: Vec<PathBuf>,
+}
+
+/// Watches `root` recursively until `stop` is set, sending batches of
+/// [`DirUpdate`]s. Setting up the watches walks the whole tree, so this
+/// runs on its own thread.
+pub fn spawn_watcher(root: PathBuf, opts: ScanOptions, stop: Arc<AtomicBool>, tx: Sender<Msg>) {
+    thread::spawn(move || {
+        let (events_tx, events) = mpsc::channel();
+        let watcher = notify::recommended_watcher(events_tx).and_then(|mut w| {
+            w.watch(&root, RecursiveMode::Recursive)?;
+            Ok(w)
+        });
+        // Keeps the watches alive for as long as this thread runs.
+        let _watcher = match watcher {
+            Ok(w) => w,
+            Err(e) => {
+                let msg = format!("Cannot watch {} for changes: {e}", root.display());
+                let _ = tx.send(Msg::Error(msg));
+                return;
+            }
+        };
+        let (excludes, _) = build_excludes(&root, &opts);
+
+        let mut dirty = HashSet::new();
+        let mut since: Option<Instant> = None;
+        while !stop.load(Ordering::Relaxed) {
+            match events.recv_timeout(QUIET) {
+                Ok(Ok(event)) => {
+                    if matches!(event.kind, EventKind::Access(_)) {
+                        continue;
+                    }
+                    for path in event.paths {
+                        if let Some(dir) = path.parent().filter(|d| d.starts_with(&root)) {
+                            dirty.insert(dir.to_path_buf());
+                        }
+                    }
+                    if since.get_or_insert_with(Instant::now).elapsed() < MAX_DELAY {
+                        continue;
+                    }
+                }
+                Ok(Err(_)) | Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+            if dirty.is_empty() || stop.load(Ordering::Relaxed) {
+                continue;
+            }
+            since = None;
+            let updates = dirty
+                .drain()
+                .filter(|d: &PathBuf| !excludes.matched(d, true).is_ignore())
+                .map(|dir| dir_update(dir, &excludes, &opts))
+                .collect();
+            let _ = tx.send(Msg::WatchUpdate(updates));
+        }
+    });
+}
+
+/// Reads what `dir` holds now, without descending into it.
+fn dir_update(dir: PathBuf, excludes: &Gitignore, opts: &ScanOptions) -> DirUpdate {
+    let Ok(md) = fs::metadata(&dir) else {
+        return DirUpdate {
+            dir,
+            gone: true,
+            mtime: None,
+            files: Vec::new(),
+            subdirs: Vec::new(),
+        };
+    };
+    let subdirs = fs::read_dir(&dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|e| e.file_type().is_ok_and(|ft| ft.is_dir()))
+        .map(|e| e.path())
+        .filter(|p| !excludes.matched(p, true).is_ignore())
+        .collect();
+    DirUpdate {
+        files: list_files(&*vfs::local(), &dir, excludes, opts),
+        dir,
+        gone: false,
+        mtime: md.modified().ok(),
+        subdirs,
+    }
+}
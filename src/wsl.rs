@@ -0,0 +1,59 @@
+//! Running inside WSL, where the Windows drives are mounted like `/mnt/c`
+//! and Windows programs such as Explorer only understand Windows paths.
+//! `wslpath` translates between the two, which also takes care of a
+//! different mount root set in `/etc/wsl.conf`.
+
+use std::{fs, path::Path, process::Command, sync::OnceLock};
+
+use anyhow::{bail, Context, Result};
+
+/// Whether this runs inside WSL.
+pub fn detected() -> bool {
+    static WSL: OnceLock<bool> = OnceLock::new();
+    *WSL.get_or_init(|| {
+        cfg!(target_os = "linux")
+            && (std::env::var_os("WSL_DISTRO_NAME").is_some()
+                || fs::read_to_string("/proc/sys/kernel/osrelease")
+                    .is_ok_and(|release| release.to_lowercase().contains("microsoft")))
+    })
+}
+
+/// `path` as Windows programs take it: `C:\Users` for `/mnt/c/Users`, and
+/// one under `\\wsl.localhost\` for the Linux side.
+pub fn windows_path(path: &Path) -> Result<String> {
+    let out = Command::new("wslpath")
+        .arg("-w")
+        .arg(path)
+        .output()
+        .context("Cannot run wslpath")?;
+    if !out.status.success() {
+        bail!("wslpath: {}", String::from_utf8_lossy(&out.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).trim_end().to_string())
+}
+
+/// The Windows drive mounted at `mount`, like `C:\` for `/mnt/c`. None
+/// outside WSL and for mounts on the Linux side.
+pub fn drive(mount: &Path) -> Option<String> {
+    if !detected() {
+        return None;
+    }
+    let drive = windows_path(mount).ok()?;
+    // A drive's root, not a `\\wsl.localhost\` path.
+    (drive.as_bytes().get(1) == Some(&b':')).then_some(drive)
+}
+
+/// `path` on the drive mounted at `mount` as a Windows path, given the
+/// drive's path.
+pub fn on_drive(path: &Path, mount: &Path, drive: &str) -> Option<String> {
+    let below = path.strip_prefix(mount).ok()?;
+    let mut out = drive.trim_end_matches('\\').to_string();
+    for part in below.iter() {
+        out.push('\\');
+        out.push_str(&part.to_string_lossy());
+    }
+    if below.as_os_str().is_empty() {
+        out.push('\\');
+    }
+    Some(out)
+}